@@ -0,0 +1,154 @@
+use actix::Addr;
+use actix_web::{
+    get,
+    http::header,
+    post,
+    web::{Data, Path, Query},
+    HttpResponse,
+};
+use askama::Template;
+
+use crate::{
+    api::{authorized, TokenQuery},
+    player::AppError,
+    session::{self, SessionCode, SessionManager, SessionSummary},
+    Html, SharedConfig,
+};
+
+fn unauthorized() -> AppError {
+    AppError::Unauthorized("A valid admin token is required".to_string())
+}
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+struct Admin<'a> {
+    sessions: &'a [SessionSummary],
+    /// Passed on to the close actions
+    token: &'a str,
+}
+
+/// Overview of all sessions for when one needs to be dealt with by hand
+#[get("/admin")]
+async fn admin(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    query: Query<TokenQuery>,
+) -> Result<Html, AppError> {
+    if !authorized(&config.get(), &query) {
+        return Err(unauthorized());
+    }
+
+    let sessions = manager.send(session::ListSessions).await?;
+
+    Html::render(Admin {
+        sessions: &sessions,
+        token: query.token.as_deref().unwrap_or_default(),
+    })
+}
+
+/// Kicks everyone out of a session and removes it
+#[post("/admin/sessions/{code}/close")]
+async fn close_session(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    code: Path<SessionCode>,
+    query: Query<TokenQuery>,
+) -> Result<HttpResponse, AppError> {
+    // Checked first so the response doesn't tell whether the session exists
+    if !authorized(&config.get(), &query) {
+        return Err(unauthorized());
+    }
+
+    if !manager
+        .send(session::CloseSession {
+            session: code.into_inner(),
+        })
+        .await?
+    {
+        return Err(AppError::NotFound("No such session exists".to_string()));
+    }
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header((
+            header::LOCATION,
+            format!(
+                "/admin?token={}",
+                crate::percent_encode(query.token.as_deref().unwrap_or_default())
+            ),
+        ))
+        .finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use actix_web::{
+        http::{header, StatusCode},
+        test::{self, TestRequest},
+        web::Data,
+        App,
+    };
+    use askama::Template;
+
+    use crate::{
+        admin::{close_session, Admin},
+        player::State,
+        session::SessionSummary,
+        testing::Harness,
+        Config, SharedConfig,
+    };
+
+    #[test]
+    fn admin_page() {
+        let sessions = [SessionSummary {
+            code: "test".to_string(),
+            players: 2,
+            roster: Vec::new(),
+            playlist_index: 3,
+            state: State::Playing,
+            title: Some("cat.mp4".to_string()),
+            uptime: 3720,
+            starts_at: None,
+        }];
+
+        let page = Admin {
+            sessions: &sessions,
+            token: "secret token",
+        }
+        .render()
+        .unwrap();
+
+        assert!(page.contains("1h 2m"));
+        assert!(page.contains("/admin/sessions/test/close?token=secret%20token"));
+    }
+
+    #[actix_web::test]
+    async fn close_redirects() {
+        let harness = Harness::start();
+        harness.new_session("doomed", 3).await.unwrap();
+        let config: Config =
+            ron::from_str(r#"(shitposts: [], bind: "", admin_token: Some("a&b #ü"))"#).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(harness.manager.clone()))
+                .app_data(Data::new(SharedConfig(RwLock::new(Arc::new(config)))))
+                .service(close_session),
+        )
+        .await;
+
+        let response = test::call_service(
+            &app,
+            TestRequest::post()
+                .uri("/admin/sessions/doomed/close?token=a%26b%20%23%C3%BC")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        // Back to the overview with the whole token
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "/admin?token=a%26b%20%23%C3%BC"
+        );
+    }
+}
@@ -0,0 +1,1107 @@
+use std::{
+    fmt::Write,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix::Addr;
+use actix_web::{
+    delete, get,
+    http::header::{
+        self, CacheControl, CacheDirective, ETag, EntityTag, Header, HttpDate, IfModifiedSince,
+        IfNoneMatch, LastModified,
+    },
+    post,
+    web::{Data, Json, Path, Query},
+    HttpRequest, HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blocklist::Blocklist,
+    media::{FolderStats, MediaIndex},
+    player,
+    presets::{Preset, PresetError, Presets},
+    session::{self, SessionCode, SessionManager},
+    Config, SharedConfig, Shitpost,
+};
+
+#[derive(Deserialize)]
+pub struct TokenQuery {
+    pub token: Option<String>,
+}
+
+/// Checks the request token against the configured admin token.
+/// Without a configured admin token the API stays closed.
+pub fn authorized(config: &Config, query: &TokenQuery) -> bool {
+    match (&config.admin_token, &query.token) {
+        (Some(admin_token), Some(token)) => admin_token == token,
+        _ => false,
+    }
+}
+
+#[get("/api/sessions")]
+async fn sessions(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    query: Query<TokenQuery>,
+) -> HttpResponse {
+    if !authorized(&config.get(), &query) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    HttpResponse::Ok().json(manager.send(session::ListSessions).await.unwrap())
+}
+
+/// Re-reads the config file, keeping the old config if the new one is invalid
+#[post("/api/reload")]
+async fn reload(
+    config: Data<SharedConfig>,
+    media: Data<MediaIndex>,
+    query: Query<TokenQuery>,
+) -> HttpResponse {
+    if !authorized(&config.get(), &query) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let new_config = match Config::load(&config.get().path) {
+        Ok(new_config) => new_config,
+        Err(why) => {
+            tracing::warn!("Rejected config reload: {}", why);
+            return HttpResponse::BadRequest().json(serde_json::json!({ "error": why }));
+        }
+    };
+
+    let missing = new_config.missing_folders();
+    if !missing.is_empty() {
+        let why = format!("Missing shitpost folders: {}", missing.join(", "));
+        tracing::warn!("Rejected config reload: {}", why);
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": why }));
+    }
+
+    tracing::info!("Reloaded config");
+    config.set(new_config);
+    media.rescan(config.get()).await;
+
+    HttpResponse::Ok().json(serde_json::json!({ "ok": true }))
+}
+
+#[get("/api/sessions/{code}/history")]
+async fn history(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    code: Path<SessionCode>,
+    query: Query<TokenQuery>,
+) -> HttpResponse {
+    if !authorized(&config.get(), &query) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    match manager
+        .send(session::GetHistory {
+            session: code.into_inner(),
+        })
+        .await
+        .unwrap()
+    {
+        Some(history) => HttpResponse::Ok().json(history),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "No such session" })),
+    }
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    token: Option<String>,
+    /// The host token of the session, for hosts looking into their own session
+    host: Option<String>,
+}
+
+/// Recent events of a session for debugging sync issues
+#[get("/api/sessions/{code}/events")]
+async fn events(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    code: Path<SessionCode>,
+    query: Query<EventsQuery>,
+) -> HttpResponse {
+    let query = query.into_inner();
+    if !authorized(&config.get(), &TokenQuery { token: query.token }) {
+        let Some(host) = query.host else {
+            return HttpResponse::Forbidden().finish();
+        };
+        let is_host = manager
+            .send(session::GetSession {
+                session: code.clone(),
+            })
+            .await
+            .ok()
+            .flatten()
+            .is_some_and(|session| session.is_host_token(&host));
+        if !is_host {
+            return HttpResponse::Forbidden().finish();
+        }
+    }
+
+    match manager
+        .send(session::GetEvents {
+            session: code.into_inner(),
+        })
+        .await
+        .unwrap()
+    {
+        Some(events) => HttpResponse::Ok().json(events),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "No such session" })),
+    }
+}
+
+#[derive(Deserialize)]
+struct KickRequest {
+    id: u64,
+    #[serde(default)]
+    reason: Option<String>,
+    #[serde(default)]
+    ban: bool,
+}
+
+#[post("/api/sessions/{code}/kick")]
+async fn kick(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    code: Path<SessionCode>,
+    query: Query<TokenQuery>,
+    request: Json<KickRequest>,
+) -> HttpResponse {
+    if !authorized(&config.get(), &query) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let request = request.into_inner();
+
+    if manager
+        .send(session::KickPlayer {
+            session: code.into_inner(),
+            id: request.id,
+            reason: request
+                .reason
+                .unwrap_or_else(|| "Kicked by the host".to_string()),
+            ban: request.ban,
+        })
+        .await
+        .unwrap()
+    {
+        HttpResponse::Ok().json(serde_json::json!({ "ok": true }))
+    } else {
+        HttpResponse::NotFound().json(serde_json::json!({ "error": "No such player" }))
+    }
+}
+
+/// Prometheus text exposition of the session manager's metrics
+#[get("/metrics")]
+async fn metrics(manager: Data<Addr<SessionManager>>) -> HttpResponse {
+    let metrics = manager.send(session::GetMetrics).await.unwrap();
+
+    let mut body = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, u64)]| {
+        let _ = writeln!(body, "# HELP {} {}", name, help);
+        let _ = writeln!(body, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            let _ = writeln!(body, "{}{} {}", name, labels, value);
+        }
+    };
+
+    metric(
+        "shitposting_active_sessions",
+        "gauge",
+        "Sessions currently in memory",
+        &[(String::new(), metrics.active_sessions as u64)],
+    );
+    metric(
+        "shitposting_connected_players",
+        "gauge",
+        "Players currently connected to a session",
+        &[(String::new(), metrics.connected_players as u64)],
+    );
+    metric(
+        "shitposting_websocket_messages_total",
+        "counter",
+        "Websocket messages handled by kind",
+        &metrics
+            .messages
+            .iter()
+            .map(|(kind, count)| (format!(r#"{{kind="{}"}}"#, kind), *count))
+            .collect::<Vec<_>>(),
+    );
+    metric(
+        "shitposting_sessions_created_total",
+        "counter",
+        "Sessions created",
+        &[(String::new(), metrics.sessions_created)],
+    );
+    metric(
+        "shitposting_playlist_advances_total",
+        "counter",
+        "Playlist changes across all sessions",
+        &[(String::new(), metrics.playlist_advances)],
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
+
+/// When the process started, for the uptime `/health` reports
+pub struct StartedAt(pub Instant);
+
+/// Longest `/health` waits for the session manager
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Serialize)]
+struct Health {
+    /// "ok", or "unavailable" if the session manager didn't answer
+    status: &'static str,
+    uptime_secs: u64,
+    /// Round trip to the session manager, None if it didn't answer
+    manager_latency_ms: Option<f64>,
+    manager_error: Option<String>,
+    active_sessions: Option<usize>,
+    connected_players: Option<usize>,
+    version: &'static str,
+    git_hash: &'static str,
+}
+
+/// Whether the server is alive, for uptime monitors. Answers 503 if the session manager
+/// doesn't answer in time. Only counts sessions, so it stays cheap to poll often.
+#[get("/health")]
+async fn health(manager: Data<Addr<SessionManager>>, started_at: Data<StartedAt>) -> HttpResponse {
+    let sent = Instant::now();
+    let pong = manager.send(session::Ping).timeout(HEALTH_TIMEOUT).await;
+
+    let mut report = Health {
+        status: "ok",
+        uptime_secs: started_at.0.elapsed().as_secs(),
+        manager_latency_ms: None,
+        manager_error: None,
+        active_sessions: None,
+        connected_players: None,
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("GIT_HASH"),
+    };
+    let mut response = match pong {
+        Ok(pong) => {
+            report.manager_latency_ms = Some(sent.elapsed().as_secs_f64() * 1000.0);
+            report.active_sessions = Some(pong.active_sessions);
+            report.connected_players = Some(pong.connected_players);
+            HttpResponse::Ok()
+        }
+        Err(why) => {
+            tracing::warn!(
+                "Health check failed, the session manager didn't answer: {}",
+                why
+            );
+            report.status = "unavailable";
+            report.manager_error = Some(why.to_string());
+            HttpResponse::ServiceUnavailable()
+        }
+    };
+
+    response
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(report)
+}
+
+#[derive(Deserialize)]
+struct PageQuery {
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+/// A page of a session's playlist, open to anyone who may join the session
+#[get("/api/sessions/{code}/playlist")]
+async fn playlist(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    code: Path<SessionCode>,
+    query: Query<PageQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let page_size = config.get().playlist_page_size;
+    let code = code.into_inner();
+
+    match manager
+        .send(session::CheckTicket {
+            session: code.clone(),
+            ticket: player::request_ticket(&req, &code),
+        })
+        .await
+        .unwrap()
+    {
+        Some(true) => {}
+        Some(false) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Wrong password" }))
+        }
+        None => {
+            return HttpResponse::NotFound().json(serde_json::json!({ "error": "No such session" }))
+        }
+    }
+
+    match manager
+        .send(session::GetPlaylistPage {
+            session: code,
+            offset: query.offset,
+            limit: query.limit.unwrap_or(page_size).min(page_size),
+        })
+        .await
+        .unwrap()
+    {
+        Some(page) => HttpResponse::Ok().json(page),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "No such session" })),
+    }
+}
+
+/// Moments bookmarked in a session, with links that jump to them
+#[get("/api/sessions/{code}/bookmarks")]
+async fn bookmarks(
+    manager: Data<Addr<SessionManager>>,
+    code: Path<SessionCode>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let code = code.into_inner();
+
+    match manager
+        .send(session::CheckTicket {
+            session: code.clone(),
+            ticket: player::request_ticket(&req, &code),
+        })
+        .await
+        .unwrap()
+    {
+        Some(true) => {}
+        Some(false) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Wrong password" }))
+        }
+        None => {
+            return HttpResponse::NotFound().json(serde_json::json!({ "error": "No such session" }))
+        }
+    }
+
+    match manager
+        .send(session::GetBookmarks {
+            session: code.clone(),
+        })
+        .await
+        .unwrap()
+    {
+        Some(bookmarks) => HttpResponse::Ok().json(
+            bookmarks
+                .into_iter()
+                .map(|bookmark| {
+                    serde_json::json!({
+                        "link": bookmark_link(&code, bookmark.position),
+                        "bookmark": bookmark,
+                    })
+                })
+                .collect::<Vec<_>>(),
+        ),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "No such session" })),
+    }
+}
+
+/// The playlist as a file to import on the host page, open to anyone who may join the session
+#[get("/api/sessions/{code}/export")]
+async fn export(
+    manager: Data<Addr<SessionManager>>,
+    code: Path<SessionCode>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let code = code.into_inner();
+
+    match manager
+        .send(session::CheckTicket {
+            session: code.clone(),
+            ticket: player::request_ticket(&req, &code),
+        })
+        .await
+        .unwrap()
+    {
+        Some(true) => {}
+        Some(false) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Wrong password" }))
+        }
+        None => {
+            return HttpResponse::NotFound().json(serde_json::json!({ "error": "No such session" }))
+        }
+    }
+
+    match manager
+        .send(session::ExportPlaylist {
+            session: code.clone(),
+        })
+        .await
+        .unwrap()
+    {
+        Some(export) => HttpResponse::Ok()
+            .insert_header((
+                header::CONTENT_DISPOSITION,
+                format!(r#"attachment; filename="playlist-{}.json""#, code),
+            ))
+            .json(export),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "No such session" })),
+    }
+}
+
+/// The entry a session is on, polled by stream overlays so any origin may read it
+#[get("/api/sessions/{code}/now")]
+async fn now_playing(
+    manager: Data<Addr<SessionManager>>,
+    code: Path<SessionCode>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let mut response = now_playing_response(&manager, code.into_inner(), &req).await;
+    response.headers_mut().insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        header::HeaderValue::from_static("*"),
+    );
+    response
+}
+
+async fn now_playing_response(
+    manager: &Addr<SessionManager>,
+    code: SessionCode,
+    req: &HttpRequest,
+) -> HttpResponse {
+    match manager
+        .send(session::CheckTicket {
+            session: code.clone(),
+            ticket: player::request_ticket(req, &code),
+        })
+        .await
+        .unwrap()
+    {
+        Some(true) => {}
+        Some(false) => {
+            return HttpResponse::Forbidden().json(serde_json::json!({ "error": "Wrong password" }))
+        }
+        None => {
+            return HttpResponse::NotFound().json(serde_json::json!({ "error": "No such session" }))
+        }
+    }
+
+    match manager
+        .send(session::GetNowPlaying { session: code })
+        .await
+        .unwrap()
+    {
+        Some(now) => HttpResponse::Ok()
+            .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+            .json(now),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": "No such session" })),
+    }
+}
+
+/// Joins the session, the fragment tells the player where to seek to
+fn bookmark_link(code: &SessionCode, position: f64) -> String {
+    format!("/join?session={}#t={:.1}", code, position)
+}
+
+#[derive(Deserialize)]
+struct PlaylistFile {
+    folder: String,
+    file: String,
+}
+
+#[derive(Deserialize)]
+struct AddRequest {
+    files: Vec<PlaylistFile>,
+}
+
+/// Appends files from the configured folders to a live session's playlist
+#[post("/api/sessions/{code}/playlist")]
+async fn add_to_playlist(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    media: Data<MediaIndex>,
+    code: Path<SessionCode>,
+    query: Query<TokenQuery>,
+    request: Json<AddRequest>,
+) -> HttpResponse {
+    if !authorized(&config.get(), &query) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let mut shitposts = Vec::new();
+    let mut unknown = Vec::new();
+    for file in &request.files {
+        match media.find(&file.folder, &file.file) {
+            Some(shitpost) => shitposts.push(shitpost),
+            None => unknown.push(format!("{}/{}", file.folder, file.file)),
+        }
+    }
+
+    if !unknown.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("No such files: {}", unknown.join(", "))
+        }));
+    }
+
+    if manager
+        .send(session::AddToPlaylist {
+            session: code.into_inner(),
+            shitposts,
+        })
+        .await
+        .unwrap()
+    {
+        HttpResponse::Ok().json(serde_json::json!({ "ok": true }))
+    } else {
+        HttpResponse::NotFound().json(serde_json::json!({ "error": "No such session" }))
+    }
+}
+
+#[delete("/api/sessions/{code}/playlist/{index}")]
+async fn remove_from_playlist(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    path: Path<(SessionCode, usize)>,
+    query: Query<TokenQuery>,
+) -> HttpResponse {
+    if !authorized(&config.get(), &query) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let (session, index) = path.into_inner();
+
+    if manager
+        .send(session::RemoveFromPlaylist { session, index })
+        .await
+        .unwrap()
+    {
+        HttpResponse::Ok().json(serde_json::json!({ "ok": true }))
+    } else {
+        HttpResponse::NotFound().json(serde_json::json!({ "error": "No such entry" }))
+    }
+}
+
+/// Rescans the shitpost folders for added or removed files
+#[post("/api/rescan")]
+async fn rescan(
+    config: Data<SharedConfig>,
+    media: Data<MediaIndex>,
+    query: Query<TokenQuery>,
+) -> HttpResponse {
+    let config = config.get();
+    if !authorized(&config, &query) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    media.rescan(config).await;
+
+    HttpResponse::Ok().json(serde_json::json!({ "ok": true }))
+}
+
+/// Files left out of new sessions for being reported as broken
+#[get("/api/blocklist")]
+async fn list_blocklist(
+    config: Data<SharedConfig>,
+    blocklist: Data<Blocklist>,
+    query: Query<TokenQuery>,
+) -> HttpResponse {
+    if !authorized(&config.get(), &query) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    HttpResponse::Ok().json(blocklist.list())
+}
+
+#[derive(Deserialize)]
+struct ClearQuery {
+    token: Option<String>,
+    /// Only unblocks this file, everything is unblocked if unset
+    url: Option<String>,
+}
+
+#[delete("/api/blocklist")]
+async fn clear_blocklist(
+    config: Data<SharedConfig>,
+    blocklist: Data<Blocklist>,
+    query: Query<ClearQuery>,
+) -> HttpResponse {
+    let query = query.into_inner();
+    if !authorized(&config.get(), &TokenQuery { token: query.token }) {
+        return HttpResponse::Forbidden().finish();
+    }
+
+    let cleared = blocklist.into_inner().clear(query.url.as_deref());
+    HttpResponse::Ok().json(serde_json::json!({ "cleared": cleared }))
+}
+
+/// A preset as the host page sees it
+#[derive(Serialize)]
+struct ListedPreset {
+    name: String,
+    #[serde(flatten)]
+    preset: Preset,
+    /// Folders no longer configured, the host page leaves them unchecked
+    missing: Vec<String>,
+}
+
+/// Saved host page settings. Like the host page itself they can be read by anyone who can reach it.
+#[get("/api/presets")]
+async fn list_presets(config: Data<SharedConfig>, presets: Data<Presets>) -> HttpResponse {
+    let config = config.get();
+    let listed = presets
+        .list()
+        .into_iter()
+        .map(|(name, preset)| ListedPreset {
+            missing: preset
+                .missing_folders(&config)
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            name,
+            preset,
+        })
+        .collect::<Vec<_>>();
+
+    HttpResponse::Ok().json(listed)
+}
+
+/// Changing presets takes the API token or the host token of a running session
+#[derive(Deserialize)]
+struct PresetQuery {
+    token: Option<String>,
+    session: Option<SessionCode>,
+    host: Option<String>,
+}
+
+async fn may_change_presets(
+    config: &Config,
+    manager: &Addr<SessionManager>,
+    query: PresetQuery,
+) -> bool {
+    if authorized(config, &TokenQuery { token: query.token }) {
+        return true;
+    }
+    let (Some(session), Some(host)) = (query.session, query.host) else {
+        return false;
+    };
+
+    manager
+        .send(session::GetSession { session })
+        .await
+        .ok()
+        .flatten()
+        .is_some_and(|session| session.is_host_token(&host))
+}
+
+fn presets_forbidden() -> HttpResponse {
+    HttpResponse::Forbidden().json(serde_json::json!({
+        "error": "Presets can only be changed with the API token or by a host"
+    }))
+}
+
+#[derive(Deserialize)]
+struct SaveRequest {
+    name: String,
+    #[serde(flatten)]
+    preset: Preset,
+}
+
+/// Saves a preset, replacing one with the same name
+#[post("/api/presets")]
+async fn save_preset(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    presets: Data<Presets>,
+    query: Query<PresetQuery>,
+    request: Json<SaveRequest>,
+) -> HttpResponse {
+    if !may_change_presets(&config.get(), &manager, query.into_inner()).await {
+        return presets_forbidden();
+    }
+    let request = request.into_inner();
+
+    match presets.into_inner().save(&request.name, request.preset) {
+        Ok(name) => HttpResponse::Ok().json(serde_json::json!({ "name": name })),
+        Err(PresetError::InvalidName) => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Preset names need a letter or digit"
+        })),
+        Err(PresetError::TooMany) => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("There can be at most {} presets", Presets::MAX_PRESETS)
+        })),
+    }
+}
+
+#[delete("/api/presets/{name}")]
+async fn delete_preset(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    presets: Data<Presets>,
+    query: Query<PresetQuery>,
+    name: Path<String>,
+) -> HttpResponse {
+    if !may_change_presets(&config.get(), &manager, query.into_inner()).await {
+        return presets_forbidden();
+    }
+
+    if presets.into_inner().remove(&name) {
+        HttpResponse::Ok().json(serde_json::json!({ "ok": true }))
+    } else {
+        HttpResponse::NotFound().json(serde_json::json!({ "error": "No such preset" }))
+    }
+}
+
+#[derive(Deserialize)]
+struct CatalogQuery {
+    /// Only titles containing this, ignoring case
+    q: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CatalogFolder {
+    name: String,
+    files: Vec<Shitpost>,
+    /// Of the whole folder, even if the files are filtered
+    stats: Option<FolderStats>,
+    /// Why the folder couldn't be read, its files are empty then
+    error: Option<String>,
+}
+
+/// The indexed files of a folder matching the filter
+fn catalog_entry(media: &MediaIndex, name: &str, filter: Option<&str>) -> CatalogFolder {
+    let (files, error) = match media.get(name) {
+        Some(Ok(shitposts)) => (
+            shitposts
+                .iter()
+                .filter(|shitpost| {
+                    filter.is_none_or(|filter| shitpost.title.to_lowercase().contains(filter))
+                })
+                .cloned()
+                .collect(),
+            None,
+        ),
+        Some(Err(why)) => (Vec::new(), Some(why)),
+        None => (Vec::new(), None),
+    };
+
+    CatalogFolder {
+        name: name.to_string(),
+        files,
+        stats: media.stats(name),
+        error,
+    }
+}
+
+/// Whether the client's copy is still current. If-Modified-Since is only
+/// looked at without If-None-Match, like the HTTP spec asks.
+fn is_fresh(request: &HttpRequest, etag: &EntityTag, changed_at: SystemTime) -> bool {
+    if request.headers().contains_key(header::IF_NONE_MATCH) {
+        return match IfNoneMatch::parse(request) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+            Err(_) => false,
+        };
+    }
+
+    let secs = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    };
+    match IfModifiedSince::parse(request) {
+        // HTTP dates only have whole seconds
+        Ok(IfModifiedSince(since)) => secs(since.into()) >= secs(changed_at),
+        Err(_) => false,
+    }
+}
+
+/// Serves a catalog response tagged with the index's last change,
+/// or 304 if the client already has it
+fn catalog_response<T: Serialize>(
+    request: &HttpRequest,
+    media: &MediaIndex,
+    body: impl FnOnce() -> T,
+) -> HttpResponse {
+    let changed_at = media.changed_at();
+    let etag = EntityTag::new_strong(
+        changed_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+            .to_string(),
+    );
+
+    if is_fresh(request, &etag, changed_at) {
+        return HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish();
+    }
+
+    HttpResponse::Ok()
+        .insert_header(ETag(etag))
+        .insert_header(LastModified(HttpDate::from(changed_at)))
+        .json(body())
+}
+
+/// Every configured folder and remote source with their indexed files
+#[get("/api/catalog")]
+async fn catalog(
+    request: HttpRequest,
+    config: Data<SharedConfig>,
+    media: Data<MediaIndex>,
+    query: Query<CatalogQuery>,
+) -> HttpResponse {
+    let config = config.get();
+    let filter = query.q.as_ref().map(|q| q.to_lowercase());
+
+    catalog_response(&request, &media, || {
+        config
+            .source_names()
+            .map(|name| catalog_entry(&media, name, filter.as_deref()))
+            .collect::<Vec<_>>()
+    })
+}
+
+/// A single folder of the catalog, only configured folders can be looked at
+#[get("/api/catalog/{folder}")]
+async fn catalog_folder(
+    request: HttpRequest,
+    config: Data<SharedConfig>,
+    media: Data<MediaIndex>,
+    folder: Path<String>,
+    query: Query<CatalogQuery>,
+) -> HttpResponse {
+    if !config.get().source_names().any(|name| name == *folder) {
+        return HttpResponse::NotFound().json(serde_json::json!({ "error": "No such folder" }));
+    }
+
+    let filter = query.q.as_ref().map(|q| q.to_lowercase());
+
+    catalog_response(&request, &media, || {
+        catalog_entry(&media, &folder, filter.as_deref())
+    })
+}
+
+/// Files of a folder missing from its loudness sidecar, for scripting the measuring.
+/// Remote sources have nothing to measure, so only folders can be looked at.
+#[get("/api/catalog/{folder}/loudness/missing")]
+async fn missing_loudness(
+    config: Data<SharedConfig>,
+    media: Data<MediaIndex>,
+    folder: Path<String>,
+) -> HttpResponse {
+    if !config
+        .get()
+        .shitposts
+        .iter()
+        .any(|configured| configured.name == *folder)
+    {
+        return HttpResponse::NotFound().json(serde_json::json!({ "error": "No such folder" }));
+    }
+
+    match media.missing_loudness(&folder) {
+        Some(files) => HttpResponse::Ok().json(serde_json::json!({ "files": files })),
+        None => HttpResponse::NotFound()
+            .json(serde_json::json!({ "error": "The folder hasn't been read" })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, RwLock},
+        time::{Duration, Instant, UNIX_EPOCH},
+    };
+
+    use actix::{Addr, AsyncContext, Context};
+    use actix_web::{
+        http::{
+            header::{self, EntityTag, HttpDate},
+            StatusCode,
+        },
+        test::{self, TestRequest},
+        web::Data,
+        App,
+    };
+    use serde_json::Value;
+
+    use crate::{
+        api::{
+            bookmark_link, events, health, is_fresh, may_change_presets, playlist, PresetQuery,
+            StartedAt,
+        },
+        session::{SessionCode, SessionManager},
+        testing::{code, Harness},
+        Config, SharedConfig,
+    };
+
+    #[test]
+    fn bookmark_links() {
+        let code = SessionCode::try_from("abc123".to_string()).unwrap();
+        assert_eq!(bookmark_link(&code, 123.44), "/join?session=abc123#t=123.4");
+        assert_eq!(bookmark_link(&code, 0.0), "/join?session=abc123#t=0.0");
+    }
+
+    #[test]
+    fn catalog_freshness() {
+        let changed_at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        let etag = EntityTag::new_strong("1700000000500".to_string());
+        let fresh = |name, value: String| {
+            let request = TestRequest::default()
+                .insert_header((name, value))
+                .to_http_request();
+            is_fresh(&request, &etag, changed_at)
+        };
+
+        assert!(!is_fresh(
+            &TestRequest::default().to_http_request(),
+            &etag,
+            changed_at
+        ));
+        assert!(fresh(header::IF_NONE_MATCH, etag.to_string()));
+        assert!(fresh(header::IF_NONE_MATCH, "*".to_string()));
+        assert!(!fresh(header::IF_NONE_MATCH, r#""1""#.to_string()));
+
+        let date = |secs| HttpDate::from(UNIX_EPOCH + Duration::from_secs(secs)).to_string();
+        assert!(fresh(header::IF_MODIFIED_SINCE, date(1_700_000_000)));
+        assert!(!fresh(header::IF_MODIFIED_SINCE, date(1_699_999_999)));
+    }
+
+    #[actix_web::test]
+    async fn health_checks() {
+        let harness = Harness::start();
+        harness.new_session("alive", 3).await.unwrap();
+        let _player = harness.connect("alive", "bob").await;
+
+        let check = |manager: Addr<SessionManager>| async move {
+            let app = test::init_service(
+                App::new()
+                    .app_data(Data::new(manager))
+                    .app_data(Data::new(StartedAt(Instant::now())))
+                    .service(health),
+            )
+            .await;
+            let response =
+                test::call_service(&app, TestRequest::get().uri("/health").to_request()).await;
+            let status = response.status();
+            let body: serde_json::Value = test::read_body_json(response).await;
+            (status, body)
+        };
+
+        let (status, body) = check(harness.manager.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["active_sessions"], 1);
+        assert_eq!(body["connected_players"], 1);
+        assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body["manager_latency_ms"].is_number());
+
+        // The mailbox closes with the context, like after the manager panicked
+        let (status, body) = check(Context::<SessionManager>::new().address()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unavailable");
+        assert!(body["active_sessions"].is_null());
+    }
+
+    #[actix_web::test]
+    async fn preset_permissions() {
+        let harness = Harness::start();
+        harness.new_session("hosted", 3).await.unwrap();
+        let config: Config =
+            ron::from_str(r#"(shitposts: [], bind: "", admin_token: Some("secret"))"#).unwrap();
+        let may = |token: Option<&str>, session: Option<&str>, host: Option<&str>| {
+            let query = PresetQuery {
+                token: token.map(String::from),
+                session: session.map(code),
+                host: host.map(String::from),
+            };
+            may_change_presets(&config, &harness.manager, query)
+        };
+
+        assert!(may(Some("secret"), None, None).await);
+        assert!(may(None, Some("hosted"), Some("host")).await);
+        assert!(!may(None, None, None).await);
+        assert!(!may(Some("guess"), None, None).await);
+        assert!(!may(None, Some("hosted"), Some("guess")).await);
+        assert!(!may(None, Some("gone"), Some("host")).await);
+        // The token of a session only counts for that session
+        assert!(!may(None, None, Some("host")).await);
+    }
+
+    #[actix_web::test]
+    async fn event_permissions() {
+        let harness = Harness::start();
+        harness.new_session("hosted", 3).await.unwrap();
+        let config: Config =
+            ron::from_str(r#"(shitposts: [], bind: "", admin_token: Some("secret"))"#).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(harness.manager.clone()))
+                .app_data(Data::new(SharedConfig(RwLock::new(Arc::new(config)))))
+                .service(events),
+        )
+        .await;
+        let status = |query: &str| {
+            let request = TestRequest::get()
+                .uri(&format!("/api/sessions/hosted/events{}", query))
+                .to_request();
+            let app = &app;
+            async move { test::call_service(app, request).await.status() }
+        };
+
+        assert_eq!(status("?token=secret").await, StatusCode::OK);
+        // Hosts may look into their own session
+        assert_eq!(status("?host=host").await, StatusCode::OK);
+        assert_eq!(status("").await, StatusCode::FORBIDDEN);
+        assert_eq!(status("?token=guess").await, StatusCode::FORBIDDEN);
+        assert_eq!(status("?host=guess").await, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn playlist_pages() {
+        let harness = Harness::start();
+        harness.new_session("paged", 5).await.unwrap();
+        harness.new_session("empty", 0).await.unwrap();
+        let config: Config =
+            ron::from_str(r#"(shitposts: [], bind: "", playlist_page_size: 2)"#).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(harness.manager.clone()))
+                .app_data(Data::new(SharedConfig(RwLock::new(Arc::new(config)))))
+                .service(playlist),
+        )
+        .await;
+        let page = |uri: &str| {
+            let request = TestRequest::get().uri(uri).to_request();
+            let app = &app;
+            async move {
+                let page: Value = test::call_and_read_body_json(app, request).await;
+                let titles: Vec<_> = page["shitposts"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|shitpost| shitpost["title"].as_str().unwrap().to_string())
+                    .collect();
+                (page["total"].as_u64().unwrap(), titles)
+            }
+        };
+
+        assert_eq!(
+            page("/api/sessions/paged/playlist").await,
+            (5, vec!["0".to_string(), "1".to_string()])
+        );
+        // Limits can't get past the page size
+        assert_eq!(
+            page("/api/sessions/paged/playlist?offset=1&limit=10").await,
+            (5, vec!["1".to_string(), "2".to_string()])
+        );
+        assert_eq!(
+            page("/api/sessions/paged/playlist?offset=4&limit=10").await,
+            (5, vec!["4".to_string()])
+        );
+        assert_eq!(
+            page("/api/sessions/paged/playlist?offset=9").await,
+            (5, vec![])
+        );
+        assert_eq!(page("/api/sessions/empty/playlist").await, (0, vec![]));
+    }
+}
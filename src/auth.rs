@@ -0,0 +1,435 @@
+//! Optional gate in front of the whole app, for instances that shouldn't be open to everyone
+//! who finds the address. Everything but the static assets is behind it, including the sockets
+//! and the shitpost files, so media can't be linked to around it.
+
+use std::{
+    future::{ready, Ready},
+    net::IpAddr,
+    sync::Arc,
+};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
+    middleware::Condition,
+    Error, HttpRequest, HttpResponse,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::future::LocalBoxFuture;
+use serde::Deserialize;
+
+/// How visitors are let in, configured as `auth` in the config
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthConfig {
+    /// HTTP basic auth with one shared login
+    Basic { username: String, password: String },
+    /// Only addresses in these ranges, like "192.168.0.0/16" or "::1"
+    Allowlist(Vec<Cidr>),
+}
+
+/// An address range, a single address if the prefix length is left out
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let (address, prefix) = match text.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (text.as_str(), None),
+        };
+        let network = address
+            .trim()
+            .parse::<IpAddr>()
+            .map_err(|_| format!(r#""{}" is not an IP address or range"#, text))?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!(r#"Invalid prefix length in "{}""#, text))?,
+            None => max,
+        };
+
+        Ok(Self { network, prefix })
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, address: IpAddr) -> bool {
+        // Dual-stack sockets report IPv4 clients as mapped IPv6 addresses
+        let address = match address {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+            IpAddr::V4(_) => address,
+        };
+
+        match (self.network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The address a request came from. Behind trusted proxies that is the last address in
+/// X-Forwarded-For that wasn't added by one of them, as clients can put anything before it.
+pub fn client_ip(req: &HttpRequest, trusted_proxies: &[Cidr]) -> Option<IpAddr> {
+    let trusted = |address: IpAddr| trusted_proxies.iter().any(|range| range.contains(address));
+    let mut address = req.peer_addr()?.ip();
+
+    let forwarded = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect::<Vec<_>>();
+    for hop in forwarded.into_iter().rev() {
+        if !trusted(address) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(hop) => address = hop,
+            // Whatever is left can't be told apart from the proxy
+            Err(_) => break,
+        }
+    }
+
+    Some(address)
+}
+
+/// The checked form of `AuthConfig`
+enum Access {
+    /// The Authorization header value expected, without the scheme
+    Basic { credentials: String },
+    Allowlist {
+        ranges: Vec<Cidr>,
+        /// Proxies whose X-Forwarded-For is believed, see `client_ip`
+        trusted_proxies: Vec<Cidr>,
+    },
+}
+
+impl Access {
+    fn new(config: &AuthConfig, trusted_proxies: &[Cidr]) -> Self {
+        match config {
+            AuthConfig::Basic { username, password } => Self::Basic {
+                credentials: STANDARD.encode(format!("{}:{}", username, password)),
+            },
+            AuthConfig::Allowlist(ranges) => Self::Allowlist {
+                ranges: ranges.clone(),
+                trusted_proxies: trusted_proxies.to_vec(),
+            },
+        }
+    }
+
+    /// The response turning the request away, if it is
+    fn check(&self, req: &HttpRequest) -> Result<(), HttpResponse> {
+        match self {
+            Self::Basic { credentials } => {
+                let given = req
+                    .headers()
+                    .get(header::AUTHORIZATION)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.split_once(' '))
+                    .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("basic"))
+                    .map(|(_, given)| given.trim());
+
+                match given {
+                    Some(given) if constant_time_eq(given.as_bytes(), credentials.as_bytes()) => {
+                        Ok(())
+                    }
+                    _ => Err(HttpResponse::Unauthorized()
+                        .insert_header((
+                            header::WWW_AUTHENTICATE,
+                            r#"Basic realm="shitposting", charset="UTF-8""#,
+                        ))
+                        .finish()),
+                }
+            }
+            Self::Allowlist {
+                ranges,
+                trusted_proxies,
+            } => {
+                let address = client_ip(req, trusted_proxies);
+                if address.is_some_and(|address| ranges.iter().any(|range| range.contains(address)))
+                {
+                    Ok(())
+                } else {
+                    tracing::debug!("Turned away {:?}, it isn't on the allowlist", address);
+                    Err(HttpResponse::Forbidden().finish())
+                }
+            }
+        }
+    }
+}
+
+/// Compares without returning early, so the time taken doesn't tell how much of a guess was right
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Static assets are the same for everyone and stay reachable, like for the login prompt
+fn is_open(path: &str) -> bool {
+    path == "/static" || path.starts_with("/static/")
+}
+
+/// The middleware around the whole app. Without an auth section it is left out of the
+/// service chain entirely, so requests don't pass through it at all.
+pub fn gate(config: Option<&AuthConfig>, trusted_proxies: &[Cidr]) -> Condition<Gate> {
+    Condition::new(
+        config.is_some(),
+        Gate(config.map(|config| Arc::new(Access::new(config, trusted_proxies)))),
+    )
+}
+
+pub struct Gate(Option<Arc<Access>>);
+
+impl<S, B> Transform<S, ServiceRequest> for Gate
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = GateMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(GateMiddleware {
+            service,
+            access: self.0.clone(),
+        }))
+    }
+}
+
+pub struct GateMiddleware<S> {
+    service: S,
+    access: Option<Arc<Access>>,
+}
+
+impl<S, B> Service<ServiceRequest> for GateMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if let Some(access) = self.access.as_ref().filter(|_| !is_open(req.path())) {
+            if let Err(response) = access.check(req.request()) {
+                let response = req.into_response(response).map_into_right_body();
+                return Box::pin(ready(Ok(response)));
+            }
+        }
+
+        let response = self.service.call(req);
+        Box::pin(async move { response.await.map(ServiceResponse::map_into_left_body) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use actix_web::{
+        http::{header, StatusCode},
+        test::{self, TestRequest},
+        web, App, HttpResponse,
+    };
+
+    use crate::auth::{client_ip, gate, AuthConfig, Cidr};
+
+    fn cidr(text: &str) -> Cidr {
+        Cidr::try_from(text.to_string()).unwrap()
+    }
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn ranges() {
+        assert!(cidr("192.168.0.0/16").contains(ip("192.168.4.20")));
+        assert!(!cidr("192.168.0.0/16").contains(ip("192.169.0.1")));
+        assert!(cidr("10.0.0.7").contains(ip("10.0.0.7")));
+        assert!(!cidr("10.0.0.7").contains(ip("10.0.0.8")));
+        assert!(cidr("0.0.0.0/0").contains(ip("8.8.8.8")));
+        assert!(cidr("fd00::/8").contains(ip("fd12:3456::1")));
+        assert!(!cidr("fd00::/8").contains(ip("fe80::1")));
+        assert!(cidr("::1").contains(ip("::1")));
+        // IPv4 clients of a dual-stack socket
+        assert!(cidr("127.0.0.0/8").contains(ip("::ffff:127.0.0.1")));
+        assert!(!cidr("::/0").contains(ip("127.0.0.1")));
+
+        assert!(Cidr::try_from("10.0.0.0/33".to_string()).is_err());
+        assert!(Cidr::try_from("10.0.0/8".to_string()).is_err());
+        assert!(Cidr::try_from("localhost".to_string()).is_err());
+    }
+
+    #[test]
+    fn forwarded_clients() {
+        let proxies = [cidr("10.0.0.0/8")];
+        let request = |peer: &str, forwarded: Option<&str>| {
+            let mut request = TestRequest::get().peer_addr(SocketAddr::new(ip(peer), 40000));
+            if let Some(forwarded) = forwarded {
+                request = request.insert_header(("X-Forwarded-For", forwarded));
+            }
+            request.to_http_request()
+        };
+
+        assert_eq!(
+            client_ip(&request("8.8.8.8", None), &proxies),
+            Some(ip("8.8.8.8"))
+        );
+        // Only believed from a trusted proxy
+        assert_eq!(
+            client_ip(&request("8.8.8.8", Some("1.1.1.1")), &proxies),
+            Some(ip("8.8.8.8"))
+        );
+        assert_eq!(
+            client_ip(&request("10.0.0.2", Some("1.1.1.1")), &proxies),
+            Some(ip("1.1.1.1"))
+        );
+        // A client making up addresses only gets its own address used
+        assert_eq!(
+            client_ip(
+                &request("10.0.0.2", Some("9.9.9.9, 1.1.1.1, 10.0.0.3")),
+                &proxies
+            ),
+            Some(ip("1.1.1.1"))
+        );
+        assert_eq!(
+            client_ip(&request("10.0.0.2", Some("garbage")), &proxies),
+            Some(ip("10.0.0.2"))
+        );
+        assert_eq!(
+            client_ip(&request("10.0.0.2", Some("1.1.1.1")), &[]),
+            Some(ip("10.0.0.2"))
+        );
+    }
+
+    #[test]
+    fn configs() {
+        let config: AuthConfig = ron::from_str(r#"allowlist(["10.0.0.0/8", "::1"])"#).unwrap();
+        assert_eq!(
+            config,
+            AuthConfig::Allowlist(vec![cidr("10.0.0.0/8"), cidr("::1")])
+        );
+        let config: AuthConfig =
+            toml::from_str("[basic]\nusername = \"friends\"\npassword = \"hunter2\"\n").unwrap();
+        assert_eq!(
+            config,
+            AuthConfig::Basic {
+                username: "friends".to_string(),
+                password: "hunter2".to_string()
+            }
+        );
+        assert!(serde_json::from_str::<AuthConfig>(r#"{"allowlist": ["10.0.0.0/99"]}"#).is_err());
+    }
+
+    #[actix_web::test]
+    async fn gates() {
+        let basic = AuthConfig::Basic {
+            username: "friends".to_string(),
+            password: "hunter2".to_string(),
+        };
+        let allowlist = AuthConfig::Allowlist(vec![cidr("192.168.0.0/16")]);
+        let start = |config: Option<AuthConfig>, trusted_proxies: Vec<Cidr>| async move {
+            test::init_service(
+                App::new()
+                    .wrap(gate(config.as_ref(), &trusted_proxies))
+                    .default_service(web::to(|| async { HttpResponse::Ok().finish() })),
+            )
+            .await
+        };
+        let call = |uri: &str, auth: Option<&str>, peer: &str| {
+            let mut request = TestRequest::get()
+                .uri(uri)
+                .peer_addr(SocketAddr::new(ip(peer), 40000));
+            if let Some(auth) = auth {
+                request = request.insert_header((header::AUTHORIZATION, auth.to_string()));
+            }
+            request.to_request()
+        };
+
+        let open = start(None, Vec::new()).await;
+        let response = test::call_service(&open, call("/", None, "8.8.8.8")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let app = start(Some(basic), Vec::new()).await;
+        // "friends:hunter2"
+        let right = Some("Basic ZnJpZW5kczpodW50ZXIy");
+        for uri in ["/", "/player/socket?session=abc", "/shitposts/clips/a.webm"] {
+            let response = test::call_service(&app, call(uri, None, "8.8.8.8")).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", uri);
+            assert!(response
+                .headers()
+                .get(header::WWW_AUTHENTICATE)
+                .is_some_and(|value| value.to_str().unwrap().starts_with("Basic ")));
+
+            let response = test::call_service(&app, call(uri, right, "8.8.8.8")).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        }
+        let response = test::call_service(
+            &app,
+            call("/", Some("basic ZnJpZW5kczpodW50ZXIy"), "8.8.8.8"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // "friends:hunter3"
+        let response = test::call_service(
+            &app,
+            call("/", Some("Basic ZnJpZW5kczpodW50ZXIz"), "8.8.8.8"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = test::call_service(&app, call("/static/style.css", None, "8.8.8.8")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let app = start(Some(allowlist.clone()), Vec::new()).await;
+        let response = test::call_service(&app, call("/api/sessions", None, "192.168.1.5")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&app, call("/api/sessions", right, "10.0.0.1")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(response.headers().get(header::WWW_AUTHENTICATE).is_none());
+        let response = test::call_service(&app, call("/static/style.css", None, "10.0.0.1")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Behind a proxy the client it forwards for is checked, not the proxy
+        let app = start(Some(allowlist), vec![cidr("10.0.0.0/8")]).await;
+        let forwarded = |client: &str| {
+            TestRequest::get()
+                .uri("/api/sessions")
+                .peer_addr(SocketAddr::new(ip("10.0.0.2"), 40000))
+                .insert_header(("X-Forwarded-For", client.to_string()))
+                .to_request()
+        };
+        let response = test::call_service(&app, forwarded("192.168.1.5")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = test::call_service(&app, forwarded("8.8.8.8")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = test::call_service(&app, call("/api/sessions", None, "10.0.0.2")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+/// Files reported as broken by enough players, kept on disk so new sessions leave them out
+pub struct Blocklist {
+    path: PathBuf,
+    /// Distinct reporters needed to block a file, 0 never blocks
+    threshold: usize,
+    /// File URLs, who reported them and when they last were, only kept until the file is
+    /// blocked or the reports expire
+    reports: Mutex<HashMap<String, (HashSet<String>, Instant)>>,
+    /// File URLs and how often reading them failed while they were streamed
+    failures: Mutex<HashMap<String, usize>>,
+    /// Blocked file URLs and when they were blocked as a unix timestamp in seconds
+    blocked: Mutex<BTreeMap<String, u64>>,
+    /// Held while writing so flushes don't interleave
+    flush: Mutex<()>,
+}
+
+#[derive(Serialize)]
+pub struct BlockedFile {
+    pub url: String,
+    pub blocked_at: u64,
+}
+
+impl Blocklist {
+    /// How long reports of a file are kept without another one coming in
+    const REPORT_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+    /// Most files with reports kept, the ones reported longest ago are forgotten first
+    const MAX_REPORTED: usize = 1000;
+
+    /// Loads the list, starting over if the file is missing or unreadable
+    pub fn load(path: impl Into<PathBuf>, threshold: usize) -> Self {
+        let path = path.into();
+
+        let blocked = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|why| {
+                tracing::warn!(r#"Ignoring invalid "{}": {}"#, path.display(), why);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+
+        Self {
+            path,
+            threshold,
+            reports: Mutex::new(HashMap::new()),
+            failures: Mutex::new(HashMap::new()),
+            blocked: Mutex::new(blocked),
+            flush: Mutex::new(()),
+        }
+    }
+
+    /// Counts a report against a file, returns whether it got blocked by it
+    pub fn report(self: &Arc<Self>, url: &str, reporter: &str) -> bool {
+        if self.threshold == 0 || self.contains(url) {
+            return false;
+        }
+
+        {
+            let mut reports = self.reports.lock().unwrap();
+            reports.retain(|_, (_, last)| last.elapsed() < Self::REPORT_TTL);
+            if !reports.contains_key(url) && reports.len() >= Self::MAX_REPORTED {
+                let oldest = reports
+                    .iter()
+                    .min_by_key(|(_, (_, last))| *last)
+                    .map(|(url, _)| url.clone());
+                if let Some(oldest) = oldest {
+                    reports.remove(&oldest);
+                }
+            }
+
+            let (reporters, last) = reports
+                .entry(url.to_string())
+                .or_insert_with(|| (HashSet::new(), Instant::now()));
+            *last = Instant::now();
+            reporters.insert(reporter.to_string());
+            if reporters.len() < self.threshold {
+                return false;
+            }
+            reports.remove(url);
+        }
+
+        tracing::info!(r#"Blocked "{}" after {} reports"#, url, self.threshold);
+        self.block(url);
+        true
+    }
+
+    /// Counts a failed read while the file was streamed, which blocks it
+    /// after as many failures as reports would. Returns whether it got blocked by it.
+    pub fn stream_failed(self: &Arc<Self>, url: &str) -> bool {
+        if self.threshold == 0 || self.contains(url) {
+            return false;
+        }
+
+        {
+            let mut failures = self.failures.lock().unwrap();
+            let count = failures.entry(url.to_string()).or_default();
+            *count += 1;
+            if *count < self.threshold {
+                return false;
+            }
+            failures.remove(url);
+        }
+
+        tracing::info!(
+            r#"Blocked "{}" after failing to stream it {} times"#,
+            url,
+            self.threshold
+        );
+        self.block(url);
+        true
+    }
+
+    fn block(self: &Arc<Self>, url: &str) {
+        self.blocked.lock().unwrap().insert(url.to_string(), now());
+
+        let store = self.clone();
+        actix_web::rt::task::spawn_blocking(move || store.flush());
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        self.blocked.lock().unwrap().contains_key(url)
+    }
+
+    /// Blocked files, ordered by URL
+    pub fn list(&self) -> Vec<BlockedFile> {
+        self.blocked
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(url, blocked_at)| BlockedFile {
+                url: url.clone(),
+                blocked_at: *blocked_at,
+            })
+            .collect()
+    }
+
+    /// Unblocks a single file or all of them, returns how many were unblocked
+    pub fn clear(self: &Arc<Self>, url: Option<&str>) -> usize {
+        let cleared = {
+            let mut blocked = self.blocked.lock().unwrap();
+            match url {
+                Some(url) => blocked.remove(url).map_or(0, |_| 1),
+                None => std::mem::take(&mut *blocked).len(),
+            }
+        };
+
+        if cleared > 0 {
+            let store = self.clone();
+            actix_web::rt::task::spawn_blocking(move || store.flush());
+        }
+        cleared
+    }
+
+    fn flush(&self) {
+        let _guard = self.flush.lock().unwrap();
+        // Serialized under the flush lock, so the last write always has the latest state
+        let json = serde_json::to_vec(&*self.blocked.lock().unwrap()).unwrap();
+
+        if let Err(why) = fs::write(&self.path, json) {
+            tracing::warn!(r#"Failed to write "{}": {}"#, self.path.display(), why);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::blocklist::Blocklist;
+
+    #[actix_web::test]
+    async fn reports() {
+        let path = std::env::temp_dir().join(format!("blocklist-{}.json", std::process::id()));
+        let blocklist = Arc::new(Blocklist::load(&path, 2));
+
+        assert!(!blocklist.report("/shitposts/a/broken.mp4", "alice"));
+        // Reporting twice doesn't count twice
+        assert!(!blocklist.report("/shitposts/a/broken.mp4", "alice"));
+        assert!(!blocklist.contains("/shitposts/a/broken.mp4"));
+        assert!(blocklist.report("/shitposts/a/broken.mp4", "bob"));
+        assert!(blocklist.contains("/shitposts/a/broken.mp4"));
+        assert!(!blocklist.report("/shitposts/a/broken.mp4", "carol"));
+
+        assert!(!blocklist.report("/shitposts/a/other.mp4", "alice"));
+        assert_eq!(blocklist.list().len(), 1);
+
+        // Files that are only ever reported once don't pile up
+        for i in 0..Blocklist::MAX_REPORTED {
+            blocklist.report(&format!("/shitposts/b/{}.mp4", i), "alice");
+        }
+        assert_eq!(
+            blocklist.reports.lock().unwrap().len(),
+            Blocklist::MAX_REPORTED
+        );
+        // The first report of the file was forgotten to make room
+        assert!(!blocklist.report("/shitposts/a/other.mp4", "bob"));
+
+        // Failures aren't told apart by who had them
+        assert!(!blocklist.stream_failed("/shitposts/a/cut.mp4"));
+        assert!(blocklist.stream_failed("/shitposts/a/cut.mp4"));
+        assert!(!blocklist.stream_failed("/shitposts/a/cut.mp4"));
+        assert_eq!(blocklist.clear(None), 2);
+        assert!(!blocklist.contains("/shitposts/a/broken.mp4"));
+
+        let _ = std::fs::remove_file(path);
+    }
+}
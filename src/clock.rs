@@ -0,0 +1,148 @@
+//! Estimates how far a client's clock is off from the server's, NTP style.
+//! The server sends its time, the client answers with it and its own time, and the round trip
+//! tells how long the answer was underway.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// One probe and its answer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    /// Client clock minus server clock
+    pub offset_ms: f64,
+    pub rtt_ms: f64,
+}
+
+impl Sample {
+    /// Answers taking longer than this are too unreliable to learn from
+    const MAX_RTT: Duration = Duration::from_secs(10);
+
+    /// The sample from a probe sent at `sent_ms` and answered at `client_ms`, received at
+    /// `received_ms`. None if the times don't add up, like for a probe the server never sent.
+    pub fn new(sent_ms: u64, client_ms: f64, received_ms: u64) -> Option<Self> {
+        let rtt_ms = received_ms.checked_sub(sent_ms)?;
+        if rtt_ms > Self::MAX_RTT.as_millis() as u64 || !client_ms.is_finite() {
+            return None;
+        }
+
+        // The client answered about halfway through the round trip
+        let rtt_ms = rtt_ms as f64;
+        Some(Self {
+            offset_ms: client_ms - (sent_ms as f64 + rtt_ms / 2.0),
+            rtt_ms,
+        })
+    }
+}
+
+/// Where a client's clock is estimated to be
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    /// Client clock minus server clock
+    pub offset_ms: f64,
+    pub rtt_ms: f64,
+}
+
+impl Estimate {
+    /// How long a message to the client is underway
+    pub fn one_way(&self) -> Duration {
+        Duration::from_secs_f64(self.rtt_ms / 2.0 / 1000.0)
+    }
+}
+
+/// The last samples of a client, whose median smooths over the occasional slow answer
+#[derive(Default)]
+pub struct ClockEstimator {
+    samples: VecDeque<Sample>,
+}
+
+impl ClockEstimator {
+    const SAMPLES: usize = 8;
+
+    pub fn push(&mut self, sample: Sample) {
+        self.samples.push_back(sample);
+        if self.samples.len() > Self::SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+
+    /// None before the first sample
+    pub fn estimate(&self) -> Option<Estimate> {
+        Some(Estimate {
+            offset_ms: median(self.samples.iter().map(|sample| sample.offset_ms))?,
+            rtt_ms: median(self.samples.iter().map(|sample| sample.rtt_ms))?,
+        })
+    }
+}
+
+fn median(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let mut values = values.collect::<Vec<_>>();
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    match values.len() {
+        0 => None,
+        len if len % 2 == 0 => Some((values[middle - 1] + values[middle]) / 2.0),
+        _ => Some(values[middle]),
+    }
+}
+
+/// Milliseconds since the unix epoch, what the clocks of browsers count in
+pub fn unix_ms(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::clock::{ClockEstimator, Sample};
+
+    #[test]
+    fn samples() {
+        // A client 500 ms ahead, answering 40 ms into a round trip of 80 ms
+        let sample = Sample::new(1_000, 1_540.0, 1_080).unwrap();
+        assert_eq!(sample.rtt_ms, 80.0);
+        assert_eq!(sample.offset_ms, 500.0);
+
+        // A client behind the server
+        let sample = Sample::new(1_000, 700.0, 1_200).unwrap();
+        assert_eq!(sample.offset_ms, -400.0);
+
+        // Answers to probes from the future or from long ago
+        assert_eq!(Sample::new(2_000, 1_500.0, 1_000), None);
+        assert_eq!(Sample::new(0, 1_500.0, 60_000), None);
+        assert_eq!(Sample::new(1_000, f64::NAN, 1_080), None);
+    }
+
+    #[test]
+    fn smoothing() {
+        let mut estimator = ClockEstimator::default();
+        assert_eq!(estimator.estimate(), None);
+
+        // The client is 250 ms ahead with a round trip of about 100 ms
+        for (sent, rtt) in [(0, 100), (1_000, 90), (2_000, 110), (3_000, 100)] {
+            let client = sent as f64 + rtt as f64 / 2.0 + 250.0;
+            estimator.push(Sample::new(sent, client, sent + rtt).unwrap());
+        }
+        let estimate = estimator.estimate().unwrap();
+        assert_eq!(estimate.offset_ms, 250.0);
+        assert_eq!(estimate.rtt_ms, 100.0);
+        assert_eq!(estimate.one_way(), Duration::from_millis(50));
+
+        // An answer stuck behind something else is asymmetric and throws the offset off,
+        // the median doesn't follow it
+        estimator.push(Sample::new(4_000, 4_300.0, 6_000).unwrap());
+        let estimate = estimator.estimate().unwrap();
+        assert_eq!(estimate.offset_ms, 250.0);
+        assert_eq!(estimate.rtt_ms, 100.0);
+
+        // Only the last samples count, so a changed clock is followed
+        for sent in (10_000..20_000).step_by(1_000) {
+            estimator.push(Sample::new(sent, sent as f64 + 50.0 - 1_000.0, sent + 100).unwrap());
+        }
+        assert_eq!(estimator.estimate().unwrap().offset_ms, -1_000.0);
+    }
+}
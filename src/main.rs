@@ -1,28 +1,1026 @@
-use std::fs;
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
-use actix::Actor;
+use actix::{Actor, Addr};
 use actix_files::Files;
-use actix_web::{body::BoxBody, web::Data, App, HttpResponse, HttpServer, Responder};
-use serde::Deserialize;
-use session::SessionManager;
+use actix_web::{
+    body::BoxBody,
+    dev::ServerHandle,
+    web::{Data, FormConfig, QueryConfig},
+    App, HttpResponse, HttpServer, Responder,
+};
+use askama::Template;
+use auth::{AuthConfig, Cidr};
+use blocklist::Blocklist;
+use media::MediaIndex;
+use player::{AppError, PollConnections};
+use presets::Presets;
+use rate_limit::RateLimiter;
+use recent::RecentlyPlayed;
+use rustls_pemfile::Item;
+use serde::{Deserialize, Serialize};
+use session::{PersistedSession, SessionManager};
+use sha2::{Digest, Sha256};
+use strings::Translations;
+use tracing_subscriber::EnvFilter;
 
+mod admin;
+mod api;
+mod auth;
+mod blocklist;
+mod clock;
+mod media;
+mod overlay;
 mod player;
+mod presets;
+mod qr;
+mod rate_limit;
+mod recent;
 mod session;
+mod stats;
+mod strings;
+#[cfg(test)]
+mod testing;
+mod thumbnails;
+mod upload;
+mod webhook;
+
+/// Looked for in this order unless a config is given with --config or SHITPOST_CONFIG
+const CONFIG_PATHS: &[&str] = &["config.ron", "config.toml", "config.json"];
+/// Printed when no config is found, as a starting point
+const SAMPLE_CONFIG: &str = r#"// config.ron, the same keys work in config.toml and config.json
+(
+    bind: "0.0.0.0:8080",
+    shitposts: [
+        "/srv/shitposts/memes",
+        (path: "/srv/shitposts/clips", name: "clips", tags: ["loud"]),
+    ],
+    // Everything else is optional, like the token for /admin and the JSON API
+    admin_token: Some("change me"),
+)
+"#;
+const RECENTLY_PLAYED_PATH: &str = "recently_played.json";
+const BLOCKLIST_PATH: &str = "blocklist.json";
+const PRESETS_PATH: &str = "presets.ron";
+/// Optional UI text by language, English is built in
+const STRINGS_PATH: &str = "strings.ron";
+/// Seconds players wait before reconnecting after a shutdown
+const RECONNECT_AFTER: u64 = 5;
+/// Name the upload folder is listed under
+const UPLOAD_FOLDER: &str = "uploads";
 
 #[derive(Deserialize)]
 struct Config {
-    shitposts: Vec<String>,
-    bind: String,
+    shitposts: Vec<Folder>,
+    /// Lists of links to files hosted elsewhere, picked from like folders
+    #[serde(default)]
+    remote_sources: Vec<RemoteSource>,
+    bind: Bind,
+    /// Serves HTTPS instead of HTTP, changing this takes a restart like the bind address
+    #[serde(default)]
+    tls: Option<TlsConfig>,
+    /// Seconds an idle session without players is kept around
+    #[serde(default = "Config::default_session_ttl")]
+    session_ttl: u64,
+    /// Seconds a player may drift from the others before being seeked back in sync
+    #[serde(default = "Config::default_drift_tolerance")]
+    drift_tolerance: f64,
+    /// Seconds a drifting player waits between corrections, seeks are followed right away
+    #[serde(default = "Config::default_correction_interval")]
+    correction_interval: u64,
+    /// Seconds an image is shown before the playlist advances
+    #[serde(default = "Config::default_image_duration")]
+    image_duration: u64,
+    /// Seconds counted down before playback starts
+    #[serde(default = "Config::default_countdown")]
+    countdown: u64,
+    /// Seconds everyone waits for a stalled player before playing on without it
+    #[serde(default = "Config::default_straggler_timeout")]
+    straggler_timeout: u64,
+    /// Seconds between pings to the players
+    #[serde(default = "Config::default_heartbeat_interval")]
+    heartbeat_interval: u64,
+    /// Seconds a player may go without answering before it is disconnected
+    #[serde(default = "Config::default_client_timeout")]
+    client_timeout: u64,
+    /// Seconds a player whose tab is in the background may go without answering
+    #[serde(default = "Config::default_background_timeout")]
+    background_timeout: u64,
+    /// Days a played file is left out of new sessions, 0 disables this
+    #[serde(default = "Config::default_replay_cooldown_days")]
+    replay_cooldown_days: u64,
+    /// Players that need to report a file as broken before it is left out of new sessions,
+    /// 0 only skips reported files in the session they were reported in
+    #[serde(default = "Config::default_report_threshold")]
+    report_threshold: usize,
+    /// MiB that should stay free on the filesystems of the shitpost folders, warned about at startup.
+    /// 0 disables the check.
+    #[serde(default = "Config::default_min_free_space")]
+    min_free_space: u64,
+    /// ffmpeg or ffmpegthumbnailer binary used for video thumbnails, disabled if unset
+    #[serde(default)]
+    thumbnailer: Option<String>,
+    /// Where generated thumbnails are kept
+    #[serde(default = "Config::default_thumbnail_dir")]
+    thumbnail_dir: String,
+    /// Seconds between rescans of the shitpost folders, only scanned at startup if unset
+    #[serde(default)]
+    rescan_interval: Option<u64>,
+    /// Sessions one address can create at once before being limited
+    #[serde(default = "Config::default_host_burst")]
+    host_burst: u32,
+    /// Seconds until an address may create another session after using up the burst
+    #[serde(default = "Config::default_host_refill")]
+    host_refill: u64,
+    /// Sessions that may exist at the same time
+    #[serde(default = "Config::default_max_sessions")]
+    max_sessions: usize,
+    /// Players a session may have at once, hosts can lower it for their session.
+    /// Unlimited if unset.
+    #[serde(default)]
+    max_players: Option<usize>,
+    /// Most entries a playlist may be created with
+    #[serde(default = "Config::default_max_amount")]
+    max_amount: usize,
+    /// Playlists longer than this are sent to players in pages of this size
+    #[serde(default = "Config::default_playlist_page_size")]
+    playlist_page_size: usize,
+    /// Folder uploaded files are put in, uploads are disabled if unset
+    #[serde(default)]
+    upload_dir: Option<String>,
+    /// Largest file that may be uploaded in bytes
+    #[serde(default = "Config::default_max_upload_size")]
+    max_upload_size: usize,
+    /// Fingerprints files while scanning, so copies in different folders aren't rolled twice.
+    /// Changes apply from the next scan.
+    #[serde(default = "Config::default_dedupe")]
+    dedupe: bool,
+    /// Where sessions are saved so they survive a restart
+    #[serde(default = "Config::default_sessions_path")]
+    sessions_path: String,
+    /// Seconds between saves of the sessions besides the one on shutdown, 0 only saves on shutdown
+    #[serde(default = "Config::default_session_save_interval")]
+    session_save_interval: u64,
+    /// Where the wrap-ups of ended sessions are saved
+    #[serde(default = "Config::default_stats_dir")]
+    stats_dir: String,
+    /// Seconds restored sessions are kept around for players to come back, if longer than the TTL
+    #[serde(default = "Config::default_restore_grace")]
+    restore_grace: u64,
+    /// Address the site is reached at from outside, like "https://example.com", used in join QR codes.
+    /// The Host header of the request is used if unset.
+    #[serde(default)]
+    public_url: Option<String>,
+    /// Token required by the JSON API and the admin page, which are closed if unset
+    #[serde(default)]
+    admin_token: Option<String>,
+    /// Discord webhook URL told about sessions starting, getting their first player and ending
+    #[serde(default)]
+    discord_webhook: Option<String>,
+    /// Basic auth or an IP allowlist in front of everything but the static assets,
+    /// the app is open to anyone if unset. Changing this takes a restart like the bind address.
+    #[serde(default)]
+    auth: Option<AuthConfig>,
+    /// Reverse proxies in front of the app, whose X-Forwarded-For header is believed when
+    /// telling hosts apart for rate limiting and the allowlist. Nothing is trusted if empty.
+    #[serde(default)]
+    trusted_proxies: Vec<Cidr>,
+    /// Where the config was read from, reloads read the same file
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Config {
+    fn load(path: &Path) -> Result<Self, String> {
+        let text =
+            fs::read_to_string(path).map_err(|why| format!("{}: {}", path.display(), why))?;
+        let mut config = Self::parse(path, &text)?;
+        config.path = path.to_path_buf();
+
+        for source in &config.remote_sources {
+            for url in &source.urls {
+                validate_url(url).map_err(|why| format!(r#"In "{}": {}"#, source.name, why))?;
+            }
+        }
+
+        if let Some(public_url) = &config.public_url {
+            validate_url(public_url).map_err(|why| format!("In public_url: {}", why))?;
+        }
+        if let Some(webhook) = &config.discord_webhook {
+            validate_url(webhook).map_err(|why| format!("In discord_webhook: {}", why))?;
+        }
+
+        match &config.auth {
+            Some(AuthConfig::Allowlist(ranges)) if ranges.is_empty() => {
+                return Err("The auth allowlist would let nobody in".to_string());
+            }
+            // Basic auth sends the two joined by a colon
+            Some(AuthConfig::Basic { username, .. }) if username.contains(':') => {
+                return Err("The auth username can't contain a colon".to_string());
+            }
+            _ => {}
+        }
+
+        if config.max_players == Some(0) {
+            return Err("max_players must be at least 1".to_string());
+        }
+
+        if config.heartbeat_interval == 0 || config.client_timeout <= config.heartbeat_interval {
+            return Err(
+                "heartbeat_interval must be at least 1 and client_timeout longer than it"
+                    .to_string(),
+            );
+        }
+
+        if config.background_timeout < config.client_timeout {
+            return Err("background_timeout must be at least client_timeout".to_string());
+        }
+
+        // Uploads are picked from like any other folder
+        if let Some(upload_dir) = &config.upload_dir {
+            fs::create_dir_all(upload_dir)
+                .map_err(|why| format!(r#"Failed to create "{}": {}"#, upload_dir, why))?;
+            config.shitposts.push(Folder {
+                path: upload_dir.clone(),
+                name: UPLOAD_FOLDER.to_string(),
+                tags: Vec::new(),
+            });
+        }
+
+        let collisions = config.name_collisions();
+        if !collisions.is_empty() {
+            return Err(format!(
+                "Folder names must be unique, give the folders an alias: {}",
+                collisions.join("; ")
+            ));
+        }
+
+        Ok(config)
+    }
+
+    /// Parses RON, TOML or JSON depending on the file extension.
+    /// Errors point at the file and line the problem is on.
+    fn parse(path: &Path, text: &str) -> Result<Self, String> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+
+        match extension.as_deref() {
+            Some("ron") => ron::de::from_str(text).map_err(|why| {
+                format!(
+                    "{}:{}:{}: {}",
+                    path.display(),
+                    why.position.line,
+                    why.position.col,
+                    why.code
+                )
+            }),
+            Some("toml") => toml::from_str(text).map_err(|why| {
+                let line = why
+                    .span()
+                    .map(|span| text[..span.start].matches('\n').count() + 1)
+                    .unwrap_or(1);
+                format!("{}:{}: {}", path.display(), line, why.message())
+            }),
+            // serde_json includes the line and column in its message
+            Some("json") => {
+                serde_json::from_str(text).map_err(|why| format!("{}: {}", path.display(), why))
+            }
+            _ => Err(format!(
+                "{}: Unknown config format, use a .ron, .toml or .json file",
+                path.display()
+            )),
+        }
+    }
+
+    /// The config given on the command line or in SHITPOST_CONFIG,
+    /// or the first of `CONFIG_PATHS` that exists
+    fn find(
+        mut args: impl Iterator<Item = String>,
+        env: Option<String>,
+    ) -> Result<Option<PathBuf>, String> {
+        while let Some(arg) = args.next() {
+            if arg == "--config" {
+                return match args.next() {
+                    Some(path) => Ok(Some(path.into())),
+                    None => Err("--config needs a path".to_string()),
+                };
+            }
+            if let Some(path) = arg.strip_prefix("--config=") {
+                return Ok(Some(path.into()));
+            }
+        }
+
+        if let Some(path) = env.filter(|path| !path.is_empty()) {
+            return Ok(Some(path.into()));
+        }
+
+        Ok(CONFIG_PATHS
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.is_file()))
+    }
+
+    /// Names of the folders and remote sources, in the order they are shown to the host
+    fn source_names(&self) -> impl Iterator<Item = &str> {
+        self.shitposts
+            .iter()
+            .map(|folder| folder.name.as_str())
+            .chain(
+                self.remote_sources
+                    .iter()
+                    .map(|source| source.name.as_str()),
+            )
+    }
+
+    /// Tags of a folder or remote source, empty if it has none
+    fn source_tags(&self, name: &str) -> &[String] {
+        self.shitposts
+            .iter()
+            .find(|folder| folder.name == name)
+            .map(|folder| folder.tags.as_slice())
+            .or_else(|| {
+                self.remote_sources
+                    .iter()
+                    .find(|source| source.name == name)
+                    .map(|source| source.tags.as_slice())
+            })
+            .unwrap_or_default()
+    }
+
+    /// Folders are told apart by name in URLs and on the host page, so names must be unique
+    fn name_collisions(&self) -> Vec<String> {
+        let mut names = HashMap::<&str, Vec<&str>>::new();
+        for folder in &self.shitposts {
+            names.entry(&folder.name).or_default().push(&folder.path);
+        }
+        for source in &self.remote_sources {
+            names.entry(&source.name).or_default().push("remote source");
+        }
+
+        names
+            .iter()
+            .filter(|(_, paths)| paths.len() > 1)
+            .map(|(name, paths)| format!(r#""{}" is used by {}"#, name, paths.join(", ")))
+            .collect()
+    }
+
+    /// Configured shitpost folders that don't exist
+    fn missing_folders(&self) -> Vec<&str> {
+        self.shitposts
+            .iter()
+            .filter(|folder| !Path::new(&folder.path).is_dir())
+            .map(|folder| folder.path.as_str())
+            .collect()
+    }
+
+    fn default_session_ttl() -> u64 {
+        30 * 60
+    }
+
+    fn default_drift_tolerance() -> f64 {
+        1.5
+    }
+
+    fn default_correction_interval() -> u64 {
+        2
+    }
+
+    fn default_image_duration() -> u64 {
+        8
+    }
+
+    fn default_countdown() -> u64 {
+        3
+    }
+
+    fn default_straggler_timeout() -> u64 {
+        15
+    }
+
+    fn default_report_threshold() -> usize {
+        3
+    }
+
+    fn default_min_free_space() -> u64 {
+        1024
+    }
+
+    fn default_heartbeat_interval() -> u64 {
+        1
+    }
+
+    fn default_client_timeout() -> u64 {
+        10
+    }
+
+    fn default_background_timeout() -> u64 {
+        300
+    }
+
+    fn default_replay_cooldown_days() -> u64 {
+        14
+    }
+
+    fn default_host_burst() -> u32 {
+        5
+    }
+
+    fn default_host_refill() -> u64 {
+        60
+    }
+
+    fn default_max_sessions() -> usize {
+        100
+    }
+
+    fn default_max_amount() -> usize {
+        1000
+    }
+
+    fn default_thumbnail_dir() -> String {
+        "thumbnails".to_string()
+    }
+
+    fn default_playlist_page_size() -> usize {
+        200
+    }
+
+    fn default_dedupe() -> bool {
+        true
+    }
+
+    fn default_max_upload_size() -> usize {
+        200 * 1024 * 1024
+    }
+
+    fn default_sessions_path() -> String {
+        "sessions.json".to_string()
+    }
+
+    fn default_session_save_interval() -> u64 {
+        60
+    }
+
+    fn default_stats_dir() -> String {
+        "stats".to_string()
+    }
+
+    fn default_restore_grace() -> u64 {
+        60 * 60
+    }
 }
 
-#[derive(Clone)]
+/// Where the server listens, configured as an address, a list of them, or "systemd" to use
+/// the socket passed on by socket activation
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(from = "BindEntry")]
+enum Bind {
+    Addresses(Vec<String>),
+    Systemd,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BindEntry {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl From<BindEntry> for Bind {
+    fn from(entry: BindEntry) -> Self {
+        match entry {
+            BindEntry::One(address) if address == "systemd" => Self::Systemd,
+            BindEntry::One(address) => Self::Addresses(vec![address]),
+            BindEntry::Many(addresses) => Self::Addresses(addresses),
+        }
+    }
+}
+
+/// The first socket systemd passed on, systemd sets the variables read here
+#[cfg(unix)]
+fn systemd_listener() -> Result<std::net::TcpListener, String> {
+    use std::os::unix::io::FromRawFd;
+
+    /// Passed sockets come right after stdin, stdout and stderr
+    const LISTEN_FDS_START: i32 = 3;
+
+    let pid = std::env::var("LISTEN_PID")
+        .map_err(|_| "LISTEN_PID isn't set, was the server started by a socket unit?")?;
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Err(format!("The sockets are for process {}, not this one", pid));
+    }
+    let fds = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<i32>().ok())
+        .unwrap_or_default();
+    if fds < 1 {
+        return Err("systemd didn't pass any sockets".to_string());
+    }
+
+    // systemd hands the socket over to this process and nothing else in it uses the descriptor
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) };
+    listener
+        .set_nonblocking(true)
+        .map_err(|why| why.to_string())?;
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+fn systemd_listener() -> Result<std::net::TcpListener, String> {
+    Err("Socket activation is only supported on Unix".to_string())
+}
+
+/// A shitpost folder, configured either as a plain path or as `(path: ..., name: ..., tags: [...])`
+#[derive(Deserialize)]
+#[serde(from = "FolderEntry")]
+struct Folder {
+    path: String,
+    /// Used in URLs and on the host page, the last path component unless aliased
+    name: String,
+    /// Lowercase, so filtering them doesn't depend on how they were written
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FolderEntry {
+    Path(String),
+    Aliased {
+        path: String,
+        name: Option<String>,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+}
+
+impl From<FolderEntry> for Folder {
+    fn from(entry: FolderEntry) -> Self {
+        let (path, name, tags) = match entry {
+            FolderEntry::Path(path) => (path, None, Vec::new()),
+            FolderEntry::Aliased { path, name, tags } => (path, name, normalize_tags(tags)),
+        };
+
+        Self {
+            name: name.unwrap_or_else(|| {
+                Path::new(&path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| path.clone())
+            }),
+            path,
+            tags,
+        }
+    }
+}
+
+/// Trimmed and lowercase without empty or repeated tags
+fn normalize_tags(tags: impl IntoIterator<Item = String>) -> Vec<String> {
+    let mut normalized = Vec::<String>::new();
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+/// Files hosted elsewhere, offered to the host like a folder
+#[derive(Deserialize)]
+struct RemoteSource {
+    name: String,
+    urls: Vec<String>,
+    #[serde(default, deserialize_with = "deserialize_tags")]
+    tags: Vec<String>,
+}
+
+fn deserialize_tags<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    Vec::<String>::deserialize(deserializer).map(normalize_tags)
+}
+
+impl RemoteSource {
+    fn shitposts(&self) -> Vec<Shitpost> {
+        self.urls
+            .iter()
+            .map(|url| {
+                let title = sanitize_title(&url_file_name(url));
+                let media_type = MediaType::from_path(&title);
+
+                Shitpost {
+                    // Links without a known extension are most likely videos
+                    kind: media_type.map_or(ShitpostKind::Video, |media_type| media_type.kind),
+                    compat: media_type.map_or(BrowserCompat::Safe, |media_type| media_type.compat),
+                    title,
+                    url: url.clone(),
+                    thumbnail: None,
+                    low_url: None,
+                    fingerprint: None,
+                    start_offset: 0.0,
+                    loudness: None,
+                    folder: self.name.clone(),
+                    size: None,
+                    modified: None,
+                    tags: self.tags.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Certificate chain and private key in PEM format
+#[derive(Deserialize)]
+struct TlsConfig {
+    cert_path: String,
+    key_path: String,
+}
+
+impl TlsConfig {
+    /// Reads the files and checks the key can be used with the certificates
+    fn load(&self) -> Result<rustls::ServerConfig, String> {
+        let certs = read_pem(&self.cert_path)?
+            .into_iter()
+            .filter_map(|item| match item {
+                Item::X509Certificate(cert) => Some(rustls::Certificate(cert)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if certs.is_empty() {
+            return Err(format!(r#"No certificates in "{}""#, self.cert_path));
+        }
+
+        let key = read_pem(&self.key_path)?
+            .into_iter()
+            .find_map(|item| match item {
+                Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => {
+                    Some(rustls::PrivateKey(key))
+                }
+                _ => None,
+            })
+            .ok_or_else(|| format!(r#"No private key in "{}""#, self.key_path))?;
+
+        rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|why| format!("Invalid certificate or key: {}", why))
+    }
+}
+
+fn read_pem(path: &str) -> Result<Vec<Item>, String> {
+    let file =
+        fs::File::open(path).map_err(|why| format!(r#"Failed to open "{}": {}"#, path, why))?;
+    rustls_pemfile::read_all(&mut io::BufReader::new(file))
+        .map_err(|why| format!(r#"Failed to read "{}": {}"#, path, why))
+}
+
+/// Accepts absolute http and https URLs with a host
+fn validate_url(url: &str) -> Result<(), String> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .ok_or_else(|| format!(r#""{}" is not an http or https URL"#, url))?;
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+
+    if host.is_empty() || url.contains(char::is_whitespace) {
+        return Err(format!(r#""{}" is not a valid URL"#, url));
+    }
+
+    Ok(())
+}
+
+/// The decoded last path segment of a URL, without the query or fragment
+fn url_file_name(url: &str) -> String {
+    let path = url
+        .split(['?', '#'])
+        .next()
+        .unwrap_or(url)
+        .trim_end_matches('/');
+    percent_decode(path.rsplit('/').next().unwrap_or(path))
+}
+
+/// The URL a file of a shitpost folder is served at, see `player::shitpost_file`
+fn file_url(folder: &str, file: &str) -> String {
+    format!(
+        "/shitposts/{}/{}",
+        percent_encode(folder),
+        percent_encode(file)
+    )
+}
+
+/// Escapes everything but unreserved characters, so names with spaces, `#` or `?` stay one segment
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Lowercase hex of the SHA-256 of the parts one after another
+fn sha256_hex(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// File names are shown as titles, which end up in HTML and in the script of the player page.
+/// Angle brackets, backslashes and control characters are left out so a name can't break out of either.
+fn sanitize_title(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_control() && !matches!(c, '<' | '>' | '\\'))
+        .collect()
+}
+
+/// Decodes %XX escapes, leaving malformed ones as they are
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i..i + 3) {
+            Some([b'%', hex @ ..]) if hex.iter().all(u8::is_ascii_hexdigit) => {
+                u8::from_str_radix(std::str::from_utf8(hex).unwrap(), 16).ok()
+            }
+            _ => None,
+        };
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The active config, which can be swapped out by reloading.
+/// Only the folder list and admin token take effect without a restart.
+struct SharedConfig(RwLock<Arc<Config>>);
+
+impl SharedConfig {
+    fn get(&self) -> Arc<Config> {
+        self.0.read().unwrap().clone()
+    }
+
+    fn set(&self, config: Config) {
+        *self.0.write().unwrap() = Arc::new(config);
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Shitpost {
     title: String,
     url: String,
+    kind: ShitpostKind,
+    /// URL of a preview image, videos only get one once it has been generated
+    #[serde(default)]
+    thumbnail: Option<String>,
+    /// URL of a smaller variant for slow connections, only scanned files can have one
+    #[serde(default)]
+    low_url: Option<String>,
+    /// Seconds skipped at the start, like dead air before the clip gets going
+    #[serde(default)]
+    start_offset: f64,
+    /// Measured loudness in LUFS, for players to even out the volume between clips
+    #[serde(default)]
+    loudness: Option<f32>,
+    /// Whether every browser can be expected to play the file
+    #[serde(default)]
+    compat: BrowserCompat,
+    /// Name of the folder or remote source the file is from
+    #[serde(default)]
+    folder: String,
+    /// File size in bytes, unknown for remote sources
+    #[serde(default)]
+    size: Option<u64>,
+    /// Unix time the file was last modified at, unknown for remote sources
+    #[serde(default)]
+    modified: Option<u64>,
+    /// Tags of the folder the file is from, like "nsfw"
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Tells copies of the same file apart from other files, only kept in the index
+    #[serde(default, skip_serializing)]
+    fingerprint: Option<u64>,
+}
+
+impl Shitpost {
+    /// Name of the file the entry was made from, the title might have been sanitized
+    fn file_name(&self) -> String {
+        url_file_name(&self.url)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShitpostKind {
+    Video,
+    Image,
+    Audio,
+}
+
+/// How likely browsers are to play a file, going by its container
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BrowserCompat {
+    #[default]
+    Safe,
+    /// Plays in some browsers, depending on the browser and the codecs inside
+    MaybeUnsupported,
+}
+
+/// Supported file extensions with their kind and MIME type
+const FILE_TYPES: &[(&str, ShitpostKind, &str)] = &[
+    ("mp4", ShitpostKind::Video, "video/mp4"),
+    ("m4v", ShitpostKind::Video, "video/mp4"),
+    ("webm", ShitpostKind::Video, "video/webm"),
+    ("mov", ShitpostKind::Video, "video/quicktime"),
+    ("mkv", ShitpostKind::Video, "video/x-matroska"),
+    ("avi", ShitpostKind::Video, "video/x-msvideo"),
+    ("jpg", ShitpostKind::Image, "image/jpeg"),
+    ("jpeg", ShitpostKind::Image, "image/jpeg"),
+    ("png", ShitpostKind::Image, "image/png"),
+    ("gif", ShitpostKind::Image, "image/gif"),
+    ("webp", ShitpostKind::Image, "image/webp"),
+    ("mp3", ShitpostKind::Audio, "audio/mpeg"),
+    ("ogg", ShitpostKind::Audio, "audio/ogg"),
+    ("opus", ShitpostKind::Audio, "audio/ogg"),
+    ("wav", ShitpostKind::Audio, "audio/wav"),
+    ("flac", ShitpostKind::Audio, "audio/flac"),
+    ("m4a", ShitpostKind::Audio, "audio/mp4"),
+];
+
+/// Containers only some browsers play, Safari skips Matroska and hardly anything plays AVI
+const MAYBE_UNSUPPORTED: &[&str] = &["mov", "mkv", "avi"];
+
+/// What a supported file is played as
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct MediaType {
+    kind: ShitpostKind,
+    mime: &'static str,
+    compat: BrowserCompat,
+}
+
+impl MediaType {
+    /// Looks up a file extension without the dot, ignoring case
+    fn from_extension(extension: &str) -> Option<Self> {
+        let extension = extension.to_lowercase();
+
+        let compat = if MAYBE_UNSUPPORTED.contains(&extension.as_str()) {
+            BrowserCompat::MaybeUnsupported
+        } else {
+            BrowserCompat::Safe
+        };
+
+        FILE_TYPES
+            .iter()
+            .find(|(supported, _, _)| *supported == extension)
+            .map(|(_, kind, mime)| Self {
+                kind: *kind,
+                mime,
+                compat,
+            })
+    }
+
+    /// Looks up the extension of a file, None for unsupported files
+    fn from_path(path: &str) -> Option<Self> {
+        Self::from_extension(Path::new(path).extension()?.to_str()?)
+    }
+}
+
+/// The MIME type of a supported file, guessing isn't reliable for webm on every system
+fn content_type(path: &str) -> Option<mime::Mime> {
+    MediaType::from_path(path).and_then(|media_type| media_type.mime.parse().ok())
+}
+
+impl fmt::Display for ShitpostKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Video => "video",
+            Self::Image => "image",
+            Self::Audio => "audio",
+        })
+    }
+}
+
+impl fmt::Display for BrowserCompat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Safe => "safe",
+            Self::MaybeUnsupported => "maybe_unsupported",
+        })
+    }
+}
+
+/// Set once shutting down, after which periodic saves must not overwrite the final one
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// Held while saving, so a periodic save still running can't finish after the one on shutdown
+static SAVING: Mutex<()> = Mutex::new(());
+
+/// Sessions from the last save, periodic or on shutdown. The file is left in place,
+/// the next save replaces it.
+fn load_sessions(path: &str) -> Vec<PersistedSession> {
+    let Ok(bytes) = fs::read(path) else {
+        return Vec::new();
+    };
+
+    serde_json::from_slice(&bytes).unwrap_or_else(|why| {
+        tracing::warn!("Failed to restore sessions: {}", why);
+        Vec::new()
+    })
+}
+
+/// Written to a temporary file first, so a crash while saving doesn't lose the previous save.
+/// Periodic saves are dropped once shutting down, their sessions are older than the final save.
+fn save_sessions(path: &str, sessions: &[PersistedSession], periodic: bool) {
+    let _saving = SAVING
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if periodic && SHUTTING_DOWN.load(Ordering::SeqCst) {
+        return;
+    }
+
+    let temp = format!("{}.tmp", path);
+    let result = serde_json::to_vec(sessions)
+        .map_err(|why| why.to_string())
+        .and_then(|json| {
+            fs::write(&temp, json)
+                .and_then(|()| fs::rename(&temp, path))
+                .map_err(|why| why.to_string())
+        });
+
+    match result {
+        Ok(()) => tracing::debug!("Saved {} sessions", sessions.len()),
+        Err(why) => tracing::error!("Failed to save sessions: {}", why),
+    }
+}
+
+/// Lets players know the server is going away and saves the sessions before stopping it
+async fn shutdown(manager: Addr<SessionManager>, server: ServerHandle, sessions_path: String) {
+    if SHUTTING_DOWN.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    tracing::info!("Shutting down");
+    match manager
+        .send(session::Shutdown {
+            reconnect_after_secs: RECONNECT_AFTER,
+        })
+        .await
+    {
+        Ok(sessions) => {
+            tracing::info!("Saving {} sessions", sessions.len());
+            save_sessions(&sessions_path, &sessions, false);
+        }
+        Err(why) => tracing::error!("Session manager unavailable: {}", why),
+    }
+
+    server.stop(true).await;
 }
 
 struct Html(String);
 
+impl Html {
+    /// Renders a page, failures are logged with the template they came from
+    fn render<T: Template>(template: T) -> Result<Self, AppError> {
+        template.render().map(Self).map_err(|why| {
+            tracing::error!("Failed to render {}: {}", std::any::type_name::<T>(), why);
+            AppError::Render
+        })
+    }
+}
+
 impl Responder for Html {
     type Body = BoxBody;
 
@@ -35,37 +1033,462 @@ impl Responder for Html {
 
 #[actix_web::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
+    let started_at = Data::new(api::StartedAt(Instant::now()));
+    // RUST_LOG=shitposting_webapp=debug also logs every message relayed to the players
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
 
-    let config: Config = ron::de::from_bytes(&fs::read("config.ron").unwrap()).unwrap();
+    let path = match Config::find(
+        std::env::args().skip(1),
+        std::env::var("SHITPOST_CONFIG").ok(),
+    ) {
+        Ok(Some(path)) => path,
+        Ok(None) => {
+            tracing::error!(
+                "No config found, looked for {} in {}",
+                CONFIG_PATHS.join(", "),
+                std::env::current_dir()
+                    .map(|dir| dir.display().to_string())
+                    .unwrap_or_default()
+            );
+            eprint!("{}", SAMPLE_CONFIG);
+            std::process::exit(1);
+        }
+        Err(why) => {
+            tracing::error!("{}", why);
+            std::process::exit(1);
+        }
+    };
+    let config = match Config::load(&path) {
+        Ok(config) => config,
+        Err(why) => {
+            tracing::error!("Invalid config: {}", why);
+            std::process::exit(1);
+        }
+    };
+
+    let missing = config.missing_folders();
+    if !missing.is_empty() {
+        tracing::error!("Missing shitpost folders: {}", missing.join(", "));
+    }
+    media::warn_low_space(&config);
 
-    let config = Data::new(config);
     let bind = config.bind.clone();
+    let auth_config = config.auth.clone();
+    let trusted_proxies = config.trusted_proxies.clone();
+    let tls = match config.tls.as_ref().map(TlsConfig::load).transpose() {
+        Ok(tls) => tls,
+        Err(why) => {
+            tracing::error!("Invalid TLS config: {}", why);
+            std::process::exit(1);
+        }
+    };
+    let recent = Arc::new(RecentlyPlayed::load(
+        RECENTLY_PLAYED_PATH,
+        Duration::from_secs(config.replay_cooldown_days * 24 * 60 * 60),
+    ));
+    let blocklist = Arc::new(Blocklist::load(BLOCKLIST_PATH, config.report_threshold));
+    let mut manager = SessionManager::new(&config, recent.clone(), blocklist.clone());
+    let sessions_path = config.sessions_path.clone();
+    let session_save_interval = config.session_save_interval;
+    manager.restore(load_sessions(&sessions_path));
+    let manager = Data::new(manager.start());
+    let recent = Data::from(recent);
+    let blocklist = Data::from(blocklist);
+    let poll_connections = Data::new(PollConnections::default());
+    let presets = Data::new(Presets::load(PRESETS_PATH));
+    let translations = Data::new(Translations::load(STRINGS_PATH));
+    let host_limiter = Data::new(RateLimiter::<IpAddr>::new(
+        config.host_burst,
+        Duration::from_secs(config.host_refill),
+    ));
+    let rescan_interval = config.rescan_interval;
+    // Changing this takes a restart like the bind address
+    let thumbnail_dir = config
+        .thumbnailer
+        .is_some()
+        .then(|| config.thumbnail_dir.clone());
+    let config = Data::new(SharedConfig(RwLock::new(Arc::new(config))));
 
-    let manager = Data::new(SessionManager::new().start());
-
-    HttpServer::new(move || {
-        let mut app = App::new()
-            .service(player::host)
-            .service(player::host_submit)
-            .service(player::join)
-            .service(player::index)
-            .service(player::socket)
-            .service(Files::new("/static", "./static"))
-            .app_data(manager.clone())
-            .app_data(config.clone());
-
-        for folder in &config.shitposts {
-            app = app.service(Files::new(
-                &format!("/shitposts/{}", folder.split('/').last().unwrap()),
-                folder,
-            ));
+    let media = Data::new(MediaIndex::default());
+    media.rescan(config.get()).await;
+
+    if let Some(rescan_interval) = rescan_interval {
+        let config = config.clone();
+        let media = media.clone();
+
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(Duration::from_secs(rescan_interval));
+            // The first tick completes immediately and the folders were just scanned
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                media.rescan(config.get()).await;
+            }
+        });
+    }
+
+    let shutdown_manager = manager.get_ref().clone();
+
+    // Saved regularly so a crash doesn't lose the sessions either
+    if session_save_interval > 0 {
+        let manager = shutdown_manager.clone();
+        let sessions_path = sessions_path.clone();
+
+        actix_web::rt::spawn(async move {
+            let mut interval =
+                actix_web::rt::time::interval(Duration::from_secs(session_save_interval));
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                let Ok(sessions) = manager.send(session::Persist).await else {
+                    continue;
+                };
+                if SHUTTING_DOWN.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                let sessions_path = sessions_path.clone();
+                let _ = actix_web::rt::task::spawn_blocking(move || {
+                    save_sessions(&sessions_path, &sessions, true)
+                })
+                .await;
+            }
+        });
+    }
+
+    let server =
+        HttpServer::new(move || {
+            App::new()
+                .wrap(auth::gate(auth_config.as_ref(), &trusted_proxies))
+                .service(player::host)
+                .service(player::host_submit)
+                .service(player::host_preview)
+                .service(player::host_import)
+                .service(player::host_reroll)
+                .service(player::host_schedule)
+                .service(player::host_cancel)
+                .service(player::join)
+                .service(player::join_code)
+                .service(player::join_unlock)
+                .service(player::index)
+                .service(player::socket)
+                .service(player::poll_connect)
+                .service(player::poll_receive)
+                .service(player::poll_send)
+                .service(player::shitpost_file)
+                .service(api::sessions)
+                .service(api::history)
+                .service(api::events)
+                .service(api::playlist)
+                .service(api::bookmarks)
+                .service(api::export)
+                .service(api::now_playing)
+                .service(api::add_to_playlist)
+                .service(api::remove_from_playlist)
+                .service(api::kick)
+                .service(api::metrics)
+                .service(api::health)
+                .service(api::reload)
+                .service(api::rescan)
+                .service(api::catalog)
+                .service(api::catalog_folder)
+                .service(api::missing_loudness)
+                .service(api::list_blocklist)
+                .service(api::clear_blocklist)
+                .service(api::list_presets)
+                .service(api::save_preset)
+                .service(api::delete_preset)
+                .service(admin::admin)
+                .service(admin::close_session)
+                .service(upload::upload)
+                .service(qr::qr)
+                .service(overlay::overlay)
+                .service(stats::stats)
+                .service(Files::new("/static", "./static"))
+                .configure(|cfg| {
+                    if let Some(thumbnail_dir) = &thumbnail_dir {
+                        cfg.service(Files::new("/thumbs", thumbnail_dir));
+                    }
+                })
+                .app_data(manager.clone())
+                .app_data(config.clone())
+                .app_data(media.clone())
+                .app_data(recent.clone())
+                .app_data(blocklist.clone())
+                .app_data(poll_connections.clone())
+                .app_data(presets.clone())
+                .app_data(translations.clone())
+                .app_data(host_limiter.clone())
+                .app_data(started_at.clone())
+                // Render malformed requests like invalid session codes as error pages
+                .app_data(QueryConfig::default().error_handler(|why, _req| {
+                    player::AppError::BadRequest(why.to_string()).into()
+                }))
+                .app_data(FormConfig::default().error_handler(|why, _req| {
+                    player::AppError::BadRequest(why.to_string()).into()
+                }))
+        });
+    let server = match bind {
+        Bind::Addresses(addresses) if addresses.is_empty() => {
+            Err("There are no addresses to bind to".to_string())
         }
-        app
-    })
-    .bind(bind)
-    .unwrap()
-    .run()
-    .await
-    .unwrap();
+        Bind::Addresses(addresses) => addresses.iter().try_fold(server, |server, address| {
+            match &tls {
+                Some(tls) => server.bind_rustls_021(address, tls.clone()),
+                None => server.bind(address),
+            }
+            .map_err(|why| format!(r#"Failed to bind "{}": {}"#, address, why))
+        }),
+        Bind::Systemd => systemd_listener().and_then(|listener| {
+            match &tls {
+                Some(tls) => server.listen_rustls_0_21(listener, tls.clone()),
+                None => server.listen(listener),
+            }
+            .map_err(|why| format!("Failed to listen on the systemd socket: {}", why))
+        }),
+    };
+    let server = match server {
+        Ok(server) => server,
+        Err(why) => {
+            tracing::error!("{}", why);
+            std::process::exit(1);
+        }
+    };
+    for address in server.addrs() {
+        tracing::info!("Listening on {}", address);
+    }
+    let server = server
+        // Signals are handled below so players can be told before the server stops
+        .disable_signals()
+        .run();
+
+    let handle = server.handle();
+    {
+        let manager = shutdown_manager.clone();
+        let handle = handle.clone();
+        let sessions_path = sessions_path.clone();
+        actix_web::rt::spawn(async move {
+            if actix_web::rt::signal::ctrl_c().await.is_ok() {
+                shutdown(manager, handle, sessions_path).await;
+            }
+        });
+    }
+    #[cfg(unix)]
+    actix_web::rt::spawn(async move {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            terminate.recv().await;
+            shutdown(shutdown_manager, handle, sessions_path).await;
+        }
+    });
+
+    server.await.unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::{
+        file_url, url_file_name, validate_url, Bind, BrowserCompat, Config, Folder, MediaType,
+        RemoteSource, ShitpostKind, SAMPLE_CONFIG,
+    };
+
+    #[test]
+    fn file_urls() {
+        assert_eq!(
+            file_url("my clips", "cat video (final).mp4"),
+            "/shitposts/my%20clips/cat%20video%20%28final%29.mp4"
+        );
+        assert_eq!(file_url("a", "😂.webm"), "/shitposts/a/%F0%9F%98%82.webm");
+
+        for name in ["100% #1?.webm", "ünïcödé 😂.mp4", "a+b=c&d.gif", "%41.png"] {
+            let url = file_url("clips", name);
+            assert_eq!(url.matches('/').count(), 3, "{}", url);
+            assert_eq!(url_file_name(&url), name);
+        }
+    }
+
+    #[test]
+    fn shitpost_kinds() {
+        assert_eq!(
+            MediaType::from_path("clip.WEBM").map(|media_type| media_type.kind),
+            Some(ShitpostKind::Video)
+        );
+        assert_eq!(
+            MediaType::from_path("clip.Mp4").map(|media_type| media_type.kind),
+            Some(ShitpostKind::Video)
+        );
+        assert_eq!(
+            MediaType::from_path("reaction.png").map(|media_type| media_type.kind),
+            Some(ShitpostKind::Image)
+        );
+        assert_eq!(
+            MediaType::from_path("song.mp3").map(|media_type| media_type.kind),
+            Some(ShitpostKind::Audio)
+        );
+        assert!(MediaType::from_path("notes.txt").is_none());
+        assert!(MediaType::from_path("mp4").is_none());
+
+        assert_eq!(
+            MediaType::from_extension("MKV"),
+            Some(MediaType {
+                kind: ShitpostKind::Video,
+                mime: "video/x-matroska",
+                compat: BrowserCompat::MaybeUnsupported,
+            })
+        );
+        assert_eq!(
+            MediaType::from_path("clip.m4v").map(|media_type| media_type.compat),
+            Some(BrowserCompat::Safe)
+        );
+    }
+
+    #[test]
+    fn folder_aliases() {
+        let folders: Vec<Folder> = ron::from_str(
+            r#"["/mnt/a/memes", (path: "/mnt/b/memes", name: "more memes"), (path: "/mnt/c/clips", tags: [" NSFW", "loud", "nsfw"])]"#,
+        )
+        .unwrap();
+
+        assert_eq!(folders[0].path, "/mnt/a/memes");
+        assert_eq!(folders[0].name, "memes");
+        assert_eq!(folders[1].path, "/mnt/b/memes");
+        assert_eq!(folders[1].name, "more memes");
+        assert_eq!(folders[2].name, "clips");
+        assert!(folders[0].tags.is_empty());
+        assert_eq!(folders[2].tags, vec!["nsfw", "loud"]);
+    }
+
+    #[test]
+    fn config_formats() {
+        let configs = [
+            (
+                "config.ron",
+                r#"(
+                    bind: "127.0.0.1:8080",
+                    shitposts: ["/srv/memes", (path: "/srv/clips", name: "clips", tags: ["loud"])],
+                    session_ttl: 60,
+                    admin_token: Some("secret"),
+                )"#,
+            ),
+            (
+                "config.toml",
+                r#"
+                    bind = "127.0.0.1:8080"
+                    shitposts = ["/srv/memes", { path = "/srv/clips", name = "clips", tags = ["loud"] }]
+                    session_ttl = 60
+                    admin_token = "secret"
+                "#,
+            ),
+            (
+                "config.json",
+                r#"{
+                    "bind": "127.0.0.1:8080",
+                    "shitposts": ["/srv/memes", { "path": "/srv/clips", "name": "clips", "tags": ["loud"] }],
+                    "session_ttl": 60,
+                    "admin_token": "secret"
+                }"#,
+            ),
+        ];
+
+        for (path, text) in configs {
+            let config = Config::parse(Path::new(path), text).unwrap();
+            assert_eq!(
+                config.bind,
+                Bind::Addresses(vec!["127.0.0.1:8080".to_string()]),
+                "{}",
+                path
+            );
+            assert_eq!(config.shitposts[0].name, "memes", "{}", path);
+            assert_eq!(config.shitposts[1].name, "clips", "{}", path);
+            assert_eq!(config.shitposts[1].tags, vec!["loud"], "{}", path);
+            assert_eq!(config.session_ttl, 60, "{}", path);
+            assert_eq!(config.admin_token.as_deref(), Some("secret"), "{}", path);
+        }
+
+        Config::parse(Path::new("config.ron"), SAMPLE_CONFIG).unwrap();
+
+        // Several addresses, or the socket of a systemd socket unit
+        let config = Config::parse(
+            Path::new("config.toml"),
+            "bind = [\"0.0.0.0:8080\", \"[::]:8080\"]\nshitposts = []\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.bind,
+            Bind::Addresses(vec!["0.0.0.0:8080".to_string(), "[::]:8080".to_string()])
+        );
+        let config = Config::parse(
+            Path::new("config.json"),
+            r#"{"bind": "systemd", "shitposts": []}"#,
+        )
+        .unwrap();
+        assert_eq!(config.bind, Bind::Systemd);
+
+        // Errors name the file and line
+        let why = Config::parse(Path::new("config.toml"), "bind = \"a\"\nshitposts = 5\n")
+            .err()
+            .unwrap();
+        assert!(why.starts_with("config.toml:2: "), "{}", why);
+        let why = Config::parse(Path::new("config.ron"), "(\n    bind: 5,\n)")
+            .err()
+            .unwrap();
+        assert!(why.starts_with("config.ron:2:"), "{}", why);
+        assert!(Config::parse(Path::new("config.yaml"), "").is_err());
+
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            Config::find(
+                args(&["--config", "a.toml"]).into_iter(),
+                Some("b.json".to_string())
+            ),
+            Ok(Some(PathBuf::from("a.toml")))
+        );
+        assert_eq!(
+            Config::find(args(&["--config=a.toml"]).into_iter(), None),
+            Ok(Some(PathBuf::from("a.toml")))
+        );
+        assert_eq!(
+            Config::find(args(&[]).into_iter(), Some("b.json".to_string())),
+            Ok(Some(PathBuf::from("b.json")))
+        );
+        assert!(Config::find(args(&["--config"]).into_iter(), None).is_err());
+    }
+
+    #[test]
+    fn remote_sources() {
+        assert!(validate_url("https://cdn.example.com/clip.mp4").is_ok());
+        assert!(validate_url("http://example.com").is_ok());
+        assert!(validate_url("ftp://example.com/clip.mp4").is_err());
+        assert!(validate_url("https:///clip.mp4").is_err());
+        assert!(validate_url("https://example.com/my clip.mp4").is_err());
+
+        let source = RemoteSource {
+            name: "cdn".to_string(),
+            urls: vec![
+                "https://cdn.example.com/memes/cat%20dance.webm?token=1".to_string(),
+                "https://cdn.example.com/watch/12345".to_string(),
+                "https://cdn.example.com/song.mp3".to_string(),
+            ],
+            tags: Vec::new(),
+        };
+        let shitposts = source.shitposts();
+
+        assert_eq!(shitposts[0].title, "cat dance.webm");
+        assert_eq!(
+            shitposts[0].url,
+            "https://cdn.example.com/memes/cat%20dance.webm?token=1"
+        );
+        assert_eq!(shitposts[1].title, "12345");
+        assert_eq!(shitposts[1].kind, ShitpostKind::Video);
+        assert_eq!(shitposts[2].kind, ShitpostKind::Audio);
+    }
 }
@@ -0,0 +1,690 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    fs,
+    hash::{Hash, Hasher},
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::web;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    thumbnails::Thumbnailer, BrowserCompat, Config, Folder, MediaType, Shitpost, ShitpostKind,
+};
+
+/// Optional file in a folder mapping file names to the seconds skipped at their start
+const OFFSETS_FILE: &str = "offsets.ron";
+
+/// Optional file in a folder mapping file names to their loudness in LUFS, measured offline
+const LOUDNESS_FILE: &str = "loudness.ron";
+
+/// Marks the smaller variant of a file, like "clip.480p.mp4" next to "clip.mp4"
+const LOW_VARIANT: &str = "480p";
+
+/// Bytes read from each end of a file for its fingerprint
+const FINGERPRINT_SPAN: u64 = 64 * 1024;
+
+/// The result of scanning a folder, the error is kept to be shown to the host
+type ScanResult = Result<Arc<Vec<Shitpost>>, String>;
+
+/// How much a scanned folder holds, shown to hosts so a filling disk is noticed
+#[derive(Clone, Default, Serialize)]
+pub struct FolderStats {
+    pub files: usize,
+    /// Total size of the files, remote files count as empty
+    pub bytes: u64,
+    /// Files only some browsers play, which hosts have to ask for
+    pub maybe_unsupported: usize,
+    pub excluded: Excluded,
+}
+
+/// Files a scan left out and why, so hosts can tell where the rest of a folder went
+#[derive(Clone, Default, Serialize)]
+pub struct Excluded {
+    /// Files of unsupported types by their lowercase extension, empty for files without one
+    pub extensions: BTreeMap<String, usize>,
+    /// Entries that couldn't be read at all
+    pub unreadable: usize,
+}
+
+impl Excluded {
+    pub fn total(&self) -> usize {
+        self.extensions.values().sum::<usize>() + self.unreadable
+    }
+
+    /// Like "3 .txt, 1 without an extension, 2 unreadable"
+    pub fn summary(&self) -> String {
+        let mut parts = self
+            .extensions
+            .iter()
+            .map(|(extension, count)| match extension.as_str() {
+                "" => format!("{} without an extension", count),
+                extension => format!("{} .{}", count, extension),
+            })
+            .collect::<Vec<_>>();
+        if self.unreadable > 0 {
+            parts.push(format!("{} unreadable", self.unreadable));
+        }
+        parts.join(", ")
+    }
+}
+
+impl FolderStats {
+    /// The size in the largest unit it makes at least one of, like "1.5 GiB"
+    pub fn size_text(&self) -> String {
+        let mut size = self.bytes as f64;
+        for unit in ["B", "KiB", "MiB", "GiB"] {
+            if size < 1024.0 {
+                return match unit {
+                    "B" => format!("{} B", self.bytes),
+                    _ => format!("{:.1} {}", size, unit),
+                };
+            }
+            size /= 1024.0;
+        }
+        format!("{:.1} TiB", size)
+    }
+}
+
+/// In-memory listing of the configured shitpost folders, so hosting a session
+/// doesn't have to walk the folders every time
+#[derive(Default)]
+pub struct MediaIndex {
+    /// Scanned folders by name
+    folders: RwLock<HashMap<String, ScanResult>>,
+    /// What the last scan left out of each readable folder
+    excluded: RwLock<HashMap<String, Excluded>>,
+    /// Set while thumbnails are being generated in the background
+    generating: AtomicBool,
+    /// Unix time in milliseconds the index last changed at, zero before the first scan
+    changed_at: AtomicU64,
+}
+
+impl MediaIndex {
+    /// The scanned contents of a folder, None if it hasn't been scanned
+    pub fn get(&self, folder_name: &str) -> Option<ScanResult> {
+        self.folders.read().unwrap().get(folder_name).cloned()
+    }
+
+    /// File count and size of a folder, None if it hasn't been scanned or couldn't be read
+    pub fn stats(&self, folder_name: &str) -> Option<FolderStats> {
+        let folders = self.folders.read().unwrap();
+        let shitposts = folders.get(folder_name)?.as_ref().ok()?;
+
+        Some(FolderStats {
+            files: shitposts.len(),
+            bytes: shitposts.iter().filter_map(|shitpost| shitpost.size).sum(),
+            maybe_unsupported: shitposts
+                .iter()
+                .filter(|shitpost| shitpost.compat == BrowserCompat::MaybeUnsupported)
+                .count(),
+            excluded: self
+                .excluded
+                .read()
+                .unwrap()
+                .get(folder_name)
+                .cloned()
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Names of the files of a folder without a measured loudness,
+    /// None if it hasn't been scanned or couldn't be read
+    pub fn missing_loudness(&self, folder_name: &str) -> Option<Vec<String>> {
+        let folders = self.folders.read().unwrap();
+        let shitposts = folders.get(folder_name)?.as_ref().ok()?;
+
+        Some(
+            shitposts
+                .iter()
+                .filter(|shitpost| shitpost.loudness.is_none())
+                .map(Shitpost::file_name)
+                .collect(),
+        )
+    }
+
+    /// Looks up an indexed file, which guarantees it exists and is playable
+    pub fn find(&self, folder_name: &str, file: &str) -> Option<Shitpost> {
+        let folders = self.folders.read().unwrap();
+        let shitposts = folders.get(folder_name)?.as_ref().ok()?;
+
+        shitposts
+            .iter()
+            .find(|shitpost| shitpost.file_name() == file)
+            .cloned()
+    }
+
+    /// Adds a file to an indexed folder, so it can be picked before the next rescan
+    pub fn insert(&self, folder_name: &str, shitpost: Shitpost) {
+        if let Some(Ok(shitposts)) = self.folders.write().unwrap().get_mut(folder_name) {
+            Arc::make_mut(shitposts).push(shitpost);
+            self.touch();
+        }
+    }
+
+    /// When the index last changed, for clients checking if their copy is stale
+    pub fn changed_at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.changed_at.load(Ordering::SeqCst))
+    }
+
+    fn touch(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        // Changes within the same millisecond must still be told apart
+        let _ = self
+            .changed_at
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+                Some(now.max(last + 1))
+            });
+    }
+
+    /// Scans all configured folders on a blocking thread and replaces the index,
+    /// missing thumbnails are generated afterwards in the background
+    pub async fn rescan(self: &Arc<Self>, config: Arc<Config>) {
+        let start = Instant::now();
+
+        let scan_config = config.clone();
+        let scanned = web::block(move || {
+            let config = scan_config;
+            let thumbnailer = Thumbnailer::from_config(&config);
+
+            let remote = config
+                .remote_sources
+                .iter()
+                .map(|source| (source.name.clone(), Ok(Arc::new(source.shitposts()))));
+
+            let mut excluded = HashMap::new();
+            let folders = config
+                .shitposts
+                .iter()
+                .map(|folder| {
+                    let result = match read_shitposts(folder, thumbnailer.as_ref(), config.dedupe) {
+                        Ok((shitposts, left_out)) => {
+                            excluded.insert(folder.name.clone(), left_out);
+                            Ok(Arc::new(shitposts))
+                        }
+                        Err(why) => {
+                            tracing::warn!(r#"Failed to read folder "{}": {}"#, folder.path, why);
+                            Err(why.to_string())
+                        }
+                    };
+                    (folder.name.clone(), result)
+                })
+                .chain(remote)
+                .collect::<HashMap<_, _>>();
+            (folders, excluded)
+        })
+        .await;
+
+        match scanned {
+            Ok((scanned, excluded)) => {
+                tracing::info!(
+                    "Indexed {} files in {} sources in {:?}",
+                    scanned
+                        .values()
+                        .filter_map(|result| result.as_ref().ok())
+                        .map(|shitposts| shitposts.len())
+                        .sum::<usize>(),
+                    scanned.len(),
+                    start.elapsed()
+                );
+                *self.folders.write().unwrap() = scanned;
+                *self.excluded.write().unwrap() = excluded;
+                self.touch();
+            }
+            Err(why) => {
+                tracing::error!("Folder scan failed: {}", why);
+                return;
+            }
+        }
+
+        if let Some(thumbnailer) = Thumbnailer::from_config(&config) {
+            if !self.generating.swap(true, Ordering::SeqCst) {
+                let index = self.clone();
+                actix_web::rt::task::spawn_blocking(move || {
+                    index.generate_thumbnails(&config, &thumbnailer);
+                    index.generating.store(false, Ordering::SeqCst);
+                });
+            }
+        }
+    }
+
+    /// Renders the thumbnails videos don't have yet, one folder at a time.
+    /// Failures are only logged, the entry just goes without a thumbnail.
+    fn generate_thumbnails(&self, config: &Config, thumbnailer: &Thumbnailer) {
+        for folder in &config.shitposts {
+            let Some(Ok(shitposts)) = self.get(&folder.name) else {
+                continue;
+            };
+
+            let mut generated = HashMap::new();
+            for shitpost in shitposts.iter() {
+                if shitpost.kind != ShitpostKind::Video || shitpost.thumbnail.is_some() {
+                    continue;
+                }
+
+                let file = shitpost.file_name();
+                let source = Path::new(&folder.path).join(&file);
+                let key = match Thumbnailer::key(&source) {
+                    Ok(key) => key,
+                    Err(why) => {
+                        tracing::warn!(r#"Failed to read "{}": {}"#, source.display(), why);
+                        continue;
+                    }
+                };
+
+                match thumbnailer.generate(&source, &key) {
+                    Ok(()) => {
+                        generated.insert(file, Thumbnailer::url(&key));
+                    }
+                    Err(why) => tracing::warn!(
+                        r#"Failed to generate a thumbnail for "{}": {}"#,
+                        source.display(),
+                        why
+                    ),
+                }
+            }
+
+            if generated.is_empty() {
+                continue;
+            }
+            tracing::info!(
+                r#"Generated {} thumbnails for "{}""#,
+                generated.len(),
+                folder.name
+            );
+
+            if let Some(Ok(shitposts)) = self.folders.write().unwrap().get_mut(&folder.name) {
+                for shitpost in Arc::make_mut(shitposts) {
+                    if let Some(thumbnail) = generated.remove(&shitpost.file_name()) {
+                        shitpost.thumbnail = Some(thumbnail);
+                    }
+                }
+                self.touch();
+            }
+        }
+    }
+}
+
+/// Reads all playable files in a folder, counting the ones it leaves out
+fn read_shitposts(
+    folder: &Folder,
+    thumbnailer: Option<&Thumbnailer>,
+    fingerprints: bool,
+) -> io::Result<(Vec<Shitpost>, Excluded)> {
+    let mut shitposts = Vec::new();
+    let mut excluded = Excluded::default();
+    let offsets = read_sidecar::<f64>(&folder.path, OFFSETS_FILE)
+        .into_iter()
+        .filter(|(_, offset)| offset.is_finite() && *offset > 0.0)
+        .collect::<HashMap<_, _>>();
+    let loudness = read_sidecar::<f32>(&folder.path, LOUDNESS_FILE)
+        .into_iter()
+        .filter(|(_, loudness)| loudness.is_finite())
+        .collect::<HashMap<_, _>>();
+
+    let mut entries = fs::read_dir(&folder.path)?
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(why) => {
+                tracing::warn!(r#"Failed to read an entry in "{}": {}"#, folder.path, why);
+                excluded.unreadable += 1;
+                None
+            }
+        })
+        // Nested folders aren't looked into, even if their name looks like a file.
+        // Symlinks to files are followed like before.
+        .filter(|entry| !entry.path().is_dir())
+        .collect::<Vec<_>>();
+    // The order read_dir lists them in can change, seeded playlists need them in the same order
+    entries.sort_by_key(|entry| entry.file_name());
+    let names = entries
+        .iter()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect::<HashSet<_>>();
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        // Variants are only listed on their own if the original is missing
+        if original_name(&name).is_some_and(|original| names.contains(&original)) {
+            continue;
+        }
+
+        if let Some(media_type) = MediaType::from_path(&name) {
+            let kind = media_type.kind;
+            let thumbnail = thumbnailer
+                .filter(|_| kind == ShitpostKind::Video)
+                .and_then(|thumbnailer| {
+                    let key = Thumbnailer::key(&entry.path()).ok()?;
+                    thumbnailer.exists(&key).then(|| Thumbnailer::url(&key))
+                });
+
+            let metadata = entry.metadata().ok();
+            // Unreadable files are only told apart by their path
+            let fingerprint = fingerprints
+                .then(|| {
+                    fingerprint(&entry.path()).map_err(|why| {
+                        tracing::warn!(
+                            r#"Failed to fingerprint "{}": {}"#,
+                            entry.path().display(),
+                            why
+                        )
+                    })
+                })
+                .and_then(Result::ok);
+
+            shitposts.push(Shitpost {
+                url: crate::file_url(&folder.name, &name),
+                low_url: low_variant_name(&name)
+                    .filter(|low| names.contains(low))
+                    .map(|low| crate::file_url(&folder.name, &low)),
+                start_offset: offsets.get(&name).copied().unwrap_or_default(),
+                loudness: loudness.get(&name).copied(),
+                compat: media_type.compat,
+                title: crate::sanitize_title(&name),
+                kind,
+                thumbnail,
+                folder: folder.name.clone(),
+                size: metadata.as_ref().map(|metadata| metadata.len()),
+                modified: metadata
+                    .and_then(|metadata| metadata.modified().ok())
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map(|modified| modified.as_secs()),
+                tags: folder.tags.clone(),
+                fingerprint,
+            });
+        } else if name != OFFSETS_FILE && name != LOUDNESS_FILE {
+            // Left out files are counted by their extension, the sidecars aren't meant to be played
+            let extension = Path::new(&name)
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            *excluded.extensions.entry(extension).or_default() += 1;
+        }
+    }
+
+    if excluded.total() > 0 {
+        tracing::info!(
+            r#"Left out {} files in "{}": {}"#,
+            excluded.total(),
+            folder.path,
+            excluded.summary()
+        );
+    }
+    Ok((shitposts, excluded))
+}
+
+/// Bytes free on the filesystem a path is on, as reported by `df`
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let output = Command::new("df").arg("-Pk").arg(path).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    parse_df(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Unexpected output from df"))
+}
+
+/// The available column of POSIX `df -Pk` output in bytes
+fn parse_df(output: &str) -> Option<u64> {
+    let line = output.lines().nth(1)?;
+    // Filesystem names can have spaces, the columns after it can't
+    let available = line.split_whitespace().rev().nth(2)?;
+    available.parse::<u64>().ok().map(|kib| kib * 1024)
+}
+
+/// Warns about folders on filesystems with less than `min_free_space` MiB left,
+/// where files start failing to stream once it runs out
+pub fn warn_low_space(config: &Config) {
+    if config.min_free_space == 0 {
+        return;
+    }
+
+    for folder in &config.shitposts {
+        match free_space(Path::new(&folder.path)) {
+            Ok(free) if free < config.min_free_space * 1024 * 1024 => tracing::warn!(
+                r#"Only {} MiB are free on the filesystem of "{}""#,
+                free / 1024 / 1024,
+                folder.path
+            ),
+            Ok(_) => {}
+            Err(why) => tracing::debug!(
+                r#"Failed to check the free space of "{}": {}"#,
+                folder.path,
+                why
+            ),
+        }
+    }
+}
+
+/// Hash of the size and both ends of a file, cheap enough for large videos
+/// while still telling apart files that only share a container header
+pub fn fingerprint(path: &Path) -> io::Result<u64> {
+    let mut file = fs::File::open(path)?;
+    let size = file.metadata()?.len();
+
+    let mut head = Vec::new();
+    (&mut file).take(FINGERPRINT_SPAN).read_to_end(&mut head)?;
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(size.saturating_sub(FINGERPRINT_SPAN)))?;
+    file.take(FINGERPRINT_SPAN).read_to_end(&mut tail)?;
+
+    let mut hasher = DefaultHasher::new();
+    size.hash(&mut hasher);
+    head.hash(&mut hasher);
+    tail.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+/// "clip.mp4" to "clip.480p.mp4"
+fn low_variant_name(name: &str) -> Option<String> {
+    let (stem, extension) = name.rsplit_once('.')?;
+    Some(format!("{}.{}.{}", stem, LOW_VARIANT, extension))
+}
+
+/// "clip.480p.mp4" to "clip.mp4", None if the name isn't of a variant
+fn original_name(name: &str) -> Option<String> {
+    let (stem, extension) = name.rsplit_once('.')?;
+    let stem = stem.strip_suffix(LOW_VARIANT)?.strip_suffix('.')?;
+    (!stem.is_empty()).then(|| format!("{}.{}", stem, extension))
+}
+
+/// A file in a folder mapping file names to values, empty if it has none or it can't be read
+fn read_sidecar<T: DeserializeOwned>(folder: &str, file: &str) -> HashMap<String, T> {
+    let path = Path::new(folder).join(file);
+    let Ok(bytes) = fs::read(&path) else {
+        return HashMap::new();
+    };
+
+    match ron::de::from_bytes::<HashMap<String, T>>(&bytes) {
+        Ok(values) => values,
+        Err(why) => {
+            tracing::warn!(r#"Failed to read "{}": {}"#, path.display(), why);
+            HashMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        media::{fingerprint, original_name, parse_df, read_shitposts, FolderStats},
+        BrowserCompat, Folder, ShitpostKind,
+    };
+
+    #[test]
+    fn folder_scan() {
+        let path = std::env::temp_dir().join(format!("scan-{}", std::process::id()));
+        fs::create_dir_all(path.join("nested")).unwrap();
+        fs::create_dir_all(path.join("folder.mp4")).unwrap();
+        for file in [
+            "clip.mp4",
+            "Image.PNG",
+            "old.MKV",
+            "notes.txt",
+            "no_extension",
+            "nested/inner.mp4",
+        ] {
+            fs::write(path.join(file), b"not really media").unwrap();
+        }
+        fs::write(path.join("offsets.ron"), r#"{"clip.mp4": 2.5}"#).unwrap();
+        fs::write(path.join("loudness.ron"), r#"{"clip.mp4": -14.5}"#).unwrap();
+
+        let folder = Folder {
+            path: path.display().to_string(),
+            name: "scan".to_string(),
+            tags: vec!["loud".to_string()],
+        };
+        let (mut shitposts, excluded) = read_shitposts(&folder, None, true).unwrap();
+        shitposts.sort_by(|a, b| a.title.cmp(&b.title));
+
+        assert_eq!(
+            shitposts
+                .iter()
+                .map(|shitpost| shitpost.title.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Image.PNG", "clip.mp4", "old.MKV"]
+        );
+        assert_eq!(shitposts[1].compat, BrowserCompat::Safe);
+        assert_eq!(shitposts[2].compat, BrowserCompat::MaybeUnsupported);
+        // The sidecars and nested folders aren't counted
+        assert_eq!(excluded.total(), 2);
+        assert_eq!(excluded.summary(), "1 without an extension, 1 .txt");
+        assert_eq!(shitposts[0].kind, ShitpostKind::Image);
+        assert_eq!(shitposts[1].url, "/shitposts/scan/clip.mp4");
+        assert_eq!(shitposts[1].start_offset, 2.5);
+        assert_eq!(shitposts[1].loudness, Some(-14.5));
+        assert_eq!(shitposts[0].loudness, None);
+        assert_eq!(shitposts[1].size, Some(16));
+        assert_eq!(shitposts[1].tags, vec!["loud"]);
+        assert!(shitposts[1].fingerprint.is_some());
+
+        assert!(read_shitposts(
+            &Folder {
+                path: path.join("missing").display().to_string(),
+                name: "missing".to_string(),
+                tags: Vec::new(),
+            },
+            None,
+            true
+        )
+        .is_err());
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn low_variants() {
+        assert_eq!(original_name("clip.480p.mp4").as_deref(), Some("clip.mp4"));
+        assert_eq!(
+            original_name("my.clip.480p.webm").as_deref(),
+            Some("my.clip.webm")
+        );
+        assert_eq!(original_name("clip.mp4"), None);
+        assert_eq!(original_name("clip480p.mp4"), None);
+        assert_eq!(original_name(".480p.mp4"), None);
+
+        let path = std::env::temp_dir().join(format!("variants-{}", std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        for file in [
+            "clip.mp4",
+            "clip.480p.mp4",
+            "only low.480p.mp4",
+            "no variant.webm",
+            // Variants have the extension of the original
+            "no variant.480p.mp4",
+        ] {
+            fs::write(path.join(file), b"not really media").unwrap();
+        }
+        // Malformed sidecars don't keep the folder from being scanned
+        fs::write(path.join("loudness.ron"), r#"{"clip.mp4": "loud"}"#).unwrap();
+
+        let folder = Folder {
+            path: path.display().to_string(),
+            name: "variants".to_string(),
+            tags: Vec::new(),
+        };
+        let (mut shitposts, _) = read_shitposts(&folder, None, true).unwrap();
+        shitposts.sort_by(|a, b| a.title.cmp(&b.title));
+
+        assert_eq!(
+            shitposts
+                .iter()
+                .map(|shitpost| (shitpost.title.as_str(), shitpost.low_url.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("clip.mp4", Some("/shitposts/variants/clip.480p.mp4")),
+                ("no variant.480p.mp4", None),
+                ("no variant.webm", None),
+                // Without the original the variant is played as it is
+                ("only low.480p.mp4", None),
+            ]
+        );
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn fingerprints() {
+        let path = std::env::temp_dir().join(format!("fingerprints-{}", std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        // Longer than both ends together, so the middle isn't read
+        let bytes = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut middle = bytes.clone();
+        middle[100_000] ^= 1;
+        let mut end = bytes.clone();
+        end[199_999] ^= 1;
+        for (name, bytes) in [
+            ("a.mp4", &bytes),
+            ("copy of a.mp4", &bytes),
+            ("middle.mp4", &middle),
+            ("end.mp4", &end),
+            ("short.mp4", &bytes[..10].to_vec()),
+        ] {
+            fs::write(path.join(name), bytes).unwrap();
+        }
+        let of = |name: &str| fingerprint(&path.join(name)).unwrap();
+
+        assert_eq!(of("a.mp4"), of("copy of a.mp4"));
+        assert_eq!(of("a.mp4"), of("middle.mp4"));
+        assert_ne!(of("a.mp4"), of("end.mp4"));
+        assert!(fingerprint(&path.join("missing.mp4")).is_err());
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn disk_usage() {
+        let output = "Filesystem     1024-blocks      Used Available Capacity Mounted on\n\
+                      /dev/my disk      10485760   9437184   1048576      90% /srv/media\n";
+        assert_eq!(parse_df(output), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_df("Filesystem 1024-blocks Used Available\n"), None);
+
+        let size = |bytes| {
+            FolderStats {
+                files: 1,
+                bytes,
+                ..Default::default()
+            }
+            .size_text()
+        };
+        assert_eq!(size(512), "512 B");
+        assert_eq!(size(1536), "1.5 KiB");
+        assert_eq!(size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+        assert_eq!(size(2048 * 1024 * 1024 * 1024), "2.0 TiB");
+    }
+}
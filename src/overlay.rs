@@ -0,0 +1,104 @@
+use actix::Addr;
+use actix_web::{
+    get,
+    web::{Data, Query},
+    HttpRequest,
+};
+use askama::Template;
+use serde::Deserialize;
+
+use crate::{
+    player::{self, AppError},
+    session::{self, NowPlaying, SessionCode, SessionManager},
+    Html,
+};
+
+/// How often the overlay asks for the current entry
+const REFRESH_MILLIS: u64 = 2000;
+
+#[derive(Deserialize)]
+struct OverlayQuery {
+    session: SessionCode,
+}
+
+#[derive(Template)]
+#[template(path = "overlay.html")]
+struct Overlay<'a> {
+    session: &'a SessionCode,
+    /// Passed on to the polling, the browser source of the overlay has no cookie
+    ticket: &'a str,
+    now: NowPlaying,
+    refresh_millis: u64,
+}
+
+/// The title and index of the current entry on a transparent page, for OBS browser sources.
+/// Sessions with a password need the ticket of a player as `ticket`.
+#[get("/overlay")]
+async fn overlay(
+    manager: Data<Addr<SessionManager>>,
+    query: Query<OverlayQuery>,
+    req: HttpRequest,
+) -> Result<Html, AppError> {
+    let ticket = player::request_ticket(&req, &query.session);
+
+    match manager
+        .send(session::CheckTicket {
+            session: query.session.clone(),
+            ticket: ticket.clone(),
+        })
+        .await?
+    {
+        Some(true) => {}
+        Some(false) => return Err(AppError::Forbidden("Wrong password".to_string())),
+        None => return Err(AppError::NotFound("No such session exists".to_string())),
+    }
+
+    let now = manager
+        .send(session::GetNowPlaying {
+            session: query.session.clone(),
+        })
+        .await?
+        .ok_or_else(|| AppError::NotFound("No such session exists".to_string()))?;
+
+    Html::render(Overlay {
+        session: &query.session,
+        ticket: ticket.as_deref().unwrap_or_default(),
+        now,
+        refresh_millis: REFRESH_MILLIS,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use askama::Template;
+
+    use crate::{
+        overlay::Overlay,
+        player::State,
+        session::{NowPlaying, SessionCode},
+    };
+
+    #[test]
+    fn overlay_page() {
+        let session = SessionCode::try_from("abc123".to_string()).unwrap();
+        let page = Overlay {
+            session: &session,
+            ticket: "a b",
+            now: NowPlaying {
+                title: Some("<cat>".to_string()),
+                url: Some("/shitposts/test/cat.mp4".to_string()),
+                index: 4,
+                total: 10,
+                state: State::Playing,
+                position_estimate: 1.0,
+            },
+            refresh_millis: 2000,
+        }
+        .render()
+        .unwrap();
+
+        assert!(page.contains("&lt;cat&gt;"));
+        assert!(page.contains("5 / 10"));
+        assert!(page.contains("/api/sessions/abc123/now?ticket=a%20b"));
+    }
+}
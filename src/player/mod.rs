@@ -0,0 +1,18 @@
+//! The pages players and hosts see, and the websocket keeping each player in sync
+
+mod poll;
+mod protocol;
+mod routes;
+mod ws;
+
+pub use poll::PollConnections;
+/// For the harness, whose players say hello themselves
+#[cfg(test)]
+pub use protocol::PROTOCOL_VERSION;
+pub use protocol::{DebugPlayer, Initiator, Quality, State};
+pub use routes::{
+    host, host_cancel, host_import, host_preview, host_reroll, host_schedule, host_submit, index,
+    join, join_code, join_unlock, poll_connect, poll_receive, poll_send, request_ticket,
+    shitpost_file, socket, AppError,
+};
+pub use ws::*;
@@ -0,0 +1,287 @@
+//! Long polling for clients whose network doesn't let websockets through.
+//! The connection is run by a `PlayerActor` like any other, only what it sends is queued here
+//! for the client to fetch.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    pin::pin,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use actix::Addr;
+use rand::{distributions::Alphanumeric, Rng};
+use tokio::sync::Notify;
+
+use crate::player::ws::PlayerActor;
+
+/// Messages waiting for a polling client, numbered so the client can say what it already has
+pub struct Outbox {
+    state: Mutex<OutboxState>,
+    /// Wakes waiting polls when a message is queued or the connection ends
+    changed: Notify,
+}
+
+struct OutboxState {
+    /// Encoded messages by sequence number, oldest first
+    messages: VecDeque<(u64, String)>,
+    next: u64,
+    /// The last message dropped for going over capacity before the client had it
+    dropped_through: u64,
+    /// Polls waiting for messages, the client counts as heard from while there are any
+    waiting: usize,
+    last_seen: Instant,
+    /// Why the connection ended, told to the client once it has everything before it
+    closed: Option<String>,
+}
+
+/// What a poll gets back
+pub struct Polled {
+    pub messages: Vec<(u64, String)>,
+    /// Messages after `since` were dropped, so the client has to reconnect to be in sync again
+    pub gap: bool,
+    pub closed: Option<String>,
+}
+
+impl Outbox {
+    /// Messages kept for a client that stopped polling, until the heartbeat gives up on it
+    const CAPACITY: usize = 500;
+
+    pub fn push(&self, text: String) {
+        let mut state = self.state.lock().unwrap();
+        if state.closed.is_some() {
+            return;
+        }
+
+        state.next += 1;
+        let seq = state.next;
+        state.messages.push_back((seq, text));
+        if state.messages.len() > Self::CAPACITY {
+            if let Some((dropped, _)) = state.messages.pop_front() {
+                state.dropped_through = dropped;
+            }
+        }
+        drop(state);
+        self.changed.notify_waiters();
+    }
+
+    /// The messages after `since`, the ones up to it are dropped as the client has them
+    pub fn since(&self, since: u64) -> Polled {
+        let mut state = self.state.lock().unwrap();
+        state.last_seen = Instant::now();
+        while state.messages.front().is_some_and(|(seq, _)| *seq <= since) {
+            state.messages.pop_front();
+        }
+
+        Polled {
+            messages: state.messages.iter().cloned().collect(),
+            gap: since < state.dropped_through,
+            closed: state.closed.clone().filter(|_| state.messages.is_empty()),
+        }
+    }
+
+    /// Like `since`, but waits until there is something to answer with or the deadline passes
+    pub async fn wait_since(&self, since: u64, deadline: Instant) -> Polled {
+        loop {
+            let mut changed = pin!(self.changed.notified());
+            // Registered before looking, so a message queued in between still wakes this up
+            changed.as_mut().enable();
+
+            let polled = self.since(since);
+            if !polled.messages.is_empty() || polled.closed.is_some() {
+                return polled;
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if actix_web::rt::time::timeout(left, changed).await.is_err() {
+                return polled;
+            }
+        }
+    }
+
+    /// Ends the connection, later reasons don't replace the first
+    pub fn close(&self, reason: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.closed.is_none() {
+            state.closed = Some(reason.to_string());
+        }
+        drop(state);
+        self.changed.notify_waiters();
+    }
+
+    /// Last time the client polled, now while a poll is waiting
+    pub fn last_seen(&self) -> Instant {
+        let state = self.state.lock().unwrap();
+        if state.waiting > 0 {
+            Instant::now()
+        } else {
+            state.last_seen
+        }
+    }
+
+    /// Keeps the client from timing out while the returned guard is held
+    pub fn wait(self: &Arc<Self>) -> Waiting {
+        self.state.lock().unwrap().waiting += 1;
+        Waiting(self.clone())
+    }
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(OutboxState {
+                messages: VecDeque::new(),
+                next: 0,
+                dropped_through: 0,
+                waiting: 0,
+                last_seen: Instant::now(),
+                closed: None,
+            }),
+            changed: Notify::new(),
+        }
+    }
+}
+
+/// A poll in progress, see `Outbox::wait`
+pub struct Waiting(Arc<Outbox>);
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.waiting -= 1;
+        state.last_seen = Instant::now();
+    }
+}
+
+impl Polled {
+    /// The messages are already JSON, so they are put in as they are
+    pub fn to_json(&self) -> String {
+        let next = self
+            .messages
+            .last()
+            .map(|(seq, _)| seq.to_string())
+            .unwrap_or_else(|| "null".to_string());
+        let messages = self
+            .messages
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        format!(
+            r#"{{"next":{},"messages":[{}],"gap":{},"closed":{}}}"#,
+            next,
+            messages,
+            self.gap,
+            serde_json::to_string(&self.closed).unwrap()
+        )
+    }
+}
+
+/// The actor running a polling connection and what it sends
+type PollConnection = (Addr<PlayerActor>, Arc<Outbox>);
+
+/// The polling connections by their token
+#[derive(Default)]
+pub struct PollConnections(Mutex<HashMap<String, PollConnection>>);
+
+impl PollConnections {
+    const TOKEN_LENGTH: usize = 32;
+
+    /// Registers a connection and returns its token. Connections whose actor has
+    /// stopped are dropped here, in case their client never came to hear about it.
+    pub fn open(&self, player: Addr<PlayerActor>, outbox: Arc<Outbox>) -> String {
+        let token = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(Self::TOKEN_LENGTH)
+            .map(char::from)
+            .collect::<String>();
+
+        let mut connections = self.0.lock().unwrap();
+        connections.retain(|_, (player, _)| player.connected());
+        connections.insert(token.clone(), (player, outbox));
+        token
+    }
+
+    pub fn get(&self, token: &str) -> Option<PollConnection> {
+        self.0.lock().unwrap().get(token).cloned()
+    }
+
+    pub fn remove(&self, token: &str) {
+        self.0.lock().unwrap().remove(token);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use crate::player::poll::Outbox;
+
+    #[test]
+    fn outboxes() {
+        let outbox = Outbox::default();
+        outbox.push(r#""sync_position""#.to_string());
+        outbox.push(r#"{"change_rate":1.5}"#.to_string());
+
+        assert_eq!(
+            outbox.since(0).to_json(),
+            r#"{"next":2,"messages":["sync_position",{"change_rate":1.5}],"gap":false,"closed":null}"#
+        );
+        // What the client has is dropped
+        assert_eq!(outbox.since(1).messages.len(), 1);
+        assert_eq!(outbox.since(0).messages.len(), 1);
+        assert_eq!(
+            outbox.since(2).to_json(),
+            r#"{"next":null,"messages":[],"gap":false,"closed":null}"#
+        );
+
+        outbox.push(r#""session_closed""#.to_string());
+        outbox.close("Kicked");
+        outbox.close("Timed out");
+        outbox.push(r#""sync_position""#.to_string());
+        // The reason only comes after the messages before it
+        let polled = outbox.since(2);
+        assert_eq!(polled.messages.len(), 1);
+        assert_eq!(polled.closed, None);
+        assert_eq!(outbox.since(3).closed.as_deref(), Some("Kicked"));
+    }
+
+    #[test]
+    fn overflowing_outboxes() {
+        let outbox = Outbox::default();
+        for i in 0..Outbox::CAPACITY + 10 {
+            outbox.push(i.to_string());
+        }
+
+        // The client missed the first ten, and is told so
+        let polled = outbox.since(5);
+        assert!(polled.gap);
+        assert_eq!(polled.messages.len(), Outbox::CAPACITY);
+        assert!(!outbox.since(10).gap);
+    }
+
+    #[actix_web::test]
+    async fn waiting_polls() {
+        let outbox = Arc::new(Outbox::default());
+        let start = Instant::now();
+        let polled = outbox
+            .wait_since(0, start + Duration::from_millis(50))
+            .await;
+        assert!(polled.messages.is_empty());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // Woken by the message instead of waiting out the deadline
+        let pushing = outbox.clone();
+        actix_web::rt::spawn(async move {
+            actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+            pushing.push(r#""sync_position""#.to_string());
+        });
+        let start = Instant::now();
+        let polled = outbox.wait_since(0, start + Duration::from_secs(30)).await;
+        assert_eq!(polled.messages.len(), 1);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,505 @@
+//! What the player page and the server say to each other over the websocket
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    session::{self, Action, DisconnectReason, LoopMode, Permissions},
+    Shitpost,
+};
+
+/// Version of the websocket protocol spoken by `PlayerMessage` and `BackendMessage`.
+/// Clients with another major version are turned away.
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 3, minor: 0 };
+
+/// What the server supports besides plain playback sync, told to clients in the hello
+pub const FEATURES: &[&str] = &[
+    "chat",
+    "reactions",
+    "skip_votes",
+    "countdown",
+    "loop_mode",
+    "start_offsets",
+    "spectators",
+    "wait_for_stragglers",
+    "shuffle_remaining",
+    "mute_folders",
+    "latency",
+    "filter_tags",
+    "report_broken",
+    "bookmarks",
+    "debug_sync",
+    "max_players",
+    "play_next",
+    "low_quality",
+    "permissions",
+    "server_advance",
+    "generations",
+    "intermission",
+    "background",
+    "scheduled_sessions",
+    "clock_sync",
+];
+
+/// Bump the minor version for additions and the major version for breaking changes
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Deserialize, Serialize)]
+pub struct ProtocolVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The messages sent from the player site itself, changes to these bump `PROTOCOL_VERSION`
+#[derive(Deserialize, Serialize)]
+pub enum PlayerMessage {
+    /// Has to be the first message, nothing else is accepted before it
+    Hello {
+        protocol_version: ProtocolVersion,
+        #[serde(default)]
+        capabilities: Vec<String>,
+        /// Generation of the session the page was made for
+        #[serde(default)]
+        generation: Option<u64>,
+    },
+    Seeked,
+    StateChanged(State),
+    RateChanged(f64),
+    Position(f64),
+    PlaylistChanged(usize),
+    Chat(String),
+    VoteSkip,
+    Ready,
+    StartCountdown,
+    RevealHostToken,
+    SetLoopMode(LoopMode),
+    Reaction(String),
+    SetStartOffset {
+        /// Named `entry` since a field named `index` would clash with the index page
+        #[serde(rename = "index")]
+        entry: usize,
+        seconds: f64,
+    },
+    SetWaitForStragglers(bool),
+    ShuffleRemaining,
+    /// Host only, the entry at `index` plays after the current one
+    PlayNext {
+        /// Named `entry` since a field named `index` would clash with the index page
+        #[serde(rename = "index")]
+        entry: usize,
+    },
+    /// Host only, ends the intermission early
+    SkipIntermission,
+    /// The tab was hidden, like by locking the phone
+    Backgrounded,
+    /// The tab is shown again after being hidden
+    Foregrounded,
+    MuteFolder(String),
+    UnmuteFolder(String),
+    /// Replaces the tags whose entries are passed over
+    FilterTags(Vec<String>),
+    /// The index of the entry that won't play
+    ReportBroken(usize),
+    Bookmark {
+        #[serde(default)]
+        note: String,
+    },
+    /// Host only, sends everyone the positions of all players every few seconds
+    SetDebug(bool),
+    SetQuality(Quality),
+    /// Host only, what players other than hosts may do
+    SetPermissions(Permissions),
+    /// Answers a `TimeProbe` right away with the client's clock in unix milliseconds
+    TimeProbeReply {
+        server_ms: u64,
+        client_ms: f64,
+    },
+}
+
+/// Changes to these bump `PROTOCOL_VERSION`
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendMessage {
+    Hello {
+        protocol_version: ProtocolVersion,
+        features: &'static [&'static str],
+    },
+    SyncPosition,
+    ChangeState {
+        state: State,
+        by: Option<Initiator>,
+    },
+    ChangeRate(f64),
+    /// Where to seek to as of `server_ms`, clients add the time the message was underway
+    ChangePosition {
+        position: f64,
+        server_ms: u64,
+    },
+    ChangePlaylist(usize),
+    Error {
+        reason: String,
+    },
+    Chat {
+        from: String,
+        text: String,
+    },
+    SkipVotes {
+        current: usize,
+        needed: usize,
+    },
+    History(Vec<session::HistoryEntry>),
+    Welcome {
+        id: u64,
+    },
+    SessionInfo {
+        generation: u64,
+        playlist_len: usize,
+    },
+    /// The session was created again since the page was loaded, this is its whole playlist
+    PlaylistReplaced {
+        generation: u64,
+        shitposts: Vec<Shitpost>,
+        /// The current entry, a field named `index` would clash with the index page
+        #[serde(rename = "index")]
+        current: usize,
+    },
+    /// The player is in the lobby until the session goes live at this unix time
+    SessionScheduled {
+        starts_at: u64,
+    },
+    /// The session went live, the lobby was sent its state just before
+    SessionStarted,
+    Kicked {
+        reason: String,
+    },
+    /// Sent before the connection is closed, since the session has no room
+    SessionFull {
+        players: usize,
+        max_players: usize,
+    },
+    ReadyStatus {
+        ready: usize,
+        total: usize,
+    },
+    Countdown {
+        seconds: u64,
+    },
+    /// The entry ended, `next_title` starts in `seconds`
+    Intermission {
+        next_title: Option<String>,
+        seconds: u64,
+    },
+    Viewers(Vec<session::ViewerInfo>),
+    ServerClosing {
+        reconnect_after_secs: u64,
+    },
+    HostToken {
+        token: String,
+    },
+    PlaylistUpdated {
+        added: Vec<Shitpost>,
+        removed: Option<usize>,
+        replaced_from: Option<usize>,
+        /// The current entry afterwards, a field named `index` would clash with the index page
+        #[serde(rename = "index")]
+        current: usize,
+    },
+    LoopModeChanged(LoopMode),
+    Reaction {
+        from: String,
+        emoji: String,
+    },
+    PlayerLeft {
+        name: String,
+        reason: DisconnectReason,
+    },
+    SessionClosed,
+    /// The session was gone by the time the player said hello
+    NoSuchSession,
+    /// The last entry ended and nothing loops, the session is idle
+    PlaylistFinished,
+    WaitForStragglersChanged(bool),
+    PermissionsChanged(Permissions),
+    /// Only sent to the player that tried, it is also sent the state to snap back to
+    PermissionDenied {
+        action: Action,
+    },
+    FolderMuted(Vec<String>),
+    TagsFiltered(Vec<String>),
+    WaitingFor {
+        name: String,
+    },
+    /// Round trip time of the last ping
+    Latency {
+        ms: u64,
+    },
+    /// Asks for a `TimeProbeReply`, the time is the server's clock in unix milliseconds
+    TimeProbe {
+        server_ms: u64,
+    },
+    /// How far the client's clock is ahead of the server's, so it can tell how old
+    /// the timestamps of the server are
+    ClockEstimate {
+        offset_ms: f64,
+        rtt_ms: f64,
+    },
+    EntrySkipped {
+        /// Named `entry` since a field named `index` would clash with the index page
+        #[serde(rename = "index")]
+        entry: usize,
+        reason: String,
+    },
+    Bookmarked {
+        by: String,
+        title: Option<String>,
+        position: f64,
+        note: String,
+    },
+    DebugChanged(bool),
+    DebugSync {
+        players: Vec<DebugPlayer>,
+    },
+}
+
+/// Where a player is as far as the server knows, for the debug overlay
+#[derive(Serialize, Clone, Debug)]
+pub struct DebugPlayer {
+    /// Already escaped
+    pub name: String,
+    /// Estimated from the last report, None before the first one
+    pub position: Option<f64>,
+    pub state: Option<State>,
+    /// Milliseconds since the last report
+    pub last_seen_ms: Option<u64>,
+    pub quality: Quality,
+}
+
+/// Which variant of the entries a player loads, entries without a smaller one are the same in both
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quality {
+    #[default]
+    High,
+    Low,
+}
+
+/// OvenPlayer state
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Idle,
+    Complete,
+    Paused,
+    Playing,
+    Error,
+    Loading,
+    Stalled,
+    AdLoaded,
+    AdPlaying,
+    AdPaused,
+    AdComplete,
+}
+
+/// The player behind a change, the name is already escaped
+#[derive(Serialize, Clone, Debug)]
+pub struct Initiator {
+    pub id: u64,
+    pub name: String,
+}
+
+/// Turns messages into the text of websocket frames and back,
+/// so the wire format is only dealt with here
+pub struct Protocol;
+
+/// Why a message from a client couldn't be read
+pub enum DecodeError {
+    /// A kind of message this server doesn't know, like from a newer client
+    Unknown(serde_json::Error),
+    Malformed(serde_json::Error),
+}
+
+impl Protocol {
+    pub fn encode(message: &BackendMessage) -> String {
+        // Only plain data with string keys is sent, which always serializes
+        serde_json::to_string(message).unwrap()
+    }
+
+    pub fn decode(text: &str) -> Result<PlayerMessage, DecodeError> {
+        serde_json::from_str(text).map_err(|why| {
+            if is_unknown_variant(&why) {
+                DecodeError::Unknown(why)
+            } else {
+                DecodeError::Malformed(why)
+            }
+        })
+    }
+}
+
+/// Whether parsing failed only because the message kind isn't known, like ones from newer clients
+fn is_unknown_variant(why: &serde_json::Error) -> bool {
+    why.is_data() && why.to_string().starts_with("unknown variant")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        player::{
+            protocol::{
+                BackendMessage, DecodeError, PlayerMessage, Protocol, ProtocolVersion, State,
+            },
+            Initiator,
+        },
+        session::DisconnectReason,
+    };
+
+    #[test]
+    fn unknown_messages() {
+        let decode = Protocol::decode;
+        assert!(matches!(
+            decode(r#"{"Teleport": 5}"#),
+            Err(DecodeError::Unknown(_))
+        ));
+        assert!(matches!(
+            decode(r#""Teleport""#),
+            Err(DecodeError::Unknown(_))
+        ));
+
+        assert!(matches!(
+            decode(r#"{"Position": "soon"}"#),
+            Err(DecodeError::Malformed(_))
+        ));
+        assert!(matches!(decode("{"), Err(DecodeError::Malformed(_))));
+
+        let hello = decode(r#"{"Hello": {"protocol_version": {"major": 1, "minor": 3}}}"#);
+        assert!(matches!(
+            hello,
+            Ok(PlayerMessage::Hello { protocol_version, capabilities, generation })
+                if protocol_version.major == 1 && capabilities.is_empty() && generation.is_none()
+        ));
+    }
+
+    #[test]
+    fn player_messages() {
+        assert!(matches!(
+            Protocol::decode(r#""Seeked""#),
+            Ok(PlayerMessage::Seeked)
+        ));
+        assert!(matches!(
+            Protocol::decode(r#"{"StateChanged":"playing"}"#),
+            Ok(PlayerMessage::StateChanged(State::Playing))
+        ));
+        assert!(matches!(
+            Protocol::decode(r#"{"Position":12.5}"#),
+            Ok(PlayerMessage::Position(position)) if position == 12.5
+        ));
+        assert!(matches!(
+            Protocol::decode(r#"{"Bookmark":{}}"#),
+            Ok(PlayerMessage::Bookmark { note }) if note.is_empty()
+        ));
+        assert!(matches!(
+            Protocol::decode(r#"{"TimeProbeReply":{"server_ms":1700000000000,"client_ms":1700000000250.5}}"#),
+            Ok(PlayerMessage::TimeProbeReply { server_ms, client_ms })
+                if server_ms == 1_700_000_000_000 && client_ms == 1_700_000_000_250.5
+        ));
+    }
+
+    /// Clients match on these exact strings, so they may only change with the protocol version
+    #[test]
+    fn backend_messages() {
+        let encoded = [
+            (BackendMessage::SyncPosition, r#""sync_position""#),
+            (BackendMessage::SessionClosed, r#""session_closed""#),
+            (BackendMessage::NoSuchSession, r#""no_such_session""#),
+            (BackendMessage::SessionStarted, r#""session_started""#),
+            (BackendMessage::PlaylistFinished, r#""playlist_finished""#),
+            (
+                BackendMessage::Hello {
+                    protocol_version: ProtocolVersion { major: 2, minor: 0 },
+                    features: &["chat"],
+                },
+                r#"{"hello":{"protocol_version":{"major":2,"minor":0},"features":["chat"]}}"#,
+            ),
+            (
+                BackendMessage::ChangeState {
+                    state: State::Playing,
+                    by: None,
+                },
+                r#"{"change_state":{"state":"playing","by":null}}"#,
+            ),
+            (
+                BackendMessage::ChangeState {
+                    state: State::Paused,
+                    by: Some(Initiator {
+                        id: 3,
+                        name: "bob".to_string(),
+                    }),
+                },
+                r#"{"change_state":{"state":"paused","by":{"id":3,"name":"bob"}}}"#,
+            ),
+            (BackendMessage::ChangeRate(1.5), r#"{"change_rate":1.5}"#),
+            (
+                BackendMessage::ChangePosition {
+                    position: 12.0,
+                    server_ms: 1_700_000_000_000,
+                },
+                r#"{"change_position":{"position":12.0,"server_ms":1700000000000}}"#,
+            ),
+            (
+                BackendMessage::ChangePlaylist(4),
+                r#"{"change_playlist":4}"#,
+            ),
+            (
+                BackendMessage::Error {
+                    reason: "nope".to_string(),
+                },
+                r#"{"error":{"reason":"nope"}}"#,
+            ),
+            (
+                BackendMessage::PlayerLeft {
+                    name: "bob".to_string(),
+                    reason: DisconnectReason::TimedOut,
+                },
+                r#"{"player_left":{"name":"bob","reason":"timed_out"}}"#,
+            ),
+            (
+                BackendMessage::Intermission {
+                    next_title: Some("next".to_string()),
+                    seconds: 60,
+                },
+                r#"{"intermission":{"next_title":"next","seconds":60}}"#,
+            ),
+            (
+                BackendMessage::Latency { ms: 42 },
+                r#"{"latency":{"ms":42}}"#,
+            ),
+            (
+                BackendMessage::SessionScheduled {
+                    starts_at: 1_700_000_000,
+                },
+                r#"{"session_scheduled":{"starts_at":1700000000}}"#,
+            ),
+            (
+                BackendMessage::TimeProbe {
+                    server_ms: 1_700_000_000_000,
+                },
+                r#"{"time_probe":{"server_ms":1700000000000}}"#,
+            ),
+            (
+                BackendMessage::ClockEstimate {
+                    offset_ms: -250.0,
+                    rtt_ms: 80.5,
+                },
+                r#"{"clock_estimate":{"offset_ms":-250.0,"rtt_ms":80.5}}"#,
+            ),
+        ];
+
+        for (message, expected) in encoded {
+            assert_eq!(Protocol::encode(&message), expected);
+        }
+    }
+}
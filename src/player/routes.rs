@@ -0,0 +1,2483 @@
+//! The pages for hosting and joining sessions, and the files they play
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    path::{Component, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+
+use actix::{Addr, MailboxError};
+use actix_files::NamedFile;
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    cookie::{Cookie, SameSite},
+    error::{ErrorNotFound, PayloadError},
+    get,
+    http::{header, StatusCode},
+    post,
+    web::{self, Bytes, Data, Form, Path, Payload, Query},
+    Either, HttpRequest, HttpResponse, ResponseError, Result,
+};
+use actix_web_actors::ws;
+use futures_util::{stream, StreamExt};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{
+    de::{Error as _, IgnoredAny, Visitor},
+    Deserialize, Serialize,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    auth,
+    blocklist::Blocklist,
+    media::MediaIndex,
+    player::{
+        poll::{Outbox, PollConnections},
+        protocol::{ProtocolVersion, PROTOCOL_VERSION},
+        ws::{ClientMessage, Heartbeat, PlayerActor, Transport},
+    },
+    rate_limit::RateLimiter,
+    recent::RecentlyPlayed,
+    session::{
+        self, InvalidSessionCode, PasswordHash, Permissions, ScheduleError, SessionCode,
+        SessionManager, SessionView, MAX_SCHEDULE_AHEAD,
+    },
+    strings::Strings,
+    BrowserCompat, Config, Html, SharedConfig, Shitpost,
+};
+
+mod templates {
+    use actix_web::{http::StatusCode, HttpResponse};
+    use askama::Template;
+
+    use crate::{media::FolderStats, session::PublicSession, strings::Strings, Shitpost};
+
+    #[derive(Template)]
+    #[template(path = "player.html")]
+    pub struct Player<'a> {
+        pub shitposts: &'a [Shitpost],
+        pub session: &'a str,
+        pub name: &'a str,
+        /// Passed on to the socket, empty for players that aren't hosts
+        pub host: &'a str,
+        /// Length of the whole playlist, of which shitposts may only be the first page
+        pub total: usize,
+        pub reactions: &'a [&'a str],
+        /// Hides the controls, spectators can't use them
+        pub spectator: bool,
+        /// Length the playlist was shortened to, since there weren't enough files
+        pub shortened: Option<usize>,
+        pub protocol_version: super::ProtocolVersion,
+        /// Sent back in the hello, so reconnects notice the session was created again
+        pub generation: u64,
+        /// Shown to hosts so the playlist can be rolled again, None if it wasn't rolled
+        pub seed: Option<u64>,
+    }
+
+    /// Shown instead of the player until a scheduled session goes live
+    #[derive(Template)]
+    #[template(path = "waiting.html")]
+    pub struct Waiting<'a> {
+        pub session: &'a str,
+        pub name: &'a str,
+        pub host: &'a str,
+        /// Shows the controls for moving the start or calling the session off
+        pub is_host: bool,
+        pub spectator: bool,
+        /// Unix time the session goes live at
+        pub starts_at: u64,
+        pub protocol_version: super::ProtocolVersion,
+    }
+
+    #[derive(Template)]
+    #[template(path = "host.html")]
+    pub struct Host<'a> {
+        pub folders: &'a [FolderInfo<'a>],
+        pub session: &'a str,
+        /// Limit of the config, hosts can only go lower
+        pub max_players: Option<usize>,
+    }
+
+    /// A folder or remote source as offered on the host page
+    pub struct FolderInfo<'a> {
+        pub name: &'a str,
+        /// Playable files in it, None if it couldn't be read
+        pub stats: Option<FolderStats>,
+        pub tags: &'a [String],
+    }
+
+    #[derive(Template)]
+    #[template(path = "join.html")]
+    pub struct Join<'a> {
+        pub strings: &'a Strings,
+    }
+
+    #[derive(Template)]
+    #[template(path = "password.html")]
+    pub struct Password<'a> {
+        pub session: &'a str,
+        pub name: &'a str,
+        pub host: &'a str,
+        pub spectator: bool,
+        /// Shown after a wrong password was entered
+        pub error: Option<&'a str>,
+        pub strings: &'a Strings,
+    }
+
+    #[derive(Template)]
+    #[template(path = "index.html")]
+    pub struct Index<'a> {
+        pub strings: &'a Strings,
+        pub sessions: &'a [PublicSession],
+        /// Shown above the form, like when the typed in code matched no session
+        pub error: Option<&'a str>,
+        /// What was typed into the form, kept when it is shown again
+        pub session: &'a str,
+        pub name: &'a str,
+    }
+
+    #[derive(Template)]
+    #[template(path = "error.html")]
+    pub struct Error<'a> {
+        pub text: &'a str,
+    }
+
+    impl Error<'_> {
+        /// The error page, or just the text if even that fails to render
+        pub fn respond(&self, status: StatusCode) -> HttpResponse {
+            match self.render() {
+                Ok(page) => HttpResponse::build(status)
+                    .content_type("text/html; charset=utf-8")
+                    .body(page),
+                Err(why) => {
+                    tracing::error!("Failed to render the error page: {}", why);
+                    HttpResponse::build(status)
+                        .content_type("text/plain; charset=utf-8")
+                        .body(self.text.to_string())
+                }
+            }
+        }
+    }
+}
+
+/// Errors shown to the user as an error page with a fitting status code
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    Unauthorized(String),
+    Forbidden(String),
+    TooManyRequests(String),
+    Conflict(String),
+    BadRequest(String),
+    Internal(String),
+    /// A page failed to render, shown as plain text since the error page might fail too
+    Render,
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(text)
+            | Self::Unauthorized(text)
+            | Self::Forbidden(text)
+            | Self::TooManyRequests(text)
+            | Self::Conflict(text)
+            | Self::BadRequest(text)
+            | Self::Internal(text) => f.write_str(text),
+            Self::Render => f.write_str("Failed to render page"),
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Internal(_) | Self::Render => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let text = self.to_string();
+
+        match self {
+            Self::Render => HttpResponse::build(self.status_code())
+                .content_type("text/plain; charset=utf-8")
+                .body(text),
+            _ => templates::Error { text: &text }.respond(self.status_code()),
+        }
+    }
+}
+
+impl From<MailboxError> for AppError {
+    fn from(why: MailboxError) -> Self {
+        tracing::error!("Session manager unavailable: {}", why);
+        Self::Internal("Session manager unavailable".to_string())
+    }
+}
+
+impl From<InvalidSessionCode> for AppError {
+    fn from(why: InvalidSessionCode) -> Self {
+        Self::BadRequest(why.to_string())
+    }
+}
+
+impl From<actix_web::Error> for AppError {
+    fn from(why: actix_web::Error) -> Self {
+        Self::BadRequest(why.to_string())
+    }
+}
+
+/// Longest a poll waits for messages, below the timeouts of common proxies
+const POLL_WAIT: Duration = Duration::from_secs(25);
+
+/// The form submitted from the host page
+struct SessionConfig {
+    amount: usize,
+    session: SessionCode,
+    folders: Vec<FolderSelection>,
+    /// Empty passwords leave the session open
+    password: Option<String>,
+    /// What players other than hosts may do
+    permissions: Permissions,
+    /// Files picked from a preview, in order. A new playlist is rolled when empty.
+    urls: Vec<String>,
+    /// URLs of files left out of the roll, like inside jokes that don't land with everyone
+    exclude: HashSet<String>,
+    /// Lowers the player limit of the config for this session, empty keeps it
+    max_players: Option<usize>,
+    /// Lists the session on the index page
+    public: bool,
+    /// Also rolls files only some browsers play
+    maybe_unsupported: bool,
+    /// Seconds between entries, empty or 0 moves on right away
+    intermission_secs: Option<u64>,
+    /// Rolls the same playlist again from the same files, empty picks a new seed
+    seed: Option<u64>,
+    /// RFC 3339, players wait in a lobby until then. Empty starts the session right away.
+    starts_at: Option<SystemTime>,
+    /// An exported playlist, only read by `host_import`
+    playlist: Option<String>,
+    /// Host token of the running session, only read by `host_reroll`
+    host: Option<String>,
+}
+
+impl SessionConfig {
+    /// The left out files in a stable order, for storing with the session
+    fn excluded(&self) -> Vec<String> {
+        let mut excluded = self.exclude.iter().cloned().collect::<Vec<_>>();
+        excluded.sort_unstable();
+        excluded
+    }
+}
+
+/// A folder picked for the roulette and how heavily it is represented
+struct FolderSelection {
+    name: String,
+    weight: u32,
+}
+
+impl<'de> Deserialize<'de> for SessionConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct FieldVisitor;
+
+        impl<'de> Visitor<'de> for FieldVisitor {
+            type Value = SessionConfig;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("amount, session and folders")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut amount = None;
+                let mut session = None;
+                let mut folders = Vec::new();
+                let mut weights = HashMap::new();
+                let mut password = None;
+                // Checkboxes are only sent when checked
+                let mut permissions = Permissions::HOST_ONLY;
+                let mut urls = Vec::new();
+                let mut exclude = HashSet::new();
+                let mut max_players = None;
+                let mut public = false;
+                let mut maybe_unsupported = false;
+                let mut intermission_secs = None;
+                let mut seed = None;
+                let mut starts_at = None;
+                let mut playlist = None;
+                let mut host_token = None;
+
+                // Form data repeats the folders and exclude keys for every checked box,
+                // weights are sent as "weight.<folder>"
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "amount" => amount = Some(map.next_value::<usize>()?),
+                        "session" => session = Some(map.next_value::<SessionCode>()?),
+                        "folders" => folders.push(map.next_value::<String>()?),
+                        "urls" => urls.push(map.next_value::<String>()?),
+                        "exclude" => {
+                            exclude.insert(map.next_value::<String>()?);
+                        }
+                        "max_players" => {
+                            let value = map.next_value::<String>()?;
+                            if !value.is_empty() {
+                                max_players =
+                                    Some(value.parse::<usize>().map_err(A::Error::custom)?);
+                            }
+                        }
+                        "intermission_secs" => {
+                            let value = map.next_value::<String>()?;
+                            if !value.is_empty() {
+                                intermission_secs =
+                                    Some(value.parse::<u64>().map_err(A::Error::custom)?);
+                            }
+                        }
+                        "seed" => {
+                            let value = map.next_value::<String>()?;
+                            if !value.trim().is_empty() {
+                                seed = Some(value.trim().parse::<u64>().map_err(A::Error::custom)?);
+                            }
+                        }
+                        "starts_at" => {
+                            let value = map.next_value::<String>()?;
+                            if !value.trim().is_empty() {
+                                starts_at = Some(parse_start(&value).map_err(A::Error::custom)?);
+                            }
+                        }
+                        "allow_pause" => {
+                            map.next_value::<IgnoredAny>()?;
+                            permissions.allow_pause = true;
+                        }
+                        "allow_seek" => {
+                            map.next_value::<IgnoredAny>()?;
+                            permissions.allow_seek = true;
+                        }
+                        "allow_playlist" => {
+                            map.next_value::<IgnoredAny>()?;
+                            permissions.allow_playlist = true;
+                        }
+                        "public" => {
+                            map.next_value::<IgnoredAny>()?;
+                            public = true;
+                        }
+                        "maybe_unsupported" => {
+                            map.next_value::<IgnoredAny>()?;
+                            maybe_unsupported = true;
+                        }
+                        "password" => {
+                            password = Some(map.next_value::<String>()?)
+                                .filter(|password| !password.is_empty())
+                        }
+                        "playlist" => {
+                            playlist = Some(map.next_value::<String>()?)
+                                .filter(|playlist| !playlist.is_empty())
+                        }
+                        "host" => {
+                            host_token =
+                                Some(map.next_value::<String>()?).filter(|token| !token.is_empty())
+                        }
+                        _ => match key.strip_prefix("weight.") {
+                            Some(folder) => {
+                                weights.insert(folder.to_string(), map.next_value::<u32>()?);
+                            }
+                            None => {
+                                map.next_value::<IgnoredAny>()?;
+                            }
+                        },
+                    }
+                }
+
+                Ok(SessionConfig {
+                    amount: amount.ok_or_else(|| A::Error::missing_field("amount"))?,
+                    session: session.ok_or_else(|| A::Error::missing_field("session"))?,
+                    folders: folders
+                        .into_iter()
+                        .map(|name| FolderSelection {
+                            weight: weights.get(&name).copied().unwrap_or(1),
+                            name,
+                        })
+                        .collect(),
+                    password,
+                    permissions,
+                    urls,
+                    exclude,
+                    max_players,
+                    public,
+                    maybe_unsupported,
+                    intermission_secs,
+                    seed,
+                    starts_at,
+                    playlist,
+                    host: host_token,
+                })
+            }
+        }
+        deserializer.deserialize_map(FieldVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+struct SessionQuery {
+    session: SessionCode,
+    /// Nickname shown to the other players
+    name: Option<String>,
+    /// Random token the browser keeps between visits, used for bans
+    client: Option<String>,
+    /// Makes the player a host of the session
+    host: Option<String>,
+    /// Only watches the session, like a projector
+    #[serde(default)]
+    spectator: bool,
+    /// Set by the host form when fewer entries were available than requested
+    shortened: Option<usize>,
+}
+
+impl SessionQuery {
+    const MAX_NAME_LENGTH: usize = 24;
+
+    /// The trimmed and shortened nickname, None if no usable one was given
+    fn name(&self) -> Option<String> {
+        match &self.name {
+            Some(name) if !name.trim().is_empty() => {
+                Some(name.trim().chars().take(Self::MAX_NAME_LENGTH).collect())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The password page, posted so the password doesn't end up in addresses and logs
+#[derive(Deserialize)]
+struct UnlockForm {
+    session: SessionCode,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    host: String,
+    #[serde(default)]
+    spectator: bool,
+    #[serde(default)]
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct TicketQuery {
+    ticket: Option<String>,
+}
+
+/// The cookie the ticket of a session with a password is kept in
+fn ticket_cookie(session: &SessionCode) -> String {
+    format!("ticket_{}", session)
+}
+
+/// The ticket a request came with, from its cookie or a `ticket` parameter for clients that
+/// don't keep cookies, like the browser sources of stream overlays
+pub fn request_ticket(req: &HttpRequest, session: &SessionCode) -> Option<String> {
+    req.cookie(&ticket_cookie(session))
+        .map(|cookie| cookie.value().to_string())
+        .or_else(|| {
+            Query::<TicketQuery>::from_query(req.query_string())
+                .ok()?
+                .into_inner()
+                .ticket
+        })
+        .filter(|ticket| !ticket.is_empty())
+}
+
+/// Lets the browser into the session from now on without asking for the password again
+fn with_ticket(response: &mut HttpResponse, session: &SessionCode, ticket: String) {
+    let cookie = Cookie::build(ticket_cookie(session), ticket)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish();
+    // Only fails for invalid header values, which a name of a session code and hex can't make
+    let _ = response.add_cookie(&cookie);
+}
+
+/// A code typed into the index page, which might be off in case or have stray spaces
+#[derive(Deserialize)]
+struct JoinForm {
+    session: String,
+    #[serde(default)]
+    name: String,
+}
+
+/// The session code is generated by the backend if the host didn't pick one
+#[derive(Deserialize)]
+struct HostQuery {
+    session: Option<String>,
+}
+
+#[get("/player/socket")]
+pub async fn socket(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    session: Query<SessionQuery>,
+    req: HttpRequest,
+    payload: Payload,
+) -> Result<HttpResponse, AppError> {
+    // Typos fail here, where the page can still find out why
+    if !check_session(&manager, &session, &req).await? {
+        return Ok(no_such_session());
+    }
+
+    Ok(ws::start(
+        player_actor(&manager, &config, &session, &req, Transport::Socket),
+        &req,
+        payload,
+    )?)
+}
+
+/// Opens a connection for a client whose network blocks websockets, answered with its token.
+/// The client then sends with `poll_send` and receives with `poll_receive`, saying hello first as usual.
+#[post("/player/connect")]
+pub async fn poll_connect(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    connections: Data<PollConnections>,
+    session: Query<SessionQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    if !check_session(&manager, &session, &req).await? {
+        return Ok(no_such_session());
+    }
+
+    let outbox = Arc::new(Outbox::default());
+    let player = player_actor(
+        &manager,
+        &config,
+        &session,
+        &req,
+        Transport::Poll(outbox.clone()),
+    );
+    // Nothing comes in as frames, and what the actor sends is already in the outbox
+    let (player, frames) = ws::WebsocketContext::create_with_addr(
+        player,
+        stream::pending::<Result<Bytes, PayloadError>>(),
+    );
+    actix_web::rt::spawn(frames.for_each(|_| async {}));
+
+    let token = connections.open(player, outbox);
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "connection": token })))
+}
+
+#[derive(Deserialize)]
+struct PollQuery {
+    connection: String,
+    /// The last message the client has, messages after it are answered with
+    #[serde(default)]
+    since: u64,
+}
+
+/// Waits for messages to a polling connection, answering right away if there already are some.
+/// A client that fell so far behind that messages were dropped is told with "gap",
+/// and has to connect again to be in sync.
+#[get("/player/poll")]
+pub async fn poll_receive(
+    connections: Data<PollConnections>,
+    query: Query<PollQuery>,
+) -> HttpResponse {
+    let Some((_, outbox)) = connections.get(&query.connection) else {
+        return HttpResponse::NotFound().json(serde_json::json!({ "error": "No such connection" }));
+    };
+
+    let _waiting = outbox.wait();
+    let polled = outbox
+        .wait_since(query.since, Instant::now() + POLL_WAIT)
+        .await;
+
+    if polled.closed.is_some() {
+        connections.remove(&query.connection);
+    }
+    HttpResponse::Ok()
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoStore]))
+        .content_type(mime::APPLICATION_JSON)
+        .body(polled.to_json())
+}
+
+/// Passes a message from a polling client on like a text frame of the websocket
+#[post("/player/send")]
+pub async fn poll_send(
+    connections: Data<PollConnections>,
+    query: Query<PollQuery>,
+    text: String,
+) -> HttpResponse {
+    match connections.get(&query.connection) {
+        Some((player, _)) if player.connected() => {
+            player.do_send(ClientMessage { text });
+            HttpResponse::NoContent().finish()
+        }
+        _ => HttpResponse::NotFound().json(serde_json::json!({ "error": "No such connection" })),
+    }
+}
+
+/// Whether the session exists, failing if it has a password that wasn't entered.
+/// The session can still be removed before the player says hello, which `PlayerConnect` handles.
+async fn check_session(
+    manager: &Addr<SessionManager>,
+    session: &SessionQuery,
+    req: &HttpRequest,
+) -> Result<bool, AppError> {
+    match manager
+        .send(session::CheckTicket {
+            session: session.session.clone(),
+            ticket: request_ticket(req, &session.session),
+        })
+        .await?
+    {
+        Some(true) => Ok(true),
+        Some(false) => Err(AppError::Forbidden("Wrong password".to_string())),
+        None => Ok(false),
+    }
+}
+
+/// Answered before upgrading, a socket that fails to open gives the page no reason
+fn no_such_session() -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({ "error": "no_such_session" }))
+}
+
+fn player_actor(
+    manager: &Addr<SessionManager>,
+    config: &SharedConfig,
+    session: &SessionQuery,
+    req: &HttpRequest,
+    transport: Transport,
+) -> PlayerActor {
+    let config = config.get();
+
+    PlayerActor::new(
+        manager.clone(),
+        session.session.clone(),
+        session.name().map(Into::into),
+        session.client.clone().map(Into::into),
+        auth::client_ip(req, &config.trusted_proxies),
+        session.host.clone().filter(|token| !token.is_empty()),
+        session.spectator,
+        Heartbeat {
+            interval: Duration::from_secs(config.heartbeat_interval),
+            timeout: Duration::from_secs(config.client_timeout),
+            background_timeout: Duration::from_secs(config.background_timeout),
+        },
+        transport,
+    )
+}
+
+#[get("/join")]
+pub async fn join(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    query: Query<SessionQuery>,
+    strings: Strings,
+    req: HttpRequest,
+) -> Result<Html, AppError> {
+    let session = manager
+        .send(session::GetSession {
+            session: query.session.clone(),
+        })
+        .await?
+        .ok_or_else(|| AppError::NotFound(strings.get("error.no_such_session").to_string()))?;
+
+    if !session.check_ticket(request_ticket(&req, &query.session).as_deref()) {
+        return Html::render(templates::Password {
+            session: query.session.as_str(),
+            name: &query.name().unwrap_or_default(),
+            host: query.host.as_deref().unwrap_or_default(),
+            spectator: query.spectator,
+            error: None,
+            strings: &strings,
+        });
+    }
+
+    join_page(&session, &config.get(), &query, &strings)
+}
+
+/// Checks the password of the password page and joins like `join`,
+/// leaving the browser with the ticket so reconnects get in without it
+#[post("/join/unlock")]
+pub async fn join_unlock(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    form: Form<UnlockForm>,
+    strings: Strings,
+) -> Result<HttpResponse, AppError> {
+    let form = form.into_inner();
+    let session = manager
+        .send(session::GetSession {
+            session: form.session.clone(),
+        })
+        .await?
+        .ok_or_else(|| AppError::NotFound(strings.get("error.no_such_session").to_string()))?;
+
+    if !session.check_password(&form.password) {
+        let page = Html::render(templates::Password {
+            session: form.session.as_str(),
+            name: &form.name,
+            host: &form.host,
+            spectator: form.spectator,
+            error: Some(strings.get("error.wrong_password")),
+            strings: &strings,
+        })?;
+        // htmx only swaps in successful responses, so the page can ask again
+        return Ok(HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(page.0));
+    }
+
+    let query = SessionQuery {
+        session: form.session,
+        name: Some(form.name),
+        client: None,
+        host: Some(form.host).filter(|token| !token.is_empty()),
+        spectator: form.spectator,
+        shortened: None,
+    };
+    let page = join_page(&session, &config.get(), &query, &strings)?;
+    let mut response = HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(page.0);
+    if let Some(ticket) = session.ticket() {
+        with_ticket(&mut response, &query.session, ticket);
+    }
+    Ok(response)
+}
+
+/// The player page, or the lobby of a session that hasn't started yet
+fn join_page(
+    session: &SessionView,
+    config: &Config,
+    query: &SessionQuery,
+    strings: &Strings,
+) -> Result<Html, AppError> {
+    let is_host = query
+        .host
+        .as_deref()
+        .is_some_and(|token| session.is_host_token(token));
+    if let Some(max_players) = session
+        .max_players()
+        .filter(|_| session.is_full() && !is_host)
+    {
+        return Err(AppError::TooManyRequests(strings.format(
+            "error.session_full",
+            format!("{}/{}", session.live_players(), max_players),
+        )));
+    }
+
+    if let Some(starts_at) = session.starts_at() {
+        return Html::render(templates::Waiting {
+            session: query.session.as_str(),
+            name: &query.name().unwrap_or_default(),
+            host: query.host.as_deref().unwrap_or_default(),
+            is_host,
+            spectator: query.spectator,
+            starts_at: session::unix_secs(starts_at),
+            protocol_version: PROTOCOL_VERSION,
+        });
+    }
+
+    let page_size = config.playlist_page_size;
+
+    Html::render(templates::Player {
+        shitposts: &session.shitposts[..session.shitposts.len().min(page_size)],
+        session: query.session.as_str(),
+        name: &query.name().unwrap_or_default(),
+        host: query.host.as_deref().unwrap_or_default(),
+        total: session.shitposts.len(),
+        reactions: session::REACTIONS,
+        spectator: query.spectator,
+        shortened: query.shortened,
+        protocol_version: PROTOCOL_VERSION,
+        generation: session.generation,
+        seed: session.seed,
+    })
+}
+
+/// Sends the player on to the session the code means,
+/// or shows the index again if there is none
+#[post("/join")]
+pub async fn join_code(
+    manager: Data<Addr<SessionManager>>,
+    form: Form<JoinForm>,
+    strings: Strings,
+) -> Result<Either<Html, HttpResponse>, AppError> {
+    let found = manager
+        .send(session::FindSession {
+            code: form.session.clone(),
+        })
+        .await?;
+
+    let Some(session) = found else {
+        let sessions = manager.send(session::ListPublicSessions).await?;
+        return Html::render(templates::Index {
+            strings: &strings,
+            sessions: &sessions,
+            error: Some(strings.get("error.no_such_session")),
+            session: form.session.trim(),
+            name: &form.name,
+        })
+        .map(Either::Left);
+    };
+
+    let mut location = format!(
+        "/join?session={}&name={}",
+        session,
+        crate::percent_encode(&form.name)
+    );
+    if !strings.lang().is_empty() {
+        location.push_str(&format!("&lang={}", crate::percent_encode(strings.lang())));
+    }
+
+    Ok(Either::Right(
+        HttpResponse::SeeOther()
+            .insert_header((header::LOCATION, location))
+            .finish(),
+    ))
+}
+
+#[get("/host")]
+pub async fn host(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    media: Data<MediaIndex>,
+    query: Query<HostQuery>,
+) -> Result<Html, AppError> {
+    let config = config.get();
+    let session = match query.into_inner().session {
+        Some(session) if !session.is_empty() => SessionCode::try_from(session)?,
+        _ => manager.send(session::CreateSession).await?,
+    };
+
+    let folders = config
+        .source_names()
+        .map(|name| templates::FolderInfo {
+            name,
+            stats: media.stats(name),
+            tags: config.source_tags(name),
+        })
+        .collect::<Vec<_>>();
+
+    Html::render(templates::Host {
+        folders: &folders,
+        session: session.as_str(),
+        max_players: config.max_players,
+    })
+}
+
+/// Players a new session may have, hosts can only lower the limit of the config
+fn session_capacity(requested: Option<usize>, limit: Option<usize>) -> Option<usize> {
+    match (requested.filter(|requested| *requested > 0), limit) {
+        (Some(requested), Some(limit)) => Some(requested.min(limit)),
+        (requested, limit) => requested.or(limit),
+    }
+}
+
+/// How many entries a playlist is created with, shortened to the files available
+fn playlist_length(
+    requested: usize,
+    available: usize,
+    max_amount: usize,
+    strings: &Strings,
+) -> Result<usize, AppError> {
+    if requested == 0 {
+        return Err(AppError::BadRequest(
+            strings.get("error.empty_playlist").to_string(),
+        ));
+    }
+    if requested > max_amount {
+        return Err(AppError::BadRequest(
+            strings.format("error.playlist_too_long", max_amount),
+        ));
+    }
+
+    Ok(requested.min(available))
+}
+
+/// Picks entries from the pools proportionally to their weights without repeating any.
+/// Pools that run out simply stop being picked from.
+fn weighted_sample<R: Rng>(
+    mut pools: Vec<(Vec<Shitpost>, u32)>,
+    amount: usize,
+    rng: &mut R,
+) -> Vec<Shitpost> {
+    for (pool, _) in &mut pools {
+        pool.shuffle(rng);
+    }
+
+    let mut shitposts = Vec::new();
+
+    while shitposts.len() < amount {
+        let available = (0..pools.len())
+            .filter(|pool| !pools[*pool].0.is_empty())
+            .collect::<Vec<_>>();
+
+        let Ok(chosen) = available.choose_weighted(rng, |pool| pools[*pool].1) else {
+            break;
+        };
+        shitposts.push(pools[*chosen].0.pop().unwrap());
+    }
+
+    shitposts
+}
+
+/// Leaves out blocked files and, if there are enough others, recently played ones,
+/// then samples the playlist from what is left. Empty if nothing is left at all.
+/// The same seed rolls the same playlist as long as the files are the same.
+/// Goes through every file of the pools, so it is run on a blocking thread.
+fn build_playlist(
+    pools: Vec<(Arc<Vec<Shitpost>>, u32)>,
+    requested: usize,
+    max_amount: usize,
+    recently_played: &HashSet<String>,
+    blocklist: &Blocklist,
+    seed: u64,
+    strings: &Strings,
+) -> Result<Vec<Shitpost>, AppError> {
+    // Files reported as broken are left out like they weren't there
+    let pools = pools
+        .iter()
+        .map(|(shitposts, weight)| {
+            let playable = shitposts
+                .iter()
+                .filter(|shitpost| !blocklist.contains(&shitpost.url))
+                .cloned()
+                .collect::<Vec<_>>();
+            (playable, *weight)
+        })
+        .collect::<Vec<_>>();
+
+    // Pools without weight are never picked from
+    let available = pools
+        .iter()
+        .filter(|(_, weight)| *weight > 0)
+        .map(|(shitposts, _)| shitposts.len())
+        .sum::<usize>();
+    if available == 0 {
+        return Ok(Vec::new());
+    }
+    let amount = playlist_length(requested, available, max_amount, strings)?;
+
+    let fresh_pools = pools
+        .iter()
+        .map(|(shitposts, weight)| {
+            let fresh = shitposts
+                .iter()
+                .filter(|shitpost| !recently_played.contains(&shitpost.url))
+                .cloned()
+                .collect::<Vec<_>>();
+            (fresh, *weight)
+        })
+        .collect::<Vec<_>>();
+    let fresh = fresh_pools
+        .iter()
+        .map(|(shitposts, _)| shitposts.len())
+        .sum::<usize>();
+
+    // Repeats are better than a short playlist
+    let pools = if fresh >= amount {
+        fresh_pools
+    } else {
+        tracing::info!(
+            "Only {} files weren't played recently, {} requested, including recently played ones",
+            fresh,
+            amount
+        );
+        pools
+    };
+
+    let shitposts = weighted_sample(pools, amount, &mut StdRng::seed_from_u64(seed));
+    Ok(without_copies(shitposts))
+}
+
+/// Keeps the first of entries with the same content, like a clip saved in two folders.
+/// Entries without a fingerprint are only the same if they are the same file.
+fn without_copies(mut shitposts: Vec<Shitpost>) -> Vec<Shitpost> {
+    let mut seen = HashSet::new();
+    shitposts.retain(|shitpost| match shitpost.fingerprint {
+        Some(fingerprint) => seen.insert(fingerprint),
+        None => true,
+    });
+    shitposts
+}
+
+/// Looks up files picked from a preview, keeping their order. Only files `build_playlist`
+/// could have rolled from the pools are accepted, so no other paths can be smuggled in.
+fn pick_playlist(
+    pools: Vec<(Arc<Vec<Shitpost>>, u32)>,
+    urls: &[String],
+    blocklist: &Blocklist,
+    strings: &Strings,
+) -> Result<Vec<Shitpost>, AppError> {
+    let playable = pools
+        .iter()
+        .filter(|(_, weight)| *weight > 0)
+        .flat_map(|(shitposts, _)| shitposts.iter())
+        .filter(|shitpost| !blocklist.contains(&shitpost.url))
+        .map(|shitpost| (shitpost.url.as_str(), shitpost))
+        .collect::<HashMap<_, _>>();
+
+    let mut picked = HashSet::new();
+    urls.iter()
+        .map(|url| match playable.get(url.as_str()) {
+            Some(shitpost) if picked.insert(url.as_str()) => Ok((*shitpost).clone()),
+            Some(_) => Err(AppError::BadRequest(
+                strings.format("error.picked_twice", url),
+            )),
+            None => Err(AppError::BadRequest(
+                strings.format("error.not_pickable", url),
+            )),
+        })
+        .collect()
+}
+
+#[post("/host/submit")]
+#[allow(clippy::too_many_arguments)]
+pub async fn host_submit(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    media: Data<MediaIndex>,
+    recent: Data<RecentlyPlayed>,
+    blocklist: Data<Blocklist>,
+    limiter: Data<RateLimiter<IpAddr>>,
+    session: Form<SessionConfig>,
+    strings: Strings,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let config = config.get();
+    let session = session.into_inner();
+
+    // Checked before anything is allocated, the files available are only known later
+    playlist_length(session.amount, usize::MAX, config.max_amount, &strings)?;
+    let (pools, readable) = selected_pools(&config, &media, &session, &strings)?;
+    let max_amount = config.max_amount;
+    if !session.urls.is_empty() {
+        playlist_length(session.urls.len(), usize::MAX, max_amount, &strings)?;
+    }
+    start_time(session.starts_at, &strings)?;
+    if session_exists(&manager, &session.session).await? {
+        return Err(AppError::Conflict(
+            strings.get("error.session_exists").to_string(),
+        ));
+    }
+    // Only charged once the form is known to be fine, so fixing a typo doesn't count
+    rate_limit(&limiter, &req, &config, &strings)?;
+
+    let blocklist = blocklist.into_inner();
+    let (requested, shitposts, seed) = if session.urls.is_empty() {
+        let seed = session.seed.unwrap_or_else(new_seed);
+        let shitposts = roll_playlist(
+            pools,
+            &readable,
+            session.amount,
+            max_amount,
+            fresh_only(&session, recent.into_inner()),
+            blocklist,
+            seed,
+            &strings,
+        )
+        .await?;
+        (session.amount, shitposts, Some(seed))
+    } else {
+        let urls = session.urls.clone();
+        let picking = strings.clone();
+        let shitposts = web::block(move || pick_playlist(pools, &urls, &blocklist, &picking))
+            .await
+            .map_err(|why| AppError::Internal(why.to_string()))??;
+        (shitposts.len(), shitposts, None)
+    };
+
+    start_session(
+        &manager, &config, &session, shitposts, requested, seed, &strings,
+    )
+    .await
+}
+
+/// Rolls a new playlist for a running session and moves everyone over to it in place.
+/// Takes the host form, with the host token of the session as "host".
+#[post("/host/reroll")]
+pub async fn host_reroll(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    media: Data<MediaIndex>,
+    recent: Data<RecentlyPlayed>,
+    blocklist: Data<Blocklist>,
+    session: Form<SessionConfig>,
+    strings: Strings,
+) -> Result<HttpResponse, AppError> {
+    let config = config.get();
+    let mut session = session.into_inner();
+    let host_token = session.host.clone().unwrap_or_default();
+
+    // Checked before rolling, so only hosts can have the server do it
+    let current = manager
+        .send(session::GetSession {
+            session: session.session.clone(),
+        })
+        .await?
+        .ok_or_else(|| AppError::NotFound(strings.get("error.no_such_session").to_string()))?;
+    if !current.is_host_token(&host_token) {
+        return Err(AppError::Forbidden(
+            strings.get("error.not_host").to_string(),
+        ));
+    }
+    // The player page doesn't know what was left out when the session was started
+    session.exclude.extend(current.excluded.iter().cloned());
+
+    playlist_length(session.amount, usize::MAX, config.max_amount, &strings)?;
+    let (pools, readable) = selected_pools(&config, &media, &session, &strings)?;
+    let seed = session.seed.unwrap_or_else(new_seed);
+    let shitposts = roll_playlist(
+        pools,
+        &readable,
+        session.amount,
+        config.max_amount,
+        fresh_only(&session, recent.into_inner()),
+        blocklist.into_inner(),
+        seed,
+        &strings,
+    )
+    .await?;
+
+    match manager
+        .send(session::ReplacePlaylist {
+            session: session.session,
+            host_token,
+            shitposts,
+            seed: Some(seed),
+        })
+        .await?
+    {
+        Some(true) => Ok(HttpResponse::NoContent().finish()),
+        Some(false) => Err(AppError::Forbidden(
+            strings.get("error.not_host").to_string(),
+        )),
+        // Closed while the playlist was rolled
+        None => Err(AppError::NotFound(
+            strings.get("error.no_such_session").to_string(),
+        )),
+    }
+}
+
+/// The host controls of the waiting page
+#[derive(Deserialize)]
+struct ScheduleForm {
+    session: SessionCode,
+    host: String,
+    /// RFC 3339, empty starts the session right away
+    #[serde(default)]
+    starts_at: String,
+}
+
+/// Moves the start of a scheduled session, the lobby is told the new time
+#[post("/host/schedule")]
+pub async fn host_schedule(
+    manager: Data<Addr<SessionManager>>,
+    form: Form<ScheduleForm>,
+    strings: Strings,
+) -> Result<HttpResponse, AppError> {
+    let form = form.into_inner();
+    let starts_at = match form.starts_at.trim() {
+        "" => None,
+        value => Some(
+            parse_start(value)
+                .map_err(|why| AppError::BadRequest(strings.format("error.invalid_start", why)))?,
+        ),
+    };
+
+    let result = manager
+        .send(session::Reschedule {
+            session: form.session,
+            host_token: form.host,
+            starts_at: start_time(starts_at, &strings)?,
+        })
+        .await?;
+    schedule_response(result, &strings)
+}
+
+/// Calls off a scheduled session before it starts
+#[post("/host/cancel")]
+pub async fn host_cancel(
+    manager: Data<Addr<SessionManager>>,
+    form: Form<ScheduleForm>,
+    strings: Strings,
+) -> Result<HttpResponse, AppError> {
+    let form = form.into_inner();
+    let result = manager
+        .send(session::CancelScheduled {
+            session: form.session,
+            host_token: form.host,
+        })
+        .await?;
+    schedule_response(result, &strings)
+}
+
+fn schedule_response(
+    result: Result<(), ScheduleError>,
+    strings: &Strings,
+) -> Result<HttpResponse, AppError> {
+    match result {
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+        Err(ScheduleError::NoSuchSession) => Err(AppError::NotFound(
+            strings.get("error.no_such_session").to_string(),
+        )),
+        Err(ScheduleError::NotHost) => Err(AppError::Forbidden(
+            strings.get("error.not_host").to_string(),
+        )),
+        Err(ScheduleError::Started) => Err(AppError::Conflict(
+            strings.get("error.already_started").to_string(),
+        )),
+    }
+}
+
+fn parse_start(value: &str) -> Result<SystemTime, time::error::Parse> {
+    OffsetDateTime::parse(value.trim(), &Rfc3339).map(SystemTime::from)
+}
+
+/// When a session set to start at `starts_at` goes live, None for right away.
+/// Times that have passed start it right away too, like from a form left open for a while.
+fn start_time(
+    starts_at: Option<SystemTime>,
+    strings: &Strings,
+) -> Result<Option<SystemTime>, AppError> {
+    let now = SystemTime::now();
+    let Some(starts_at) = starts_at.filter(|starts_at| *starts_at > now) else {
+        return Ok(None);
+    };
+    if starts_at > now + MAX_SCHEDULE_AHEAD {
+        return Err(AppError::BadRequest(strings.format(
+            "error.schedule_too_far",
+            MAX_SCHEDULE_AHEAD.as_secs() / (24 * 60 * 60),
+        )));
+    }
+
+    Ok(Some(starts_at))
+}
+
+/// Random, but short enough to be written down
+fn new_seed() -> u64 {
+    rand::thread_rng().gen_range(0..1_000_000_000)
+}
+
+/// The recently played files to leave out. A seed that was given is most likely
+/// from a playlist played before, which can only be rolled again with its files.
+fn fresh_only(session: &SessionConfig, recent: Arc<RecentlyPlayed>) -> Option<Arc<RecentlyPlayed>> {
+    session.seed.is_none().then_some(recent)
+}
+
+/// Creates a session with an exported playlist as it is, instead of rolling one.
+/// Every entry has to be a file of the configured sources, looked up by its URL.
+#[post("/host/import")]
+#[allow(clippy::too_many_arguments)]
+pub async fn host_import(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    media: Data<MediaIndex>,
+    blocklist: Data<Blocklist>,
+    limiter: Data<RateLimiter<IpAddr>>,
+    session: Form<SessionConfig>,
+    strings: Strings,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    let config = config.get();
+    let mut session = session.into_inner();
+
+    let export = serde_json::from_str::<session::PlaylistExport>(
+        session.playlist.as_deref().unwrap_or_default(),
+    )
+    .map_err(|why| AppError::BadRequest(strings.format("error.invalid_playlist", why)))?;
+    if export.version > session::EXPORT_VERSION {
+        return Err(AppError::BadRequest(
+            strings.format("error.playlist_version", export.version),
+        ));
+    }
+    playlist_length(
+        export.shitposts.len(),
+        usize::MAX,
+        config.max_amount,
+        &strings,
+    )?;
+
+    let sources = config
+        .shitposts
+        .iter()
+        .map(|folder| &folder.name)
+        .chain(config.remote_sources.iter().map(|source| &source.name))
+        .filter_map(|name| media.get(name)?.ok())
+        .collect::<Vec<_>>();
+    let shitposts = imported_playlist(&sources, &export, &blocklist, &strings)?;
+    start_time(session.starts_at, &strings)?;
+    if session_exists(&manager, &session.session).await? {
+        return Err(AppError::Conflict(
+            strings.get("error.session_exists").to_string(),
+        ));
+    }
+    rate_limit(&limiter, &req, &config, &strings)?;
+    let requested = shitposts.len();
+    // The playlist is played as exported, what was left out only matters for rerolls
+    session.exclude.extend(export.excluded.iter().cloned());
+
+    start_session(
+        &manager,
+        &config,
+        &session,
+        shitposts,
+        requested,
+        export.seed,
+        &strings,
+    )
+    .await
+}
+
+/// Looks up the entries of an exported playlist in the scanned sources, keeping their order.
+/// Unlike a preview an entry may come up more than once, like after playing it again.
+fn imported_playlist(
+    sources: &[Arc<Vec<Shitpost>>],
+    export: &session::PlaylistExport,
+    blocklist: &Blocklist,
+    strings: &Strings,
+) -> Result<Vec<Shitpost>, AppError> {
+    let playable = sources
+        .iter()
+        .flat_map(|shitposts| shitposts.iter())
+        .filter(|shitpost| !blocklist.contains(&shitpost.url))
+        .map(|shitpost| (shitpost.url.as_str(), shitpost))
+        .collect::<HashMap<_, _>>();
+
+    export
+        .shitposts
+        .iter()
+        .map(|entry| match playable.get(entry.url.as_str()) {
+            Some(shitpost) => Ok((*shitpost).clone()),
+            None => Err(AppError::BadRequest(
+                strings.format("error.not_importable", &entry.url),
+            )),
+        })
+        .collect()
+}
+
+/// Whether the code is taken, checked up front so hosts aren't rate limited for it.
+/// Creating the session still fails if someone else takes the code in the meantime.
+async fn session_exists(
+    manager: &Addr<SessionManager>,
+    session: &SessionCode,
+) -> Result<bool, AppError> {
+    Ok(manager
+        .send(session::GetSession {
+            session: session.clone(),
+        })
+        .await?
+        .is_some())
+}
+
+fn rate_limit(
+    limiter: &RateLimiter<IpAddr>,
+    req: &HttpRequest,
+    config: &Config,
+    strings: &Strings,
+) -> Result<(), AppError> {
+    if let Some(address) = auth::client_ip(req, &config.trusted_proxies) {
+        if !limiter.try_take(address) {
+            tracing::warn!("Rate limited session creation from {}", address);
+            return Err(AppError::TooManyRequests(
+                strings.get("error.rate_limited").to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Creates the session and sends the host on to it
+async fn start_session(
+    manager: &Addr<SessionManager>,
+    config: &Config,
+    session: &SessionConfig,
+    shitposts: Vec<Shitpost>,
+    requested: usize,
+    seed: Option<u64>,
+    strings: &Strings,
+) -> Result<HttpResponse, AppError> {
+    let amount = shitposts.len();
+    let host_token = SessionManager::generate_host_token();
+    let starts_at = start_time(session.starts_at, strings)?;
+    let password = session.password.as_deref().map(PasswordHash::new);
+
+    match manager
+        .send(session::NewSession {
+            session: session.session.clone(),
+            shitposts,
+            password: password.clone(),
+            host_token: host_token.clone(),
+            permissions: session.permissions,
+            max_players: session_capacity(session.max_players, config.max_players),
+            public: session.public,
+            intermission_secs: session.intermission_secs,
+            seed,
+            excluded: session.excluded(),
+            starts_at,
+        })
+        .await?
+    {
+        Ok(()) => {
+            let mut location = format!("/join?session={}&host={}", session.session, host_token);
+            if amount < requested {
+                location.push_str(&format!("&shortened={}", amount));
+            }
+
+            let mut response = HttpResponse::SeeOther()
+                .insert_header((header::LOCATION, location))
+                .finish();
+            // The host chose the password, so isn't asked for it
+            if let Some(password) = password {
+                with_ticket(&mut response, &session.session, password.ticket());
+            }
+            Ok(response)
+        }
+        Err(session::NewSessionError::Exists) => Err(AppError::Conflict(
+            strings.get("error.session_exists").to_string(),
+        )),
+        Err(session::NewSessionError::TooManySessions) => Err(AppError::TooManyRequests(
+            strings.get("error.too_many_sessions").to_string(),
+        )),
+    }
+}
+
+/// A file rolled by a preview, the URL is what the host page submits back
+#[derive(Serialize)]
+struct PreviewEntry<'a> {
+    title: &'a str,
+    folder: &'a str,
+    url: &'a str,
+}
+
+/// Rolls a playlist the way `host_submit` would without creating the session,
+/// so the host can look it over and submit it as is or with some files removed
+#[get("/host/preview")]
+pub async fn host_preview(
+    config: Data<SharedConfig>,
+    media: Data<MediaIndex>,
+    recent: Data<RecentlyPlayed>,
+    blocklist: Data<Blocklist>,
+    session: Query<SessionConfig>,
+    strings: Strings,
+) -> Result<HttpResponse, AppError> {
+    let config = config.get();
+    let session = session.into_inner();
+    playlist_length(session.amount, usize::MAX, config.max_amount, &strings)?;
+
+    let (pools, readable) = selected_pools(&config, &media, &session, &strings)?;
+    let shitposts = roll_playlist(
+        pools,
+        &readable,
+        session.amount,
+        config.max_amount,
+        fresh_only(&session, recent.into_inner()),
+        blocklist.into_inner(),
+        session.seed.unwrap_or_else(new_seed),
+        &strings,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(
+        shitposts
+            .iter()
+            .map(|shitpost| PreviewEntry {
+                title: &shitpost.title,
+                folder: &shitpost.folder,
+                url: &shitpost.url,
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+/// Scanned files to pick from with the weight of their folder
+type Pools = Vec<(Arc<Vec<Shitpost>>, u32)>;
+
+/// The scanned files of the folders picked on the host page and their weights,
+/// along with the names of the folders that could be read.
+/// Files only some browsers play are left out unless the host asked for them.
+fn selected_pools<'a>(
+    config: &'a Config,
+    media: &MediaIndex,
+    session: &SessionConfig,
+    strings: &Strings,
+) -> Result<(Pools, Vec<&'a str>), AppError> {
+    let mut pools = Vec::new();
+    let mut selected = 0;
+    let mut failed = Vec::new();
+    let mut readable = Vec::new();
+
+    for name in config.source_names() {
+        let Some(selection) = session
+            .folders
+            .iter()
+            .find(|selection| selection.name == name)
+        else {
+            continue;
+        };
+        selected += 1;
+
+        match media.get(name) {
+            Some(Ok(folder_shitposts)) => {
+                let supported = |shitpost: &Shitpost| {
+                    (session.maybe_unsupported || shitpost.compat == BrowserCompat::Safe)
+                        && !session.exclude.contains(&shitpost.url)
+                };
+                // Most folders have nothing to leave out, so they aren't copied for nothing
+                let folder_shitposts = if folder_shitposts.iter().all(supported) {
+                    folder_shitposts
+                } else {
+                    Arc::new(
+                        folder_shitposts
+                            .iter()
+                            .filter(|shitpost| supported(shitpost))
+                            .cloned()
+                            .collect(),
+                    )
+                };
+                pools.push((folder_shitposts, selection.weight));
+                readable.push(name);
+            }
+            Some(Err(why)) => failed.push(format!("{} ({})", name, why)),
+            None => failed.push(format!("{} (not indexed yet)", name)),
+        }
+    }
+
+    if selected > 0 && failed.len() == selected {
+        return Err(AppError::Internal(
+            strings.format("error.unreadable_folders", failed.join(", ")),
+        ));
+    }
+    if selected == 0 {
+        return Err(AppError::BadRequest(
+            strings.get("error.no_folders").to_string(),
+        ));
+    }
+
+    Ok((pools, readable))
+}
+
+/// Runs `build_playlist` on a blocking thread, failing if nothing could be picked
+#[allow(clippy::too_many_arguments)]
+async fn roll_playlist(
+    pools: Vec<(Arc<Vec<Shitpost>>, u32)>,
+    readable: &[&str],
+    requested: usize,
+    max_amount: usize,
+    recent: Option<Arc<RecentlyPlayed>>,
+    blocklist: Arc<Blocklist>,
+    seed: u64,
+    strings: &Strings,
+) -> Result<Vec<Shitpost>, AppError> {
+    let building = strings.clone();
+    let shitposts = web::block(move || {
+        build_playlist(
+            pools,
+            requested,
+            max_amount,
+            &recent.map(|recent| recent.urls()).unwrap_or_default(),
+            &blocklist,
+            seed,
+            &building,
+        )
+    })
+    .await
+    .map_err(|why| AppError::Internal(why.to_string()))??;
+
+    if shitposts.is_empty() {
+        return Err(AppError::BadRequest(
+            strings.format("error.no_files", readable.join(", ")),
+        ));
+    }
+    Ok(shitposts)
+}
+
+/// Serves files from the shitpost folders of the current config,
+/// so folders added by a reload are available without a restart.
+/// Range requests are handled by `NamedFile`, which players need for seeking.
+#[get("/shitposts/{folder}/{file}")]
+pub async fn shitpost_file(
+    config: Data<SharedConfig>,
+    blocklist: Option<Data<Blocklist>>,
+    path: Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let (folder_name, file) = path.into_inner();
+
+    // Only plain file names inside the folder itself
+    let mut components = std::path::Path::new(&file).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        return Err(ErrorNotFound("No such file"));
+    }
+    let content_type = crate::content_type(&file).ok_or_else(|| ErrorNotFound("No such file"))?;
+
+    let config = config.get();
+    let folder = config
+        .shitposts
+        .iter()
+        .find(|folder| folder.name == folder_name)
+        .ok_or_else(|| ErrorNotFound("No such folder"))?;
+
+    let path = PathBuf::from(&folder.path).join(&file);
+    let response = NamedFile::open_async(&path)
+        .await?
+        .set_content_type(content_type)
+        .into_response(&req);
+
+    let url = crate::file_url(&folder.name, &file);
+    Ok(response
+        .map_body(|_, body| Streamed {
+            body,
+            path,
+            url,
+            blocklist: blocklist.map(Data::into_inner),
+        })
+        .map_into_boxed_body())
+}
+
+/// The body of a file being served, failed reads are logged and counted against the file.
+/// They otherwise only show up as clips cutting off for the players.
+struct Streamed {
+    body: BoxBody,
+    path: PathBuf,
+    url: String,
+    blocklist: Option<Arc<Blocklist>>,
+}
+
+impl MessageBody for Streamed {
+    type Error = <BoxBody as MessageBody>::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let polled = Pin::new(&mut this.body).poll_next(cx);
+
+        if let Poll::Ready(Some(Err(why))) = &polled {
+            tracing::error!(r#"Failed to stream "{}": {}"#, this.path.display(), why);
+            if let Some(blocklist) = &this.blocklist {
+                blocklist.stream_failed(&this.url);
+            }
+        }
+        polled
+    }
+}
+
+#[get("/")]
+pub async fn index(
+    manager: Data<Addr<SessionManager>>,
+    strings: Strings,
+) -> Result<Html, AppError> {
+    let sessions = manager.send(session::ListPublicSessions).await?;
+
+    Html::render(templates::Index {
+        strings: &strings,
+        sessions: &sessions,
+        error: None,
+        session: "",
+        name: "",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        fs,
+        sync::{Arc, RwLock},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use actix_web::{
+        http::{header, StatusCode},
+        test::{self},
+        web::{Data, Query},
+        App,
+    };
+    use askama::Template;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{
+        blocklist::Blocklist,
+        media::{Excluded, FolderStats, MediaIndex},
+        player::{
+            poll::PollConnections,
+            protocol::PROTOCOL_VERSION,
+            routes::{
+                build_playlist, imported_playlist, join, join_code, join_unlock, parse_start,
+                pick_playlist, playlist_length, poll_connect, poll_receive, poll_send,
+                selected_pools, session_capacity, shitpost_file, socket, start_time, templates,
+                weighted_sample, without_copies, SessionConfig, SessionQuery,
+            },
+        },
+        session::{
+            PasswordHash, PlaylistExport, PublicSession, SessionCode, EXPORT_VERSION,
+            MAX_SCHEDULE_AHEAD,
+        },
+        strings::Strings,
+        testing::{kind, Harness},
+        BrowserCompat, Config, SharedConfig, Shitpost, ShitpostKind,
+    };
+
+    #[test]
+    fn nicknames() {
+        let query = |name: Option<&str>| SessionQuery {
+            session: SessionCode::try_from("test".to_string()).unwrap(),
+            name: name.map(String::from),
+            client: None,
+            host: None,
+            spectator: false,
+            shortened: None,
+        };
+
+        assert_eq!(query(Some("  bob ")).name().as_deref(), Some("bob"));
+        assert_eq!(query(Some("   ")).name(), None);
+        assert_eq!(query(None).name(), None);
+        assert_eq!(
+            query(Some(&"a".repeat(100)))
+                .name()
+                .unwrap()
+                .chars()
+                .count(),
+            SessionQuery::MAX_NAME_LENGTH
+        );
+    }
+
+    fn pool(folder: &str, size: usize) -> Vec<Shitpost> {
+        (0..size)
+            .map(|i| Shitpost {
+                title: format!("{}{}", folder, i),
+                url: format!("/shitposts/{}/{}", folder, i),
+                kind: ShitpostKind::Video,
+                thumbnail: None,
+                low_url: None,
+                fingerprint: None,
+                start_offset: 0.0,
+                loudness: None,
+                compat: BrowserCompat::Safe,
+                folder: folder.to_string(),
+                size: None,
+                modified: None,
+                tags: Vec::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn weighted_sampling() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let shitposts = weighted_sample(
+            vec![(pool("huge", 1000), 1), (pool("tiny", 100), 3)],
+            100,
+            &mut rng,
+        );
+        let tiny = shitposts
+            .iter()
+            .filter(|shitpost| shitpost.title.starts_with("tiny"))
+            .count();
+
+        assert_eq!(shitposts.len(), 100);
+        assert!((60..=90).contains(&tiny));
+
+        // Pools that run out leave the rest to the others
+        let shitposts = weighted_sample(
+            vec![(pool("huge", 1000), 1), (pool("tiny", 5), 100)],
+            50,
+            &mut rng,
+        );
+        let mut urls = shitposts
+            .iter()
+            .map(|shitpost| shitpost.url.as_str())
+            .collect::<Vec<_>>();
+        urls.sort();
+        urls.dedup();
+
+        assert_eq!(urls.len(), 50);
+    }
+
+    #[test]
+    fn copies() {
+        let mut shitposts = pool("memes", 3);
+        shitposts.extend(pool("clips", 2));
+        shitposts[0].fingerprint = Some(1);
+        shitposts[3].fingerprint = Some(1);
+        shitposts[4].fingerprint = Some(2);
+
+        assert_eq!(
+            without_copies(shitposts)
+                .iter()
+                .map(|shitpost| shitpost.title.as_str())
+                .collect::<Vec<_>>(),
+            vec!["memes0", "memes1", "memes2", "clips1"]
+        );
+    }
+
+    #[test]
+    fn seeded_playlists() {
+        let path = std::env::temp_dir().join(format!("seeded-{}.json", std::process::id()));
+        let blocklist = Blocklist::load(&path, 1);
+        let strings = Strings::default();
+        let pools = vec![
+            (Arc::new(pool("memes", 20)), 2),
+            (Arc::new(pool("clips", 20)), 1),
+        ];
+        let roll = |seed| {
+            build_playlist(
+                pools.clone(),
+                10,
+                100,
+                &HashSet::new(),
+                &blocklist,
+                seed,
+                &strings,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|shitpost| shitpost.url)
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(roll(1234), roll(1234));
+        assert_ne!(roll(1234), roll(4321));
+    }
+
+    #[test]
+    fn picked_playlists() {
+        let path = std::env::temp_dir().join(format!("picked-{}.json", std::process::id()));
+        let blocklist = Blocklist::load(&path, 1);
+        let strings = Strings::default();
+        let pools = vec![
+            (Arc::new(pool("memes", 3)), 1),
+            (Arc::new(pool("muted", 3)), 0),
+        ];
+        let urls = |urls: &[&str]| urls.iter().map(|url| url.to_string()).collect::<Vec<_>>();
+
+        let shitposts = pick_playlist(
+            pools.clone(),
+            &urls(&["/shitposts/memes/2", "/shitposts/memes/0"]),
+            &blocklist,
+            &strings,
+        )
+        .unwrap();
+        assert_eq!(
+            shitposts
+                .iter()
+                .map(|shitpost| shitpost.title.as_str())
+                .collect::<Vec<_>>(),
+            vec!["memes2", "memes0"]
+        );
+
+        // Only files that could have been rolled
+        for picked in [
+            "/shitposts/memes/../../etc/passwd",
+            "/shitposts/other/0",
+            "/shitposts/muted/0",
+        ] {
+            assert!(pick_playlist(pools.clone(), &urls(&[picked]), &blocklist, &strings).is_err());
+        }
+        assert!(pick_playlist(
+            pools,
+            &urls(&["/shitposts/memes/1", "/shitposts/memes/1"]),
+            &blocklist,
+            &strings,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn imported_playlists() {
+        let path = std::env::temp_dir().join(format!("imported-{}.json", std::process::id()));
+        let blocklist = Blocklist::load(&path, 1);
+        let strings = Strings::default();
+        let sources = vec![Arc::new(pool("memes", 3)), Arc::new(pool("clips", 2))];
+
+        let mut entries = pool("memes", 3);
+        entries.swap(0, 2);
+        entries.push(pool("clips", 1).remove(0));
+        // Played again after the first time
+        entries.push(entries[0].clone());
+        let export = serde_json::to_string(&PlaylistExport {
+            version: EXPORT_VERSION,
+            code: "test".to_string(),
+            exported_at: 0,
+            folders: Vec::new(),
+            permissions: Default::default(),
+            loop_mode: Default::default(),
+            seed: None,
+            excluded: Vec::new(),
+            shitposts: entries,
+        })
+        .unwrap();
+        let mut export = serde_json::from_str::<PlaylistExport>(&export).unwrap();
+
+        let shitposts = imported_playlist(&sources, &export, &blocklist, &strings).unwrap();
+        assert_eq!(
+            shitposts
+                .iter()
+                .map(|shitpost| shitpost.title.as_str())
+                .collect::<Vec<_>>(),
+            vec!["memes2", "memes1", "memes0", "clips0", "memes2"]
+        );
+
+        // Tampered files can't point anywhere else
+        for url in [
+            "/shitposts/memes/../../etc/passwd",
+            "https://evil.example/x.mp4",
+        ] {
+            export.shitposts[1].url = url.to_string();
+            assert!(imported_playlist(&sources, &export, &blocklist, &strings).is_err());
+        }
+    }
+
+    #[test]
+    fn session_capacities() {
+        assert_eq!(session_capacity(None, None), None);
+        assert_eq!(session_capacity(Some(5), None), Some(5));
+        assert_eq!(session_capacity(None, Some(10)), Some(10));
+        assert_eq!(session_capacity(Some(5), Some(10)), Some(5));
+        // The config limit can't be raised
+        assert_eq!(session_capacity(Some(50), Some(10)), Some(10));
+        assert_eq!(session_capacity(Some(0), Some(10)), Some(10));
+    }
+
+    #[test]
+    fn playlist_lengths() {
+        let strings = Strings::default();
+        assert!(playlist_length(0, 10, 100, &strings).is_err());
+        assert_eq!(playlist_length(1, 10, 100, &strings).unwrap(), 1);
+        assert_eq!(playlist_length(10, 10, 100, &strings).unwrap(), 10);
+        // Shortened to what is available
+        assert_eq!(playlist_length(11, 10, 100, &strings).unwrap(), 10);
+        assert_eq!(playlist_length(100, 200, 100, &strings).unwrap(), 100);
+        assert!(playlist_length(101, 200, 100, &strings).is_err());
+        assert!(playlist_length(10_000_000, usize::MAX, 100, &strings).is_err());
+    }
+
+    #[test]
+    fn templates_render() {
+        let shitposts = pool("memes", 2);
+        let strings = Strings::default();
+
+        let pages = [
+            templates::Player {
+                shitposts: &shitposts,
+                session: "test",
+                name: "bob",
+                host: "token",
+                total: 5,
+                reactions: &["🔥"],
+                spectator: false,
+                shortened: Some(3),
+                protocol_version: PROTOCOL_VERSION,
+                generation: 1,
+                seed: Some(42),
+            }
+            .render(),
+            templates::Host {
+                folders: &[
+                    templates::FolderInfo {
+                        name: "memes",
+                        stats: Some(FolderStats {
+                            files: 12,
+                            bytes: 3 << 30,
+                            maybe_unsupported: 2,
+                            excluded: Excluded {
+                                extensions: [("txt".to_string(), 3)].into(),
+                                unreadable: 1,
+                            },
+                        }),
+                        tags: &["nsfw".to_string()],
+                    },
+                    templates::FolderInfo {
+                        name: "broken",
+                        stats: None,
+                        tags: &[],
+                    },
+                ],
+                session: "test",
+                max_players: Some(10),
+            }
+            .render(),
+            templates::Join { strings: &strings }.render(),
+            templates::Password {
+                session: "test",
+                name: "bob",
+                host: "",
+                spectator: true,
+                error: Some("Wrong password"),
+                strings: &strings,
+            }
+            .render(),
+            templates::Index {
+                strings: &strings,
+                sessions: &[PublicSession {
+                    code: "test".to_string(),
+                    player_count: 3,
+                    current_title: Some("cat.mp4".to_string()),
+                }],
+                error: Some("No such session exists"),
+                session: "test",
+                name: "bob",
+            }
+            .render(),
+            templates::Error { text: "oops" }.render(),
+        ];
+
+        for page in pages {
+            assert!(!page.unwrap().is_empty());
+        }
+    }
+
+    #[actix_web::test]
+    async fn shitpost_ranges() {
+        let folder = std::env::temp_dir().join(format!("shitposts-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        fs::write(folder.join("clip.webm"), (0..=255).collect::<Vec<u8>>()).unwrap();
+
+        let config: Config = ron::from_str(&format!(
+            r#"(shitposts: [(path: "{}", name: "clips")], bind: "")"#,
+            folder.display()
+        ))
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(SharedConfig(RwLock::new(Arc::new(config)))))
+                .service(shitpost_file),
+        )
+        .await;
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/shitposts/clips/clip.webm")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "video/webm"
+        );
+        assert_eq!(
+            response.headers().get(header::ACCEPT_RANGES).unwrap(),
+            "bytes"
+        );
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/shitposts/clips/clip.webm")
+                .insert_header((header::RANGE, "bytes=10-19"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes 10-19/256"
+        );
+        assert_eq!(
+            test::read_body(response).await,
+            (10..20).collect::<Vec<u8>>()
+        );
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/shitposts/clips/..%2Fclip.webm")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn hostile_filenames() {
+        let folder = std::env::temp_dir().join(format!("hostile-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let names = [
+            "<img src=x onerror=alert(1)>.webm",
+            "space # 100%?.webm",
+            "quote\"\\.webm",
+            "cat video (final).mp4",
+            "😂 tröllö.webm",
+        ];
+        // Each file holds its own name, so a response shows which file was served
+        for name in names {
+            fs::write(folder.join(name), name).unwrap();
+        }
+
+        let config: Arc<Config> = Arc::new(
+            ron::from_str(&format!(
+                r#"(shitposts: [(path: "{}", name: "my clips")], bind: "")"#,
+                folder.display()
+            ))
+            .unwrap(),
+        );
+        let media = Arc::new(MediaIndex::default());
+        media.rescan(config.clone()).await;
+        let shitposts = media.get("my clips").unwrap().unwrap();
+        assert_eq!(shitposts.len(), names.len());
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(SharedConfig(RwLock::new(config))))
+                .service(shitpost_file),
+        )
+        .await;
+        for shitpost in shitposts.iter() {
+            let name = shitpost.file_name();
+            assert!(names.contains(&name.as_str()), "{}", name);
+            assert!(!shitpost.title.contains(['<', '>', '\\']));
+            assert!(shitpost.url.is_ascii());
+            assert!(!shitpost.url.contains([' ', '#', '?', '"', '<', '(']));
+            assert!(media.find("my clips", &name).is_some());
+
+            let response = test::call_service(
+                &app,
+                test::TestRequest::get().uri(&shitpost.url).to_request(),
+            )
+            .await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", shitpost.url);
+            assert_eq!(test::read_body(response).await, name.as_bytes());
+        }
+        // Names that need no sanitizing are shown as they are
+        for title in [
+            "cat video (final).mp4",
+            "😂 tröllö.webm",
+            "space # 100%?.webm",
+        ] {
+            assert!(shitposts.iter().any(|shitpost| shitpost.title == title));
+        }
+
+        let page = templates::Player {
+            shitposts: &shitposts,
+            session: "test",
+            name: "bob",
+            host: "",
+            total: 3,
+            reactions: &[],
+            spectator: false,
+            shortened: None,
+            protocol_version: PROTOCOL_VERSION,
+            generation: 1,
+            seed: None,
+        }
+        .render()
+        .unwrap();
+        // The player page has an <img> of its own, so look for the injected markup itself
+        assert!(!page.contains("<img src=x onerror"));
+        assert!(page.contains("img src=x onerror=alert(1).webm"));
+        assert!(page.contains("quote&quot;.webm"));
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn left_out_files() {
+        let folder = std::env::temp_dir().join(format!("left-out-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        for name in ["a.webm", "b.webm", "joke.webm"] {
+            fs::write(folder.join(name), name).unwrap();
+        }
+
+        let config: Arc<Config> = Arc::new(
+            ron::from_str(&format!(
+                r#"(shitposts: [(path: "{}", name: "clips")], bind: "")"#,
+                folder.display()
+            ))
+            .unwrap(),
+        );
+        let media = Arc::new(MediaIndex::default());
+        media.rescan(config.clone()).await;
+        let joke = media.find("clips", "joke.webm").unwrap().url;
+
+        // Every checked box repeats the key
+        let query = format!(
+            "amount=10&session=test&folders=clips&exclude={}&exclude={}&exclude=%2Fgone",
+            joke, joke
+        );
+        let session = Query::<SessionConfig>::from_query(&query)
+            .unwrap()
+            .into_inner();
+        assert_eq!(session.excluded(), ["/gone".to_string(), joke.clone()]);
+
+        let (pools, readable) =
+            selected_pools(&config, &media, &session, &Strings::default()).unwrap();
+        assert_eq!(readable, ["clips"]);
+        assert_eq!(pools[0].0.len(), 2);
+        assert!(pools[0].0.iter().all(|shitpost| shitpost.url != joke));
+
+        // Nothing is left out without the key
+        let session = Query::<SessionConfig>::from_query("amount=10&session=test&folders=clips")
+            .unwrap()
+            .into_inner();
+        assert!(session.exclude.is_empty());
+        let (pools, _) = selected_pools(&config, &media, &session, &Strings::default()).unwrap();
+        assert_eq!(pools[0].0.len(), 3);
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn typed_in_codes() {
+        let harness = Harness::start();
+        harness.new_session("Movie-Night", 3).await.unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(harness.manager.clone()))
+                .service(join_code),
+        )
+        .await;
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/join?lang=fi")
+                .set_form([("session", " movie-NIGHT "), ("name", "bob & co")])
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers().get(header::LOCATION).unwrap(),
+            "/join?session=Movie-Night&name=bob%20%26%20co"
+        );
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/join")
+                .set_form([("session", "movie night"), ("name", "bob")])
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let page = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        assert!(page.contains("No such session exists"));
+        assert!(page.contains(r#"value="movie night""#));
+    }
+
+    #[actix_web::test]
+    async fn password_sessions() {
+        let harness = Harness::start();
+        harness
+            .new_session_with("secret", 3, |msg| {
+                msg.password = Some(PasswordHash::new("hunter2"))
+            })
+            .await
+            .unwrap();
+        let config: Config = ron::from_str(r#"(shitposts: [], bind: "")"#).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(harness.manager.clone()))
+                .app_data(Data::new(SharedConfig(RwLock::new(Arc::new(config)))))
+                .service(join)
+                .service(join_unlock),
+        )
+        .await;
+
+        let page = test::call_and_read_body(
+            &app,
+            test::TestRequest::get()
+                .uri("/join?session=secret&name=bob")
+                .to_request(),
+        )
+        .await;
+        assert!(String::from_utf8_lossy(&page).contains("/join/unlock"));
+
+        let unlock = |password: &'static str| {
+            test::TestRequest::post()
+                .uri("/join/unlock")
+                .set_form([
+                    ("session", "secret"),
+                    ("name", "bob"),
+                    ("host", ""),
+                    ("password", password),
+                ])
+                .to_request()
+        };
+        // Asked again, with a status htmx still swaps in
+        let response = test::call_service(&app, unlock("hunter3")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::SET_COOKIE).is_none());
+        let page = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        assert!(page.contains("Wrong password"));
+
+        let response = test::call_service(&app, unlock("hunter2")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let ticket = response
+            .response()
+            .cookies()
+            .find(|cookie| cookie.name() == "ticket_secret")
+            .unwrap()
+            .into_owned();
+        assert_eq!(ticket.http_only(), Some(true));
+        let page = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+        assert!(!page.contains("/join/unlock"));
+        assert!(!page.contains("hunter2"));
+
+        // The ticket lets the player back in without the password
+        let page = test::call_and_read_body(
+            &app,
+            test::TestRequest::get()
+                .uri("/join?session=secret&name=bob")
+                .cookie(ticket)
+                .to_request(),
+        )
+        .await;
+        assert!(!String::from_utf8_lossy(&page).contains("/join/unlock"));
+    }
+
+    #[actix_web::test]
+    async fn polling() {
+        let harness = Harness::start();
+        harness.new_session("poll", 3).await.unwrap();
+        let config: Config = ron::from_str(r#"(shitposts: [], bind: "")"#).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(harness.manager.clone()))
+                .app_data(Data::new(SharedConfig(RwLock::new(Arc::new(config)))))
+                .app_data(Data::new(PollConnections::default()))
+                .service(poll_connect)
+                .service(poll_receive)
+                .service(poll_send),
+        )
+        .await;
+
+        let opened: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::post()
+                .uri("/player/connect?session=poll&name=bob")
+                .to_request(),
+        )
+        .await;
+        let connection = opened["connection"].as_str().unwrap();
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri(&format!("/player/send?connection={}", connection))
+                .set_payload(format!(
+                    r#"{{"Hello": {{"protocol_version": {{"major": {}, "minor": 0}}}}}}"#,
+                    PROTOCOL_VERSION.major
+                ))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        // Let the session answer before polling
+        actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        let polled: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            test::TestRequest::get()
+                .uri(&format!("/player/poll?connection={}", connection))
+                .to_request(),
+        )
+        .await;
+        let kinds = polled["messages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(kind)
+            .collect::<Vec<_>>();
+        assert_eq!(kinds[..2], ["hello", "welcome"]);
+        assert_eq!(polled["next"], kinds.len());
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/player/send?connection=nope")
+                .set_payload("\"seeked\"")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn missing_sessions() {
+        let harness = Harness::start();
+        harness.new_session("exists", 3).await.unwrap();
+        let config: Config = ron::from_str(r#"(shitposts: [], bind: "")"#).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(harness.manager.clone()))
+                .app_data(Data::new(SharedConfig(RwLock::new(Arc::new(config)))))
+                .app_data(Data::new(PollConnections::default()))
+                .service(socket)
+                .service(poll_connect),
+        )
+        .await;
+
+        for request in [
+            test::TestRequest::get().uri("/player/socket?session=typo"),
+            test::TestRequest::post().uri("/player/connect?session=typo"),
+        ] {
+            let response = test::call_service(&app, request.to_request()).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            let body: serde_json::Value = test::read_body_json(response).await;
+            assert_eq!(body, serde_json::json!({ "error": "no_such_session" }));
+        }
+
+        // Existing sessions get as far as the upgrade, which a plain request fails
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/player/socket?session=exists")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn start_times() {
+        assert_eq!(
+            parse_start("2023-11-14T23:13:20+01:00").unwrap(),
+            UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+        assert!(parse_start("tomorrow").is_err());
+
+        let strings = Strings::default();
+        let now = SystemTime::now();
+        assert_eq!(start_time(None, &strings).unwrap(), None);
+        // Passed already, so there is nothing to wait for
+        assert_eq!(
+            start_time(Some(now - Duration::from_secs(60)), &strings).unwrap(),
+            None
+        );
+        let tonight = now + Duration::from_secs(6 * 60 * 60);
+        assert_eq!(start_time(Some(tonight), &strings).unwrap(), Some(tonight));
+        assert!(start_time(
+            Some(now + MAX_SCHEDULE_AHEAD + Duration::from_secs(60)),
+            &strings
+        )
+        .is_err());
+    }
+}
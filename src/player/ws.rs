@@ -0,0 +1,1356 @@
+//! The actor behind each player's websocket, relaying between the client and the session
+
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use actix::{Actor, ActorContext, Addr, AsyncContext, Handler, Message, StreamHandler};
+use actix_web_actors::ws;
+use rand::Rng;
+use serde::Serialize;
+
+use crate::{
+    clock::{self, ClockEstimator, Sample},
+    player::{
+        poll::Outbox,
+        protocol::{
+            BackendMessage, DebugPlayer, DecodeError, Initiator, PlayerMessage, Protocol,
+            ProtocolVersion, State, FEATURES, PROTOCOL_VERSION,
+        },
+    },
+    rate_limit::TokenBucket,
+    session::{self, Action, DisconnectReason, LoopMode, Permissions, SessionCode, SessionManager},
+    Shitpost,
+};
+
+#[derive(Message, Serialize)]
+#[rtype(result = "()")]
+pub struct ChangeState {
+    pub state: State,
+    /// None if the server changed it, like after a countdown
+    pub by: Option<Initiator>,
+}
+
+#[derive(Message, Serialize)]
+#[rtype(result = "()")]
+pub struct ChangeRate {
+    pub rate: f64,
+}
+
+#[derive(Message, Serialize)]
+#[rtype(result = "()")]
+pub struct ChangePlaylist {
+    pub index: usize,
+}
+
+#[derive(Message, Serialize)]
+#[rtype(result = "()")]
+pub struct ChangePosition {
+    pub position: f64,
+}
+
+#[derive(Message, Serialize)]
+#[rtype(result = "()")]
+pub struct SyncPosition;
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Welcome {
+    pub id: u64,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SessionInfo {
+    pub generation: u64,
+    pub playlist_len: usize,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PlaylistReplaced {
+    pub generation: u64,
+    pub shitposts: Vec<Shitpost>,
+    pub index: usize,
+}
+
+/// Tells a player in the lobby when the session goes live
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SessionScheduled {
+    pub starts_at: SystemTime,
+}
+
+/// Sent to the lobby after the state of the session it now plays
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SessionStarted;
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Kicked {
+    pub reason: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SessionFull {
+    pub players: usize,
+    pub max_players: usize,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct HostToken {
+    pub token: String,
+}
+
+/// Folders whose entries are passed over
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MutedFolders {
+    pub folders: Vec<String>,
+}
+
+/// An entry was moved on from for a reason besides a skip vote, the reason is already escaped
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct EntrySkipped {
+    pub index: usize,
+    pub reason: String,
+}
+
+/// Someone bookmarked a moment, everything but the position is already escaped
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Bookmarked {
+    pub by: String,
+    pub title: Option<String>,
+    pub position: f64,
+    pub note: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct DebugChanged {
+    pub enabled: bool,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PermissionsChanged {
+    pub permissions: Permissions,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PermissionDenied {
+    pub action: Action,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct DebugSync {
+    pub players: Vec<DebugPlayer>,
+}
+
+/// Tags whose entries are passed over
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct FilteredTags {
+    pub tags: Vec<String>,
+}
+
+/// Tells the player something went wrong that it didn't cause itself
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ShowError {
+    pub reason: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LoopModeChanged {
+    pub mode: LoopMode,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct WaitForStragglersChanged {
+    pub enabled: bool,
+}
+
+/// Tells the player playback is paused until a stalled player catches up
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct WaitingFor {
+    pub name: String,
+}
+
+/// Closes the websocket
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Disconnect;
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PlaylistFinished;
+
+/// Closes the websocket, telling the player the session is gone
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SessionClosed;
+
+/// Closes the websocket of a player that said hello to a session that doesn't exist
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct NoSuchSession;
+
+/// Closes the websocket, telling the player when to try coming back
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ServerClosing {
+    pub reconnect_after_secs: u64,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReadyStatus {
+    pub ready: usize,
+    pub total: usize,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Countdown {
+    pub seconds: u64,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Intermission {
+    pub next_title: Option<String>,
+    pub seconds: u64,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct History {
+    pub entries: Vec<session::HistoryEntry>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SkipVotes {
+    pub current: usize,
+    pub needed: usize,
+}
+
+/// Entries were appended to or removed from the playlist, `index` is the current entry afterwards
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PlaylistUpdated {
+    pub added: Vec<Shitpost>,
+    pub removed: Option<usize>,
+    /// The entries from this index on were replaced by `added` instead of it being appended
+    pub replaced_from: Option<usize>,
+    pub index: usize,
+}
+
+/// Everyone watching the session, names already escaped
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Viewers {
+    pub viewers: Vec<session::ViewerInfo>,
+}
+
+/// Chat message with the sender's name and text already escaped
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ChatMessage {
+    pub from: String,
+    pub text: String,
+}
+
+/// Another player is gone, with the name already escaped
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PlayerLeft {
+    pub name: String,
+    pub reason: DisconnectReason,
+}
+
+/// Reaction with the sender's name already escaped
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReactionMessage {
+    pub from: String,
+    pub emoji: String,
+}
+
+/// Text a polling client sent, handled like a text frame
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ClientMessage {
+    pub text: String,
+}
+
+pub struct PlayerActor {
+    manager: Addr<SessionManager>,
+    session: SessionCode,
+    name: Option<Arc<str>>,
+    client: Option<Arc<str>>,
+    /// Where the client connected from, None if that isn't known
+    address: Option<IpAddr>,
+    host_token: Option<String>,
+    heartbeat: Heartbeat,
+    /// Last time anything was heard from the client
+    hb: Instant,
+    /// Pings carry the milliseconds since this, so the pong tells the round trip time
+    connected: Instant,
+    /// Round trip time of the last answered ping
+    latency: Duration,
+    /// How far the client's clock is off, from the answers to time probes
+    clock: ClockEstimator,
+    /// Consecutive messages that failed to parse
+    malformed: usize,
+    /// Limits how fast reactions can be sent
+    reactions: TokenBucket,
+    /// Reported to the session once the actor stops
+    disconnect: DisconnectReason,
+    /// Only position reports are passed on for spectators
+    spectator: bool,
+    /// Carries the session code and a short connection id
+    span: tracing::Span,
+    /// Set once the client said hello and joined the session
+    joined: bool,
+    /// The client's tab is hidden, so its timers may barely run
+    backgrounded: bool,
+    transport: Transport,
+}
+
+/// How messages reach the client
+pub enum Transport {
+    Socket,
+    /// Queued for a client that polls instead, see `poll`
+    Poll(Arc<Outbox>),
+}
+
+/// How often clients are pinged and how long they may stay silent
+#[derive(Clone, Copy)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Used instead of `timeout` while the client's tab is in the background
+    pub background_timeout: Duration,
+}
+
+impl PlayerActor {
+    /// How often the client is asked to report its position
+    const POSITION_INTERVAL: Duration = Duration::from_secs(5);
+    /// How many malformed messages in a row are tolerated before the socket is closed
+    const MAX_MALFORMED: usize = 10;
+    /// How much of a malformed payload gets logged
+    const LOGGED_PAYLOAD_LENGTH: usize = 128;
+    /// Reactions allowed in a burst, refilling at the same amount per second
+    const REACTION_BURST: u32 = 5;
+    const REACTION_REFILL: Duration = Duration::from_millis(200);
+    /// Time probes sent right after joining, so the clock is known before the first seek
+    const PROBE_ROUNDS: u32 = 5;
+    const PROBE_SPACING: Duration = Duration::from_millis(200);
+    /// How often the clock is probed after that, as clocks and routes drift
+    const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        manager: Addr<SessionManager>,
+        session: SessionCode,
+        name: Option<Arc<str>>,
+        client: Option<Arc<str>>,
+        address: Option<IpAddr>,
+        host_token: Option<String>,
+        spectator: bool,
+        heartbeat: Heartbeat,
+        transport: Transport,
+    ) -> Self {
+        Self {
+            manager,
+            name,
+            client,
+            address,
+            host_token,
+            heartbeat,
+            hb: Instant::now(),
+            connected: Instant::now(),
+            latency: Duration::ZERO,
+            clock: ClockEstimator::default(),
+            malformed: 0,
+            reactions: TokenBucket::new(Self::REACTION_BURST, Self::REACTION_REFILL),
+            disconnect: DisconnectReason::TimedOut,
+            spectator,
+            span: tracing::info_span!(
+                "player",
+                session = %session,
+                conn = %format!("{:06x}", rand::thread_rng().gen_range(0..0x1000000))
+            ),
+            session,
+            joined: false,
+            backgrounded: false,
+            transport,
+        }
+    }
+
+    /// Sends a message to the client
+    fn send(&self, ctx: &mut <Self as Actor>::Context, message: BackendMessage) {
+        let _span = self.span.enter();
+        let text = Protocol::encode(&message);
+        tracing::debug!("Relaying {}", text);
+        match &self.transport {
+            Transport::Socket => ctx.text(text),
+            Transport::Poll(outbox) => outbox.push(text),
+        }
+    }
+
+    fn hb(&self, ctx: &mut <Self as Actor>::Context) {
+        ctx.run_interval(self.heartbeat.interval, |act, ctx| {
+            if Instant::now().duration_since(act.last_heard()) > act.timeout() {
+                act.span.in_scope(|| tracing::debug!("Heartbeat timed out"));
+                act.disconnect = DisconnectReason::TimedOut;
+                ctx.stop();
+            } else if let Transport::Socket = act.transport {
+                let sent = act.connected.elapsed().as_millis() as u64;
+                ctx.ping(&sent.to_be_bytes());
+            }
+        });
+    }
+
+    /// Polling clients can't be pinged, polling is what shows they are still there
+    fn last_heard(&self) -> Instant {
+        match &self.transport {
+            Transport::Socket => self.hb,
+            Transport::Poll(outbox) => outbox.last_seen().max(self.hb),
+        }
+    }
+
+    /// How long the client may stay silent, longer while its tab is in the background
+    fn timeout(&self) -> Duration {
+        if self.backgrounded {
+            self.heartbeat.background_timeout
+        } else {
+            self.heartbeat.timeout
+        }
+    }
+
+    /// Asks the client for its clock
+    fn probe(&self, ctx: &mut <Self as Actor>::Context) {
+        self.send(
+            ctx,
+            BackendMessage::TimeProbe {
+                server_ms: clock::unix_ms(SystemTime::now()),
+            },
+        );
+    }
+
+    /// Learns from the answer to a probe and tells the client and the session the new estimate
+    fn probe_answered(
+        &mut self,
+        server_ms: u64,
+        client_ms: f64,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let received_ms = clock::unix_ms(SystemTime::now());
+        let Some(sample) = Sample::new(server_ms, client_ms, received_ms) else {
+            tracing::debug!("Ignored an answer to a time probe that wasn't sent");
+            return;
+        };
+        self.clock.push(sample);
+        let Some(estimate) = self.clock.estimate() else {
+            return;
+        };
+
+        self.send(
+            ctx,
+            BackendMessage::ClockEstimate {
+                offset_ms: estimate.offset_ms,
+                rtt_ms: estimate.rtt_ms,
+            },
+        );
+        self.manager.do_send(session::ClockEstimated {
+            session: self.session.clone(),
+            player: ctx.address(),
+            estimate,
+        });
+    }
+
+    /// Time since the ping a pong answers was sent, None if the pong wasn't asked for
+    fn round_trip(&self, payload: &[u8]) -> Option<Duration> {
+        let sent = u64::from_be_bytes(payload.try_into().ok()?);
+        self.connected
+            .elapsed()
+            .checked_sub(Duration::from_millis(sent))
+    }
+
+    /// Checks the protocol version and joins the session if it is compatible
+    fn hello(
+        &mut self,
+        protocol_version: ProtocolVersion,
+        capabilities: Vec<String>,
+        generation: Option<u64>,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        if protocol_version.major != PROTOCOL_VERSION.major {
+            self.close(
+                ctx,
+                ws::CloseCode::Unsupported,
+                &format!(
+                    "Protocol version {} is not supported, the server speaks {}",
+                    protocol_version, PROTOCOL_VERSION
+                ),
+            );
+            return;
+        }
+
+        self.span.in_scope(|| {
+            tracing::debug!(
+                "Hello with protocol version {} and capabilities {:?}",
+                protocol_version,
+                capabilities
+            )
+        });
+        self.send(
+            ctx,
+            BackendMessage::Hello {
+                protocol_version: PROTOCOL_VERSION,
+                features: FEATURES,
+            },
+        );
+
+        self.joined = true;
+        ctx.run_interval(Self::POSITION_INTERVAL, |act, ctx| {
+            act.send(ctx, BackendMessage::SyncPosition);
+        });
+        // Polled messages wait for the next poll, which would count as time underway
+        if let Transport::Socket = self.transport {
+            for round in 0..Self::PROBE_ROUNDS {
+                ctx.run_later(Self::PROBE_SPACING * round, |act, ctx| act.probe(ctx));
+            }
+            ctx.run_interval(Self::PROBE_INTERVAL, |act, ctx| act.probe(ctx));
+        }
+        self.manager.do_send(session::PlayerConnect {
+            session: self.session.clone(),
+            player: ctx.address(),
+            name: self.name.clone(),
+            client: self.client.clone(),
+            address: self.address,
+            host_token: self.host_token.clone(),
+            spectator: self.spectator,
+            generation,
+        });
+    }
+
+    /// Handles a message from the client, whichever way it came in
+    fn receive(&mut self, text: &str, ctx: &mut <Self as Actor>::Context) {
+        let message = match Protocol::decode(text) {
+            Ok(message) => {
+                self.malformed = 0;
+                message
+            }
+            // Messages from newer clients are rejected without counting against them
+            Err(DecodeError::Unknown(why)) => {
+                self.send(
+                    ctx,
+                    BackendMessage::Error {
+                        reason: why.to_string(),
+                    },
+                );
+                return;
+            }
+            Err(DecodeError::Malformed(why)) => {
+                self.malformed += 1;
+                tracing::warn!(
+                    r#"Malformed message in session "{}": {}: {}"#,
+                    self.session,
+                    why,
+                    text.chars()
+                        .take(Self::LOGGED_PAYLOAD_LENGTH)
+                        .collect::<String>()
+                );
+
+                if self.malformed >= Self::MAX_MALFORMED {
+                    self.close(ctx, ws::CloseCode::Policy, "Too many malformed messages");
+                } else {
+                    self.send(
+                        ctx,
+                        BackendMessage::Error {
+                            reason: why.to_string(),
+                        },
+                    );
+                }
+                return;
+            }
+        };
+
+        if let PlayerMessage::Hello {
+            protocol_version,
+            capabilities,
+            generation,
+        } = message
+        {
+            if self.joined {
+                self.send(
+                    ctx,
+                    BackendMessage::Error {
+                        reason: "Already said hello".to_string(),
+                    },
+                );
+            } else {
+                self.hello(protocol_version, capabilities, generation, ctx);
+            }
+            return;
+        }
+        if !self.joined {
+            self.close(ctx, ws::CloseCode::Protocol, "Expected a hello first");
+            return;
+        }
+
+        // Spectators still report their position and tab so they can be kept in sync
+        if self.spectator
+            && !matches!(
+                message,
+                PlayerMessage::Position(_)
+                    | PlayerMessage::TimeProbeReply { .. }
+                    | PlayerMessage::Backgrounded
+                    | PlayerMessage::Foregrounded
+            )
+        {
+            tracing::debug!(
+                r#"Ignored a message from a spectator in session "{}""#,
+                self.session
+            );
+            return;
+        }
+
+        match message {
+            // Answered before joining
+            PlayerMessage::Hello { .. } => {}
+            PlayerMessage::TimeProbeReply {
+                server_ms,
+                client_ms,
+            } => self.probe_answered(server_ms, client_ms, ctx),
+            PlayerMessage::Seeked => self.manager.do_send(session::Seeked {
+                session: self.session.clone(),
+                player: ctx.address(),
+            }),
+            PlayerMessage::StateChanged(state) => self.manager.do_send(session::StateChanged {
+                session: self.session.clone(),
+                player: ctx.address(),
+                state,
+            }),
+            PlayerMessage::RateChanged(rate) => self.manager.do_send(session::RateChanged {
+                session: self.session.clone(),
+                player: ctx.address(),
+                rate,
+            }),
+            PlayerMessage::Position(position) => self.manager.do_send(session::Position {
+                session: self.session.clone(),
+                player: ctx.address(),
+                position,
+                latency: self.latency,
+            }),
+            PlayerMessage::PlaylistChanged(_index) => {
+                self.manager.do_send(session::PlaylistChanged {
+                    session: self.session.clone(),
+                    player: ctx.address(),
+                    index: _index,
+                })
+            }
+            PlayerMessage::Chat(text) => self.manager.do_send(session::Chat {
+                session: self.session.clone(),
+                player: ctx.address(),
+                text,
+            }),
+            PlayerMessage::VoteSkip => self.manager.do_send(session::VoteSkip {
+                session: self.session.clone(),
+                player: ctx.address(),
+            }),
+            PlayerMessage::Ready => self.manager.do_send(session::Ready {
+                session: self.session.clone(),
+                player: ctx.address(),
+            }),
+            PlayerMessage::StartCountdown => self.manager.do_send(session::StartCountdown {
+                session: self.session.clone(),
+                player: ctx.address(),
+            }),
+            PlayerMessage::RevealHostToken => self.manager.do_send(session::RevealHostToken {
+                session: self.session.clone(),
+                player: ctx.address(),
+            }),
+            PlayerMessage::MuteFolder(folder) => self.manager.do_send(session::MuteFolder {
+                session: self.session.clone(),
+                player: ctx.address(),
+                folder,
+                muted: true,
+            }),
+            PlayerMessage::UnmuteFolder(folder) => self.manager.do_send(session::MuteFolder {
+                session: self.session.clone(),
+                player: ctx.address(),
+                folder,
+                muted: false,
+            }),
+            PlayerMessage::FilterTags(tags) => self.manager.do_send(session::FilterTags {
+                session: self.session.clone(),
+                player: ctx.address(),
+                tags,
+            }),
+            PlayerMessage::Bookmark { note } => self.manager.do_send(session::AddBookmark {
+                session: self.session.clone(),
+                player: ctx.address(),
+                note,
+            }),
+            PlayerMessage::SetDebug(enabled) => self.manager.do_send(session::SetDebug {
+                session: self.session.clone(),
+                player: ctx.address(),
+                enabled,
+            }),
+            PlayerMessage::SetPermissions(permissions) => {
+                self.manager.do_send(session::SetPermissions {
+                    session: self.session.clone(),
+                    player: ctx.address(),
+                    permissions,
+                })
+            }
+            PlayerMessage::SetQuality(quality) => self.manager.do_send(session::SetQuality {
+                session: self.session.clone(),
+                player: ctx.address(),
+                quality,
+            }),
+            PlayerMessage::ReportBroken(entry) => self.manager.do_send(session::ReportBroken {
+                session: self.session.clone(),
+                player: ctx.address(),
+                index: entry,
+            }),
+            PlayerMessage::ShuffleRemaining => self.manager.do_send(session::ShuffleRemaining {
+                session: self.session.clone(),
+                player: ctx.address(),
+            }),
+            PlayerMessage::PlayNext { entry } => self.manager.do_send(session::PlayNext {
+                session: self.session.clone(),
+                player: ctx.address(),
+                index: entry,
+            }),
+            PlayerMessage::SkipIntermission => self.manager.do_send(session::SkipIntermission {
+                session: self.session.clone(),
+                player: ctx.address(),
+            }),
+            PlayerMessage::Backgrounded | PlayerMessage::Foregrounded => {
+                self.backgrounded = matches!(message, PlayerMessage::Backgrounded);
+                self.manager.do_send(session::SetBackgrounded {
+                    session: self.session.clone(),
+                    player: ctx.address(),
+                    backgrounded: self.backgrounded,
+                })
+            }
+            PlayerMessage::SetLoopMode(mode) => self.manager.do_send(session::SetLoopMode {
+                session: self.session.clone(),
+                player: ctx.address(),
+                mode,
+            }),
+            PlayerMessage::SetWaitForStragglers(enabled) => {
+                self.manager.do_send(session::SetWaitForStragglers {
+                    session: self.session.clone(),
+                    player: ctx.address(),
+                    enabled,
+                })
+            }
+            PlayerMessage::SetStartOffset { entry, seconds } => {
+                self.manager.do_send(session::SetStartOffset {
+                    session: self.session.clone(),
+                    player: ctx.address(),
+                    index: entry,
+                    seconds,
+                })
+            }
+            // Reactions over the limit are dropped silently
+            PlayerMessage::Reaction(emoji) => {
+                if self.reactions.try_take(Instant::now()) {
+                    self.manager.do_send(session::Reaction {
+                        session: self.session.clone(),
+                        player: ctx.address(),
+                        emoji,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Closes the websocket for breaking the protocol
+    fn close(&mut self, ctx: &mut <Self as Actor>::Context, code: ws::CloseCode, reason: &str) {
+        self.span
+            .in_scope(|| tracing::debug!("Closing the socket: {}", reason));
+        self.disconnect = DisconnectReason::Misbehaved;
+        if let Transport::Poll(outbox) = &self.transport {
+            outbox.close(reason);
+        }
+        ctx.close(Some(ws::CloseReason {
+            code,
+            description: Some(reason.to_string()),
+        }));
+        ctx.stop();
+    }
+}
+
+impl Actor for PlayerActor {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.span.in_scope(|| tracing::debug!("Connected"));
+        self.hb(ctx);
+        ctx.run_later(self.heartbeat.timeout, |act, ctx| {
+            if !act.joined {
+                act.close(ctx, ws::CloseCode::Policy, "No hello was received");
+            }
+        });
+    }
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        if let Transport::Poll(outbox) = &self.transport {
+            outbox.close("Disconnected");
+        }
+
+        // Clients that never joined have nothing to leave
+        if !self.joined {
+            return;
+        }
+
+        // Stopping only happens once, so this is the only place the disconnect is reported
+        self.span
+            .in_scope(|| tracing::debug!("Disconnected: {:?}", self.disconnect));
+        self.manager.do_send(session::PlayerDisconnect {
+            session: self.session.clone(),
+            player: ctx.address(),
+            reason: self.disconnect,
+        });
+    }
+}
+
+impl Handler<SyncPosition> for PlayerActor {
+    type Result = <SyncPosition as Message>::Result;
+
+    fn handle(&mut self, msg: SyncPosition, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::SyncPosition);
+    }
+}
+
+impl Handler<ChangePosition> for PlayerActor {
+    type Result = <ChangePosition as Message>::Result;
+
+    fn handle(&mut self, msg: ChangePosition, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::ChangePosition {
+                position: msg.position,
+                server_ms: clock::unix_ms(SystemTime::now()),
+            },
+        );
+    }
+}
+
+impl Handler<ChangeState> for PlayerActor {
+    type Result = <ChangeState as Message>::Result;
+
+    fn handle(&mut self, msg: ChangeState, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::ChangeState {
+                state: msg.state,
+                by: msg.by,
+            },
+        );
+    }
+}
+
+impl Handler<ChangeRate> for PlayerActor {
+    type Result = <ChangeRate as Message>::Result;
+
+    fn handle(&mut self, msg: ChangeRate, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::ChangeRate(msg.rate));
+    }
+}
+
+impl Handler<ChangePlaylist> for PlayerActor {
+    type Result = <ChangePlaylist as Message>::Result;
+
+    fn handle(&mut self, msg: ChangePlaylist, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::ChangePlaylist(msg.index));
+    }
+}
+
+impl Handler<ChatMessage> for PlayerActor {
+    type Result = <ChatMessage as Message>::Result;
+
+    fn handle(&mut self, msg: ChatMessage, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::Chat {
+                from: msg.from,
+                text: msg.text,
+            },
+        );
+    }
+}
+
+impl Handler<PlayerLeft> for PlayerActor {
+    type Result = <PlayerLeft as Message>::Result;
+
+    fn handle(&mut self, msg: PlayerLeft, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::PlayerLeft {
+                name: msg.name,
+                reason: msg.reason,
+            },
+        );
+    }
+}
+
+impl Handler<ReactionMessage> for PlayerActor {
+    type Result = <ReactionMessage as Message>::Result;
+
+    fn handle(&mut self, msg: ReactionMessage, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::Reaction {
+                from: msg.from,
+                emoji: msg.emoji,
+            },
+        );
+    }
+}
+
+impl Handler<PlaylistUpdated> for PlayerActor {
+    type Result = <PlaylistUpdated as Message>::Result;
+
+    fn handle(&mut self, msg: PlaylistUpdated, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::PlaylistUpdated {
+                added: msg.added,
+                removed: msg.removed,
+                replaced_from: msg.replaced_from,
+                current: msg.index,
+            },
+        );
+    }
+}
+
+impl Handler<Viewers> for PlayerActor {
+    type Result = <Viewers as Message>::Result;
+
+    fn handle(&mut self, msg: Viewers, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::Viewers(msg.viewers));
+    }
+}
+
+impl Handler<Welcome> for PlayerActor {
+    type Result = <Welcome as Message>::Result;
+
+    fn handle(&mut self, msg: Welcome, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::Welcome { id: msg.id });
+    }
+}
+
+impl Handler<SessionInfo> for PlayerActor {
+    type Result = <SessionInfo as Message>::Result;
+
+    fn handle(&mut self, msg: SessionInfo, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::SessionInfo {
+                generation: msg.generation,
+                playlist_len: msg.playlist_len,
+            },
+        );
+    }
+}
+
+impl Handler<SessionScheduled> for PlayerActor {
+    type Result = <SessionScheduled as Message>::Result;
+
+    fn handle(&mut self, msg: SessionScheduled, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::SessionScheduled {
+                starts_at: session::unix_secs(msg.starts_at),
+            },
+        );
+    }
+}
+
+impl Handler<SessionStarted> for PlayerActor {
+    type Result = <SessionStarted as Message>::Result;
+
+    fn handle(&mut self, msg: SessionStarted, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::SessionStarted);
+    }
+}
+
+impl Handler<PlaylistReplaced> for PlayerActor {
+    type Result = <PlaylistReplaced as Message>::Result;
+
+    fn handle(&mut self, msg: PlaylistReplaced, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::PlaylistReplaced {
+                generation: msg.generation,
+                shitposts: msg.shitposts,
+                current: msg.index,
+            },
+        );
+    }
+}
+
+impl Handler<Kicked> for PlayerActor {
+    type Result = <Kicked as Message>::Result;
+
+    fn handle(&mut self, msg: Kicked, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::Kicked { reason: msg.reason });
+    }
+}
+
+impl Handler<SessionFull> for PlayerActor {
+    type Result = <SessionFull as Message>::Result;
+
+    fn handle(&mut self, msg: SessionFull, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::SessionFull {
+                players: msg.players,
+                max_players: msg.max_players,
+            },
+        );
+    }
+}
+
+impl Handler<HostToken> for PlayerActor {
+    type Result = <HostToken as Message>::Result;
+
+    fn handle(&mut self, msg: HostToken, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::HostToken { token: msg.token });
+    }
+}
+
+impl Handler<LoopModeChanged> for PlayerActor {
+    type Result = <LoopModeChanged as Message>::Result;
+
+    fn handle(&mut self, msg: LoopModeChanged, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::LoopModeChanged(msg.mode));
+    }
+}
+
+impl Handler<Bookmarked> for PlayerActor {
+    type Result = <Bookmarked as Message>::Result;
+
+    fn handle(&mut self, msg: Bookmarked, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::Bookmarked {
+                by: msg.by,
+                title: msg.title,
+                position: msg.position,
+                note: msg.note,
+            },
+        );
+    }
+}
+
+impl Handler<EntrySkipped> for PlayerActor {
+    type Result = <EntrySkipped as Message>::Result;
+
+    fn handle(&mut self, msg: EntrySkipped, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::EntrySkipped {
+                entry: msg.index,
+                reason: msg.reason,
+            },
+        );
+    }
+}
+
+impl Handler<FilteredTags> for PlayerActor {
+    type Result = <FilteredTags as Message>::Result;
+
+    fn handle(&mut self, msg: FilteredTags, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::TagsFiltered(msg.tags));
+    }
+}
+
+impl Handler<MutedFolders> for PlayerActor {
+    type Result = <MutedFolders as Message>::Result;
+
+    fn handle(&mut self, msg: MutedFolders, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::FolderMuted(msg.folders));
+    }
+}
+
+impl Handler<ShowError> for PlayerActor {
+    type Result = <ShowError as Message>::Result;
+
+    fn handle(&mut self, msg: ShowError, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::Error { reason: msg.reason });
+    }
+}
+
+impl Handler<WaitForStragglersChanged> for PlayerActor {
+    type Result = <WaitForStragglersChanged as Message>::Result;
+
+    fn handle(&mut self, msg: WaitForStragglersChanged, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::WaitForStragglersChanged(msg.enabled));
+    }
+}
+
+impl Handler<DebugChanged> for PlayerActor {
+    type Result = <DebugChanged as Message>::Result;
+
+    fn handle(&mut self, msg: DebugChanged, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::DebugChanged(msg.enabled));
+    }
+}
+
+impl Handler<PermissionsChanged> for PlayerActor {
+    type Result = <PermissionsChanged as Message>::Result;
+
+    fn handle(&mut self, msg: PermissionsChanged, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::PermissionsChanged(msg.permissions));
+    }
+}
+
+impl Handler<PermissionDenied> for PlayerActor {
+    type Result = <PermissionDenied as Message>::Result;
+
+    fn handle(&mut self, msg: PermissionDenied, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::PermissionDenied { action: msg.action });
+    }
+}
+
+impl Handler<DebugSync> for PlayerActor {
+    type Result = <DebugSync as Message>::Result;
+
+    fn handle(&mut self, msg: DebugSync, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::DebugSync {
+                players: msg.players,
+            },
+        );
+    }
+}
+
+impl Handler<WaitingFor> for PlayerActor {
+    type Result = <WaitingFor as Message>::Result;
+
+    fn handle(&mut self, msg: WaitingFor, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::WaitingFor { name: msg.name });
+    }
+}
+
+impl Handler<PlaylistFinished> for PlayerActor {
+    type Result = <PlaylistFinished as Message>::Result;
+
+    fn handle(&mut self, msg: PlaylistFinished, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::PlaylistFinished);
+    }
+}
+
+impl Handler<SessionClosed> for PlayerActor {
+    type Result = <SessionClosed as Message>::Result;
+
+    fn handle(&mut self, msg: SessionClosed, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::SessionClosed);
+        self.disconnect = DisconnectReason::Kicked;
+        ctx.close(Some(ws::CloseCode::Normal.into()));
+        ctx.stop();
+    }
+}
+
+impl Handler<NoSuchSession> for PlayerActor {
+    type Result = <NoSuchSession as Message>::Result;
+
+    fn handle(&mut self, msg: NoSuchSession, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::NoSuchSession);
+        self.disconnect = DisconnectReason::Kicked;
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Normal,
+            description: Some("No such session exists".to_string()),
+        }));
+        ctx.stop();
+    }
+}
+
+impl Handler<ServerClosing> for PlayerActor {
+    type Result = <ServerClosing as Message>::Result;
+
+    fn handle(&mut self, msg: ServerClosing, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::ServerClosing {
+                reconnect_after_secs: msg.reconnect_after_secs,
+            },
+        );
+        self.disconnect = DisconnectReason::Shutdown;
+        ctx.close(Some(ws::CloseCode::Away.into()));
+        ctx.stop();
+    }
+}
+
+impl Handler<Disconnect> for PlayerActor {
+    type Result = <Disconnect as Message>::Result;
+
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Self::Context) -> Self::Result {
+        self.disconnect = DisconnectReason::Kicked;
+        ctx.close(Some(ws::CloseCode::Policy.into()));
+        ctx.stop();
+    }
+}
+
+impl Handler<ReadyStatus> for PlayerActor {
+    type Result = <ReadyStatus as Message>::Result;
+
+    fn handle(&mut self, msg: ReadyStatus, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::ReadyStatus {
+                ready: msg.ready,
+                total: msg.total,
+            },
+        );
+    }
+}
+
+impl Handler<Intermission> for PlayerActor {
+    type Result = <Intermission as Message>::Result;
+
+    fn handle(&mut self, msg: Intermission, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::Intermission {
+                next_title: msg.next_title,
+                seconds: msg.seconds,
+            },
+        );
+    }
+}
+
+impl Handler<Countdown> for PlayerActor {
+    type Result = <Countdown as Message>::Result;
+
+    fn handle(&mut self, msg: Countdown, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::Countdown {
+                seconds: msg.seconds,
+            },
+        );
+    }
+}
+
+impl Handler<History> for PlayerActor {
+    type Result = <History as Message>::Result;
+
+    fn handle(&mut self, msg: History, ctx: &mut Self::Context) -> Self::Result {
+        self.send(ctx, BackendMessage::History(msg.entries));
+    }
+}
+
+impl Handler<SkipVotes> for PlayerActor {
+    type Result = <SkipVotes as Message>::Result;
+
+    fn handle(&mut self, msg: SkipVotes, ctx: &mut Self::Context) -> Self::Result {
+        self.send(
+            ctx,
+            BackendMessage::SkipVotes {
+                current: msg.current,
+                needed: msg.needed,
+            },
+        );
+    }
+}
+
+impl Handler<ClientMessage> for PlayerActor {
+    type Result = <ClientMessage as Message>::Result;
+
+    fn handle(&mut self, msg: ClientMessage, ctx: &mut Self::Context) -> Self::Result {
+        let span = self.span.clone();
+        let _span = span.enter();
+        self.hb = Instant::now();
+        self.receive(&msg.text, ctx);
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for PlayerActor {
+    fn handle(&mut self, item: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let span = self.span.clone();
+        let _span = span.enter();
+        // Any traffic shows the client is still there, not just answered pings
+        if item.is_ok() {
+            self.hb = Instant::now();
+        }
+
+        match item {
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Pong(payload)) => {
+                if let Some(latency) = self.round_trip(&payload) {
+                    self.latency = latency;
+                    if self.joined {
+                        self.send(
+                            ctx,
+                            BackendMessage::Latency {
+                                ms: latency.as_millis() as u64,
+                            },
+                        );
+                    }
+                }
+            }
+            Ok(ws::Message::Text(text)) => self.receive(&text, ctx),
+            Ok(ws::Message::Close(reason)) => {
+                match &reason {
+                    Some(reason) => tracing::debug!(
+                        r#"Player left session "{}": {:?} {}"#,
+                        self.session,
+                        reason.code,
+                        reason.description.as_deref().unwrap_or_default()
+                    ),
+                    None => tracing::debug!(r#"Player left session "{}""#, self.session),
+                }
+                self.disconnect = DisconnectReason::Left;
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(ws::Message::Binary(_)) => {
+                self.disconnect = DisconnectReason::Misbehaved;
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Unsupported,
+                    description: Some("Binary messages are not supported".to_string()),
+                }));
+                ctx.stop();
+            }
+            // Clients only ever send messages small enough for a single frame
+            Ok(ws::Message::Continuation(_)) => {
+                self.disconnect = DisconnectReason::Misbehaved;
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Protocol,
+                    description: Some("Fragmented messages are not supported".to_string()),
+                }));
+                ctx.stop();
+            }
+            Ok(ws::Message::Nop) => {}
+            Err(why) => {
+                tracing::warn!(r#"Protocol error in session "{}": {}"#, self.session, why);
+                self.disconnect = DisconnectReason::Misbehaved;
+                ctx.stop();
+            }
+        }
+    }
+}
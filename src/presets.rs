@@ -0,0 +1,276 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{session::Permissions, Config};
+
+/// Named host page settings, kept on disk so a host doesn't pick the same folders every week
+pub struct Presets {
+    path: PathBuf,
+    presets: Mutex<BTreeMap<String, Preset>>,
+    /// Held while writing so flushes don't interleave
+    flush: Mutex<()>,
+}
+
+/// What the host form is filled in with
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    pub folders: Vec<PresetFolder>,
+    pub amount: usize,
+    #[serde(default)]
+    pub options: PresetOptions,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PresetFolder {
+    pub name: String,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+/// The rest of the host form, the password and start time are left out on purpose
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresetOptions {
+    pub permissions: Permissions,
+    pub max_players: Option<usize>,
+    pub public: bool,
+    pub maybe_unsupported: bool,
+    pub intermission_secs: Option<u64>,
+    /// URLs of files left out of the roll
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PresetError {
+    /// Nothing usable was left of the name
+    InvalidName,
+    TooMany,
+}
+
+impl Preset {
+    /// Folders no longer in the config, the rest of the preset can still be used
+    pub fn missing_folders<'a>(&'a self, config: &Config) -> Vec<&'a str> {
+        self.folders
+            .iter()
+            .map(|folder| folder.name.as_str())
+            .filter(|name| !config.source_names().any(|known| known == *name))
+            .collect()
+    }
+}
+
+impl Presets {
+    pub const MAX_PRESETS: usize = 100;
+    const MAX_NAME_LENGTH: usize = 40;
+
+    /// Loads the presets, starting over if the file is missing or unreadable
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+
+        let presets = match fs::read_to_string(&path) {
+            Ok(text) => ron::from_str(&text).unwrap_or_else(|why| {
+                tracing::warn!(r#"Ignoring invalid "{}": {}"#, path.display(), why);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+
+        Self {
+            path,
+            presets: Mutex::new(presets),
+            flush: Mutex::new(()),
+        }
+    }
+
+    /// The name as it is stored: trimmed, with inner whitespace collapsed and only letters,
+    /// digits, spaces, dashes and underscores kept. None if nothing is left.
+    pub fn sanitize_name(name: &str) -> Option<String> {
+        let name = name
+            .chars()
+            .filter(|c| c.is_alphanumeric() || *c == ' ' || *c == '-' || *c == '_')
+            .collect::<String>()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .take(Self::MAX_NAME_LENGTH)
+            .collect::<String>();
+
+        Some(name.trim_end().to_string()).filter(|name| !name.is_empty())
+    }
+
+    /// Presets ordered by name
+    pub fn list(&self) -> Vec<(String, Preset)> {
+        self.presets
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, preset)| (name.clone(), preset.clone()))
+            .collect()
+    }
+
+    /// Saves a preset, replacing one with the same name. Returns the name it was saved under.
+    pub fn save(self: &Arc<Self>, name: &str, preset: Preset) -> Result<String, PresetError> {
+        let name = Self::sanitize_name(name).ok_or(PresetError::InvalidName)?;
+
+        {
+            let mut presets = self.presets.lock().unwrap();
+            if !presets.contains_key(&name) && presets.len() >= Self::MAX_PRESETS {
+                return Err(PresetError::TooMany);
+            }
+            presets.insert(name.clone(), preset);
+        }
+
+        tracing::info!(r#"Saved preset "{}""#, name);
+        let store = self.clone();
+        actix_web::rt::task::spawn_blocking(move || store.flush());
+        Ok(name)
+    }
+
+    /// Returns whether there was a preset with the name, which is looked up as it would be saved
+    pub fn remove(self: &Arc<Self>, name: &str) -> bool {
+        let Some(name) = Self::sanitize_name(name) else {
+            return false;
+        };
+        if self.presets.lock().unwrap().remove(&name).is_none() {
+            return false;
+        }
+
+        tracing::info!(r#"Deleted preset "{}""#, name);
+        let store = self.clone();
+        actix_web::rt::task::spawn_blocking(move || store.flush());
+        true
+    }
+
+    fn flush(&self) {
+        let _guard = self.flush.lock().unwrap();
+        // Serialized under the flush lock, so the last write always has the latest state
+        let text = ron::ser::to_string_pretty(
+            &*self.presets.lock().unwrap(),
+            ron::ser::PrettyConfig::default(),
+        )
+        .unwrap();
+
+        if let Err(why) = fs::write(&self.path, text) {
+            tracing::warn!(r#"Failed to write "{}": {}"#, self.path.display(), why);
+        }
+    }
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        presets::{Preset, PresetError, PresetFolder, PresetOptions, Presets},
+        Config,
+    };
+
+    fn preset(folders: &[&str]) -> Preset {
+        Preset {
+            folders: folders
+                .iter()
+                .map(|name| PresetFolder {
+                    name: name.to_string(),
+                    weight: 2,
+                })
+                .collect(),
+            amount: 50,
+            options: PresetOptions {
+                intermission_secs: Some(5),
+                exclude: vec!["/shitposts/memes/joke.webm".to_string()],
+                ..PresetOptions::default()
+            },
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            Presets::sanitize_name("  Friday   night ").as_deref(),
+            Some("Friday night")
+        );
+        assert_eq!(
+            Presets::sanitize_name("../../etc/passwd").as_deref(),
+            Some("etcpasswd")
+        );
+        assert_eq!(
+            Presets::sanitize_name(r#"a"), (b: "#).as_deref(),
+            Some("a b")
+        );
+        assert_eq!(
+            Presets::sanitize_name("tröllö_2-1").as_deref(),
+            Some("tröllö_2-1")
+        );
+        assert_eq!(Presets::sanitize_name(" /// "), None);
+        assert_eq!(
+            Presets::sanitize_name(&"a".repeat(100)).map(|name| name.len()),
+            Some(Presets::MAX_NAME_LENGTH)
+        );
+    }
+
+    #[actix_web::test]
+    async fn storing() {
+        let path = std::env::temp_dir().join(format!("presets-{}.ron", std::process::id()));
+        let presets = Arc::new(Presets::load(&path));
+        assert!(presets.list().is_empty());
+
+        assert_eq!(
+            presets.save(" weekly ", preset(&["memes", "clips"])),
+            Ok("weekly".to_string())
+        );
+        assert_eq!(
+            presets.save("!!!", preset(&[])),
+            Err(PresetError::InvalidName)
+        );
+        // Saving again replaces it
+        presets.save("weekly", preset(&["memes"])).unwrap();
+        assert_eq!(presets.list(), [("weekly".to_string(), preset(&["memes"]))]);
+
+        // Written in the background
+        presets.flush();
+        let reloaded = Presets::load(&path);
+        assert_eq!(reloaded.list(), presets.list());
+
+        presets.save("friday night", preset(&[])).unwrap();
+        assert!(presets.remove("weekly"));
+        assert!(!presets.remove("weekly"));
+        // Found like it was saved
+        assert!(presets.remove("  friday   night!"));
+        assert!(!presets.remove("../.."));
+
+        for i in 0..Presets::MAX_PRESETS {
+            presets.save(&i.to_string(), preset(&[])).unwrap();
+        }
+        assert_eq!(
+            presets.save("one more", preset(&[])),
+            Err(PresetError::TooMany)
+        );
+        presets.save("0", preset(&["clips"])).unwrap();
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn missing_folders() {
+        let config: Config =
+            ron::from_str(r#"(shitposts: [(path: "/tmp", name: "memes")], bind: "")"#).unwrap();
+        assert_eq!(
+            preset(&["memes", "gone"]).missing_folders(&config),
+            ["gone"]
+        );
+        // Older files without options still load
+        let preset: Preset = ron::from_str(r#"(folders: [(name: "memes")], amount: 10)"#).unwrap();
+        assert_eq!(preset.folders[0].weight, 1);
+        assert_eq!(preset.options, PresetOptions::default());
+    }
+}
@@ -0,0 +1,79 @@
+use actix::Addr;
+use actix_web::{
+    get,
+    web::{Data, Query},
+    HttpRequest, HttpResponse,
+};
+use qrcode::{render::svg, QrCode};
+use serde::Deserialize;
+
+use crate::{
+    player::AppError,
+    session::{self, SessionCode, SessionManager},
+    SharedConfig,
+};
+
+/// Smallest size the code is rendered at in pixels
+const MIN_SIZE: u32 = 200;
+
+#[derive(Deserialize)]
+struct QrQuery {
+    session: SessionCode,
+}
+
+/// QR code of the link to join a session, only for sessions that exist
+#[get("/qr")]
+async fn qr(
+    manager: Data<Addr<SessionManager>>,
+    config: Data<SharedConfig>,
+    query: Query<QrQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    manager
+        .send(session::GetSession {
+            session: query.session.clone(),
+        })
+        .await?
+        .ok_or_else(|| AppError::NotFound("No such session exists".to_string()))?;
+
+    let base = match &config.get().public_url {
+        Some(public_url) => public_url.clone(),
+        None => {
+            let info = req.connection_info();
+            format!("{}://{}", info.scheme(), info.host())
+        }
+    };
+
+    let code = QrCode::new(join_url(&base, &query.session))
+        .map_err(|why| AppError::Internal(why.to_string()))?;
+    let image = code
+        .render::<svg::Color>()
+        .min_dimensions(MIN_SIZE, MIN_SIZE)
+        .build();
+
+    Ok(HttpResponse::Ok().content_type("image/svg+xml").body(image))
+}
+
+/// The index joins right away when given a session, unlike /join which only returns the player
+pub fn join_url(base: &str, session: &SessionCode) -> String {
+    format!("{}/?session={}", base.trim_end_matches('/'), session)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{qr::join_url, session::SessionCode};
+
+    #[test]
+    fn join_urls() {
+        let session = SessionCode::try_from("abc123".to_string()).unwrap();
+
+        assert_eq!(
+            join_url("https://example.com", &session),
+            "https://example.com/?session=abc123"
+        );
+        assert_eq!(
+            join_url("https://example.com/memes/", &session),
+            "https://example.com/memes/?session=abc123"
+        );
+    }
+}
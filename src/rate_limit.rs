@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Allows bursts of up to `capacity` actions, refilling one every `refill`
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    capacity: u32,
+    refill: Duration,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, refill: Duration) -> Self {
+        Self {
+            capacity,
+            refill,
+            tokens: capacity as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Takes a token if there is one
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last);
+        self.last = now;
+
+        self.tokens = if self.refill.is_zero() {
+            self.capacity as f64
+        } else {
+            (self.tokens + elapsed.as_secs_f64() / self.refill.as_secs_f64())
+                .min(self.capacity as f64)
+        };
+    }
+
+    /// A full bucket behaves like a new one and doesn't need to be kept
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity as f64
+    }
+}
+
+/// A token bucket per key, like per IP address
+pub struct RateLimiter<K> {
+    capacity: u32,
+    refill: Duration,
+    buckets: Mutex<HashMap<K, TokenBucket>>,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    /// Buckets are only pruned once there are this many
+    const PRUNE_THRESHOLD: usize = 1024;
+
+    pub fn new(capacity: u32, refill: Duration) -> Self {
+        Self {
+            capacity,
+            refill,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn try_take(&self, key: K) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= Self::PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| !bucket.is_full(now));
+        }
+
+        buckets
+            .entry(key)
+            .or_insert_with(|| TokenBucket::new(self.capacity, self.refill))
+            .try_take(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::rate_limit::TokenBucket;
+
+    #[test]
+    fn bucket_refill() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(3, Duration::from_secs(10));
+        bucket.last = start;
+
+        // The whole burst is available right away
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+
+        // Half a refill isn't enough
+        assert!(!bucket.try_take(start + Duration::from_secs(5)));
+        assert!(bucket.try_take(start + Duration::from_secs(10)));
+        assert!(!bucket.try_take(start + Duration::from_secs(10)));
+
+        // Refilling stops at the capacity
+        let later = start + Duration::from_secs(1000);
+        assert!(bucket.try_take(later));
+        assert!(bucket.try_take(later));
+        assert!(bucket.try_take(later));
+        assert!(!bucket.try_take(later));
+    }
+
+    #[test]
+    fn full_buckets() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2, Duration::from_secs(1));
+        bucket.last = start;
+
+        assert!(bucket.is_full(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.is_full(start));
+        assert!(bucket.is_full(start + Duration::from_secs(1)));
+    }
+}
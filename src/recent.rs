@@ -0,0 +1,110 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Files played across all sessions, kept on disk so hosting picks different ones for a while
+pub struct RecentlyPlayed {
+    path: PathBuf,
+    cooldown: Duration,
+    /// File URLs and when they were last played as a unix timestamp in seconds
+    played: Mutex<HashMap<String, u64>>,
+    /// Held while writing so flushes don't interleave
+    flush: Mutex<()>,
+}
+
+impl RecentlyPlayed {
+    /// Loads the store, starting over if the file is missing or unreadable
+    pub fn load(path: impl Into<PathBuf>, cooldown: Duration) -> Self {
+        let path = path.into();
+
+        let mut played = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|why| {
+                tracing::warn!(r#"Ignoring invalid "{}": {}"#, path.display(), why);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        prune(&mut played, now(), cooldown);
+
+        Self {
+            path,
+            cooldown,
+            played: Mutex::new(played),
+            flush: Mutex::new(()),
+        }
+    }
+
+    /// Marks a file as played and writes the store out on a blocking thread
+    pub fn record(self: &Arc<Self>, url: &str) {
+        if self.cooldown.is_zero() {
+            return;
+        }
+
+        {
+            let mut played = self.played.lock().unwrap();
+            played.insert(url.to_string(), now());
+            prune(&mut played, now(), self.cooldown);
+        }
+
+        let store = self.clone();
+        actix_web::rt::task::spawn_blocking(move || store.flush());
+    }
+
+    /// URLs of the files played within the cooldown
+    pub fn urls(&self) -> HashSet<String> {
+        let mut played = self.played.lock().unwrap();
+        prune(&mut played, now(), self.cooldown);
+
+        played.keys().cloned().collect()
+    }
+
+    fn flush(&self) {
+        let _guard = self.flush.lock().unwrap();
+        // Serialized under the flush lock, so the last write always has the latest state
+        let json = serde_json::to_vec(&*self.played.lock().unwrap()).unwrap();
+
+        if let Err(why) = fs::write(&self.path, json) {
+            tracing::warn!(r#"Failed to write "{}": {}"#, self.path.display(), why);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Forgets files played longer than the cooldown ago
+fn prune(played: &mut HashMap<String, u64>, now: u64, cooldown: Duration) {
+    played.retain(|_, played_at| now.saturating_sub(*played_at) < cooldown.as_secs());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use crate::recent::prune;
+
+    #[test]
+    fn cooldown_pruning() {
+        let day = 24 * 60 * 60;
+        let now = 100 * day;
+        let mut played = HashMap::from([
+            ("/shitposts/a/old.mp4".to_string(), now - 15 * day),
+            ("/shitposts/a/recent.mp4".to_string(), now - 13 * day),
+            ("/shitposts/a/today.mp4".to_string(), now),
+        ]);
+
+        prune(&mut played, now, Duration::from_secs(14 * day));
+
+        assert!(!played.contains_key("/shitposts/a/old.mp4"));
+        assert!(played.contains_key("/shitposts/a/recent.mp4"));
+        assert!(played.contains_key("/shitposts/a/today.mp4"));
+    }
+}
@@ -1,200 +1,5756 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    borrow::Borrow,
+    cmp::Ordering,
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque},
+    fmt,
+    hash::Hash,
+    net::IpAddr,
     sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use actix::{Actor, Addr, Context, Handler, Message, MessageResponse};
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message, MessageResponse, SpawnHandle};
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    auth,
+    blocklist::Blocklist,
+    clock,
     player::{self, PlayerActor},
-    Shitpost,
+    qr,
+    recent::RecentlyPlayed,
+    stats::{self, StatsCollector},
+    webhook::{duration_text, Webhook},
+    Config, Shitpost, ShitpostKind,
 };
 
+/// Longer chat messages are cut off
+const MAX_CHAT_LENGTH: usize = 500;
+/// How many playlist transitions are remembered per session
+const MAX_HISTORY: usize = 500;
+/// How many events are kept per session
+const MAX_EVENTS: usize = 1000;
+/// Most tags a session can filter at once
+const MAX_FILTERED_TAGS: usize = 32;
+/// Longer bookmark notes are cut off
+const MAX_NOTE_LENGTH: usize = 200;
+/// Most bookmarks a session keeps
+const MAX_BOOKMARKS: usize = 500;
+/// Reports of an entry ending this soon after the next one started are about the one left behind
+const COMPLETION_GRACE: Duration = Duration::from_secs(2);
+/// Playback rates players can agree on
+const RATE_RANGE: (f64, f64) = (0.25, 4.0);
+/// Furthest ahead a session can be scheduled
+pub const MAX_SCHEDULE_AHEAD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Emoji players can react with
+pub const REACTIONS: &[&str] = &[
+    "😂", "🤣", "😭", "💀", "😱", "🤮", "😍", "🔥", "👏", "👀", "🗿", "❤️",
+];
+
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct StateChanged {
-    pub session: Arc<str>,
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
     pub state: player::State,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct Seeked {
+pub struct RateChanged {
+    pub session: SessionCode,
     pub player: Addr<PlayerActor>,
+    pub rate: f64,
 }
 
+/// Changes what happens once an entry is over
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct PlaylistChanged {
-    pub session: Arc<str>,
+pub struct SetLoopMode {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub mode: LoopMode,
+}
+
+/// Sets the seconds skipped at the start of an entry
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetStartOffset {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
     pub index: usize,
+    pub seconds: f64,
 }
 
+/// Stops or resumes playing the entries of a folder
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct Position {
-    pub session: Arc<str>,
-    pub position: f64,
+pub struct MuteFolder {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub folder: String,
+    pub muted: bool,
 }
 
+/// Reports the entry at the index as broken, which skips it if it is still on
 #[derive(Message)]
-#[rtype(result = "bool")]
-pub struct NewSession {
-    pub session: Arc<str>,
-    pub shitposts: Vec<Shitpost>,
+#[rtype(result = "()")]
+pub struct ReportBroken {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub index: usize,
 }
 
+/// Passes over the entries of folders with any of the tags, host only
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct PlayerConnect {
-    pub session: Arc<str>,
+pub struct FilterTags {
+    pub session: SessionCode,
     pub player: Addr<PlayerActor>,
+    pub tags: Vec<String>,
 }
 
+/// Turns pausing everyone while a player is stalled on or off
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct PlayerDisconnect {
-    pub session: Arc<str>,
+pub struct SetWaitForStragglers {
+    pub session: SessionCode,
     pub player: Addr<PlayerActor>,
+    pub enabled: bool,
 }
 
+/// Turns the periodic debug overlay of every player on or off
 #[derive(Message)]
-#[rtype(result = "Option<Session>")]
-pub struct GetSession {
-    pub session: Arc<str>,
+#[rtype(result = "()")]
+pub struct SetDebug {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub enabled: bool,
 }
 
-#[derive(MessageResponse, Clone)]
-pub struct Session {
-    pub shitposts: Vec<Shitpost>,
-    pub state: player::State,
-    pub playlist_index: usize,
-    players: Vec<Addr<PlayerActor>>,
+/// Remembers which variant a player loads, it is sent the position to continue from
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetQuality {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub quality: player::Quality,
 }
 
-pub struct SessionManager {
-    sessions: HashMap<Arc<str>, Session>,
+/// Replaces what players other than hosts may do, only hosts may do this
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetPermissions {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub permissions: Permissions,
 }
 
-impl SessionManager {
-    pub fn new() -> Self {
+/// What players other than hosts may do for everyone, hosts may do all of it
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Permissions {
+    /// Pause and resume playback
+    pub allow_pause: bool,
+    /// Seek and set start offsets
+    pub allow_seek: bool,
+    /// Change the entry and how the playlist goes on, like looping or muting folders
+    pub allow_playlist: bool,
+}
+
+impl Permissions {
+    pub const HOST_ONLY: Self = Self {
+        allow_pause: false,
+        allow_seek: false,
+        allow_playlist: false,
+    };
+
+    fn allows(&self, action: Action) -> bool {
+        match action {
+            Action::Pause => self.allow_pause,
+            Action::Seek => self.allow_seek,
+            Action::Playlist => self.allow_playlist,
+        }
+    }
+}
+
+/// Everyone may do everything
+impl Default for Permissions {
+    fn default() -> Self {
         Self {
-            sessions: HashMap::new(),
+            allow_pause: true,
+            allow_seek: true,
+            allow_playlist: true,
         }
     }
 }
 
-impl Actor for SessionManager {
-    type Context = Context<Self>;
+/// What a player tried to do, for telling it that it may not
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Pause,
+    Seek,
+    Playlist,
 }
 
-impl Handler<NewSession> for SessionManager {
-    type Result = <NewSession as Message>::Result;
+/// What happens once an entry is over
+#[derive(Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LoopMode {
+    /// Stop after the last entry
+    #[default]
+    Once,
+    /// Start over from the first entry after the last one
+    LoopPlaylist,
+    /// Replay the current entry
+    LoopOne,
+}
 
-    /// Returns false if the session already exists
-    fn handle(&mut self, msg: NewSession, ctx: &mut Self::Context) -> Self::Result {
-        if let Entry::Vacant(e) = self.sessions.entry(msg.session.clone()) {
-            tracing::info!(r#"Created session "{}""#, msg.session);
-            e.insert(Session {
-                shitposts: msg.shitposts,
-                state: player::State::Paused,
-                playlist_index: 0,
-                players: Vec::new(),
-            });
-            true
-        } else {
-            false
-        }
-    }
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Seeked {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
 }
 
-impl Handler<PlayerConnect> for SessionManager {
-    type Result = <PlayerConnect as Message>::Result;
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PlaylistChanged {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub index: usize,
+}
 
-    fn handle(&mut self, msg: PlayerConnect, ctx: &mut Self::Context) -> Self::Result {
-        if let Some(session) = self.sessions.get_mut(&msg.session) {
-            msg.player.do_send(player::ChangeState {
-                state: session.state,
-            });
-            msg.player.do_send(player::ChangePlaylist {
-                index: session.playlist_index,
-            });
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Position {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub position: f64,
+    /// Round trip time to the player, half of it has passed since the position was taken
+    pub latency: Duration,
+}
+
+/// The player's clock was probed again
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ClockEstimated {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub estimate: clock::Estimate,
+}
 
-            session.players.push(msg.player);
+/// A validated session code: 1 to 32 letters, digits, dashes or underscores
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Deserialize, MessageResponse)]
+#[serde(try_from = "String")]
+pub struct SessionCode(Arc<str>);
 
-            session.players[0].do_send(player::SyncPosition);
-        }
+impl SessionCode {
+    const MAX_LENGTH: usize = 32;
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// A span for work done on behalf of this session
+    pub fn span(&self) -> tracing::Span {
+        tracing::info_span!("session", code = %self)
     }
 }
 
-impl Handler<PlayerDisconnect> for SessionManager {
-    type Result = <PlayerDisconnect as Message>::Result;
+#[derive(Debug)]
+pub struct InvalidSessionCode;
 
-    fn handle(&mut self, msg: PlayerDisconnect, ctx: &mut Self::Context) -> Self::Result {
-        if if let Some(session) = self.sessions.get_mut(&msg.session) {
-            session.players.retain(|player| *player != msg.player);
-            session.players.is_empty()
-        } else {
-            false
-        } {
-            tracing::info!(r#"Session "{}" removed"#, msg.session);
-            self.sessions.remove(&msg.session);
-        }
+impl fmt::Display for InvalidSessionCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Session codes must be 1 to {} letters, digits, dashes or underscores",
+            SessionCode::MAX_LENGTH
+        )
     }
 }
 
-impl Handler<StateChanged> for SessionManager {
-    type Result = <StateChanged as Message>::Result;
+impl TryFrom<String> for SessionCode {
+    type Error = InvalidSessionCode;
 
-    fn handle(&mut self, msg: StateChanged, ctx: &mut Self::Context) -> Self::Result {
-        if let Some(session) = self.sessions.get_mut(&msg.session) {
-            session.state = msg.state;
-            for player in &session.players {
-                player.do_send(player::ChangeState { state: msg.state });
-            }
+    fn try_from(code: String) -> Result<Self, Self::Error> {
+        if (1..=Self::MAX_LENGTH).contains(&code.len())
+            && code
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            Ok(Self(code.into()))
+        } else {
+            Err(InvalidSessionCode)
         }
     }
 }
 
-impl Handler<PlaylistChanged> for SessionManager {
-    type Result = <PlaylistChanged as Message>::Result;
+impl Borrow<str> for SessionCode {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
 
-    fn handle(&mut self, msg: PlaylistChanged, ctx: &mut Self::Context) -> Self::Result {
-        if let Some(session) = self.sessions.get_mut(&msg.session) {
-            session.playlist_index = msg.index;
-            for player in &session.players {
-                player.do_send(player::ChangePlaylist { index: msg.index });
-            }
-        }
+impl fmt::Display for SessionCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
     }
 }
 
-impl Handler<Seeked> for SessionManager {
-    type Result = <Seeked as Message>::Result;
+#[derive(Debug)]
+pub enum NewSessionError {
+    Exists,
+    /// The configured maximum of sessions has been reached
+    TooManySessions,
+}
 
-    fn handle(&mut self, msg: Seeked, ctx: &mut Self::Context) -> Self::Result {
-        msg.player.do_send(player::SyncPosition);
-    }
+/// Why the schedule of a session couldn't be changed
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScheduleError {
+    NoSuchSession,
+    NotHost,
+    /// The session went live already
+    Started,
 }
 
-impl Handler<Position> for SessionManager {
-    type Result = <Position as Message>::Result;
+/// Generates an unused session code
+#[derive(Message)]
+#[rtype(result = "SessionCode")]
+pub struct CreateSession;
 
-    fn handle(&mut self, msg: Position, ctx: &mut Self::Context) -> Self::Result {
-        if let Some(session) = self.sessions.get_mut(&msg.session) {
-            for player in &session.players {
-                player.do_send(player::ChangePosition {
-                    position: msg.position,
-                });
-            }
-        }
-    }
+#[derive(Message)]
+#[rtype(result = "Result<(), NewSessionError>")]
+pub struct NewSession {
+    pub session: SessionCode,
+    pub shitposts: Vec<Shitpost>,
+    /// Required from everyone joining if set
+    pub password: Option<PasswordHash>,
+    /// Players joining with this token are hosts
+    pub host_token: String,
+    /// What players other than hosts may do
+    pub permissions: Permissions,
+    /// Players the session may have at once, hosts not included
+    pub max_players: Option<usize>,
+    /// Listed on the index page for anyone to join
+    pub public: bool,
+    /// Seconds between entries when the playlist moves on by itself
+    pub intermission_secs: Option<u64>,
+    /// What the playlist was rolled with, None if it was picked or imported without one
+    pub seed: Option<u64>,
+    /// URLs of files the host left out of the roll, kept for rerolls and exports
+    pub excluded: Vec<String>,
+    /// Players wait in a lobby until then, None starts the session right away
+    pub starts_at: Option<SystemTime>,
 }
 
-impl Handler<GetSession> for SessionManager {
-    type Result = <GetSession as Message>::Result;
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PlayerConnect {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    /// Falls back to "anonymous-N" if None
+    pub name: Option<Arc<str>>,
+    pub client: Option<Arc<str>>,
+    /// Where the player connected from, None if that isn't known
+    pub address: Option<IpAddr>,
+    pub host_token: Option<String>,
+    pub spectator: bool,
+    /// Generation of the session the player's page was made for, None for a first connect
+    pub generation: Option<u64>,
+}
 
-    fn handle(&mut self, msg: GetSession, ctx: &mut Self::Context) -> Self::Result {
-        self.sessions.get(&msg.session).cloned()
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PlayerDisconnect {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub reason: DisconnectReason,
+}
+
+/// Why a player's connection ended
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+    /// Closed the connection properly
+    Left,
+    /// Went away without closing the connection
+    TimedOut,
+    Kicked,
+    /// Broke the protocol or sent too many malformed messages
+    Misbehaved,
+    /// The server is shutting down
+    Shutdown,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Chat {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub text: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Reaction {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub emoji: String,
+}
+
+/// Returns false if there is no such player in the session
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct KickPlayer {
+    pub session: SessionCode,
+    pub id: u64,
+    pub reason: String,
+    /// Also keep the player's client from rejoining
+    pub ban: bool,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Ready {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StartCountdown {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct VoteSkip {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+}
+
+/// Appends entries to the playlist, returns false if there is no such session
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct AddToPlaylist {
+    pub session: SessionCode,
+    pub shitposts: Vec<Shitpost>,
+}
+
+/// Shuffles the entries after the current one, only hosts may do this
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ShuffleRemaining {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+}
+
+/// Moves an entry right after the current one, only hosts may do this.
+/// Entries that were already played are copied there instead, so the history stays as it was.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PlayNext {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub index: usize,
+}
+
+/// Host only, moves on to the next entry without waiting for the intermission to end
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SkipIntermission {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+}
+
+/// Hidden players keep their spot but stop counting towards the sync, votes and ready checks
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetBackgrounded {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub backgrounded: bool,
+}
+
+/// Returns false if there is no such session or entry, or if it is the last entry left
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct RemoveFromPlaylist {
+    pub session: SessionCode,
+    pub index: usize,
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<PlaylistPage>")]
+pub struct GetPlaylistPage {
+    pub session: SessionCode,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+#[derive(Serialize)]
+pub struct PlaylistPage {
+    pub total: usize,
+    pub shitposts: Vec<Shitpost>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<NowPlaying>")]
+pub struct GetNowPlaying {
+    pub session: SessionCode,
+}
+
+/// The entry a session is on, for overlays
+#[derive(Serialize)]
+pub struct NowPlaying {
+    /// None once the playlist has run out
+    pub title: Option<String>,
+    pub url: Option<String>,
+    pub index: usize,
+    pub total: usize,
+    pub state: player::State,
+    /// Seconds into the entry, moved on by the time since the last report while playing
+    pub position_estimate: f64,
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<PlaylistExport>")]
+pub struct ExportPlaylist {
+    pub session: SessionCode,
+}
+
+/// Version of the exported playlists written now, bumped when the format changes
+pub const EXPORT_VERSION: u32 = 1;
+
+/// A session's playlist as a file that can be shared and imported on the host page
+#[derive(Serialize, Deserialize)]
+pub struct PlaylistExport {
+    pub version: u32,
+    pub code: String,
+    /// Unix time the playlist was exported at
+    #[serde(default)]
+    pub exported_at: u64,
+    /// The folders the entries are from, in the order they first appear
+    #[serde(default)]
+    pub folders: Vec<ExportedFolder>,
+    #[serde(default)]
+    pub permissions: Permissions,
+    #[serde(default)]
+    pub loop_mode: LoopMode,
+    /// What the playlist was rolled with, kept by sessions imported from it
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// URLs of files the host left out, left out again by rerolls of the imported session
+    #[serde(default)]
+    pub excluded: Vec<String>,
+    /// Only the URLs are read back, everything else is looked up again
+    pub shitposts: Vec<Shitpost>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ExportedFolder {
+    pub name: String,
+    pub entries: usize,
+}
+
+/// None if there is no such session, otherwise whether the ticket lets a player in
+#[derive(Message)]
+#[rtype(result = "Option<bool>")]
+pub struct CheckTicket {
+    pub session: SessionCode,
+    pub ticket: Option<String>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<SessionView>")]
+pub struct GetSession {
+    pub session: SessionCode,
+}
+
+/// Moves everyone over to a new playlist, paused at its first entry.
+/// None if there is no such session, otherwise whether the host token was right.
+#[derive(Message)]
+#[rtype(result = "Option<bool>")]
+pub struct ReplacePlaylist {
+    pub session: SessionCode,
+    pub host_token: String,
+    pub shitposts: Vec<Shitpost>,
+    pub seed: Option<u64>,
+}
+
+/// Moves the start of a scheduled session, None starts it right away
+#[derive(Message)]
+#[rtype(result = "Result<(), ScheduleError>")]
+pub struct Reschedule {
+    pub session: SessionCode,
+    pub host_token: String,
+    pub starts_at: Option<SystemTime>,
+}
+
+/// Calls off a scheduled session before it starts, the lobby is sent home
+#[derive(Message)]
+#[rtype(result = "Result<(), ScheduleError>")]
+pub struct CancelScheduled {
+    pub session: SessionCode,
+    pub host_token: String,
+}
+
+/// The session a typed in code means, ignoring case and surrounding whitespace
+#[derive(Message)]
+#[rtype(result = "Option<SessionCode>")]
+pub struct FindSession {
+    pub code: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<SessionSummary>")]
+pub struct ListSessions;
+
+/// The public sessions someone is watching, busiest first
+#[derive(Message)]
+#[rtype(result = "Vec<PublicSession>")]
+pub struct ListPublicSessions;
+
+/// Disconnects everyone from a session and removes it, returns false if there is no such session
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct CloseSession {
+    pub session: SessionCode,
+}
+
+#[derive(Message)]
+#[rtype(result = "MetricsSnapshot")]
+pub struct GetMetrics;
+
+/// Counters collected since startup
+#[derive(Default, Clone)]
+struct Metrics {
+    /// Handled websocket messages by player message kind
+    messages: BTreeMap<&'static str, u64>,
+    sessions_created: u64,
+    playlist_advances: u64,
+}
+
+impl Metrics {
+    fn count_message(&mut self, kind: &'static str) {
+        *self.messages.entry(kind).or_default() += 1;
+    }
+}
+
+#[derive(MessageResponse)]
+pub struct MetricsSnapshot {
+    pub active_sessions: usize,
+    pub connected_players: usize,
+    pub messages: BTreeMap<&'static str, u64>,
+    pub sessions_created: u64,
+    pub playlist_advances: u64,
+}
+
+/// Answered right away, to check the manager is alive without copying anything
+#[derive(Message)]
+#[rtype(result = "Pong")]
+pub struct Ping;
+
+#[derive(MessageResponse)]
+pub struct Pong {
+    pub active_sessions: usize,
+    pub connected_players: usize,
+}
+
+/// The sessions as they would be restored after a restart
+#[derive(Message)]
+#[rtype(result = "Vec<PersistedSession>")]
+pub struct Persist;
+
+/// Tells everyone the server is going down and returns the sessions to restore afterwards
+#[derive(Message)]
+#[rtype(result = "Vec<PersistedSession>")]
+pub struct Shutdown {
+    pub reconnect_after_secs: u64,
+}
+
+/// The state of a session that survives a restart
+#[derive(Serialize, Deserialize)]
+pub struct PersistedSession {
+    code: String,
+    shitposts: Vec<Shitpost>,
+    playlist_index: usize,
+    state: player::State,
+    position: f64,
+    rate: f64,
+    history: Vec<HistoryEntry>,
+    banned: Vec<String>,
+    /// Replaced by `password_hash`, only read from older files
+    #[serde(default, skip_serializing)]
+    password: Option<u64>,
+    #[serde(default)]
+    password_hash: Option<PasswordHash>,
+    host_token: String,
+    /// Replaced by `permissions`, only read from older files
+    #[serde(default, skip_serializing)]
+    host_only: bool,
+    #[serde(default)]
+    permissions: Option<Permissions>,
+    #[serde(default)]
+    loop_mode: LoopMode,
+    #[serde(default)]
+    wait_for_stragglers: bool,
+    #[serde(default)]
+    muted: Vec<String>,
+    #[serde(default)]
+    filtered_tags: Vec<String>,
+    #[serde(default)]
+    bookmarks: Vec<Bookmark>,
+    #[serde(default)]
+    max_players: Option<usize>,
+    #[serde(default)]
+    public: bool,
+    #[serde(default = "new_generation")]
+    generation: u64,
+    #[serde(default)]
+    intermission_secs: Option<u64>,
+    #[serde(default)]
+    seed: Option<u64>,
+    #[serde(default)]
+    excluded: Vec<String>,
+    /// Seconds the session had existed for
+    #[serde(default)]
+    uptime: u64,
+    /// Unix time a scheduled session goes live at
+    #[serde(default)]
+    starts_at: Option<u64>,
+}
+
+/// Sends the host token to the player if it is a host, so it can be passed on to a co-host
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RevealHostToken {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<Vec<LoggedEvent>>")]
+pub struct GetEvents {
+    pub session: SessionCode,
+}
+
+/// Something that happened in a session, player ids are None for things the backend did itself
+#[derive(Serialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SessionEvent {
+    Connected {
+        player: u64,
+        name: String,
+    },
+    Disconnected {
+        player: u64,
+        reason: DisconnectReason,
+    },
+    Kicked {
+        player: u64,
+        reason: String,
+    },
+    StateChanged {
+        player: Option<u64>,
+        state: player::State,
+    },
+    PlaylistChanged {
+        player: Option<u64>,
+        index: usize,
+    },
+    Seeked {
+        player: u64,
+        position: f64,
+    },
+}
+
+#[derive(Serialize, Clone)]
+pub struct LoggedEvent {
+    /// Unix timestamp in milliseconds
+    pub at: u64,
+    #[serde(flatten)]
+    pub event: SessionEvent,
+}
+
+/// The latest events of a session, the oldest are dropped once it is full
+#[derive(Clone, Default)]
+struct EventLog(VecDeque<LoggedEvent>);
+
+impl EventLog {
+    fn push(&mut self, event: SessionEvent) {
+        if self.0.len() == MAX_EVENTS {
+            self.0.pop_front();
+        }
+        self.0.push_back(LoggedEvent {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            event,
+        });
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<Vec<HistoryEntry>>")]
+pub struct GetHistory {
+    pub session: SessionCode,
+}
+
+/// Marks the moment the session is at, with a note on what was so good about it
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AddBookmark {
+    pub session: SessionCode,
+    pub player: Addr<PlayerActor>,
+    pub note: String,
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<Vec<Bookmark>>")]
+pub struct GetBookmarks {
+    pub session: SessionCode,
+}
+
+/// A moment someone wanted to find again
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Bookmark {
+    pub by: String,
+    pub index: usize,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    /// Seconds into the entry
+    pub position: f64,
+    pub note: String,
+    /// Unix timestamp in seconds
+    pub created_at: u64,
+}
+
+/// A previously played entry
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    pub index: usize,
+    pub title: Option<String>,
+    /// Unix timestamp in seconds
+    pub played_at: u64,
+}
+
+/// Serializable overview of a session
+#[derive(Serialize)]
+pub struct SessionSummary {
+    pub code: String,
+    pub players: usize,
+    pub roster: Vec<PlayerSummary>,
+    pub playlist_index: usize,
+    pub state: player::State,
+    pub title: Option<String>,
+    /// Seconds since the session was created or restored
+    pub uptime: u64,
+    /// Unix time the session goes live at, if it is scheduled
+    pub starts_at: Option<u64>,
+}
+
+/// A session as listed on the index page
+pub struct PublicSession {
+    pub code: String,
+    pub player_count: usize,
+    pub current_title: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PlayerSummary {
+    pub id: u64,
+    pub name: String,
+    pub is_host: bool,
+}
+
+/// A player as shown to the other players
+#[derive(Serialize)]
+pub struct ViewerInfo {
+    pub id: u64,
+    /// HTML-escaped
+    pub name: String,
+    pub is_host: bool,
+    pub spectator: bool,
+}
+
+pub struct Session {
+    /// Shared with lookups, so changes go through `playlist_mut` or `edit_playlist`
+    shitposts: Arc<[Shitpost]>,
+    pub state: player::State,
+    pub playlist_index: usize,
+    /// Playback speed everyone watches at
+    pub rate: f64,
+    /// Tells a session apart from an earlier one with the same code
+    pub generation: u64,
+    /// What the playlist was rolled with, so it can be rolled again
+    pub seed: Option<u64>,
+    /// URLs of files the host left out, so rerolls leave them out too
+    excluded: Arc<[String]>,
+    players: Vec<PlayerHandle>,
+    /// Last reported position of each player and when it was reported
+    positions: HashMap<Addr<PlayerActor>, (f64, Instant)>,
+    /// When each player was last corrected for drifting
+    corrected: HashMap<Addr<PlayerActor>, Instant>,
+    /// Last authoritative position of the session and when it was recorded
+    position: (f64, Instant),
+    /// Player whose next position report comes from an explicit seek
+    seeker: Option<Addr<PlayerActor>>,
+    /// Advances the playlist once the current image has been shown long enough
+    image_timer: Option<SpawnHandle>,
+    /// Players that voted to skip the current entry
+    skip_votes: HashSet<Addr<PlayerActor>>,
+    /// Players that are ready for the current entry to start
+    ready: HashSet<Addr<PlayerActor>>,
+    /// Starts playback once the countdown is over
+    countdown: Option<SpawnHandle>,
+    /// Seconds between entries when the playlist moves on by itself
+    intermission_secs: Option<u64>,
+    /// Moves on to the next entry once the intermission is over
+    intermission: Option<SpawnHandle>,
+    /// Playlist transitions, with the title at the time since entries can be removed
+    history: VecDeque<(usize, Option<String>, SystemTime)>,
+    next_player_id: u64,
+    /// Client tokens that may not join
+    banned: HashSet<Arc<str>>,
+    /// Hash of the password needed to join, the session is open to anyone if None
+    password: Option<PasswordHash>,
+    host_token: String,
+    /// What players other than hosts may do
+    permissions: Permissions,
+    /// Players turned away once this many are connected, hosts still get in
+    max_players: Option<usize>,
+    /// Listed on the index page
+    public: bool,
+    loop_mode: LoopMode,
+    /// Whether everyone is paused while a player is stalled
+    wait_for_stragglers: bool,
+    /// The stalled player everyone is waiting for, and the timer playing on without it
+    straggler: Option<(Addr<PlayerActor>, SpawnHandle)>,
+    /// Sends everyone the positions of all players while debugging is on
+    debug: Option<SpawnHandle>,
+    /// Folders whose entries are passed over
+    muted: HashSet<String>,
+    /// Tags whose entries are passed over
+    filtered_tags: HashSet<String>,
+    bookmarks: Vec<Bookmark>,
+    events: EventLog,
+    created: Instant,
+    last_activity: Instant,
+    /// Extends the TTL of restored sessions, so players have time to come back
+    grace: Duration,
+    /// A player joined while nobody was playing, so the next one to play is asked for its position
+    pending_sync: bool,
+    /// Start offset of the current entry, seeked to once someone starts playing it
+    pending_offset: Option<f64>,
+    /// When the current entry was moved to, None for the one the session started or was restored on
+    entered: Option<Instant>,
+    /// Saved as the wrap-up once the session is over
+    stats: StatsCollector,
+    /// When a scheduled session goes live, until then players wait in the lobby
+    starts_at: Option<SystemTime>,
+    /// Starts the scheduled session at `starts_at`
+    start_timer: Option<SpawnHandle>,
+}
+
+/// A session as a lookup sees it, without the players and with the playlist shared
+pub struct SessionView {
+    pub shitposts: Arc<[Shitpost]>,
+    pub generation: u64,
+    pub seed: Option<u64>,
+    /// URLs of files the host left out of the roll
+    pub excluded: Arc<[String]>,
+    live_players: usize,
+    max_players: Option<usize>,
+    password: Option<PasswordHash>,
+    host_token: String,
+    starts_at: Option<SystemTime>,
+}
+
+impl SessionView {
+    /// Players connected at the time of the lookup
+    pub fn live_players(&self) -> usize {
+        self.live_players
+    }
+
+    pub fn max_players(&self) -> Option<usize> {
+        self.max_players
+    }
+
+    /// Whether players that aren't hosts are turned away
+    pub fn is_full(&self) -> bool {
+        self.max_players
+            .is_some_and(|max_players| self.live_players >= max_players)
+    }
+
+    pub fn is_host_token(&self, token: &str) -> bool {
+        self.host_token == token
+    }
+
+    /// When the session goes live, None once it has
+    pub fn starts_at(&self) -> Option<SystemTime> {
+        self.starts_at
+    }
+
+    pub fn check_password(&self, password: &str) -> bool {
+        self.password
+            .as_ref()
+            .is_none_or(|hash| hash.verify(password))
+    }
+
+    pub fn check_ticket(&self, ticket: Option<&str>) -> bool {
+        check_ticket(self.password.as_ref(), ticket)
+    }
+
+    /// What a player that entered the password gets to keep, None for open sessions
+    pub fn ticket(&self) -> Option<String> {
+        self.password.as_ref().map(PasswordHash::ticket)
+    }
+}
+
+/// A connected player and the nickname it goes by
+#[derive(Clone)]
+pub struct PlayerHandle {
+    pub addr: Addr<PlayerActor>,
+    /// Unique within the session
+    pub id: u64,
+    pub name: Arc<str>,
+    pub client: Option<Arc<str>>,
+    pub address: Option<IpAddr>,
+    /// Joined with the host token
+    pub is_host: bool,
+    /// Only watches, doesn't control anything or count towards votes
+    pub spectator: bool,
+    /// Its tab is hidden, so it is left out like a spectator until it comes back
+    pub backgrounded: bool,
+    /// Last state the player reported
+    pub state: Option<player::State>,
+    /// Round trip time the player last reported its position with
+    pub latency: Duration,
+    /// How far its clock is off and the smoothed round trip time, None until it answered a probe
+    pub clock: Option<clock::Estimate>,
+    pub quality: player::Quality,
+}
+
+/// What picking the reference looks at in a player
+#[derive(Clone, Copy)]
+struct Candidate {
+    is_host: bool,
+    spectator: bool,
+    backgrounded: bool,
+    state: Option<player::State>,
+}
+
+impl From<&PlayerHandle> for Candidate {
+    fn from(player: &PlayerHandle) -> Self {
+        Self {
+            is_host: player.is_host,
+            spectator: player.spectator,
+            backgrounded: player.backgrounded,
+            state: player.state,
+        }
+    }
+}
+
+impl Session {
+    fn is_host(&self, addr: &Addr<PlayerActor>) -> bool {
+        self.players
+            .iter()
+            .any(|player| player.addr == *addr && player.is_host)
+    }
+
+    /// Who a change is attributed to, as shown to the players
+    fn initiator(&self, id: u64) -> Option<player::Initiator> {
+        self.players
+            .iter()
+            .find(|player| player.id == id)
+            .map(|player| player::Initiator {
+                id,
+                name: escape_html(&player.name),
+            })
+    }
+
+    fn player_id(&self, addr: &Addr<PlayerActor>) -> Option<u64> {
+        self.players
+            .iter()
+            .find(|player| player.addr == *addr)
+            .map(|player| player.id)
+    }
+
+    /// Whether the player may do this for everyone, spectators never may
+    fn may(&self, addr: &Addr<PlayerActor>, action: Action) -> bool {
+        self.players.iter().any(|player| {
+            player.addr == *addr
+                && !player.spectator
+                && (player.is_host || self.permissions.allows(action))
+        })
+    }
+
+    /// The player everyone else is kept in sync with, None if nobody is playing
+    fn reference(&self) -> Option<&Addr<PlayerActor>> {
+        pick_reference(self.players.iter().map(Candidate::from))
+            .map(|index| &self.players[index].addr)
+    }
+
+    /// Players that count towards skip votes and ready checks
+    fn participants(&self) -> usize {
+        self.players
+            .iter()
+            .filter(|player| !player.spectator && !player.backgrounded)
+            .count()
+    }
+
+    fn is_backgrounded(&self, addr: &Addr<PlayerActor>) -> bool {
+        self.players
+            .iter()
+            .any(|player| player.addr == *addr && player.backgrounded)
+    }
+
+    /// Players still connected, so ones that left or timed out free up their slot right away
+    pub fn live_players(&self) -> usize {
+        self.players
+            .iter()
+            .filter(|player| player.addr.connected())
+            .count()
+    }
+
+    /// Whether players that aren't hosts are turned away
+    pub fn is_full(&self) -> bool {
+        self.max_players
+            .is_some_and(|max_players| self.live_players() >= max_players)
+    }
+
+    pub fn is_host_token(&self, token: &str) -> bool {
+        self.host_token == token
+    }
+
+    pub fn check_ticket(&self, ticket: Option<&str>) -> bool {
+        check_ticket(self.password.as_ref(), ticket)
+    }
+
+    /// The playlist to change in place, copied first if a lookup still holds on to it
+    fn playlist_mut(&mut self) -> &mut [Shitpost] {
+        Arc::make_mut(&mut self.shitposts)
+    }
+
+    /// Changes the length of the playlist, which builds a new one
+    fn edit_playlist<T>(&mut self, edit: impl FnOnce(&mut Vec<Shitpost>) -> T) -> T {
+        let mut shitposts = self.shitposts.to_vec();
+        let result = edit(&mut shitposts);
+        self.shitposts = shitposts.into();
+        result
+    }
+
+    /// What a lookup needs, the playlist is shared rather than copied
+    fn view(&self) -> SessionView {
+        SessionView {
+            shitposts: self.shitposts.clone(),
+            generation: self.generation,
+            seed: self.seed,
+            excluded: self.excluded.clone(),
+            live_players: self.live_players(),
+            max_players: self.max_players,
+            password: self.password.clone(),
+            host_token: self.host_token.clone(),
+            starts_at: self.starts_at,
+        }
+    }
+
+    fn record_history(&mut self) {
+        let title = self
+            .shitposts
+            .get(self.playlist_index)
+            .map(|shitpost| shitpost.title.clone());
+        self.history
+            .push_back((self.playlist_index, title, SystemTime::now()));
+        if self.history.len() > MAX_HISTORY {
+            self.history.pop_front();
+        }
+    }
+
+    fn history(&self) -> Vec<HistoryEntry> {
+        self.history
+            .iter()
+            .map(|(index, title, played_at)| HistoryEntry {
+                index: *index,
+                title: title.clone(),
+                played_at: played_at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+            })
+            .collect()
+    }
+
+    /// The first entry from `index` on that isn't muted or filtered, None if all of them are
+    fn playable_from(&self, index: usize) -> Option<usize> {
+        first_playable(
+            &self.shitposts,
+            &self.muted,
+            &self.filtered_tags,
+            index,
+            self.loop_mode == LoopMode::LoopPlaylist,
+        )
+    }
+
+    /// Whether the current entry is one that would be passed over
+    fn current_passed_over(&self) -> bool {
+        self.shitposts
+            .get(self.playlist_index)
+            .is_some_and(|shitpost| passed_over(shitpost, &self.muted, &self.filtered_tags))
+    }
+
+    /// Muted folders in a stable order for the players
+    fn muted_folders(&self) -> Vec<String> {
+        let mut folders = self.muted.iter().cloned().collect::<Vec<_>>();
+        folders.sort();
+        folders
+    }
+
+    /// Filtered tags in a stable order for the players
+    fn filtered_tags(&self) -> Vec<String> {
+        let mut tags = self.filtered_tags.iter().cloned().collect::<Vec<_>>();
+        tags.sort();
+        tags
+    }
+
+    fn next_index(&self) -> Option<usize> {
+        index_after_completion(self.loop_mode, self.playlist_index, self.shitposts.len())
+    }
+
+    /// Extrapolates a position recorded at some point to the current moment
+    fn extrapolate(&self, (position, recorded): (f64, Instant)) -> f64 {
+        match self.state {
+            player::State::Playing => position + recorded.elapsed().as_secs_f64() * self.rate,
+            _ => position,
+        }
+    }
+
+    /// Whether the player's last correction is long enough ago for another one
+    fn correctable(&self, addr: &Addr<PlayerActor>, interval: Duration, now: Instant) -> bool {
+        self.corrected
+            .get(addr)
+            .is_none_or(|corrected| now.saturating_duration_since(*corrected) >= interval)
+    }
+
+    /// Where the player is estimated to be right now based on its last report
+    fn estimated_position(&self, player: &Addr<PlayerActor>) -> Option<f64> {
+        Some(self.extrapolate(*self.positions.get(player)?))
+    }
+}
+
+pub struct SessionManager {
+    sessions: HashMap<SessionCode, Session>,
+    /// How long a session without players may stay idle before it is evicted
+    ttl: Duration,
+    /// How far in seconds a player may drift from the reference player before it is corrected
+    drift_tolerance: f64,
+    /// How long a drifting player goes uncorrected after a correction, so reports don't echo
+    correction_interval: Duration,
+    /// How long an image is shown before the playlist advances
+    image_duration: Duration,
+    /// How long the countdown before playback lasts
+    countdown: Duration,
+    metrics: Metrics,
+    recent: Arc<RecentlyPlayed>,
+    blocklist: Arc<Blocklist>,
+    max_sessions: usize,
+    /// How long restored sessions are kept without players
+    restore_grace: Duration,
+    /// How long everyone waits for a stalled player
+    straggler_timeout: Duration,
+    /// Where the wrap-ups of ended sessions are saved
+    stats_dir: String,
+    /// Told about sessions starting and ending, if configured
+    webhook: Option<Webhook>,
+    /// Used for the join links in announcements
+    public_url: Option<String>,
+}
+
+impl SessionManager {
+    const CODE_LENGTH: usize = 6;
+    const HOST_TOKEN_LENGTH: usize = 24;
+    /// Alphanumerics without the easily confused 0/O, 1/l/I
+    const CODE_CHARSET: &'static [u8] =
+        b"23456789abcdefghijkmnopqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ";
+
+    const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+    const DEBUG_INTERVAL: Duration = Duration::from_secs(2);
+    /// Most sessions shown on the index page
+    const MAX_PUBLIC_SESSIONS: usize = 20;
+
+    pub fn new(config: &Config, recent: Arc<RecentlyPlayed>, blocklist: Arc<Blocklist>) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            ttl: Duration::from_secs(config.session_ttl),
+            drift_tolerance: config.drift_tolerance,
+            correction_interval: Duration::from_secs(config.correction_interval),
+            image_duration: Duration::from_secs(config.image_duration),
+            countdown: Duration::from_secs(config.countdown),
+            metrics: Metrics::default(),
+            recent,
+            blocklist,
+            max_sessions: config.max_sessions,
+            restore_grace: Duration::from_secs(config.restore_grace),
+            straggler_timeout: Duration::from_secs(config.straggler_timeout),
+            stats_dir: config.stats_dir.clone(),
+            webhook: config.discord_webhook.clone().map(Webhook::new),
+            public_url: config.public_url.clone(),
+        }
+    }
+
+    /// Moves the session to another playlist entry and tells every player about it
+    fn change_playlist(
+        &mut self,
+        name: &SessionCode,
+        index: usize,
+        by: Option<u64>,
+        ctx: &mut Context<Self>,
+    ) {
+        let Some(session) = self.touch(name) else {
+            return;
+        };
+
+        // Muted and filtered entries are passed over
+        let Some(index) = session.playable_from(index) else {
+            self.stop_at_passed_over(name, ctx);
+            return;
+        };
+
+        // Players echo the change back, which must not restart the image timer
+        if session.playlist_index == index {
+            return;
+        }
+
+        self.enter_entry(name, index, by, ctx);
+    }
+
+    /// Moves on from a finished entry after the intermission, if the session has one
+    fn advance(&mut self, name: &SessionCode, ctx: &mut Context<Self>) {
+        let Some(session) = self.sessions.get_mut(name) else {
+            return;
+        };
+        if session.intermission.is_some() {
+            return;
+        }
+
+        let next = session
+            .next_index()
+            .and_then(|next| session.playable_from(next));
+        let (Some(seconds), Some(next)) =
+            (session.intermission_secs.filter(|secs| *secs > 0), next)
+        else {
+            self.advance_now(name, ctx);
+            return;
+        };
+
+        for player in &session.players {
+            player.addr.do_send(player::Intermission {
+                next_title: session
+                    .shitposts
+                    .get(next)
+                    .map(|shitpost| shitpost.title.clone()),
+                seconds,
+            });
+        }
+        let name = name.clone();
+        session.intermission = Some(ctx.run_later(
+            Duration::from_secs(seconds),
+            move |act, ctx| {
+                if let Some(session) = act.sessions.get_mut(&name) {
+                    session.intermission = None;
+                }
+                act.advance_now(&name, ctx);
+            },
+        ));
+    }
+
+    /// Moves on from a finished entry right away, or settles once the playlist is over
+    fn advance_now(&mut self, name: &SessionCode, ctx: &mut Context<Self>) {
+        let Some(session) = self.sessions.get(name) else {
+            return;
+        };
+
+        let Some(next) = session.next_index() else {
+            for player in &session.players {
+                player.addr.do_send(player::PlaylistFinished);
+            }
+            self.change_state(name, player::State::Idle, None, ctx);
+            return;
+        };
+        let Some(next) = session.playable_from(next) else {
+            self.stop_at_passed_over(name, ctx);
+            return;
+        };
+        let looped = next <= session.playlist_index;
+
+        self.enter_entry(name, next, None, ctx);
+        if looped {
+            if let Some(session) = self.sessions.get(name) {
+                // Players already on the entry don't start it over by themselves
+                let position = session.position.0;
+                for player in &session.players {
+                    player.addr.do_send(player::ChangePosition { position });
+                }
+            }
+        }
+        self.change_state(name, player::State::Playing, None, ctx);
+    }
+
+    /// Pauses when everything left is passed over, instead of going around in circles
+    fn stop_at_passed_over(&mut self, name: &SessionCode, ctx: &mut Context<Self>) {
+        if let Some(session) = self.sessions.get(name) {
+            for player in &session.players {
+                player.addr.do_send(player::ShowError {
+                    reason: "Everything left in the playlist is muted or filtered".to_string(),
+                });
+            }
+        }
+        self.change_state(name, player::State::Paused, None, ctx);
+    }
+
+    /// Starts an entry from the beginning, even if it is the current index
+    fn enter_entry(
+        &mut self,
+        name: &SessionCode,
+        index: usize,
+        by: Option<u64>,
+        ctx: &mut Context<Self>,
+    ) {
+        let recent = self.recent.clone();
+        let Some(session) = self.touch(name) else {
+            return;
+        };
+
+        let start_offset = session
+            .shitposts
+            .get(index)
+            .map(|shitpost| shitpost.start_offset)
+            .unwrap_or_default();
+        if let Some(shitpost) = session.shitposts.get(index) {
+            recent.record(&shitpost.url);
+        }
+        session.stats.enter(
+            session
+                .shitposts
+                .get(index)
+                .map(|shitpost| shitpost.title.clone()),
+            Instant::now(),
+        );
+        session
+            .events
+            .push(SessionEvent::PlaylistChanged { player: by, index });
+        session.playlist_index = index;
+        // Late joiners start at the offset too
+        session.position = (start_offset, Instant::now());
+        session.pending_offset = (start_offset > 0.0).then_some(start_offset);
+        session.entered = Some(Instant::now());
+        session.skip_votes.clear();
+        session.ready.clear();
+        session.record_history();
+        for player in &session.players {
+            player.addr.do_send(player::ChangePlaylist { index });
+        }
+
+        if let Some(timer) = session.image_timer.take() {
+            ctx.cancel_future(timer);
+        }
+        if let Some(countdown) = session.countdown.take() {
+            ctx.cancel_future(countdown);
+        }
+        if let Some(intermission) = session.intermission.take() {
+            ctx.cancel_future(intermission);
+        }
+        // The stall was on the old entry
+        if let Some((_, timer)) = session.straggler.take() {
+            ctx.cancel_future(timer);
+        }
+        self.metrics.playlist_advances += 1;
+        self.schedule_image_advance(name, ctx);
+    }
+
+    fn change_state(
+        &mut self,
+        name: &SessionCode,
+        state: player::State,
+        by: Option<u64>,
+        ctx: &mut Context<Self>,
+    ) {
+        if let Some(session) = self.touch(name) {
+            // Players echo the change back
+            if session.state != state {
+                session
+                    .events
+                    .push(SessionEvent::StateChanged { player: by, state });
+                // Whoever changed it decided for the room, the stalled player isn't waited for anymore
+                if let Some((_, timer)) = session.straggler.take() {
+                    ctx.cancel_future(timer);
+                }
+            }
+            // Positions are extrapolated with the old state up to now, time spent paused doesn't count
+            let now = Instant::now();
+            session.position = (session.extrapolate(session.position), now);
+            session.positions = session
+                .positions
+                .iter()
+                .map(|(player, recorded)| (player.clone(), (session.extrapolate(*recorded), now)))
+                .collect();
+            session.stats.state_changed(state, now);
+            session.state = state;
+            let by = by.and_then(|id| session.initiator(id));
+            for player in &session.players {
+                player.addr.do_send(player::ChangeState {
+                    state,
+                    by: by.clone(),
+                });
+            }
+        }
+    }
+
+    /// Pauses everyone but a stalled player until it plays again or the timeout is over
+    fn wait_for(
+        &mut self,
+        name: &SessionCode,
+        straggler: &Addr<PlayerActor>,
+        ctx: &mut Context<Self>,
+    ) {
+        let timeout = self.straggler_timeout;
+        let Some(session) = self.touch(name) else {
+            return;
+        };
+        let Some(waiting_for) = session
+            .players
+            .iter()
+            .find(|player| player.addr == *straggler)
+        else {
+            return;
+        };
+
+        let waiting_for = escape_html(&waiting_for.name);
+        // Everyone continues from where they were paused
+        session.position = (session.extrapolate(session.position), Instant::now());
+        session
+            .stats
+            .state_changed(player::State::Paused, Instant::now());
+        session.state = player::State::Paused;
+        for player in session
+            .players
+            .iter()
+            .filter(|player| player.addr != *straggler)
+        {
+            player.addr.do_send(player::ChangeState {
+                state: player::State::Paused,
+                by: None,
+            });
+            player.addr.do_send(player::WaitingFor {
+                name: waiting_for.clone(),
+            });
+        }
+
+        let name = name.clone();
+        let timer = ctx.run_later(timeout, move |act, ctx| {
+            let _span = name.span().entered();
+            tracing::info!("Stopped waiting for a stalled player");
+            act.stop_waiting(&name, ctx);
+        });
+        session.straggler = Some((straggler.clone(), timer));
+    }
+
+    /// Resumes playback for everyone after waiting for a stalled player
+    fn stop_waiting(&mut self, name: &SessionCode, ctx: &mut Context<Self>) {
+        let Some(session) = self.touch(name) else {
+            return;
+        };
+        let Some((_, timer)) = session.straggler.take() else {
+            return;
+        };
+
+        ctx.cancel_future(timer);
+        let now = Instant::now();
+        let position = session.position.0;
+        session.position = (position, now);
+        for player in &session.players {
+            session
+                .positions
+                .insert(player.addr.clone(), (position, now));
+            player.addr.do_send(player::ChangePosition { position });
+        }
+        self.change_state(name, player::State::Playing, None, ctx);
+    }
+
+    /// Writes the wrap-up of a session that is over on a blocking thread
+    fn save_stats(&self, name: &SessionCode, session: Session) {
+        let mut stats = session
+            .stats
+            .summary(name, session.created.elapsed(), Instant::now());
+        stats.bookmarks = session.bookmarks;
+        let stats_dir = self.stats_dir.clone();
+
+        actix_web::rt::task::spawn_blocking(move || {
+            if let Err(why) = stats::save(&stats_dir, &stats) {
+                tracing::error!(
+                    r#"Failed to save the stats of session "{}": {}"#,
+                    stats.code,
+                    why
+                );
+            }
+        });
+    }
+
+    /// Asks the reference for its position, or the next player to start playing if there is none
+    fn request_sync(session: &mut Session) {
+        match session.reference() {
+            Some(reference) => {
+                reference.do_send(player::SyncPosition);
+                session.pending_sync = false;
+            }
+            None => session.pending_sync = true,
+        }
+    }
+
+    /// Tells everyone how many players are ready
+    fn broadcast_ready_status(session: &Session) {
+        let ready = session.ready.len();
+        let total = session.participants();
+
+        for player in &session.players {
+            player.addr.do_send(player::ReadyStatus { ready, total });
+        }
+    }
+
+    /// Tells everyone why a player is gone
+    fn broadcast_player_left(session: &Session, left: &PlayerHandle, reason: DisconnectReason) {
+        let name = escape_html(&left.name);
+
+        for player in &session.players {
+            player.addr.do_send(player::PlayerLeft {
+                name: name.clone(),
+                reason,
+            });
+        }
+    }
+
+    /// Tells everyone who is watching
+    fn broadcast_viewers(session: &Session) {
+        let viewers = || {
+            session
+                .players
+                .iter()
+                .map(|player| ViewerInfo {
+                    id: player.id,
+                    name: escape_html(&player.name),
+                    is_host: player.is_host,
+                    spectator: player.spectator,
+                })
+                .collect()
+        };
+
+        for player in &session.players {
+            player.addr.do_send(player::Viewers { viewers: viewers() });
+        }
+    }
+
+    /// Counts down for everyone and starts playback when done
+    fn start_countdown(&mut self, name: &SessionCode, ctx: &mut Context<Self>) {
+        let duration = self.countdown;
+        let Some(session) = self.touch(name) else {
+            return;
+        };
+
+        if session.countdown.is_some() {
+            return;
+        }
+
+        for player in &session.players {
+            player.addr.do_send(player::Countdown {
+                seconds: duration.as_secs(),
+            });
+        }
+
+        let name = name.clone();
+        session.countdown = Some(ctx.run_later(duration, move |act, ctx| {
+            if let Some(session) = act.sessions.get_mut(&name) {
+                session.countdown = None;
+            }
+            act.change_state(&name, player::State::Playing, None, ctx);
+        }));
+    }
+
+    /// Tells everyone how the skip vote stands and skips once more than half have voted
+    fn tally_skip_votes(&mut self, name: &SessionCode, ctx: &mut Context<Self>) {
+        let Some(session) = self.sessions.get_mut(name) else {
+            return;
+        };
+
+        let current = session.skip_votes.len();
+        let needed = session.participants() / 2 + 1;
+
+        for player in &session.players {
+            player.addr.do_send(player::SkipVotes { current, needed });
+        }
+
+        if current >= needed {
+            session.stats.skip();
+            let next = session.playlist_index + 1;
+            self.change_playlist(name, next, None, ctx);
+        }
+    }
+
+    /// Starts the timer advancing past the current entry if it is an image
+    fn schedule_image_advance(&mut self, name: &SessionCode, ctx: &mut Context<Self>) {
+        let duration = self.image_duration;
+        let Some(session) = self.sessions.get_mut(name) else {
+            return;
+        };
+
+        // A looped image just stays up
+        if session
+            .next_index()
+            .filter(|next| *next != session.playlist_index)
+            .is_none()
+            || session.image_timer.is_some()
+            || session.starts_at.is_some()
+            || session.shitposts[session.playlist_index].kind != ShitpostKind::Image
+        {
+            return;
+        }
+
+        let name = name.clone();
+        session.image_timer = Some(ctx.run_later(duration, move |act, ctx| {
+            let Some(session) = act.sessions.get_mut(&name) else {
+                return;
+            };
+            session.image_timer = None;
+            // Looked up again, entries can have been removed or moved while the image was up
+            let Some(next) = session
+                .next_index()
+                .filter(|next| *next != session.playlist_index)
+            else {
+                return;
+            };
+            act.change_playlist(&name, next, None, ctx);
+        }));
+    }
+
+    /// Everything a player needs to catch up with the session, besides the playlist
+    fn send_state(session: &Session, player: &Addr<PlayerActor>) {
+        player.do_send(player::ChangeState {
+            state: session.state,
+            by: None,
+        });
+        player.do_send(player::ChangeRate { rate: session.rate });
+        player.do_send(player::ChangePlaylist {
+            index: session.playlist_index,
+        });
+        player.do_send(player::LoopModeChanged {
+            mode: session.loop_mode,
+        });
+        player.do_send(player::WaitForStragglersChanged {
+            enabled: session.wait_for_stragglers,
+        });
+        player.do_send(player::DebugChanged {
+            enabled: session.debug.is_some(),
+        });
+        player.do_send(player::PermissionsChanged {
+            permissions: session.permissions,
+        });
+        player.do_send(player::MutedFolders {
+            folders: session.muted_folders(),
+        });
+        player.do_send(player::FilteredTags {
+            tags: session.filtered_tags(),
+        });
+        // Reconnecting or joining mid-entry shouldn't start from the beginning
+        player.do_send(player::ChangePosition {
+            position: session.extrapolate(session.position),
+        });
+        player.do_send(player::History {
+            entries: session.history(),
+        });
+    }
+
+    /// Starts the timer taking a scheduled session live, replacing the one running
+    fn schedule_start(&mut self, name: &SessionCode, ctx: &mut Context<Self>) {
+        let Some(session) = self.sessions.get_mut(name) else {
+            return;
+        };
+        if let Some(timer) = session.start_timer.take() {
+            ctx.cancel_future(timer);
+        }
+        let Some(starts_at) = session.starts_at else {
+            return;
+        };
+
+        let delay = starts_at
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        let name = name.clone();
+        session.start_timer = Some(ctx.run_later(delay, move |act, ctx| {
+            if let Some(session) = act.sessions.get_mut(&name) {
+                session.start_timer = None;
+            }
+            act.go_live(&name, ctx);
+        }));
+    }
+
+    /// Takes a scheduled session live, sending the lobby what players joining a running one get
+    fn go_live(&mut self, name: &SessionCode, ctx: &mut Context<Self>) {
+        let Some(session) = self.touch(name) else {
+            return;
+        };
+        if session.starts_at.take().is_none() {
+            return;
+        }
+        if let Some(timer) = session.start_timer.take() {
+            ctx.cancel_future(timer);
+        }
+
+        tracing::info!(r#"Scheduled session "{}" started"#, name);
+        // The wait in the lobby isn't part of the session
+        let title = session
+            .shitposts
+            .get(session.playlist_index)
+            .map(|shitpost| shitpost.title.clone());
+        session.history =
+            VecDeque::from([(session.playlist_index, title.clone(), SystemTime::now())]);
+        session.created = Instant::now();
+        session.stats = StatsCollector::new(title, Instant::now());
+
+        for player in &session.players {
+            player.addr.do_send(player::SessionInfo {
+                generation: session.generation,
+                playlist_len: session.shitposts.len(),
+            });
+            player.addr.do_send(player::PlaylistReplaced {
+                generation: session.generation,
+                shitposts: session.shitposts.to_vec(),
+                index: session.playlist_index,
+            });
+            Self::send_state(session, &player.addr);
+            player.addr.do_send(player::SessionStarted);
+        }
+        if !session.players.is_empty() {
+            Self::request_sync(session);
+        }
+
+        self.announce(format!("Session {} is live", name), ctx);
+        self.schedule_image_advance(name, ctx);
+    }
+
+    /// Looks up a session and marks it as active
+    fn touch(&mut self, session: &SessionCode) -> Option<&mut Session> {
+        let session = self.sessions.get_mut(session)?;
+        session.last_activity = Instant::now();
+        Some(session)
+    }
+
+    /// Every session in the form it is saved in
+    fn persist(&self) -> Vec<PersistedSession> {
+        self.sessions
+            .iter()
+            .map(|(code, session)| PersistedSession {
+                code: code.to_string(),
+                shitposts: session.shitposts.to_vec(),
+                playlist_index: session.playlist_index,
+                state: session.state,
+                position: session.extrapolate(session.position),
+                rate: session.rate,
+                history: session.history(),
+                banned: session
+                    .banned
+                    .iter()
+                    .map(|client| client.to_string())
+                    .collect(),
+                password: None,
+                password_hash: session.password.clone(),
+                host_token: session.host_token.clone(),
+                host_only: false,
+                permissions: Some(session.permissions),
+                loop_mode: session.loop_mode,
+                wait_for_stragglers: session.wait_for_stragglers,
+                muted: session.muted.iter().cloned().collect(),
+                filtered_tags: session.filtered_tags.iter().cloned().collect(),
+                bookmarks: session.bookmarks.clone(),
+                max_players: session.max_players,
+                public: session.public,
+                generation: session.generation,
+                intermission_secs: session.intermission_secs,
+                seed: session.seed,
+                excluded: session.excluded.to_vec(),
+                uptime: session.created.elapsed().as_secs(),
+                starts_at: session.starts_at.map(unix_secs),
+            })
+            .collect()
+    }
+
+    /// Brings back sessions from before a restart, they expire like any other unless rejoined
+    pub fn restore(&mut self, sessions: Vec<PersistedSession>) {
+        for persisted in sessions {
+            let Ok(code) = SessionCode::try_from(persisted.code) else {
+                continue;
+            };
+            // The old hash can't be checked against anymore, and the session isn't left open instead
+            if persisted.password.is_some() && persisted.password_hash.is_none() {
+                tracing::warn!(
+                    r#"Not restoring session "{}", its password was saved by an older version"#,
+                    code
+                );
+                continue;
+            }
+
+            tracing::info!(r#"Restored session "{}""#, code);
+            let title = persisted
+                .shitposts
+                .get(persisted.playlist_index)
+                .map(|shitpost| shitpost.title.clone());
+            self.sessions.insert(
+                code,
+                Session {
+                    history: persisted
+                        .history
+                        .into_iter()
+                        .map(|entry| {
+                            (
+                                entry.index,
+                                entry.title,
+                                UNIX_EPOCH + Duration::from_secs(entry.played_at),
+                            )
+                        })
+                        .collect(),
+                    shitposts: persisted.shitposts.into(),
+                    state: persisted.state,
+                    playlist_index: persisted.playlist_index,
+                    rate: persisted.rate,
+                    players: Vec::new(),
+                    positions: HashMap::new(),
+                    corrected: HashMap::new(),
+                    position: (persisted.position, Instant::now()),
+                    seeker: None,
+                    image_timer: None,
+                    skip_votes: HashSet::new(),
+                    ready: HashSet::new(),
+                    countdown: None,
+                    intermission_secs: persisted.intermission_secs,
+                    intermission: None,
+                    next_player_id: 0,
+                    banned: persisted.banned.into_iter().map(Into::into).collect(),
+                    password: persisted.password_hash,
+                    host_token: persisted.host_token,
+                    permissions: persisted.permissions.unwrap_or(if persisted.host_only {
+                        Permissions::HOST_ONLY
+                    } else {
+                        Permissions::default()
+                    }),
+                    max_players: persisted.max_players,
+                    public: persisted.public,
+                    generation: persisted.generation,
+                    seed: persisted.seed,
+                    excluded: persisted.excluded.into(),
+                    loop_mode: persisted.loop_mode,
+                    wait_for_stragglers: persisted.wait_for_stragglers,
+                    straggler: None,
+                    debug: None,
+                    muted: persisted.muted.into_iter().collect(),
+                    filtered_tags: persisted.filtered_tags.into_iter().collect(),
+                    bookmarks: persisted.bookmarks,
+                    events: EventLog::default(),
+                    created: Instant::now()
+                        .checked_sub(Duration::from_secs(persisted.uptime))
+                        .unwrap_or_else(Instant::now),
+                    last_activity: Instant::now(),
+                    grace: self.restore_grace,
+                    pending_sync: false,
+                    pending_offset: None,
+                    entered: None,
+                    stats: StatsCollector::new(title, Instant::now()),
+                    starts_at: persisted
+                        .starts_at
+                        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+                    start_timer: None,
+                },
+            );
+        }
+    }
+
+    /// Tells everyone where each player is estimated to be, players that haven't
+    /// reported a position yet are sent without one
+    fn broadcast_debug(&self, name: &SessionCode) {
+        let Some(session) = self.sessions.get(name) else {
+            return;
+        };
+
+        let players = session
+            .players
+            .iter()
+            .map(|player| {
+                let report = session.positions.get(&player.addr);
+                player::DebugPlayer {
+                    name: escape_html(&player.name),
+                    position: report.map(|report| session.extrapolate(*report)),
+                    state: player.state,
+                    last_seen_ms: report.map(|(_, recorded)| recorded.elapsed().as_millis() as u64),
+                    quality: player.quality,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for player in &session.players {
+            player.addr.do_send(player::DebugSync {
+                players: players.clone(),
+            });
+        }
+    }
+
+    /// Stops the timers of a session that was removed, and closes the
+    /// connections of any players it still had so they don't linger
+    fn discard(session: &Session, ctx: &mut Context<Self>) {
+        let timers = [
+            session.image_timer,
+            session.countdown,
+            session.intermission,
+            session.debug,
+            session.straggler.as_ref().map(|(_, timer)| *timer),
+            session.start_timer,
+        ];
+        for timer in timers.into_iter().flatten() {
+            ctx.cancel_future(timer);
+        }
+
+        for player in &session.players {
+            player.addr.do_send(player::SessionClosed);
+        }
+    }
+
+    /// Evicts sessions that have had no players and no activity for longer than the TTL
+    fn cleanup(&mut self, ctx: &mut Context<Self>) {
+        let ttl = self.ttl;
+        let mut expired = Vec::new();
+
+        self.sessions.retain(|name, session| {
+            // Players that timed out without a clean disconnect
+            session.players.retain(|player| player.addr.connected());
+            session
+                .positions
+                .retain(|addr, _| session.players.iter().any(|player| player.addr == *addr));
+
+            // Scheduled sessions wait for their start with nobody in the lobby
+            if session.players.is_empty()
+                && session.starts_at.is_none()
+                && session.last_activity.elapsed() > ttl.max(session.grace)
+            {
+                tracing::info!(r#"Session "{}" expired"#, name);
+                Self::discard(session, ctx);
+                expired.push((name.clone(), session.created.elapsed()));
+                false
+            } else {
+                true
+            }
+        });
+
+        for (name, lasted) in expired {
+            self.announce_end(&name, lasted, ctx);
+        }
+    }
+
+    /// Queues a line for the Discord webhook if there is one. The first line of a
+    /// burst schedules the post, the rest of the burst goes out with it.
+    fn announce(&mut self, line: String, ctx: &mut Context<Self>) {
+        let Some(webhook) = &mut self.webhook else {
+            return;
+        };
+
+        if let Some(delay) = webhook.push(line, Instant::now()) {
+            ctx.run_later(delay, |act, _ctx| {
+                if let Some(webhook) = &mut act.webhook {
+                    if let Some(content) = webhook.take(Instant::now()) {
+                        webhook.post(content);
+                    }
+                }
+            });
+        }
+    }
+
+    fn announce_end(&mut self, name: &SessionCode, lasted: Duration, ctx: &mut Context<Self>) {
+        self.announce(
+            format!("Session {} ended after {}", name, duration_text(lasted)),
+            ctx,
+        );
+    }
+
+    /// A random token for the hosts of a session
+    pub fn generate_host_token() -> String {
+        let mut rng = rand::thread_rng();
+
+        (0..Self::HOST_TOKEN_LENGTH)
+            .map(|_| Self::CODE_CHARSET[rng.gen_range(0..Self::CODE_CHARSET.len())] as char)
+            .collect()
+    }
+
+    fn generate_code() -> SessionCode {
+        let mut rng = rand::thread_rng();
+
+        SessionCode(
+            (0..Self::CODE_LENGTH)
+                .map(|_| Self::CODE_CHARSET[rng.gen_range(0..Self::CODE_CHARSET.len())] as char)
+                .collect::<String>()
+                .into(),
+        )
+    }
+}
+
+/// Limits a requested rate to the supported range, None if it isn't a number at all
+fn clamp_rate(rate: f64) -> Option<f64> {
+    rate.is_finite()
+        .then(|| rate.clamp(RATE_RANGE.0, RATE_RANGE.1))
+}
+
+/// Index of the player everyone else is synced to: a playing host, otherwise whoever has been
+/// connected the longest of the playing players. Spectators are never picked, a stalled
+/// projector shouldn't hold everyone back, and neither is anyone that isn't playing or whose
+/// tab is in the background.
+fn pick_reference(players: impl Iterator<Item = Candidate>) -> Option<usize> {
+    let mut longest_playing = None;
+
+    for (index, player) in players.enumerate() {
+        if player.spectator || player.backgrounded || player.state != Some(player::State::Playing) {
+            continue;
+        }
+        if player.is_host {
+            return Some(index);
+        }
+        longest_playing.get_or_insert(index);
+    }
+
+    longest_playing
+}
+
+/// Random, but small enough for the browser to read it as a number without rounding
+fn new_generation() -> u64 {
+    rand::thread_rng().gen_range(0..1 << 53)
+}
+
+/// Seconds since the epoch, like the timestamps sent to players
+pub fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Keeps pointing at the same entry, or whatever took the place of the removed one
+fn index_after_removal(removed: usize, current: usize, len: usize) -> usize {
+    match removed.cmp(&current) {
+        Ordering::Less => current - 1,
+        Ordering::Equal => current.min(len - 1),
+        Ordering::Greater => current,
+    }
+}
+
+/// The entry to continue with once the current one is over, None if playback stops
+/// Skips over entries of muted folders or with filtered tags, wrapping around to the start if the playlist loops.
+/// Indexes past the end are left alone like they would be without muting.
+fn first_playable(
+    shitposts: &[Shitpost],
+    muted: &HashSet<String>,
+    filtered_tags: &HashSet<String>,
+    from: usize,
+    wrap: bool,
+) -> Option<usize> {
+    if (muted.is_empty() && filtered_tags.is_empty()) || from >= shitposts.len() {
+        return Some(from);
+    }
+
+    let wrapped = if wrap { 0..from } else { 0..0 };
+    (from..shitposts.len())
+        .chain(wrapped)
+        .find(|index| !passed_over(&shitposts[*index], muted, filtered_tags))
+}
+
+fn passed_over(
+    shitpost: &Shitpost,
+    muted: &HashSet<String>,
+    filtered_tags: &HashSet<String>,
+) -> bool {
+    muted.contains(&shitpost.folder) || shitpost.tags.iter().any(|tag| filtered_tags.contains(tag))
+}
+
+fn index_after_completion(mode: LoopMode, current: usize, len: usize) -> Option<usize> {
+    match mode {
+        LoopMode::LoopOne => Some(current),
+        _ if current + 1 < len => Some(current + 1),
+        LoopMode::LoopPlaylist => Some(0),
+        LoopMode::Once => None,
+    }
+}
+
+/// A salted SHA-256 of a session password, the password itself isn't kept
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PasswordHash {
+    salt: String,
+    hash: String,
+}
+
+impl PasswordHash {
+    const SALT_LENGTH: usize = 16;
+
+    pub fn new(password: &str) -> Self {
+        let salt = rand::thread_rng()
+            .sample_iter(&rand::distributions::Alphanumeric)
+            .take(Self::SALT_LENGTH)
+            .map(char::from)
+            .collect::<String>();
+        let hash = crate::sha256_hex(&[salt.as_bytes(), password.as_bytes()]);
+
+        Self { salt, hash }
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        let hash = crate::sha256_hex(&[self.salt.as_bytes(), password.as_bytes()]);
+        auth::constant_time_eq(hash.as_bytes(), self.hash.as_bytes())
+    }
+
+    /// Kept by players that entered the password, so it doesn't have to be sent again.
+    /// Derived from the hash, so it stays valid across restarts and can't be turned back into it.
+    pub fn ticket(&self) -> String {
+        crate::sha256_hex(&[b"ticket:", self.salt.as_bytes(), self.hash.as_bytes()])
+    }
+}
+
+/// Whether the ticket is the one of the password, anything goes for sessions without one
+fn check_ticket(hash: Option<&PasswordHash>, ticket: Option<&str>) -> bool {
+    match hash {
+        Some(hash) => ticket.is_some_and(|ticket| {
+            auth::constant_time_eq(ticket.as_bytes(), hash.ticket().as_bytes())
+        }),
+        None => true,
+    }
+}
+
+/// Escapes text so it can't inject markup into other clients
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+impl Actor for SessionManager {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(Self::CLEANUP_INTERVAL, |act, ctx| act.cleanup(ctx));
+
+        // Restored sessions that were still scheduled
+        let scheduled = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.starts_at.is_some())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        for name in scheduled {
+            self.schedule_start(&name, ctx);
+        }
+    }
+}
+
+impl Handler<CreateSession> for SessionManager {
+    type Result = <CreateSession as Message>::Result;
+
+    fn handle(&mut self, msg: CreateSession, ctx: &mut Self::Context) -> Self::Result {
+        loop {
+            let code = Self::generate_code();
+            if !self.sessions.contains_key(&code) {
+                break code;
+            }
+        }
+    }
+}
+
+impl Handler<NewSession> for SessionManager {
+    type Result = <NewSession as Message>::Result;
+
+    fn handle(&mut self, msg: NewSession, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        // A taken code is reported as such even when the limit is reached
+        if self.sessions.contains_key(&msg.session) {
+            return Err(NewSessionError::Exists);
+        }
+        if self.sessions.len() >= self.max_sessions {
+            tracing::warn!(
+                r#"Refused session "{}", {} sessions exist already"#,
+                msg.session,
+                self.sessions.len()
+            );
+            return Err(NewSessionError::TooManySessions);
+        }
+
+        if let Entry::Vacant(e) = self.sessions.entry(msg.session.clone()) {
+            tracing::info!(r#"Created session "{}""#, msg.session);
+            self.metrics.sessions_created += 1;
+            // Discord shows the timestamp in the time zone of whoever reads it
+            let opening = match msg.starts_at {
+                Some(starts_at) => format!(
+                    "Session {} starts <t:{}:R> with {} clips",
+                    msg.session,
+                    unix_secs(starts_at),
+                    msg.shitposts.len()
+                ),
+                None => format!(
+                    "Session {} started with {} clips",
+                    msg.session,
+                    msg.shitposts.len()
+                ),
+            };
+            let announcement = match &self.public_url {
+                Some(public_url) => format!(
+                    "{}, join: {}",
+                    opening,
+                    qr::join_url(public_url, &msg.session)
+                ),
+                None => opening,
+            };
+            let title = msg.shitposts.first().map(|shitpost| shitpost.title.clone());
+            let start_offset = msg
+                .shitposts
+                .first()
+                .map(|shitpost| shitpost.start_offset)
+                .unwrap_or_default();
+            // The first entry is never entered through a playlist change
+            if let Some(shitpost) = msg.shitposts.first() {
+                self.recent.record(&shitpost.url);
+            }
+            e.insert(Session {
+                history: VecDeque::from([(0, title.clone(), SystemTime::now())]),
+                shitposts: msg.shitposts.into(),
+                state: player::State::Paused,
+                playlist_index: 0,
+                rate: 1.0,
+                players: Vec::new(),
+                positions: HashMap::new(),
+                corrected: HashMap::new(),
+                position: (start_offset, Instant::now()),
+                seeker: None,
+                image_timer: None,
+                skip_votes: HashSet::new(),
+                ready: HashSet::new(),
+                countdown: None,
+                intermission_secs: msg.intermission_secs,
+                intermission: None,
+                next_player_id: 0,
+                banned: HashSet::new(),
+                password: msg.password,
+                host_token: msg.host_token,
+                permissions: msg.permissions,
+                max_players: msg.max_players,
+                public: msg.public,
+                generation: new_generation(),
+                seed: msg.seed,
+                excluded: msg.excluded.into(),
+                loop_mode: LoopMode::default(),
+                wait_for_stragglers: false,
+                straggler: None,
+                debug: None,
+                muted: HashSet::new(),
+                filtered_tags: HashSet::new(),
+                bookmarks: Vec::new(),
+                events: EventLog::default(),
+                created: Instant::now(),
+                last_activity: Instant::now(),
+                grace: Duration::ZERO,
+                pending_sync: false,
+                // Like entering any other entry, the first one starts at its offset
+                pending_offset: (start_offset > 0.0).then_some(start_offset),
+                entered: None,
+                stats: StatsCollector::new(title, Instant::now()),
+                starts_at: msg.starts_at,
+                start_timer: None,
+            });
+            self.schedule_start(&msg.session, ctx);
+            self.announce(announcement, ctx);
+            Ok(())
+        } else {
+            Err(NewSessionError::Exists)
+        }
+    }
+}
+
+impl Handler<PlayerConnect> for SessionManager {
+    type Result = <PlayerConnect as Message>::Result;
+
+    fn handle(&mut self, msg: PlayerConnect, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        let first = if let Some(session) = self.touch(&msg.session) {
+            if msg
+                .client
+                .as_ref()
+                .is_some_and(|client| session.banned.contains(client))
+            {
+                msg.player.do_send(player::Kicked {
+                    reason: "You are banned from this session".to_string(),
+                });
+                msg.player.do_send(player::Disconnect);
+                return;
+            }
+
+            // Hosts always get in, they can kick someone to make room
+            let is_host = msg.host_token.as_ref() == Some(&session.host_token);
+            if !is_host && session.is_full() {
+                tracing::info!(
+                    "Turned a player away, {} players are connected",
+                    session.live_players()
+                );
+                msg.player.do_send(player::SessionFull {
+                    players: session.live_players(),
+                    max_players: session.max_players.unwrap_or_default(),
+                });
+                msg.player.do_send(player::Disconnect);
+                return;
+            }
+
+            let id = session.next_player_id;
+            session.next_player_id += 1;
+            msg.player.do_send(player::Welcome { id });
+            if let Some(starts_at) = session.starts_at {
+                // Everything else is sent once the session starts
+                msg.player.do_send(player::SessionScheduled { starts_at });
+            } else {
+                msg.player.do_send(player::SessionInfo {
+                    generation: session.generation,
+                    playlist_len: session.shitposts.len(),
+                });
+                // The page was made for an earlier session with the same code
+                if msg
+                    .generation
+                    .is_some_and(|generation| generation != session.generation)
+                {
+                    msg.player.do_send(player::PlaylistReplaced {
+                        generation: session.generation,
+                        shitposts: session.shitposts.to_vec(),
+                        index: session.playlist_index,
+                    });
+                }
+                Self::send_state(session, &msg.player);
+            }
+
+            session.players.push(PlayerHandle {
+                addr: msg.player,
+                id,
+                name: msg
+                    .name
+                    .unwrap_or_else(|| format!("anonymous-{}", id).into()),
+                client: msg.client,
+                address: msg.address,
+                is_host,
+                spectator: msg.spectator,
+                backgrounded: false,
+                state: None,
+                latency: Duration::ZERO,
+                clock: None,
+                quality: player::Quality::default(),
+            });
+            let name = session.players.last().unwrap().name.to_string();
+            let first = (id == 0).then(|| name.clone());
+            session
+                .events
+                .push(SessionEvent::Connected { player: id, name });
+            Self::broadcast_viewers(session);
+            // The new player has to know about a ready check going on, and makes it take one more
+            if !session.ready.is_empty() {
+                Self::broadcast_ready_status(session);
+            }
+
+            if session.starts_at.is_none() {
+                Self::request_sync(session);
+            }
+            first
+        } else {
+            tracing::info!("A player said hello to a session that doesn't exist");
+            msg.player.do_send(player::NoSuchSession);
+            return;
+        };
+
+        if let Some(name) = first {
+            self.announce(
+                format!("{} is the first to join session {}", name, msg.session),
+                ctx,
+            );
+        }
+
+        // Images at the start of the playlist only start counting down once someone is watching
+        self.schedule_image_advance(&msg.session, ctx);
+    }
+}
+
+impl Handler<PlayerDisconnect> for SessionManager {
+    type Result = <PlayerDisconnect as Message>::Result;
+
+    fn handle(&mut self, msg: PlayerDisconnect, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        // Nobody is left to wait for
+        if self
+            .sessions
+            .get(&msg.session)
+            .and_then(|session| session.straggler.as_ref())
+            .is_some_and(|(straggler, _)| *straggler == msg.player)
+        {
+            self.stop_waiting(&msg.session, ctx);
+        }
+        if if let Some(session) = self.touch(&msg.session) {
+            let left = session
+                .players
+                .iter()
+                .position(|player| player.addr == msg.player)
+                .map(|index| session.players.remove(index));
+            if let Some(left) = &left {
+                session.events.push(SessionEvent::Disconnected {
+                    player: left.id,
+                    reason: msg.reason,
+                });
+                Self::broadcast_player_left(session, left, msg.reason);
+            }
+            session.positions.remove(&msg.player);
+            session.corrected.remove(&msg.player);
+            session.skip_votes.remove(&msg.player);
+            if session.ready.remove(&msg.player) {
+                Self::broadcast_ready_status(session);
+            }
+            Self::broadcast_viewers(session);
+            // Players turned away were never in, they don't end a session nobody has rejoined yet
+            left.is_some() && session.players.is_empty() && session.starts_at.is_none()
+        } else {
+            false
+        } {
+            tracing::info!(r#"Session "{}" removed"#, msg.session);
+            if let Some(session) = self.sessions.remove(&msg.session) {
+                Self::discard(&session, ctx);
+                self.announce_end(&msg.session, session.created.elapsed(), ctx);
+                self.save_stats(&msg.session, session);
+            }
+        } else if self
+            .sessions
+            .get(&msg.session)
+            .is_some_and(|session| !session.skip_votes.is_empty())
+        {
+            // The threshold is lower with one player less
+            self.tally_skip_votes(&msg.session, ctx);
+        }
+    }
+}
+
+impl Handler<StateChanged> for SessionManager {
+    type Result = <StateChanged as Message>::Result;
+
+    fn handle(&mut self, msg: StateChanged, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("StateChanged");
+        let Some(session) = self.sessions.get_mut(&msg.session) else {
+            return;
+        };
+
+        // Decided before the report, which can make someone else the reference
+        let was_reference = session.reference() == Some(&msg.player);
+        if let Some(player) = session
+            .players
+            .iter_mut()
+            .find(|player| player.addr == msg.player)
+        {
+            player.state = Some(msg.state);
+        }
+
+        if session
+            .straggler
+            .as_ref()
+            .is_some_and(|(straggler, _)| *straggler == msg.player)
+        {
+            match msg.state {
+                player::State::Playing => {
+                    self.stop_waiting(&msg.session, ctx);
+                    return;
+                }
+                // Still catching up
+                player::State::Stalled | player::State::Loading => return,
+                _ => {}
+            }
+        } else if msg.state == player::State::Stalled
+            && session.wait_for_stragglers
+            && session.straggler.is_none()
+            && session.state == player::State::Playing
+            && !session.is_backgrounded(&msg.player)
+        {
+            self.wait_for(&msg.session, &msg.player, ctx);
+            return;
+        }
+
+        if msg.state == player::State::Playing && session.pending_sync {
+            Self::request_sync(session);
+        }
+        if msg.state == player::State::Playing {
+            if let Some(offset) = session.pending_offset.take() {
+                let now = Instant::now();
+                session.position = (offset, now);
+                for player in &session.players {
+                    session.positions.insert(player.addr.clone(), (offset, now));
+                    player
+                        .addr
+                        .do_send(player::ChangePosition { position: offset });
+                }
+            }
+        }
+
+        // Everyone reports the end of the entry, only the reference's report counts
+        if msg.state == player::State::Complete {
+            // A player that moved on first already took the session along
+            let stale = session
+                .entered
+                .is_some_and(|entered| entered.elapsed() < COMPLETION_GRACE);
+            if was_reference && !stale {
+                self.advance(&msg.session, ctx);
+            }
+            return;
+        }
+
+        if !session.may(&msg.player, Action::Pause) {
+            // Echoes of the state everyone is in aren't attempts
+            if matches!(msg.state, player::State::Paused | player::State::Playing)
+                && msg.state != session.state
+            {
+                msg.player.do_send(player::PermissionDenied {
+                    action: Action::Pause,
+                });
+            }
+            // Undo the change on the player's side
+            msg.player.do_send(player::ChangeState {
+                state: session.state,
+                by: None,
+            });
+            return;
+        }
+
+        // Echoes of someone else's pause don't count
+        if msg.state == player::State::Paused && session.state != player::State::Paused {
+            if let Some(player) = session
+                .players
+                .iter()
+                .find(|player| player.addr == msg.player)
+            {
+                session.stats.pause(&player.name);
+            }
+        }
+
+        let by = session.player_id(&msg.player);
+
+        self.change_state(&msg.session, msg.state, by, ctx);
+    }
+}
+
+impl Handler<RateChanged> for SessionManager {
+    type Result = <RateChanged as Message>::Result;
+
+    fn handle(&mut self, msg: RateChanged, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("RateChanged");
+        let Some(rate) = clamp_rate(msg.rate) else {
+            return;
+        };
+
+        if let Some(session) = self.touch(&msg.session) {
+            // Players echo the change back
+            if session.rate == rate {
+                return;
+            }
+            // Throws everyone out of sync just like seeking does
+            if !session.may(&msg.player, Action::Seek) {
+                msg.player.do_send(player::PermissionDenied {
+                    action: Action::Seek,
+                });
+                // Undo the change on the player's side
+                msg.player
+                    .do_send(player::ChangeRate { rate: session.rate });
+                return;
+            }
+
+            // Positions recorded at the old rate are extrapolated up to now before switching
+            let now = Instant::now();
+            session.position = (session.extrapolate(session.position), now);
+            session.positions = session
+                .positions
+                .iter()
+                .map(|(player, recorded)| (player.clone(), (session.extrapolate(*recorded), now)))
+                .collect();
+            session.rate = rate;
+
+            for player in &session.players {
+                player.addr.do_send(player::ChangeRate { rate });
+            }
+        }
+    }
+}
+
+impl Handler<PlaylistChanged> for SessionManager {
+    type Result = <PlaylistChanged as Message>::Result;
+
+    fn handle(&mut self, msg: PlaylistChanged, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("PlaylistChanged");
+        let Some(session) = self.sessions.get(&msg.session) else {
+            return;
+        };
+        if !session.may(&msg.player, Action::Playlist) {
+            // Players advance on their own at the end of an entry, which isn't an attempt
+            if msg.index != session.playlist_index && msg.index != session.playlist_index + 1 {
+                msg.player.do_send(player::PermissionDenied {
+                    action: Action::Playlist,
+                });
+            }
+            if session.playlist_index != msg.index {
+                msg.player.do_send(player::ChangePlaylist {
+                    index: session.playlist_index,
+                });
+            }
+            return;
+        }
+
+        // Players advance on their own at the end of an entry, which looping one entry overrides
+        if session.loop_mode == LoopMode::LoopOne && msg.index == session.playlist_index + 1 {
+            msg.player.do_send(player::ChangePlaylist {
+                index: session.playlist_index,
+            });
+            return;
+        }
+
+        // Players that moved onto a muted entry are sent on, or back if nothing after it is left
+        let Some(index) = session.playable_from(msg.index) else {
+            self.stop_at_passed_over(&msg.session, ctx);
+            return;
+        };
+        if index != msg.index && index == session.playlist_index {
+            msg.player.do_send(player::ChangePlaylist { index });
+            return;
+        }
+
+        let by = session.player_id(&msg.player);
+        self.change_playlist(&msg.session, index, by, ctx);
+    }
+}
+
+impl Handler<SetLoopMode> for SessionManager {
+    type Result = <SetLoopMode as Message>::Result;
+
+    fn handle(&mut self, msg: SetLoopMode, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("SetLoopMode");
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        if !session.may(&msg.player, Action::Playlist) {
+            msg.player.do_send(player::PermissionDenied {
+                action: Action::Playlist,
+            });
+            msg.player.do_send(player::LoopModeChanged {
+                mode: session.loop_mode,
+            });
+            return;
+        }
+
+        session.loop_mode = msg.mode;
+        for player in &session.players {
+            player
+                .addr
+                .do_send(player::LoopModeChanged { mode: msg.mode });
+        }
+        // Images only advance on their own if there is somewhere to advance to
+        if let Some(timer) = session.image_timer.take() {
+            ctx.cancel_future(timer);
+        }
+        self.schedule_image_advance(&msg.session, ctx);
+    }
+}
+
+impl Handler<MuteFolder> for SessionManager {
+    type Result = <MuteFolder as Message>::Result;
+
+    fn handle(&mut self, msg: MuteFolder, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("MuteFolder");
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        if !session.may(&msg.player, Action::Playlist) {
+            msg.player.do_send(player::PermissionDenied {
+                action: Action::Playlist,
+            });
+            msg.player.do_send(player::MutedFolders {
+                folders: session.muted_folders(),
+            });
+            return;
+        }
+
+        let changed = if msg.muted {
+            session.muted.insert(msg.folder)
+        } else {
+            session.muted.remove(&msg.folder)
+        };
+        if !changed {
+            return;
+        }
+
+        let folders = session.muted_folders();
+        for player in &session.players {
+            player.addr.do_send(player::MutedFolders {
+                folders: folders.clone(),
+            });
+        }
+
+        // Muting what is on right now moves on from it
+        if session.current_passed_over() {
+            let next = session.playlist_index + 1;
+            self.change_playlist(&msg.session, next, None, ctx);
+        }
+    }
+}
+
+impl Handler<ReportBroken> for SessionManager {
+    type Result = <ReportBroken as Message>::Result;
+
+    fn handle(&mut self, msg: ReportBroken, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("ReportBroken");
+        let blocklist = self.blocklist.clone();
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        // Reports of an entry that was already moved on from are late, not wrong
+        if msg.index != session.playlist_index || !session.may(&msg.player, Action::Playlist) {
+            return;
+        }
+        let Some(shitpost) = session.shitposts.get(msg.index) else {
+            return;
+        };
+        let Some(reporter) = session
+            .players
+            .iter()
+            .find(|player| player.addr == msg.player)
+        else {
+            return;
+        };
+
+        // Client tokens are made up by the client and player ids change with every reconnect,
+        // so reporters are told apart by address. Without one the session only counts once.
+        let reporter_id = match reporter.address {
+            Some(address) => address.to_string(),
+            None => format!("session {}", msg.session),
+        };
+        tracing::info!(r#"{} reported "{}" as broken"#, reporter.name, shitpost.url);
+        blocklist.report(&shitpost.url, &reporter_id);
+
+        let reason = format!("Reported as broken by {}", escape_html(&reporter.name));
+        for player in &session.players {
+            player.addr.do_send(player::EntrySkipped {
+                index: msg.index,
+                reason: reason.clone(),
+            });
+        }
+        self.change_playlist(&msg.session, msg.index + 1, None, ctx);
+    }
+}
+
+impl Handler<FilterTags> for SessionManager {
+    type Result = <FilterTags as Message>::Result;
+
+    fn handle(&mut self, msg: FilterTags, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("FilterTags");
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        if !session.is_host(&msg.player) {
+            msg.player.do_send(player::FilteredTags {
+                tags: session.filtered_tags(),
+            });
+            return;
+        }
+
+        session.filtered_tags = crate::normalize_tags(msg.tags)
+            .into_iter()
+            .take(MAX_FILTERED_TAGS)
+            .collect();
+        let tags = session.filtered_tags();
+        for player in &session.players {
+            player
+                .addr
+                .do_send(player::FilteredTags { tags: tags.clone() });
+        }
+
+        if session.current_passed_over() {
+            let next = session.playlist_index + 1;
+            self.change_playlist(&msg.session, next, None, ctx);
+        }
+    }
+}
+
+impl Handler<SetWaitForStragglers> for SessionManager {
+    type Result = <SetWaitForStragglers as Message>::Result;
+
+    fn handle(&mut self, msg: SetWaitForStragglers, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("SetWaitForStragglers");
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        if !session.is_host(&msg.player) {
+            msg.player.do_send(player::WaitForStragglersChanged {
+                enabled: session.wait_for_stragglers,
+            });
+            return;
+        }
+
+        session.wait_for_stragglers = msg.enabled;
+        for player in &session.players {
+            player.addr.do_send(player::WaitForStragglersChanged {
+                enabled: msg.enabled,
+            });
+        }
+        if !msg.enabled {
+            self.stop_waiting(&msg.session, ctx);
+        }
+    }
+}
+
+impl Handler<SetDebug> for SessionManager {
+    type Result = <SetDebug as Message>::Result;
+
+    fn handle(&mut self, msg: SetDebug, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("SetDebug");
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        if !session.is_host(&msg.player) {
+            msg.player.do_send(player::DebugChanged {
+                enabled: session.debug.is_some(),
+            });
+            return;
+        }
+
+        // Only runs while someone is looking, not for every session
+        match (msg.enabled, session.debug.take()) {
+            (true, None) => {
+                let name = msg.session.clone();
+                session.debug = Some(ctx.run_interval(Self::DEBUG_INTERVAL, move |act, _ctx| {
+                    act.broadcast_debug(&name)
+                }));
+            }
+            (true, Some(debug)) => session.debug = Some(debug),
+            (false, Some(debug)) => {
+                ctx.cancel_future(debug);
+            }
+            (false, None) => {}
+        }
+        for player in &session.players {
+            player.addr.do_send(player::DebugChanged {
+                enabled: msg.enabled,
+            });
+        }
+    }
+}
+
+impl Handler<SetPermissions> for SessionManager {
+    type Result = <SetPermissions as Message>::Result;
+
+    fn handle(&mut self, msg: SetPermissions, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("SetPermissions");
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        if !session.is_host(&msg.player) {
+            msg.player.do_send(player::PermissionsChanged {
+                permissions: session.permissions,
+            });
+            return;
+        }
+
+        tracing::info!(
+            r#"Permissions of session "{}" changed to {:?}"#,
+            msg.session,
+            msg.permissions
+        );
+        session.permissions = msg.permissions;
+        for player in &session.players {
+            player.addr.do_send(player::PermissionsChanged {
+                permissions: msg.permissions,
+            });
+        }
+    }
+}
+
+impl Handler<SetQuality> for SessionManager {
+    type Result = <SetQuality as Message>::Result;
+
+    fn handle(&mut self, msg: SetQuality, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("SetQuality");
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        let Some(player) = session
+            .players
+            .iter_mut()
+            .find(|player| player.addr == msg.player)
+        else {
+            return;
+        };
+        if player.quality == msg.quality {
+            return;
+        }
+        player.quality = msg.quality;
+
+        // The player reloads its playlist with the other files, which starts the entry over
+        msg.player.do_send(player::ChangePosition {
+            position: session.extrapolate(session.position),
+        });
+    }
+}
+
+impl Handler<SetStartOffset> for SessionManager {
+    type Result = <SetStartOffset as Message>::Result;
+
+    fn handle(&mut self, msg: SetStartOffset, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("SetStartOffset");
+        if !msg.seconds.is_finite() || msg.seconds < 0.0 {
+            return;
+        }
+
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        if !session.may(&msg.player, Action::Seek) {
+            msg.player.do_send(player::PermissionDenied {
+                action: Action::Seek,
+            });
+            return;
+        }
+
+        if msg.index < session.shitposts.len() {
+            session.playlist_mut()[msg.index].start_offset = msg.seconds;
+        }
+    }
+}
+
+impl Handler<Seeked> for SessionManager {
+    type Result = <Seeked as Message>::Result;
+
+    fn handle(&mut self, msg: Seeked, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("Seeked");
+        if let Some(session) = self.touch(&msg.session) {
+            // Without permission the seek is treated as drift and undone
+            if session.may(&msg.player, Action::Seek) {
+                session.seeker = Some(msg.player.clone());
+            } else {
+                msg.player.do_send(player::PermissionDenied {
+                    action: Action::Seek,
+                });
+            }
+        }
+        msg.player.do_send(player::SyncPosition);
+    }
+}
+
+impl Handler<Position> for SessionManager {
+    type Result = <Position as Message>::Result;
+
+    fn handle(&mut self, msg: Position, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("Position");
+        let tolerance = self.drift_tolerance;
+        let interval = self.correction_interval;
+
+        if let Some(session) = self.touch(&msg.session) {
+            // The position was taken about half a round trip ago, the probed round trip
+            // is smoothed over several answers so it is trusted over the last ping
+            let now = Instant::now();
+            let mut one_way = msg.latency / 2;
+            if let Some(player) = session
+                .players
+                .iter_mut()
+                .find(|player| player.addr == msg.player)
+            {
+                player.latency = msg.latency;
+                if let Some(clock) = player.clock {
+                    one_way = clock.one_way();
+                }
+            }
+            let recorded = now.checked_sub(one_way).unwrap_or(now);
+            session
+                .positions
+                .insert(msg.player.clone(), (msg.position, recorded));
+
+            // An explicit seek is followed by everyone
+            if session.seeker.as_ref() == Some(&msg.player) {
+                if let Some(id) = session.player_id(&msg.player) {
+                    session.events.push(SessionEvent::Seeked {
+                        player: id,
+                        position: msg.position,
+                    });
+                }
+                session.seeker = None;
+                session.position = (msg.position, recorded);
+                for player in &session.players {
+                    session
+                        .positions
+                        .insert(player.addr.clone(), (msg.position, recorded));
+                    if player.addr != msg.player {
+                        player.addr.do_send(player::ChangePosition {
+                            position: msg.position,
+                        });
+                    }
+                }
+                return;
+            }
+
+            let Some(reference) = session.reference().cloned() else {
+                return;
+            };
+
+            let current = session.extrapolate((msg.position, recorded));
+
+            // Corrections take a moment to land, until then the reports still drift.
+            // Players make up for the time a correction is underway from its timestamp.
+            let mut corrections = Vec::new();
+            if reference == msg.player {
+                session.position = (msg.position, recorded);
+                for player in session
+                    .players
+                    .iter()
+                    .filter(|player| player.addr != msg.player)
+                {
+                    let drifted = match session.estimated_position(&player.addr) {
+                        Some(position) => (position - current).abs() > tolerance,
+                        // Players that haven't reported yet, like ones that just joined
+                        None => true,
+                    };
+
+                    if drifted && session.correctable(&player.addr, interval, now) {
+                        corrections.push((player.addr.clone(), current));
+                    }
+                }
+            } else if let Some(position) = session.estimated_position(&reference) {
+                if (position - current).abs() > tolerance
+                    && session.correctable(&msg.player, interval, now)
+                {
+                    corrections.push((msg.player.clone(), position));
+                }
+            }
+
+            for (addr, position) in corrections {
+                session.corrected.insert(addr.clone(), now);
+                addr.do_send(player::ChangePosition { position });
+            }
+        }
+    }
+}
+
+impl Handler<ClockEstimated> for SessionManager {
+    type Result = <ClockEstimated as Message>::Result;
+
+    fn handle(&mut self, msg: ClockEstimated, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("ClockEstimated");
+        // Probes are sent by the server, so they don't keep an idle session around
+        let Some(session) = self.sessions.get_mut(&msg.session) else {
+            return;
+        };
+        if let Some(player) = session
+            .players
+            .iter_mut()
+            .find(|player| player.addr == msg.player)
+        {
+            player.clock = Some(msg.estimate);
+        }
+    }
+}
+
+impl Handler<Chat> for SessionManager {
+    type Result = <Chat as Message>::Result;
+
+    fn handle(&mut self, msg: Chat, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("Chat");
+        if let Some(session) = self.touch(&msg.session) {
+            let Some(from) = session
+                .players
+                .iter()
+                .find(|player| player.addr == msg.player)
+                .map(|player| escape_html(&player.name))
+            else {
+                return;
+            };
+
+            let text = escape_html(&msg.text.chars().take(MAX_CHAT_LENGTH).collect::<String>());
+
+            for player in &session.players {
+                player.addr.do_send(player::ChatMessage {
+                    from: from.clone(),
+                    text: text.clone(),
+                });
+            }
+        }
+    }
+}
+
+impl Handler<AddBookmark> for SessionManager {
+    type Result = <AddBookmark as Message>::Result;
+
+    fn handle(&mut self, msg: AddBookmark, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("AddBookmark");
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        let Some(by) = session
+            .players
+            .iter()
+            .find(|player| player.addr == msg.player)
+            .map(|player| player.name.to_string())
+        else {
+            return;
+        };
+        if session.bookmarks.len() >= MAX_BOOKMARKS {
+            msg.player.do_send(player::ShowError {
+                reason: "This session has too many bookmarks".to_string(),
+            });
+            return;
+        }
+
+        let shitpost = session.shitposts.get(session.playlist_index);
+        let bookmark = Bookmark {
+            by,
+            index: session.playlist_index,
+            title: shitpost.map(|shitpost| shitpost.title.clone()),
+            url: shitpost.map(|shitpost| shitpost.url.clone()),
+            position: session.extrapolate(session.position),
+            note: msg.note.trim().chars().take(MAX_NOTE_LENGTH).collect(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+
+        let by = escape_html(&bookmark.by);
+        let title = bookmark.title.as_deref().map(escape_html);
+        let note = escape_html(&bookmark.note);
+        for player in &session.players {
+            player.addr.do_send(player::Bookmarked {
+                by: by.clone(),
+                title: title.clone(),
+                position: bookmark.position,
+                note: note.clone(),
+            });
+        }
+        session.bookmarks.push(bookmark);
+    }
+}
+
+impl Handler<GetBookmarks> for SessionManager {
+    type Result = <GetBookmarks as Message>::Result;
+
+    fn handle(&mut self, msg: GetBookmarks, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.sessions
+            .get(&msg.session)
+            .map(|session| session.bookmarks.clone())
+    }
+}
+
+impl Handler<Reaction> for SessionManager {
+    type Result = <Reaction as Message>::Result;
+
+    fn handle(&mut self, msg: Reaction, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("Reaction");
+        if !REACTIONS.contains(&msg.emoji.as_str()) {
+            return;
+        }
+
+        if let Some(session) = self.touch(&msg.session) {
+            if session.state == player::State::Idle {
+                return;
+            }
+
+            let Some(from) = session
+                .players
+                .iter()
+                .find(|player| player.addr == msg.player)
+                .map(|player| escape_html(&player.name))
+            else {
+                return;
+            };
+
+            for player in &session.players {
+                player.addr.do_send(player::ReactionMessage {
+                    from: from.clone(),
+                    emoji: msg.emoji.clone(),
+                });
+            }
+        }
+    }
+}
+
+impl Handler<KickPlayer> for SessionManager {
+    type Result = <KickPlayer as Message>::Result;
+
+    fn handle(&mut self, msg: KickPlayer, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        let Some(session) = self.touch(&msg.session) else {
+            return false;
+        };
+        let Some(index) = session
+            .players
+            .iter()
+            .position(|player| player.id == msg.id)
+        else {
+            return false;
+        };
+
+        let player = session.players.remove(index);
+        session.events.push(SessionEvent::Kicked {
+            player: player.id,
+            reason: msg.reason.clone(),
+        });
+        session.positions.remove(&player.addr);
+        session.corrected.remove(&player.addr);
+        session.skip_votes.remove(&player.addr);
+
+        if msg.ban {
+            if let Some(client) = &player.client {
+                session.banned.insert(client.clone());
+            }
+        }
+
+        tracing::info!(
+            r#"Kicked "{}" from session "{}": {}"#,
+            player.name,
+            msg.session,
+            msg.reason
+        );
+        Self::broadcast_player_left(session, &player, DisconnectReason::Kicked);
+        Self::broadcast_viewers(session);
+        player.addr.do_send(player::Kicked { reason: msg.reason });
+        player.addr.do_send(player::Disconnect);
+
+        true
+    }
+}
+
+impl Handler<Ready> for SessionManager {
+    type Result = <Ready as Message>::Result;
+
+    fn handle(&mut self, msg: Ready, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("Ready");
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+
+        if !session
+            .players
+            .iter()
+            .any(|player| player.addr == msg.player)
+        {
+            return;
+        }
+
+        session.ready.insert(msg.player);
+        Self::broadcast_ready_status(session);
+
+        if session.ready.len() == session.participants() {
+            self.start_countdown(&msg.session, ctx);
+        }
+    }
+}
+
+impl Handler<StartCountdown> for SessionManager {
+    type Result = <StartCountdown as Message>::Result;
+
+    fn handle(&mut self, msg: StartCountdown, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("StartCountdown");
+        let Some(session) = self.sessions.get(&msg.session) else {
+            return;
+        };
+        // Ends in everyone playing, so it takes what starting playback does
+        if !session.may(&msg.player, Action::Pause) {
+            msg.player.do_send(player::PermissionDenied {
+                action: Action::Pause,
+            });
+            msg.player.do_send(player::ChangeState {
+                state: session.state,
+                by: None,
+            });
+            return;
+        }
+
+        self.start_countdown(&msg.session, ctx);
+    }
+}
+
+impl Handler<VoteSkip> for SessionManager {
+    type Result = <VoteSkip as Message>::Result;
+
+    fn handle(&mut self, msg: VoteSkip, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("VoteSkip");
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+
+        if session
+            .players
+            .iter()
+            .any(|player| player.addr == msg.player)
+        {
+            session.skip_votes.insert(msg.player);
+            self.tally_skip_votes(&msg.session, ctx);
+        }
+    }
+}
+
+impl Handler<GetMetrics> for SessionManager {
+    type Result = <GetMetrics as Message>::Result;
+
+    fn handle(&mut self, msg: GetMetrics, ctx: &mut Self::Context) -> Self::Result {
+        MetricsSnapshot {
+            active_sessions: self.sessions.len(),
+            connected_players: self
+                .sessions
+                .values()
+                .map(|session| session.players.len())
+                .sum(),
+            messages: self.metrics.messages.clone(),
+            sessions_created: self.metrics.sessions_created,
+            playlist_advances: self.metrics.playlist_advances,
+        }
+    }
+}
+
+impl Handler<Ping> for SessionManager {
+    type Result = <Ping as Message>::Result;
+
+    fn handle(&mut self, msg: Ping, ctx: &mut Self::Context) -> Self::Result {
+        Pong {
+            active_sessions: self.sessions.len(),
+            connected_players: self
+                .sessions
+                .values()
+                .map(|session| session.players.len())
+                .sum(),
+        }
+    }
+}
+
+impl Handler<GetHistory> for SessionManager {
+    type Result = <GetHistory as Message>::Result;
+
+    fn handle(&mut self, msg: GetHistory, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.sessions.get(&msg.session).map(Session::history)
+    }
+}
+
+impl Handler<AddToPlaylist> for SessionManager {
+    type Result = <AddToPlaylist as Message>::Result;
+
+    fn handle(&mut self, msg: AddToPlaylist, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        let Some(session) = self.touch(&msg.session) else {
+            return false;
+        };
+
+        tracing::info!(
+            r#"Added {} entries to session "{}""#,
+            msg.shitposts.len(),
+            msg.session
+        );
+        session.edit_playlist(|shitposts| shitposts.extend(msg.shitposts.iter().cloned()));
+        for player in &session.players {
+            player.addr.do_send(player::PlaylistUpdated {
+                added: msg.shitposts.clone(),
+                removed: None,
+                replaced_from: None,
+                index: session.playlist_index,
+            });
+        }
+
+        true
+    }
+}
+
+impl Handler<RemoveFromPlaylist> for SessionManager {
+    type Result = <RemoveFromPlaylist as Message>::Result;
+
+    fn handle(&mut self, msg: RemoveFromPlaylist, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        let Some(session) = self.touch(&msg.session) else {
+            return false;
+        };
+        if msg.index >= session.shitposts.len() || session.shitposts.len() == 1 {
+            return false;
+        }
+
+        let removed = session.edit_playlist(|shitposts| shitposts.remove(msg.index));
+        tracing::info!(
+            r#"Removed "{}" from session "{}""#,
+            removed.title,
+            msg.session
+        );
+
+        let current = session.playlist_index;
+        let index = index_after_removal(msg.index, current, session.shitposts.len());
+        session.playlist_index = index;
+
+        for player in &session.players {
+            player.addr.do_send(player::PlaylistUpdated {
+                added: Vec::new(),
+                removed: Some(msg.index),
+                replaced_from: None,
+                index,
+            });
+        }
+
+        if msg.index == current {
+            self.enter_entry(&msg.session, index, None, ctx);
+        } else {
+            // Players rebuild their playlist, which starts the entry over
+            let position = session.extrapolate(session.position);
+            for player in &session.players {
+                player.addr.do_send(player::ChangePosition { position });
+            }
+        }
+
+        true
+    }
+}
+
+impl Handler<ShuffleRemaining> for SessionManager {
+    type Result = <ShuffleRemaining as Message>::Result;
+
+    fn handle(&mut self, msg: ShuffleRemaining, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("ShuffleRemaining");
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        if !session.is_host(&msg.player) {
+            return;
+        }
+
+        // What has been played and the current entry stay where they are
+        let from = session.playlist_index + 1;
+        if session.shitposts.len() < from + 2 {
+            return;
+        }
+        let remaining = &mut session.playlist_mut()[from..];
+        remaining.shuffle(&mut rand::thread_rng());
+        let remaining = remaining.to_vec();
+        tracing::info!(
+            r#"Shuffled the remaining {} entries of session "{}""#,
+            remaining.len(),
+            msg.session
+        );
+
+        // Players rebuild their playlist, which starts the entry over
+        let position = session.extrapolate(session.position);
+        for player in &session.players {
+            player.addr.do_send(player::PlaylistUpdated {
+                added: remaining.clone(),
+                removed: None,
+                replaced_from: Some(from),
+                index: session.playlist_index,
+            });
+            player.addr.do_send(player::ChangePosition { position });
+        }
+    }
+}
+
+impl Handler<PlayNext> for SessionManager {
+    type Result = <PlayNext as Message>::Result;
+
+    fn handle(&mut self, msg: PlayNext, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("PlayNext");
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        if !session.is_host(&msg.player) {
+            return;
+        }
+        if msg.index >= session.shitposts.len() {
+            msg.player.do_send(player::ShowError {
+                reason: format!("There is no entry {} to play next", msg.index + 1),
+            });
+            return;
+        }
+
+        let next = session.playlist_index + 1;
+        if msg.index == next {
+            return;
+        }
+        if msg.index < next {
+            let copy = session.shitposts[msg.index].clone();
+            session.edit_playlist(|shitposts| shitposts.insert(next, copy));
+        } else {
+            session.playlist_mut()[next..=msg.index].rotate_right(1);
+        }
+        tracing::info!(
+            r#"Playing "{}" next in session "{}""#,
+            session.shitposts[next].title,
+            msg.session
+        );
+
+        // Players rebuild their playlist, which starts the entry over
+        let remaining = session.shitposts[next..].to_vec();
+        let position = session.extrapolate(session.position);
+        for player in &session.players {
+            player.addr.do_send(player::PlaylistUpdated {
+                added: remaining.clone(),
+                removed: None,
+                replaced_from: Some(next),
+                index: session.playlist_index,
+            });
+            player.addr.do_send(player::ChangePosition { position });
+        }
+    }
+}
+
+impl Handler<SetBackgrounded> for SessionManager {
+    type Result = <SetBackgrounded as Message>::Result;
+
+    fn handle(&mut self, msg: SetBackgrounded, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("SetBackgrounded");
+        // A hidden tab can't catch up, so there is nothing to wait for
+        if msg.backgrounded
+            && self
+                .sessions
+                .get(&msg.session)
+                .and_then(|session| session.straggler.as_ref())
+                .is_some_and(|(straggler, _)| *straggler == msg.player)
+        {
+            self.stop_waiting(&msg.session, ctx);
+        }
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        let Some(player) = session
+            .players
+            .iter_mut()
+            .find(|player| player.addr == msg.player)
+        else {
+            return;
+        };
+        if player.backgrounded == msg.backgrounded {
+            return;
+        }
+        player.backgrounded = msg.backgrounded;
+
+        if msg.backgrounded {
+            session.skip_votes.remove(&msg.player);
+            session.ready.remove(&msg.player);
+        } else {
+            // Only the returning player is caught up, the others are already in sync
+            session.corrected.remove(&msg.player);
+            msg.player.do_send(player::ChangeState {
+                state: session.state,
+                by: None,
+            });
+            msg.player.do_send(player::ChangePosition {
+                position: session.extrapolate(session.position),
+            });
+        }
+        Self::broadcast_ready_status(session);
+
+        // The thresholds change with the number of players counted
+        if !session.ready.is_empty() && session.ready.len() == session.participants() {
+            self.start_countdown(&msg.session, ctx);
+        }
+        if self
+            .sessions
+            .get(&msg.session)
+            .is_some_and(|session| !session.skip_votes.is_empty())
+        {
+            self.tally_skip_votes(&msg.session, ctx);
+        }
+    }
+}
+
+impl Handler<SkipIntermission> for SessionManager {
+    type Result = <SkipIntermission as Message>::Result;
+
+    fn handle(&mut self, msg: SkipIntermission, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.metrics.count_message("SkipIntermission");
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+        if !session.is_host(&msg.player) {
+            return;
+        }
+        let Some(intermission) = session.intermission.take() else {
+            return;
+        };
+
+        ctx.cancel_future(intermission);
+        self.advance_now(&msg.session, ctx);
+    }
+}
+
+impl Handler<ExportPlaylist> for SessionManager {
+    type Result = <ExportPlaylist as Message>::Result;
+
+    fn handle(&mut self, msg: ExportPlaylist, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        let session = self.sessions.get(&msg.session)?;
+
+        let mut folders: Vec<ExportedFolder> = Vec::new();
+        for shitpost in session.shitposts.iter() {
+            match folders
+                .iter_mut()
+                .find(|folder| folder.name == shitpost.folder)
+            {
+                Some(folder) => folder.entries += 1,
+                None => folders.push(ExportedFolder {
+                    name: shitpost.folder.clone(),
+                    entries: 1,
+                }),
+            }
+        }
+
+        Some(PlaylistExport {
+            version: EXPORT_VERSION,
+            code: msg.session.to_string(),
+            exported_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            folders,
+            permissions: session.permissions,
+            loop_mode: session.loop_mode,
+            seed: session.seed,
+            excluded: session.excluded.to_vec(),
+            shitposts: session.shitposts.to_vec(),
+        })
+    }
+}
+
+impl Handler<GetPlaylistPage> for SessionManager {
+    type Result = <GetPlaylistPage as Message>::Result;
+
+    fn handle(&mut self, msg: GetPlaylistPage, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        let session = self.sessions.get(&msg.session)?;
+
+        Some(PlaylistPage {
+            total: session.shitposts.len(),
+            shitposts: session
+                .shitposts
+                .iter()
+                .skip(msg.offset)
+                .take(msg.limit)
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+impl Handler<GetNowPlaying> for SessionManager {
+    type Result = <GetNowPlaying as Message>::Result;
+
+    fn handle(&mut self, msg: GetNowPlaying, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        let session = self.sessions.get(&msg.session)?;
+        let current = session.shitposts.get(session.playlist_index);
+
+        Some(NowPlaying {
+            title: current.map(|shitpost| shitpost.title.clone()),
+            url: current.map(|shitpost| shitpost.url.clone()),
+            index: session.playlist_index,
+            total: session.shitposts.len(),
+            state: session.state,
+            position_estimate: session.extrapolate(session.position),
+        })
+    }
+}
+
+impl Handler<CheckTicket> for SessionManager {
+    type Result = <CheckTicket as Message>::Result;
+
+    fn handle(&mut self, msg: CheckTicket, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.sessions
+            .get(&msg.session)
+            .map(|session| session.check_ticket(msg.ticket.as_deref()))
+    }
+}
+
+impl Handler<Persist> for SessionManager {
+    type Result = <Persist as Message>::Result;
+
+    fn handle(&mut self, msg: Persist, ctx: &mut Self::Context) -> Self::Result {
+        self.persist()
+    }
+}
+
+impl Handler<Shutdown> for SessionManager {
+    type Result = <Shutdown as Message>::Result;
+
+    fn handle(&mut self, msg: Shutdown, ctx: &mut Self::Context) -> Self::Result {
+        let persisted = self.persist();
+
+        for session in self.sessions.values() {
+            for player in &session.players {
+                player.addr.do_send(player::ServerClosing {
+                    reconnect_after_secs: msg.reconnect_after_secs,
+                });
+            }
+        }
+
+        persisted
+    }
+}
+
+impl Handler<RevealHostToken> for SessionManager {
+    type Result = <RevealHostToken as Message>::Result;
+
+    fn handle(&mut self, msg: RevealHostToken, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        let Some(session) = self.touch(&msg.session) else {
+            return;
+        };
+
+        if session.is_host(&msg.player) {
+            msg.player.do_send(player::HostToken {
+                token: session.host_token.clone(),
+            });
+        }
+    }
+}
+
+impl Handler<GetEvents> for SessionManager {
+    type Result = <GetEvents as Message>::Result;
+
+    fn handle(&mut self, msg: GetEvents, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.sessions
+            .get(&msg.session)
+            .map(|session| session.events.0.iter().cloned().collect())
+    }
+}
+
+impl Handler<GetSession> for SessionManager {
+    type Result = <GetSession as Message>::Result;
+
+    fn handle(&mut self, msg: GetSession, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        self.touch(&msg.session).map(|session| session.view())
+    }
+}
+
+impl Handler<ReplacePlaylist> for SessionManager {
+    type Result = <ReplacePlaylist as Message>::Result;
+
+    fn handle(&mut self, msg: ReplacePlaylist, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        let session = self.touch(&msg.session)?;
+        if !session.is_host_token(&msg.host_token) {
+            return Some(false);
+        }
+
+        // Swapped within one message, so players connecting meanwhile get one playlist whole
+        session.shitposts = msg.shitposts.into();
+        session.seed = msg.seed;
+        // Pages made for the old playlist are sent this one when they reconnect
+        session.generation = new_generation();
+        let index = session.playable_from(0).unwrap_or_default();
+        for player in &session.players {
+            player.addr.do_send(player::PlaylistReplaced {
+                generation: session.generation,
+                shitposts: session.shitposts.to_vec(),
+                index,
+            });
+        }
+        tracing::info!(
+            "Replaced the playlist with {} entries",
+            session.shitposts.len()
+        );
+
+        self.enter_entry(&msg.session, index, None, ctx);
+        self.change_state(&msg.session, player::State::Paused, None, ctx);
+        Some(true)
+    }
+}
+
+impl Handler<Reschedule> for SessionManager {
+    type Result = <Reschedule as Message>::Result;
+
+    fn handle(&mut self, msg: Reschedule, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        let session = self
+            .touch(&msg.session)
+            .ok_or(ScheduleError::NoSuchSession)?;
+        if !session.is_host_token(&msg.host_token) {
+            return Err(ScheduleError::NotHost);
+        }
+        if session.starts_at.is_none() {
+            return Err(ScheduleError::Started);
+        }
+
+        let Some(starts_at) = msg.starts_at else {
+            self.go_live(&msg.session, ctx);
+            return Ok(());
+        };
+        session.starts_at = Some(starts_at);
+        for player in &session.players {
+            player.addr.do_send(player::SessionScheduled { starts_at });
+        }
+        tracing::info!(
+            "Rescheduled to start in {}",
+            duration_text(
+                starts_at
+                    .duration_since(SystemTime::now())
+                    .unwrap_or_default()
+            )
+        );
+
+        self.schedule_start(&msg.session, ctx);
+        self.announce(
+            format!(
+                "Session {} now starts <t:{}:R>",
+                msg.session,
+                unix_secs(starts_at)
+            ),
+            ctx,
+        );
+        Ok(())
+    }
+}
+
+impl Handler<CancelScheduled> for SessionManager {
+    type Result = <CancelScheduled as Message>::Result;
+
+    fn handle(&mut self, msg: CancelScheduled, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        let session = self
+            .sessions
+            .get(&msg.session)
+            .ok_or(ScheduleError::NoSuchSession)?;
+        if !session.is_host_token(&msg.host_token) {
+            return Err(ScheduleError::NotHost);
+        }
+        if session.starts_at.is_none() {
+            return Err(ScheduleError::Started);
+        }
+
+        // Nothing was played, so there is no wrap-up to save
+        if let Some(session) = self.sessions.remove(&msg.session) {
+            Self::discard(&session, ctx);
+        }
+        tracing::info!(r#"Scheduled session "{}" was called off"#, msg.session);
+        self.announce(format!("Session {} was called off", msg.session), ctx);
+        Ok(())
+    }
+}
+
+impl Handler<FindSession> for SessionManager {
+    type Result = <FindSession as Message>::Result;
+
+    fn handle(&mut self, msg: FindSession, ctx: &mut Self::Context) -> Self::Result {
+        let code = msg.code.trim();
+        // Codes only differing in case can both exist, the exact one wins
+        if let Some((code, _)) = self.sessions.get_key_value(code) {
+            return Some(code.clone());
+        }
+
+        self.sessions
+            .keys()
+            .filter(|session| session.as_str().eq_ignore_ascii_case(code))
+            .min_by(|a, b| a.as_str().cmp(b.as_str()))
+            .cloned()
+    }
+}
+
+impl Handler<ListPublicSessions> for SessionManager {
+    type Result = <ListPublicSessions as Message>::Result;
+
+    fn handle(&mut self, msg: ListPublicSessions, ctx: &mut Self::Context) -> Self::Result {
+        let mut sessions = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.public)
+            .map(|(code, session)| PublicSession {
+                code: code.to_string(),
+                player_count: session.live_players(),
+                current_title: session
+                    .shitposts
+                    .get(session.playlist_index)
+                    .map(|shitpost| shitpost.title.clone()),
+            })
+            // Nobody to watch with, and sessions made just to fill the list stay out of it
+            .filter(|session| session.player_count > 0)
+            .collect::<Vec<_>>();
+
+        sessions.sort_by(|a, b| {
+            b.player_count
+                .cmp(&a.player_count)
+                .then_with(|| a.code.cmp(&b.code))
+        });
+        sessions.truncate(Self::MAX_PUBLIC_SESSIONS);
+        sessions
+    }
+}
+
+impl Handler<ListSessions> for SessionManager {
+    type Result = <ListSessions as Message>::Result;
+
+    fn handle(&mut self, msg: ListSessions, ctx: &mut Self::Context) -> Self::Result {
+        let mut summaries = self
+            .sessions
+            .iter()
+            .map(|(code, session)| SessionSummary {
+                code: code.to_string(),
+                players: session.players.len(),
+                roster: session
+                    .players
+                    .iter()
+                    .map(|player| PlayerSummary {
+                        id: player.id,
+                        name: player.name.to_string(),
+                        is_host: player.is_host,
+                    })
+                    .collect(),
+                playlist_index: session.playlist_index,
+                state: session.state,
+                title: session
+                    .shitposts
+                    .get(session.playlist_index)
+                    .map(|shitpost| shitpost.title.clone()),
+                uptime: session.created.elapsed().as_secs(),
+                starts_at: session.starts_at.map(unix_secs),
+            })
+            .collect::<Vec<_>>();
+
+        summaries.sort_by(|a, b| a.code.cmp(&b.code));
+        summaries
+    }
+}
+
+impl Handler<CloseSession> for SessionManager {
+    type Result = <CloseSession as Message>::Result;
+
+    fn handle(&mut self, msg: CloseSession, ctx: &mut Self::Context) -> Self::Result {
+        let _span = msg.session.span().entered();
+        let Some(session) = self.sessions.remove(&msg.session) else {
+            return false;
+        };
+
+        Self::discard(&session, ctx);
+        self.announce_end(&msg.session, session.created.elapsed(), ctx);
+        tracing::info!(r#"Session "{}" closed by an admin"#, msg.session);
+        self.save_stats(&msg.session, session);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        mem,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use actix_web_actors::ws;
+
+    use crate::clock::{self, Estimate};
+    use crate::player::State;
+    use crate::session::{
+        check_ticket, clamp_rate, escape_html, first_playable, index_after_completion,
+        index_after_removal, pick_reference, CancelScheduled, Candidate, ClockEstimated,
+        DisconnectReason, EventLog, FindSession, GetEvents, GetNowPlaying, GetPlaylistPage,
+        GetSession, ListPublicSessions, LoopMode, NewSessionError, PasswordHash, Permissions,
+        PlayNext, PlayerDisconnect, PlaylistChanged, PlaylistPage, Position, RateChanged, Ready,
+        RemoveFromPlaylist, ReplacePlaylist, Reschedule, ScheduleError, Seeked, SessionCode,
+        SessionEvent, SessionManager, SetBackgrounded, SetPermissions, SetStartOffset,
+        SetWaitForStragglers, ShuffleRemaining, SkipIntermission, StartCountdown, StateChanged,
+        VoteSkip, MAX_EVENTS,
+    };
+    use crate::testing::{allocated, code, kind, shitposts, Harness, TestPlayer};
+    use crate::{BrowserCompat, Shitpost, ShitpostKind};
+
+    #[test]
+    fn session_codes() {
+        let code = SessionManager::generate_code();
+
+        assert_eq!(code.as_str().len(), SessionManager::CODE_LENGTH);
+        assert!(!code.as_str().contains(['0', 'O', '1', 'l', 'I']));
+        assert!(SessionCode::try_from(code.to_string()).is_ok());
+    }
+
+    #[test]
+    fn session_code_validation() {
+        assert!(SessionCode::try_from("movie-night_2".to_string()).is_ok());
+        assert!(SessionCode::try_from("".to_string()).is_err());
+        assert!(SessionCode::try_from("a".repeat(33)).is_err());
+        assert!(SessionCode::try_from("<script>".to_string()).is_err());
+        assert!(SessionCode::try_from("spaces are bad".to_string()).is_err());
+    }
+
+    #[test]
+    fn chat_escaping() {
+        assert_eq!(
+            escape_html(r#"<img src="x" onerror='alert(1)'> & co"#),
+            "&lt;img src=&quot;x&quot; onerror=&#x27;alert(1)&#x27;&gt; &amp; co"
+        );
+    }
+
+    #[test]
+    fn removal_shifts_index() {
+        // Removed before the current entry
+        assert_eq!(index_after_removal(1, 3, 9), 2);
+        // Removed after it
+        assert_eq!(index_after_removal(5, 3, 9), 3);
+        // The current entry itself, the next one takes its place
+        assert_eq!(index_after_removal(3, 3, 9), 3);
+        // The current entry was the last one
+        assert_eq!(index_after_removal(9, 9, 9), 8);
+    }
+
+    #[test]
+    fn reference_selection() {
+        let player = |is_host, spectator, state| Candidate {
+            is_host,
+            spectator,
+            backgrounded: false,
+            state,
+        };
+        let pick = |players: &[Candidate]| pick_reference(players.iter().copied());
+
+        // Nobody is playing yet, the sync waits
+        let mut players = vec![player(false, false, None)];
+        assert_eq!(pick(&players), None);
+
+        // A spectator starting first isn't picked
+        players.insert(0, player(false, true, Some(State::Playing)));
+        assert_eq!(pick(&players), None);
+
+        players[1].state = Some(State::Playing);
+        players.push(player(false, false, Some(State::Playing)));
+        assert_eq!(pick(&players), Some(1));
+
+        // A buffering player is passed over for the next longest connected one
+        players[1].state = Some(State::Loading);
+        assert_eq!(pick(&players), Some(2));
+
+        // A playing host wins, but not while it is paused
+        players.push(player(true, false, Some(State::Paused)));
+        assert_eq!(pick(&players), Some(2));
+        players[3].state = Some(State::Playing);
+        assert_eq!(pick(&players), Some(3));
+
+        // The host leaving falls back to the longest connected player again
+        players.remove(3);
+        players[1].state = Some(State::Playing);
+        assert_eq!(pick(&players), Some(1));
+        players.remove(1);
+        assert_eq!(pick(&players), Some(1));
+
+        // Nor is a player whose tab is hidden
+        players[1].backgrounded = true;
+        assert_eq!(pick(&players), None);
+    }
+
+    #[test]
+    fn muted_folders() {
+        let shitposts = ["a", "b", "a", "c", "a"]
+            .iter()
+            .enumerate()
+            .map(|(i, folder)| Shitpost {
+                title: i.to_string(),
+                url: format!("/shitposts/{}/{}", folder, i),
+                kind: ShitpostKind::Video,
+                thumbnail: None,
+                low_url: None,
+                fingerprint: None,
+                start_offset: 0.0,
+                loudness: None,
+                compat: BrowserCompat::Safe,
+                folder: folder.to_string(),
+                size: None,
+                modified: None,
+                tags: if *folder == "c" {
+                    vec!["nsfw".to_string()]
+                } else {
+                    Vec::new()
+                },
+            })
+            .collect::<Vec<_>>();
+        let set = |items: &[&str]| {
+            items
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<HashSet<_>>()
+        };
+        let none = HashSet::new();
+
+        assert_eq!(
+            first_playable(&shitposts, &set(&[]), &none, 2, false),
+            Some(2)
+        );
+        assert_eq!(
+            first_playable(&shitposts, &set(&["a"]), &none, 2, false),
+            Some(3)
+        );
+        assert_eq!(
+            first_playable(&shitposts, &set(&["a"]), &none, 4, false),
+            None
+        );
+        assert_eq!(
+            first_playable(&shitposts, &set(&["a"]), &none, 4, true),
+            Some(1)
+        );
+        assert_eq!(
+            first_playable(&shitposts, &set(&["a", "b", "c"]), &none, 0, true),
+            None
+        );
+        // Past the end is left to the players like before
+        assert_eq!(
+            first_playable(&shitposts, &set(&["a"]), &none, 5, false),
+            Some(5)
+        );
+
+        // Tags are filtered like muted folders
+        assert_eq!(
+            first_playable(&shitposts, &none, &set(&["nsfw"]), 3, false),
+            Some(4)
+        );
+        assert_eq!(
+            first_playable(&shitposts, &set(&["a"]), &set(&["nsfw"]), 2, true),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn loop_modes() {
+        assert_eq!(index_after_completion(LoopMode::Once, 3, 9), Some(4));
+        assert_eq!(index_after_completion(LoopMode::Once, 8, 9), None);
+        assert_eq!(
+            index_after_completion(LoopMode::LoopPlaylist, 3, 9),
+            Some(4)
+        );
+        assert_eq!(
+            index_after_completion(LoopMode::LoopPlaylist, 8, 9),
+            Some(0)
+        );
+        assert_eq!(index_after_completion(LoopMode::LoopOne, 8, 9), Some(8));
+    }
+
+    #[test]
+    fn rate_clamping() {
+        assert_eq!(clamp_rate(1.5), Some(1.5));
+        assert_eq!(clamp_rate(0.0), Some(0.25));
+        assert_eq!(clamp_rate(16.0), Some(4.0));
+        assert_eq!(clamp_rate(f64::NAN), None);
+        assert_eq!(clamp_rate(f64::INFINITY), None);
+    }
+
+    #[test]
+    fn event_log_caps() {
+        let mut events = EventLog::default();
+        for player in 0..MAX_EVENTS as u64 + 10 {
+            events.push(SessionEvent::Disconnected {
+                player,
+                reason: DisconnectReason::Left,
+            });
+        }
+
+        assert_eq!(events.0.len(), MAX_EVENTS);
+        // The oldest ones were dropped
+        assert!(matches!(
+            events.0.front().unwrap().event,
+            SessionEvent::Disconnected { player: 10, .. }
+        ));
+        assert!(matches!(
+            events.0.back().unwrap().event,
+            SessionEvent::Disconnected { player, .. } if player == MAX_EVENTS as u64 + 9
+        ));
+    }
+
+    #[test]
+    fn password_hashes() {
+        let hash = PasswordHash::new("hunter2");
+        assert!(hash.verify("hunter2"));
+        assert!(!hash.verify("hunter3"));
+        // Salted, so the same password doesn't hash the same twice
+        let other = PasswordHash::new("hunter2");
+        assert_ne!(hash, other);
+        assert_ne!(hash.ticket(), other.ticket());
+
+        assert!(check_ticket(Some(&hash), Some(&hash.ticket())));
+        assert!(!check_ticket(Some(&hash), Some(&other.ticket())));
+        assert!(!check_ticket(Some(&hash), None));
+        assert!(check_ticket(None, None));
+    }
+
+    #[actix_web::test]
+    async fn duplicate_sessions() {
+        let harness = Harness::start();
+
+        assert!(harness.new_session("dupe", 3).await.is_ok());
+        assert!(matches!(
+            harness.new_session("dupe", 3).await,
+            Err(NewSessionError::Exists)
+        ));
+    }
+
+    #[actix_web::test]
+    async fn finding_sessions() {
+        let harness = Harness::start();
+        harness.new_session("Movie-Night", 3).await.unwrap();
+        harness.new_session("movie-night", 3).await.unwrap();
+        harness.new_session("cats", 3).await.unwrap();
+
+        let find = |code: &str| {
+            harness.manager.send(FindSession {
+                code: code.to_string(),
+            })
+        };
+        assert_eq!(find("  CATS\n").await.unwrap(), Some(code("cats")));
+        assert_eq!(
+            find("movie-night").await.unwrap(),
+            Some(code("movie-night"))
+        );
+        assert_eq!(
+            find("Movie-Night").await.unwrap(),
+            Some(code("Movie-Night"))
+        );
+        assert_eq!(
+            find("MOVIE-NIGHT").await.unwrap(),
+            Some(code("Movie-Night"))
+        );
+        assert_eq!(find("dogs").await.unwrap(), None);
+        assert_eq!(find("").await.unwrap(), None);
+    }
+
+    #[actix_web::test]
+    async fn connect_burst() {
+        let harness = Harness::start();
+        harness.new_session("burst", 3).await.unwrap();
+        let mut alice = harness.connect("burst", "alice").await;
+
+        let kinds = alice.received_kinds().await;
+        assert_eq!(
+            kinds[..5],
+            [
+                "welcome",
+                "session_info",
+                "change_state",
+                "change_rate",
+                "change_playlist"
+            ]
+        );
+        // Joining mid-entry picks up where everyone is
+        assert!(kinds.contains(&"change_position".to_string()));
+        assert!(kinds.contains(&"history".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn broadcasts() {
+        let harness = Harness::start();
+        harness.new_session("fanout", 3).await.unwrap();
+        let mut alice = harness.connect("fanout", "alice").await;
+        let mut bob = harness.connect("fanout", "bob").await;
+        alice.received().await;
+        bob.received().await;
+
+        harness
+            .manager
+            .send(StateChanged {
+                session: code("fanout"),
+                player: alice.addr.clone(),
+                state: State::Playing,
+            })
+            .await
+            .unwrap();
+        for player in [&mut alice, &mut bob] {
+            let received = player.received().await;
+            let change = received
+                .iter()
+                .find(|message| kind(message) == "change_state")
+                .unwrap();
+            assert_eq!(change["change_state"]["state"], "playing");
+            assert_eq!(change["change_state"]["by"]["name"], "alice");
+        }
+
+        // Alice plays, so she is the reference and bob hasn't reported anything yet
+        harness
+            .manager
+            .send(Position {
+                session: code("fanout"),
+                player: alice.addr.clone(),
+                position: 10.0,
+                latency: Duration::ZERO,
+            })
+            .await
+            .unwrap();
+        assert!(!alice
+            .received_kinds()
+            .await
+            .contains(&"change_position".to_string()));
+        let received = bob.received().await;
+        let position = received
+            .iter()
+            .find_map(|message| message["change_position"]["position"].as_f64())
+            .unwrap();
+        assert!((10.0..11.0).contains(&position), "{}", position);
+
+        harness
+            .manager
+            .send(PlaylistChanged {
+                session: code("fanout"),
+                player: bob.addr.clone(),
+                index: 1,
+            })
+            .await
+            .unwrap();
+        for player in [&mut alice, &mut bob] {
+            assert!(player
+                .received()
+                .await
+                .iter()
+                .any(|message| message["change_playlist"] == 1));
+        }
+    }
+
+    #[actix_web::test]
+    async fn seeks() {
+        let harness = Harness::start();
+        harness.new_session("seek", 3).await.unwrap();
+        let mut alice = harness.connect("seek", "alice").await;
+        let mut bob = harness.connect("seek", "bob").await;
+        alice.received().await;
+        bob.received().await;
+
+        harness
+            .manager
+            .send(Seeked {
+                session: code("seek"),
+                player: bob.addr.clone(),
+            })
+            .await
+            .unwrap();
+
+        // Only the one that seeked is asked where it ended up
+        assert_eq!(bob.received_kinds().await, ["sync_position"]);
+        assert!(alice.received().await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn paused_time() {
+        let harness = Harness::start();
+        harness.new_session("pause", 3).await.unwrap();
+        let host = harness.connect_host("pause", "host").await;
+
+        let change = |state| {
+            harness.manager.send(StateChanged {
+                session: code("pause"),
+                player: host.addr.clone(),
+                state,
+            })
+        };
+        let position = || async {
+            harness
+                .manager
+                .send(GetNowPlaying {
+                    session: code("pause"),
+                })
+                .await
+                .unwrap()
+                .unwrap()
+                .position_estimate
+        };
+        change(State::Playing).await.unwrap();
+        actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+        change(State::Paused).await.unwrap();
+        let paused_at = position().await;
+        assert!(paused_at >= 0.2);
+
+        actix_web::rt::time::sleep(Duration::from_millis(400)).await;
+        change(State::Playing).await.unwrap();
+        // Picks up where it was paused, not where it would be had it kept playing
+        let resumed_at = position().await;
+        assert!(resumed_at >= paused_at);
+        assert!(resumed_at < paused_at + 0.2, "{}", resumed_at);
+    }
+
+    /// A session waiting for stragglers, with the host playing and bob stalled
+    async fn stalled_session(harness: &Harness, session: &str) -> (TestPlayer, TestPlayer) {
+        harness.new_session(session, 3).await.unwrap();
+        let mut host = harness.connect_host(session, "host").await;
+        let mut bob = harness.connect(session, "bob").await;
+        harness
+            .manager
+            .send(SetWaitForStragglers {
+                session: code(session),
+                player: host.addr.clone(),
+                enabled: true,
+            })
+            .await
+            .unwrap();
+        for (player, state) in [
+            (&host, State::Playing),
+            (&bob, State::Playing),
+            (&bob, State::Stalled),
+        ] {
+            harness
+                .manager
+                .send(StateChanged {
+                    session: code(session),
+                    player: player.addr.clone(),
+                    state,
+                })
+                .await
+                .unwrap();
+        }
+
+        let received = host.received().await;
+        assert!(received
+            .iter()
+            .any(|message| message["waiting_for"]["name"] == "bob"));
+        assert_eq!(
+            received
+                .iter()
+                .rev()
+                .find_map(|message| message["change_state"]["state"].as_str()),
+            Some("paused")
+        );
+        bob.received().await;
+        (host, bob)
+    }
+
+    #[actix_web::test]
+    async fn recovered_stragglers() {
+        let harness = Harness::start();
+        let (mut host, bob) = stalled_session(&harness, "recovered").await;
+
+        harness
+            .manager
+            .send(StateChanged {
+                session: code("recovered"),
+                player: bob.addr.clone(),
+                state: State::Playing,
+            })
+            .await
+            .unwrap();
+        // Everyone continues from where they were paused
+        let kinds = host.received_kinds().await;
+        assert_eq!(kinds, ["change_position", "change_state"]);
+    }
+
+    #[actix_web::test]
+    async fn timed_out_stragglers() {
+        let harness = Harness::start_with("straggler_timeout: 1");
+        let (mut host, _bob) = stalled_session(&harness, "timeout").await;
+
+        actix_web::rt::time::sleep(Duration::from_millis(1100)).await;
+        let received = host.received().await;
+        assert!(received
+            .iter()
+            .any(|message| message["change_state"]["state"] == "playing"));
+    }
+
+    #[actix_web::test]
+    async fn overruled_stragglers() {
+        let harness = Harness::start_with("straggler_timeout: 1");
+        let (mut host, bob) = stalled_session(&harness, "overruled").await;
+
+        harness
+            .manager
+            .send(StateChanged {
+                session: code("overruled"),
+                player: host.addr.clone(),
+                state: State::Playing,
+            })
+            .await
+            .unwrap();
+        assert_eq!(host.received_kinds().await, ["change_state"]);
+
+        // Neither the straggler catching up nor the timeout snap anyone back anymore
+        harness
+            .manager
+            .send(StateChanged {
+                session: code("overruled"),
+                player: bob.addr.clone(),
+                state: State::Playing,
+            })
+            .await
+            .unwrap();
+        assert!(!host
+            .received_kinds()
+            .await
+            .contains(&"change_position".to_string()));
+        actix_web::rt::time::sleep(Duration::from_millis(1100)).await;
+        assert!(host.received().await.is_empty());
+    }
+
+    #[actix_web::test]
+    async fn last_disconnect() {
+        let harness = Harness::start();
+        harness.new_session("leave", 3).await.unwrap();
+        let alice = harness.connect("leave", "alice").await;
+        let bob = harness.connect("leave", "bob").await;
+
+        for (player, removed) in [(&alice, false), (&bob, true)] {
+            harness
+                .manager
+                .send(PlayerDisconnect {
+                    session: code("leave"),
+                    player: player.addr.clone(),
+                    reason: DisconnectReason::Left,
+                })
+                .await
+                .unwrap();
+            let session = harness
+                .manager
+                .send(GetSession {
+                    session: code("leave"),
+                })
+                .await
+                .unwrap();
+            assert_eq!(session.is_none(), removed);
+        }
+    }
+
+    #[actix_web::test]
+    async fn socket_disconnects() {
+        let harness = Harness::start();
+        harness.new_session("socket", 3).await.unwrap();
+        let mut watcher = harness.connect("socket", "watcher").await;
+        let mut alice = harness.open_socket("socket", "alice").await;
+        let mut bob = harness.open_socket("socket", "bob").await;
+        alice.received().await;
+        bob.received().await;
+        watcher.received().await;
+
+        alice.send_frame(8, &1000u16.to_be_bytes());
+        // Whatever follows the first bad frame mustn't count as another disconnect
+        bob.send_frame(2, b"binary");
+        bob.send_frame(2, b"binary");
+        alice.received().await;
+        bob.received().await;
+        assert!(alice.closed.is_some());
+        assert!(bob.closed.is_some());
+
+        let left: Vec<_> = watcher
+            .received()
+            .await
+            .into_iter()
+            .filter(|message| kind(message) == "player_left")
+            .map(|message| {
+                (
+                    message["player_left"]["name"].clone(),
+                    message["player_left"]["reason"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(left.len(), 2);
+        assert!(left.contains(&("alice".into(), "left".into())));
+        assert!(left.contains(&("bob".into(), "misbehaved".into())));
+
+        let events = harness
+            .manager
+            .send(GetEvents {
+                session: code("socket"),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let reasons: Vec<_> = events
+            .iter()
+            .filter_map(|logged| match logged.event {
+                SessionEvent::Disconnected { reason, .. } => Some(reason),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(reasons.len(), 2);
+        assert!(reasons.contains(&DisconnectReason::Left));
+        assert!(reasons.contains(&DisconnectReason::Misbehaved));
+    }
+
+    #[actix_web::test]
+    async fn turned_away_after_restore() {
+        let mut harness = Harness::start();
+        harness
+            .new_session_with("restored", 3, |msg| msg.max_players = Some(0))
+            .await
+            .unwrap();
+        harness.restart().await;
+
+        let mut bob = harness.connect("restored", "bob").await;
+        assert!(bob
+            .received_kinds()
+            .await
+            .contains(&"session_full".to_string()));
+        // Reported by the player as it stops, players of the harness never said hello to do it
+        harness
+            .manager
+            .send(PlayerDisconnect {
+                session: code("restored"),
+                player: bob.addr.clone(),
+                reason: DisconnectReason::Kicked,
+            })
+            .await
+            .unwrap();
+        // Still there for the players coming back
+        let session = harness
+            .manager
+            .send(GetSession {
+                session: code("restored"),
+            })
+            .await
+            .unwrap();
+        assert!(session.is_some());
+    }
+
+    #[actix_web::test]
+    async fn unknown_sessions() {
+        let harness = Harness::start();
+        let mut player = harness.connect("typo", "bob").await;
+
+        assert_eq!(player.received_kinds().await, ["no_such_session"]);
+        assert_eq!(
+            player.closed,
+            Some(ws::CloseReason {
+                code: ws::CloseCode::Normal,
+                description: Some("No such session exists".to_string()),
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn scheduled_sessions() {
+        let harness = Harness::start();
+        harness
+            .schedule_session("later", 3, SystemTime::now() + Duration::from_secs(3600))
+            .await
+            .unwrap();
+
+        // An empty lobby doesn't end a session that hasn't started
+        let carol = harness.connect("later", "carol").await;
+        harness
+            .manager
+            .send(PlayerDisconnect {
+                session: code("later"),
+                player: carol.addr.clone(),
+                reason: DisconnectReason::Left,
+            })
+            .await
+            .unwrap();
+
+        // The lobby hears nothing of the playlist until the start
+        let mut alice = harness.connect("later", "alice").await;
+        assert_eq!(
+            alice.received_kinds().await,
+            ["welcome", "session_scheduled", "viewers"]
+        );
+
+        let reschedule = |host_token: &str, starts_at| Reschedule {
+            session: code("later"),
+            host_token: host_token.to_string(),
+            starts_at,
+        };
+        assert_eq!(
+            harness
+                .manager
+                .send(reschedule("guess", None))
+                .await
+                .unwrap(),
+            Err(ScheduleError::NotHost)
+        );
+        let soon = SystemTime::now() + Duration::from_millis(100);
+        harness
+            .manager
+            .send(reschedule("host", Some(soon)))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(alice.received_kinds().await, ["session_scheduled"]);
+
+        actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+        let kinds = alice.received_kinds().await;
+        assert!(kinds.contains(&"playlist_replaced".to_string()));
+        assert!(kinds.contains(&"change_playlist".to_string()));
+        assert_eq!(kinds.last().map(String::as_str), Some("session_started"));
+
+        // Too late to change anything
+        assert_eq!(
+            harness
+                .manager
+                .send(reschedule("host", None))
+                .await
+                .unwrap(),
+            Err(ScheduleError::Started)
+        );
+        let cancel = |session: &str| CancelScheduled {
+            session: code(session),
+            host_token: "host".to_string(),
+        };
+        assert_eq!(
+            harness.manager.send(cancel("later")).await.unwrap(),
+            Err(ScheduleError::Started)
+        );
+
+        harness
+            .schedule_session("never", 3, SystemTime::now() + Duration::from_secs(3600))
+            .await
+            .unwrap();
+        let mut bob = harness.connect("never", "bob").await;
+        bob.received().await;
+        harness
+            .manager
+            .send(cancel("never"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(bob.received_kinds().await, ["session_closed"]);
+        let session = harness
+            .manager
+            .send(GetSession {
+                session: code("never"),
+            })
+            .await
+            .unwrap();
+        assert!(session.is_none());
+    }
+
+    #[actix_web::test]
+    async fn shared_playlists() {
+        const ENTRIES: usize = 2000;
+
+        let harness = Harness::start();
+        harness.new_session("big", ENTRIES).await.unwrap();
+        let host = harness.connect_host("big", "host").await;
+        let lookup = || {
+            harness.manager.send(GetSession {
+                session: code("big"),
+            })
+        };
+
+        // Lookups like the one of every join share the playlist instead of copying it
+        let first = lookup().await.unwrap().unwrap();
+        let before = allocated();
+        let second = lookup().await.unwrap().unwrap();
+        let lookup_bytes = allocated() - before;
+        assert!(Arc::ptr_eq(&first.shitposts, &second.shitposts));
+        assert!(
+            lookup_bytes < ENTRIES * mem::size_of::<Shitpost>() / 10,
+            "{} bytes",
+            lookup_bytes
+        );
+
+        // Changes copy the playlist while a lookup holds on to it, and leave that one alone
+        harness
+            .manager
+            .send(SetStartOffset {
+                session: code("big"),
+                player: host.addr.clone(),
+                index: 3,
+                seconds: 12.5,
+            })
+            .await
+            .unwrap();
+        let changed = lookup().await.unwrap().unwrap();
+        assert!(!Arc::ptr_eq(&first.shitposts, &changed.shitposts));
+        assert_eq!(first.shitposts[3].start_offset, 0.0);
+        assert_eq!(changed.shitposts[3].start_offset, 12.5);
+        assert_eq!(changed.shitposts.len(), ENTRIES);
+    }
+
+    #[actix_web::test]
+    async fn playlist_pages() {
+        let harness = Harness::start();
+        harness.new_session("paged", 5).await.unwrap();
+        harness.new_session("empty", 0).await.unwrap();
+        let page = |session: &str, offset: usize, limit: usize| {
+            harness.manager.send(GetPlaylistPage {
+                session: code(session),
+                offset,
+                limit,
+            })
+        };
+        let titles = |page: PlaylistPage| {
+            let titles: Vec<_> = page
+                .shitposts
+                .into_iter()
+                .map(|shitpost| shitpost.title)
+                .collect();
+            (page.total, titles)
+        };
+
+        assert_eq!(
+            titles(page("paged", 3, 2).await.unwrap().unwrap()),
+            (5, vec!["3".to_string(), "4".to_string()])
+        );
+        // Limits longer than the rest of the playlist end with it
+        assert_eq!(
+            titles(page("paged", 4, 100).await.unwrap().unwrap()),
+            (5, vec!["4".to_string()])
+        );
+        assert_eq!(
+            titles(page("paged", 9, 2).await.unwrap().unwrap()),
+            (5, vec![])
+        );
+        assert_eq!(
+            titles(page("empty", 0, 2).await.unwrap().unwrap()),
+            (0, vec![])
+        );
+        assert!(page("nothing", 0, 2).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn first_entry_offset() {
+        let harness = Harness::start();
+        harness
+            .new_session_with("offset", 3, |msg| msg.shitposts[0].start_offset = 7.5)
+            .await
+            .unwrap();
+
+        // Starts where the first entry was trimmed to, like any entry moved to later
+        let now_playing = harness
+            .manager
+            .send(GetNowPlaying {
+                session: code("offset"),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(now_playing.position_estimate, 7.5);
+    }
+
+    #[actix_web::test]
+    async fn play_next() {
+        let harness = Harness::start();
+        harness.new_session("pinned", 5).await.unwrap();
+        let mut host = harness.connect_host("pinned", "host").await;
+        let mut bob = harness.connect("pinned", "bob").await;
+
+        harness
+            .manager
+            .send(PlaylistChanged {
+                session: code("pinned"),
+                player: host.addr.clone(),
+                index: 2,
+            })
+            .await
+            .unwrap();
+        host.received().await;
+        bob.received().await;
+
+        // Applied in the order they arrive, already played entries are copied
+        for (player, index) in [(&host, 4), (&host, 0), (&bob, 1), (&host, 9)] {
+            harness
+                .manager
+                .send(PlayNext {
+                    session: code("pinned"),
+                    player: player.addr.clone(),
+                    index,
+                })
+                .await
+                .unwrap();
+        }
+
+        let session = harness
+            .manager
+            .send(GetSession {
+                session: code("pinned"),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let now_playing = harness
+            .manager
+            .send(GetNowPlaying {
+                session: code("pinned"),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(now_playing.index, 2);
+        assert_eq!(
+            session
+                .shitposts
+                .iter()
+                .map(|shitpost| shitpost.title.as_str())
+                .collect::<Vec<_>>(),
+            ["0", "1", "2", "0", "4", "3"]
+        );
+
+        let received = bob.received().await;
+        let updates = received
+            .iter()
+            .filter_map(|message| message.get("playlist_updated"))
+            .collect::<Vec<_>>();
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[1]["replaced_from"], 3);
+        assert_eq!(updates[1]["added"].as_array().unwrap().len(), 3);
+        assert!(!received.iter().any(|message| kind(message) == "error"));
+        // Only the host is told about the entry that doesn't exist
+        assert!(host.received_kinds().await.contains(&"error".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn public_sessions() {
+        let harness = Harness::start();
+        for (session, public) in [
+            ("busy", true),
+            ("quiet", true),
+            ("empty", true),
+            ("secret", false),
+        ] {
+            harness
+                .new_session_with(session, 1, |msg| msg.public = public)
+                .await
+                .unwrap();
+        }
+        let _players = [
+            harness.connect("quiet", "alice").await,
+            harness.connect("busy", "bob").await,
+            harness.connect("busy", "carol").await,
+            harness.connect("secret", "dave").await,
+        ];
+
+        let sessions = harness.manager.send(ListPublicSessions).await.unwrap();
+        assert_eq!(
+            sessions
+                .iter()
+                .map(|session| (session.code.as_str(), session.player_count))
+                .collect::<Vec<_>>(),
+            [("busy", 2), ("quiet", 1)]
+        );
+    }
+
+    #[actix_web::test]
+    async fn host_only_controls() {
+        let harness = Harness::start();
+        harness
+            .new_session_with("locked", 3, |msg| msg.permissions = Permissions::HOST_ONLY)
+            .await
+            .unwrap();
+        let mut host = harness.connect_host("locked", "host").await;
+        let mut bob = harness.connect("locked", "bob").await;
+        host.received().await;
+        bob.received().await;
+
+        harness
+            .manager
+            .send(RateChanged {
+                session: code("locked"),
+                player: bob.addr.clone(),
+                rate: 2.0,
+            })
+            .await
+            .unwrap();
+        harness
+            .manager
+            .send(StartCountdown {
+                session: code("locked"),
+                player: bob.addr.clone(),
+            })
+            .await
+            .unwrap();
+        // Only bob is put back
+        let received = bob.received().await;
+        assert!(received.iter().any(|message| message["change_rate"] == 1.0));
+        assert!(received
+            .iter()
+            .any(|message| message["change_state"]["state"] == "paused"));
+        assert!(host.received().await.is_empty());
+
+        harness
+            .manager
+            .send(RateChanged {
+                session: code("locked"),
+                player: host.addr.clone(),
+                rate: 2.0,
+            })
+            .await
+            .unwrap();
+        assert!(bob
+            .received()
+            .await
+            .iter()
+            .any(|message| message["change_rate"] == 2.0));
+    }
+
+    #[actix_web::test]
+    async fn permissions() {
+        let harness = Harness::start();
+        harness
+            .new_session_with("strict", 3, |msg| {
+                msg.permissions = Permissions {
+                    allow_pause: true,
+                    ..Permissions::HOST_ONLY
+                }
+            })
+            .await
+            .unwrap();
+        let mut host = harness.connect_host("strict", "host").await;
+        let mut bob = harness.connect("strict", "bob").await;
+        host.received().await;
+        bob.received().await;
+
+        // Anyone may pause and play
+        harness
+            .manager
+            .send(StateChanged {
+                session: code("strict"),
+                player: bob.addr.clone(),
+                state: State::Playing,
+            })
+            .await
+            .unwrap();
+        assert!(host
+            .received_kinds()
+            .await
+            .contains(&"change_state".to_string()));
+        assert!(!bob
+            .received_kinds()
+            .await
+            .contains(&"permission_denied".to_string()));
+
+        harness
+            .manager
+            .send(PlaylistChanged {
+                session: code("strict"),
+                player: bob.addr.clone(),
+                index: 2,
+            })
+            .await
+            .unwrap();
+        harness
+            .manager
+            .send(Seeked {
+                session: code("strict"),
+                player: bob.addr.clone(),
+            })
+            .await
+            .unwrap();
+        harness
+            .manager
+            .send(RateChanged {
+                session: code("strict"),
+                player: bob.addr.clone(),
+                rate: 2.0,
+            })
+            .await
+            .unwrap();
+        let received = bob.received().await;
+        let denied = received
+            .iter()
+            .filter_map(|message| message["permission_denied"]["action"].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(denied, ["playlist", "seek", "seek"]);
+        assert!(received.iter().any(|message| message["change_rate"] == 1.0));
+        // Only bob is put back
+        assert!(received
+            .iter()
+            .any(|message| message["change_playlist"] == 0));
+        assert!(host.received().await.is_empty());
+
+        harness
+            .manager
+            .send(SetPermissions {
+                session: code("strict"),
+                player: host.addr.clone(),
+                permissions: Permissions::default(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            bob.received().await[0]["permissions_changed"]["allow_playlist"],
+            true
+        );
+        harness
+            .manager
+            .send(PlaylistChanged {
+                session: code("strict"),
+                player: bob.addr.clone(),
+                index: 2,
+            })
+            .await
+            .unwrap();
+        assert!(host
+            .received()
+            .await
+            .iter()
+            .any(|message| message["change_playlist"] == 2));
+
+        // Counting down ends in everyone playing, which takes the pause permission
+        harness
+            .manager
+            .send(SetPermissions {
+                session: code("strict"),
+                player: host.addr.clone(),
+                permissions: Permissions::HOST_ONLY,
+            })
+            .await
+            .unwrap();
+        bob.received().await;
+        harness
+            .manager
+            .send(StartCountdown {
+                session: code("strict"),
+                player: bob.addr.clone(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            bob.received().await[0]["permission_denied"]["action"],
+            "pause"
+        );
+        assert!(!host
+            .received_kinds()
+            .await
+            .contains(&"countdown".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn server_advance() {
+        let harness = Harness::start();
+        harness.new_session("advance", 2).await.unwrap();
+        let host = harness.connect_host("advance", "host").await;
+        let mut bob = harness.connect("advance", "bob").await;
+
+        let state = |player: &TestPlayer, state| StateChanged {
+            session: code("advance"),
+            player: player.addr.clone(),
+            state,
+        };
+        let complete = |player: &TestPlayer| state(player, State::Complete);
+        // Playing makes the host the reference
+        harness
+            .manager
+            .send(state(&host, State::Playing))
+            .await
+            .unwrap();
+        bob.received().await;
+
+        // Only the host, as the reference, is listened to
+        harness.manager.send(complete(&bob)).await.unwrap();
+        assert!(bob.received().await.is_empty());
+        harness.manager.send(complete(&host)).await.unwrap();
+        let received = bob.received().await;
+        assert!(received
+            .iter()
+            .any(|message| message["change_playlist"] == 1));
+        assert!(received
+            .iter()
+            .any(|message| message["change_state"]["state"] == "playing"));
+
+        // Late reports and players catching up don't move it again
+        harness.manager.send(complete(&host)).await.unwrap();
+        harness
+            .manager
+            .send(PlaylistChanged {
+                session: code("advance"),
+                player: bob.addr.clone(),
+                index: 1,
+            })
+            .await
+            .unwrap();
+        let now_playing = harness
+            .manager
+            .send(GetNowPlaying {
+                session: code("advance"),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(now_playing.index, 1);
+        assert!(bob.received().await.is_empty());
+
+        harness.new_session("finish", 1).await.unwrap();
+        let host = harness.connect_host("finish", "host").await;
+        let mut bob = harness.connect("finish", "bob").await;
+        for state in [State::Playing, State::Complete] {
+            harness
+                .manager
+                .send(StateChanged {
+                    session: code("finish"),
+                    player: host.addr.clone(),
+                    state,
+                })
+                .await
+                .unwrap();
+        }
+        let received = bob.received().await;
+        assert!(received
+            .iter()
+            .any(|message| message == "playlist_finished"));
+        assert!(received
+            .iter()
+            .any(|message| message["change_state"]["state"] == "idle"));
+    }
+
+    #[actix_web::test]
+    async fn clock_estimates() {
+        let harness = Harness::start();
+        harness.new_session("clocks", 1).await.unwrap();
+        let mut host = harness.connect_host("clocks", "host").await;
+        let mut bob = harness.connect("clocks", "bob").await;
+        let mut carol = harness.connect("clocks", "carol").await;
+        harness
+            .manager
+            .send(StateChanged {
+                session: code("clocks"),
+                player: host.addr.clone(),
+                state: State::Playing,
+            })
+            .await
+            .unwrap();
+        for player in [&mut host, &mut bob, &mut carol] {
+            player.received().await;
+        }
+
+        // Bob's answers take 4 seconds to come back
+        harness
+            .manager
+            .send(ClockEstimated {
+                session: code("clocks"),
+                player: bob.addr.clone(),
+                estimate: Estimate {
+                    offset_ms: 250.0,
+                    rtt_ms: 4000.0,
+                },
+            })
+            .await
+            .unwrap();
+        let report = |player: &TestPlayer, position: f64| Position {
+            session: code("clocks"),
+            player: player.addr.clone(),
+            position,
+            latency: Duration::ZERO,
+        };
+        harness.manager.send(report(&bob, 8.0)).await.unwrap();
+        harness.manager.send(report(&carol, 8.0)).await.unwrap();
+        harness.manager.send(report(&host, 10.0)).await.unwrap();
+
+        // Bob's report was 2 seconds old by the time it arrived, so he is where the host is
+        assert!(!bob
+            .received_kinds()
+            .await
+            .contains(&"change_position".to_string()));
+        let received = carol.received().await;
+        let correction = received
+            .iter()
+            .find(|message| kind(message) == "change_position")
+            .unwrap();
+        let position = correction["change_position"]["position"].as_f64().unwrap();
+        assert!((10.0..11.0).contains(&position), "{}", position);
+        // Carol makes up for the time the correction was underway herself
+        let server_ms = correction["change_position"]["server_ms"].as_u64().unwrap();
+        assert!(clock::unix_ms(SystemTime::now()).abs_diff(server_ms) < 1000);
+    }
+
+    #[actix_web::test]
+    async fn throttled_corrections() {
+        let harness = Harness::start();
+        harness.new_session("crowd", 1).await.unwrap();
+        let mut host = harness.connect_host("crowd", "host").await;
+        let mut players = Vec::new();
+        for i in 0..10 {
+            players.push(harness.connect("crowd", &format!("player{}", i)).await);
+        }
+        // Playing makes the host the reference
+        harness
+            .manager
+            .send(StateChanged {
+                session: code("crowd"),
+                player: host.addr.clone(),
+                state: State::Playing,
+            })
+            .await
+            .unwrap();
+        host.received().await;
+        for player in &mut players {
+            player.received().await;
+        }
+
+        let report = |player: &TestPlayer, position: f64| Position {
+            session: code("crowd"),
+            player: player.addr.clone(),
+            position,
+            latency: Duration::ZERO,
+        };
+        // Everyone keeps reporting a position far from the host's
+        for round in 0..20 {
+            harness
+                .manager
+                .send(report(&host, 30.0 + round as f64))
+                .await
+                .unwrap();
+            for player in &players {
+                harness.manager.send(report(player, 0.0)).await.unwrap();
+            }
+        }
+
+        // 420 reports, but each drifting player is only corrected once in the interval
+        let mut corrections = 0;
+        for player in &mut players {
+            let received = player.received_kinds().await;
+            corrections += received
+                .iter()
+                .filter(|kind| *kind == "change_position")
+                .count();
+        }
+        assert_eq!(corrections, players.len());
+        assert!(!host
+            .received_kinds()
+            .await
+            .contains(&"change_position".to_string()));
+
+        // Seeks are still followed right away
+        harness
+            .manager
+            .send(Seeked {
+                session: code("crowd"),
+                player: host.addr.clone(),
+            })
+            .await
+            .unwrap();
+        harness.manager.send(report(&host, 5.0)).await.unwrap();
+        assert!(players[0]
+            .received()
+            .await
+            .iter()
+            .any(|message| message["change_position"]["position"] == 5.0));
+    }
+
+    #[actix_web::test]
+    async fn generations() {
+        let harness = Harness::start();
+        harness.new_session("again", 3).await.unwrap();
+        let generation = harness
+            .manager
+            .send(GetSession {
+                session: code("again"),
+            })
+            .await
+            .unwrap()
+            .unwrap()
+            .generation;
+
+        let mut alice = harness.reconnect("again", "alice", generation).await;
+        let received = alice.received().await;
+        let info = received
+            .iter()
+            .find_map(|message| message.get("session_info"))
+            .unwrap();
+        assert_eq!(info["generation"], generation);
+        assert_eq!(info["playlist_len"], 3);
+        assert!(!received
+            .iter()
+            .any(|message| kind(message) == "playlist_replaced"));
+
+        // A page of the session that used the code before gets the whole new playlist
+        let mut bob = harness.reconnect("again", "bob", generation + 1).await;
+        let received = bob.received().await;
+        let replaced = received
+            .iter()
+            .find_map(|message| message.get("playlist_replaced"))
+            .unwrap();
+        assert_eq!(replaced["generation"], generation);
+        assert_eq!(replaced["shitposts"].as_array().unwrap().len(), 3);
+    }
+
+    #[actix_web::test]
+    async fn intermissions() {
+        let harness = Harness::start();
+        harness
+            .new_session_with("breather", 3, |msg| msg.intermission_secs = Some(60))
+            .await
+            .unwrap();
+        let host = harness.connect_host("breather", "host").await;
+        let mut bob = harness.connect("breather", "bob").await;
+        for state in [State::Playing, State::Complete] {
+            harness
+                .manager
+                .send(StateChanged {
+                    session: code("breather"),
+                    player: host.addr.clone(),
+                    state,
+                })
+                .await
+                .unwrap();
+        }
+
+        let received = bob.received().await;
+        let intermission = received
+            .iter()
+            .find_map(|message| message.get("intermission"))
+            .unwrap();
+        assert_eq!(intermission["next_title"], "1");
+        assert_eq!(intermission["seconds"], 60);
+        assert!(!received
+            .iter()
+            .any(|message| message["change_playlist"] == 1));
+
+        // Only the host can cut it short
+        let skip = |player: &TestPlayer| SkipIntermission {
+            session: code("breather"),
+            player: player.addr.clone(),
+        };
+        harness.manager.send(skip(&bob)).await.unwrap();
+        assert!(bob.received().await.is_empty());
+        harness.manager.send(skip(&host)).await.unwrap();
+        assert!(bob
+            .received()
+            .await
+            .iter()
+            .any(|message| message["change_playlist"] == 1));
+    }
+
+    #[actix_web::test]
+    async fn joining_ready_checks() {
+        let harness = Harness::start();
+        harness.new_session("ready", 3).await.unwrap();
+        let mut alice = harness.connect("ready", "alice").await;
+        harness
+            .manager
+            .send(Ready {
+                session: code("ready"),
+                player: alice.addr.clone(),
+            })
+            .await
+            .unwrap();
+        alice.received().await;
+
+        let mut bob = harness.connect("ready", "bob").await;
+        for received in [bob.received().await, alice.received().await] {
+            assert!(received.iter().any(|message| {
+                message["ready_status"]["ready"] == 1 && message["ready_status"]["total"] == 2
+            }));
+        }
+    }
+
+    #[actix_web::test]
+    async fn images_after_removals() {
+        let harness = Harness::start_with("image_duration: 1");
+        harness
+            .new_session_with("slides", 4, |msg| {
+                msg.shitposts[1].kind = ShitpostKind::Image
+            })
+            .await
+            .unwrap();
+        let host = harness.connect_host("slides", "host").await;
+        harness
+            .manager
+            .send(PlaylistChanged {
+                session: code("slides"),
+                player: host.addr.clone(),
+                index: 1,
+            })
+            .await
+            .unwrap();
+
+        // The image moves up to the front, and the entry after it with it
+        harness
+            .manager
+            .send(RemoveFromPlaylist {
+                session: code("slides"),
+                index: 0,
+            })
+            .await
+            .unwrap();
+        actix_web::rt::time::sleep(Duration::from_millis(1100)).await;
+        let now_playing = harness
+            .manager
+            .send(GetNowPlaying {
+                session: code("slides"),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(now_playing.index, 1);
+        assert_eq!(now_playing.title.as_deref(), Some("2"));
+    }
+
+    #[actix_web::test]
+    async fn shuffled_remaining() {
+        const ENTRIES: usize = 20;
+        let harness = Harness::start();
+        harness.new_session("shuffle", ENTRIES).await.unwrap();
+        let host = harness.connect_host("shuffle", "host").await;
+        let bob = harness.connect("shuffle", "bob").await;
+        harness
+            .manager
+            .send(PlaylistChanged {
+                session: code("shuffle"),
+                player: host.addr.clone(),
+                index: 3,
+            })
+            .await
+            .unwrap();
+
+        let titles = || async {
+            harness
+                .manager
+                .send(GetPlaylistPage {
+                    session: code("shuffle"),
+                    offset: 0,
+                    limit: ENTRIES,
+                })
+                .await
+                .unwrap()
+                .unwrap()
+                .shitposts
+                .into_iter()
+                .map(|shitpost| shitpost.title.parse::<usize>().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let shuffle = |player: &TestPlayer| {
+            harness.manager.send(ShuffleRemaining {
+                session: code("shuffle"),
+                player: player.addr.clone(),
+            })
+        };
+        let original = (0..ENTRIES).collect::<Vec<_>>();
+
+        shuffle(&bob).await.unwrap();
+        assert_eq!(titles().await, original);
+
+        shuffle(&host).await.unwrap();
+        let shuffled = titles().await;
+        // Played entries and the current one stay where they are
+        assert_eq!(shuffled[..=3], original[..=3]);
+        let mut remaining = shuffled[4..].to_vec();
+        // The odds of the same order coming out again are 1 in 16!
+        assert_ne!(remaining, original[4..]);
+        remaining.sort_unstable();
+        assert_eq!(remaining, original[4..]);
+    }
+
+    #[actix_web::test]
+    async fn backgrounded_players() {
+        let harness = Harness::start();
+        harness.new_session("pocket", 3).await.unwrap();
+        let mut host = harness.connect_host("pocket", "host").await;
+        let mut bob = harness.connect("pocket", "bob").await;
+        let bob_addr = bob.addr.clone();
+        let background = |backgrounded| SetBackgrounded {
+            session: code("pocket"),
+            player: bob_addr.clone(),
+            backgrounded,
+        };
+
+        // Half of two players isn't enough to skip
+        harness
+            .manager
+            .send(VoteSkip {
+                session: code("pocket"),
+                player: host.addr.clone(),
+            })
+            .await
+            .unwrap();
+        host.received().await;
+        bob.received().await;
+
+        // With bob's phone locked, the host's vote is all it takes
+        harness.manager.send(background(true)).await.unwrap();
+        let received = host.received().await;
+        assert!(received
+            .iter()
+            .any(|message| message["ready_status"]["total"] == 1));
+        assert!(received
+            .iter()
+            .any(|message| message["change_playlist"] == 1));
+        bob.received().await;
+
+        // Coming back only catches bob up
+        harness.manager.send(background(false)).await.unwrap();
+        let received = bob.received_kinds().await;
+        assert!(received.contains(&"change_state".to_string()));
+        assert!(received.contains(&"change_position".to_string()));
+        assert!(!host
+            .received_kinds()
+            .await
+            .contains(&"change_position".to_string()));
+    }
+
+    #[actix_web::test]
+    async fn replaced_playlists() {
+        let harness = Harness::start();
+        harness.new_session("swap", 3).await.unwrap();
+        let mut bob = harness.connect("swap", "bob").await;
+        bob.received().await;
+        let generation = harness
+            .manager
+            .send(GetSession {
+                session: code("swap"),
+            })
+            .await
+            .unwrap()
+            .unwrap()
+            .generation;
+
+        let replace = |host_token: &str| ReplacePlaylist {
+            session: code("swap"),
+            host_token: host_token.to_string(),
+            shitposts: shitposts(5),
+            seed: Some(7),
+        };
+        assert_eq!(
+            harness.manager.send(replace("guess")).await.unwrap(),
+            Some(false)
+        );
+        assert!(bob.received().await.is_empty());
+        assert_eq!(
+            harness.manager.send(replace("host")).await.unwrap(),
+            Some(true)
+        );
+
+        let received = bob.received().await;
+        let replaced = &received[0]["playlist_replaced"];
+        assert_eq!(replaced["shitposts"].as_array().unwrap().len(), 5);
+        assert_eq!(replaced["index"], 0);
+        assert_ne!(replaced["generation"], generation);
+        assert!(received
+            .iter()
+            .any(|message| message["change_state"]["state"] == "paused"));
+
+        // A page made for the old playlist is caught up when it reconnects
+        let mut carol = harness.reconnect("swap", "carol", generation).await;
+        assert!(carol
+            .received()
+            .await
+            .iter()
+            .any(|message| message["playlist_replaced"]["shitposts"]
+                .as_array()
+                .is_some_and(|shitposts| shitposts.len() == 5)));
     }
 }
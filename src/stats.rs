@@ -0,0 +1,247 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs, io,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    get,
+    web::{self, Data},
+};
+use askama::Template;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    player::{self, AppError},
+    session::{Bookmark, SessionCode},
+    Html, SharedConfig,
+};
+
+/// Most entries whose time is listed individually, the totals count all of them
+const MAX_CLIPS: usize = 1000;
+
+/// Counters collected over the lifetime of a session
+#[derive(Default)]
+pub struct StatsCollector {
+    /// Pauses by player name
+    pauses: HashMap<Arc<str>, usize>,
+    watched: usize,
+    skipped: usize,
+    /// Time spent on each entry, in the order they were shown
+    clips: VecDeque<(Option<String>, Duration)>,
+    /// The entry being shown and since when
+    current: Option<(Option<String>, Instant)>,
+    watch_time: Duration,
+    /// When playback last started, None while not playing
+    playing_since: Option<Instant>,
+}
+
+impl StatsCollector {
+    /// Starts counting with the first entry already shown
+    pub fn new(title: Option<String>, now: Instant) -> Self {
+        let mut collector = Self::default();
+        collector.enter(title, now);
+        collector
+    }
+
+    pub fn pause(&mut self, name: &Arc<str>) {
+        *self.pauses.entry(name.clone()).or_default() += 1;
+    }
+
+    pub fn skip(&mut self) {
+        self.skipped += 1;
+    }
+
+    /// Moves on to another entry, finishing the time spent on the previous one
+    pub fn enter(&mut self, title: Option<String>, now: Instant) {
+        self.finish_clip(now);
+        self.watched += 1;
+        self.current = Some((title, now));
+    }
+
+    pub fn state_changed(&mut self, state: player::State, now: Instant) {
+        match (state, self.playing_since) {
+            (player::State::Playing, None) => self.playing_since = Some(now),
+            (player::State::Playing, Some(_)) => {}
+            (_, Some(since)) => {
+                self.watch_time += now.saturating_duration_since(since);
+                self.playing_since = None;
+            }
+            (_, None) => {}
+        }
+    }
+
+    fn finish_clip(&mut self, now: Instant) {
+        if let Some((title, since)) = self.current.take() {
+            self.clips
+                .push_back((title, now.saturating_duration_since(since)));
+            if self.clips.len() > MAX_CLIPS {
+                self.clips.pop_front();
+            }
+        }
+    }
+
+    /// Wraps up the counters once the session is over
+    pub fn summary(mut self, code: &SessionCode, uptime: Duration, now: Instant) -> SessionStats {
+        self.finish_clip(now);
+        self.state_changed(player::State::Idle, now);
+
+        let mut pauses = self
+            .pauses
+            .into_iter()
+            .map(|(name, pauses)| (name.to_string(), pauses))
+            .collect::<Vec<_>>();
+        pauses.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        SessionStats {
+            code: code.to_string(),
+            ended_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            uptime: uptime.as_secs(),
+            watched: self.watched,
+            skipped: self.skipped,
+            watch_time: self.watch_time.as_secs(),
+            pauses,
+            clips: self
+                .clips
+                .into_iter()
+                .map(|(title, time)| ClipStats {
+                    title,
+                    seconds: time.as_secs(),
+                })
+                .collect(),
+            bookmarks: Vec::new(),
+        }
+    }
+}
+
+/// How a session went, kept after it is over
+#[derive(Serialize, Deserialize)]
+pub struct SessionStats {
+    pub code: String,
+    /// Unix time the session ended at
+    pub ended_at: u64,
+    /// Seconds the session ran for
+    pub uptime: u64,
+    /// Entries that were shown
+    pub watched: usize,
+    /// Entries that were skipped by a vote
+    pub skipped: usize,
+    /// Seconds spent playing
+    pub watch_time: u64,
+    /// Players and how often they paused, most first
+    pub pauses: Vec<(String, usize)>,
+    pub clips: Vec<ClipStats>,
+    #[serde(default)]
+    pub bookmarks: Vec<Bookmark>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClipStats {
+    pub title: Option<String>,
+    /// Seconds the entry was on
+    pub seconds: u64,
+}
+
+#[derive(Template)]
+#[template(path = "stats.html")]
+struct Stats<'a> {
+    stats: &'a SessionStats,
+}
+
+/// Written to a temporary file first like the sessions, so a half written file is never read
+pub fn save(dir: &str, summary: &SessionStats) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let path = Path::new(dir).join(format!("{}.json", summary.code));
+    let temp = path.with_extension("json.tmp");
+
+    fs::write(&temp, serde_json::to_vec(summary)?)?;
+    fs::rename(&temp, &path)
+}
+
+fn load(path: &Path) -> io::Result<SessionStats> {
+    Ok(serde_json::from_slice(&fs::read(path)?)?)
+}
+
+/// Wrap-up of a session that has ended
+#[get("/stats/{code}")]
+async fn stats(config: Data<SharedConfig>, code: web::Path<SessionCode>) -> Result<Html, AppError> {
+    let path = Path::new(&config.get().stats_dir).join(format!("{}.json", code));
+
+    let stats = web::block(move || load(&path))
+        .await
+        .map_err(|why| AppError::Internal(why.to_string()))?
+        .map_err(|why| match why.kind() {
+            io::ErrorKind::NotFound => AppError::NotFound(
+                "No stats for this session, it might still be running".to_string(),
+            ),
+            _ => {
+                tracing::error!(r#"Failed to read the stats of session "{}": {}"#, code, why);
+                AppError::Internal("Failed to read the stats".to_string())
+            }
+        })?;
+
+    Html::render(Stats { stats: &stats })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use askama::Template;
+
+    use crate::{
+        player::State,
+        session::SessionCode,
+        stats::{Stats, StatsCollector},
+    };
+
+    #[test]
+    fn session_stats() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let bob: Arc<str> = "bob".into();
+        let alice: Arc<str> = "alice".into();
+
+        let mut collector = StatsCollector::default();
+        collector.enter(Some("first".to_string()), at(0));
+        collector.state_changed(State::Playing, at(0));
+        collector.pause(&bob);
+        collector.state_changed(State::Paused, at(10));
+        collector.state_changed(State::Playing, at(20));
+        collector.enter(Some("second".to_string()), at(30));
+        collector.skip();
+        collector.pause(&alice);
+        collector.pause(&bob);
+        collector.enter(None, at(35));
+
+        let code = SessionCode::try_from("test".to_string()).unwrap();
+        let stats = collector.summary(&code, Duration::from_secs(45), at(45));
+
+        assert_eq!(stats.watched, 3);
+        assert_eq!(stats.skipped, 1);
+        // Paused from 10 to 20, played until the end
+        assert_eq!(stats.watch_time, 35);
+        assert_eq!(
+            stats.pauses,
+            vec![("bob".to_string(), 2), ("alice".to_string(), 1)]
+        );
+        assert_eq!(
+            stats
+                .clips
+                .iter()
+                .map(|clip| clip.seconds)
+                .collect::<Vec<_>>(),
+            vec![30, 5, 10]
+        );
+
+        Stats { stats: &stats }.render().unwrap();
+    }
+}
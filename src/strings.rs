@@ -0,0 +1,246 @@
+use std::{collections::HashMap, fs, path::Path, sync::Arc};
+
+use actix_web::{
+    dev::Payload,
+    http::header::{self, Header, Preference},
+    web::{Data, Query},
+    FromRequest, HttpRequest,
+};
+use futures_util::future::{ready, Ready};
+use serde::Deserialize;
+
+/// The built-in text, used for any key a configured table leaves out.
+/// `{}` is replaced with the detail the text is about.
+const ENGLISH: &[(&str, &str)] = &[
+    ("index.title", "Shitposting!"),
+    ("index.join", "Join session"),
+    ("index.host", "Host session"),
+    ("index.public", "Running now"),
+    ("index.watching", "{} watching"),
+    ("form.session", "Session ID"),
+    ("form.name", "Nickname"),
+    ("form.password", "Password"),
+    ("form.join", "Join"),
+    ("password.needed", "This session needs a password."),
+    ("error.no_such_session", "No such session exists"),
+    ("error.wrong_password", "Wrong password"),
+    ("error.not_host", "Only hosts can do this"),
+    ("error.session_full", "This session is full ({} players)"),
+    (
+        "error.rate_limited",
+        "Too many sessions created, try again later",
+    ),
+    ("error.no_folders", "No folders were selected"),
+    ("error.unreadable_folders", "Failed to read folders: {}"),
+    (
+        "error.no_files",
+        "The selected folders have no files to play: {}",
+    ),
+    ("error.empty_playlist", "Playlists need at least one entry"),
+    (
+        "error.playlist_too_long",
+        "Playlists can have at most {} entries",
+    ),
+    ("error.picked_twice", r#""{}" was picked more than once"#),
+    (
+        "error.not_pickable",
+        r#""{}" isn't a playable file of the selected folders"#,
+    ),
+    (
+        "error.not_importable",
+        r#""{}" isn't a playable file of the configured folders"#,
+    ),
+    ("error.invalid_playlist", "The playlist can't be read: {}"),
+    (
+        "error.playlist_version",
+        "The playlist is of a newer version ({}) than this server reads",
+    ),
+    ("error.session_exists", "Session already exists"),
+    ("error.invalid_start", "The start time can't be read: {}"),
+    (
+        "error.schedule_too_far",
+        "Sessions can be scheduled at most {} days ahead",
+    ),
+    ("error.already_started", "The session has started already"),
+    (
+        "error.too_many_sessions",
+        "Too many sessions are running, try again later",
+    ),
+];
+
+/// UI text by key in one language, falling back to English
+#[derive(Clone, Default)]
+pub struct Strings {
+    /// Language the table was picked for, None for the built-in English
+    lang: Option<Arc<str>>,
+    table: Arc<HashMap<String, String>>,
+}
+
+impl Strings {
+    /// The key itself is shown if not even English has it, so typos are easy to spot
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.table
+            .get(key)
+            .map(String::as_str)
+            .or_else(|| {
+                ENGLISH
+                    .iter()
+                    .find(|(english, _)| *english == key)
+                    .map(|(_, text)| *text)
+            })
+            .unwrap_or(key)
+    }
+
+    /// The text with `{}` replaced by the detail
+    pub fn format(&self, key: &str, detail: impl std::fmt::Display) -> String {
+        self.get(key).replacen("{}", &detail.to_string(), 1)
+    }
+
+    /// Passed on by links and forms, so the language sticks without an Accept-Language
+    pub fn lang(&self) -> &str {
+        self.lang.as_deref().unwrap_or_default()
+    }
+}
+
+/// The tables configured in strings.ron, by language like "fi" or "pt-BR"
+#[derive(Default)]
+pub struct Translations(HashMap<String, Strings>);
+
+#[derive(Deserialize)]
+struct LangQuery {
+    lang: Option<String>,
+}
+
+impl Translations {
+    /// Only English is available if the file is missing or invalid
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let Ok(text) = fs::read_to_string(path) else {
+            return Self::default();
+        };
+
+        match ron::from_str::<HashMap<String, HashMap<String, String>>>(&text) {
+            Ok(tables) => {
+                tracing::info!(
+                    r#"Loaded {} languages from "{}""#,
+                    tables.len(),
+                    path.display()
+                );
+                Self::new(tables)
+            }
+            Err(why) => {
+                tracing::warn!(r#"Ignoring invalid "{}": {}"#, path.display(), why);
+                Self::default()
+            }
+        }
+    }
+
+    fn new(tables: HashMap<String, HashMap<String, String>>) -> Self {
+        Self(
+            tables
+                .into_iter()
+                .map(|(lang, table)| {
+                    let lang = lang.to_lowercase();
+                    let strings = Strings {
+                        lang: Some(lang.as_str().into()),
+                        table: Arc::new(table),
+                    };
+                    (lang, strings)
+                })
+                .collect(),
+        )
+    }
+
+    /// A language tag like "fi-FI" matches a "fi-fi" table first, then "fi"
+    fn find(&self, tag: &str) -> Option<&Strings> {
+        let tag = tag.to_lowercase();
+        self.0.get(&tag).or_else(|| {
+            let primary = tag.split('-').next()?;
+            self.0.get(primary)
+        })
+    }
+
+    /// Picks the table from the lang query parameter, then Accept-Language, then English
+    pub fn select(&self, req: &HttpRequest) -> Strings {
+        let lang = Query::<LangQuery>::from_query(req.query_string())
+            .ok()
+            .and_then(|query| query.into_inner().lang);
+        if let Some(strings) = lang.and_then(|lang| self.find(&lang)) {
+            return strings.clone();
+        }
+
+        header::AcceptLanguage::parse(req)
+            .map(|accept| accept.ranked())
+            .unwrap_or_default()
+            .into_iter()
+            .find_map(|preference| match preference {
+                Preference::Specific(tag) => self.find(tag.as_str()),
+                Preference::Any => None,
+            })
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+impl FromRequest for Strings {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let strings = req
+            .app_data::<Data<Translations>>()
+            .map(|translations| translations.select(req))
+            .unwrap_or_default();
+
+        ready(Ok(strings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_web::test::TestRequest;
+
+    use crate::strings::Translations;
+
+    #[test]
+    fn language_selection() {
+        let translations = Translations::new(HashMap::from([
+            (
+                "fi".to_string(),
+                HashMap::from([("form.join".to_string(), "Liity".to_string())]),
+            ),
+            (
+                "SV".to_string(),
+                HashMap::from([("form.join".to_string(), "Gå med".to_string())]),
+            ),
+        ]));
+
+        let strings = translations.select(
+            &TestRequest::default()
+                .insert_header(("Accept-Language", "de;q=0.9, fi-FI, en;q=0.5"))
+                .to_http_request(),
+        );
+        assert_eq!(strings.get("form.join"), "Liity");
+        // Left out of the table, so English
+        assert_eq!(strings.get("form.name"), "Nickname");
+        assert_eq!(strings.lang(), "fi");
+
+        // The query parameter wins over the header
+        let strings = translations.select(
+            &TestRequest::with_uri("/?lang=sv")
+                .insert_header(("Accept-Language", "fi"))
+                .to_http_request(),
+        );
+        assert_eq!(strings.get("form.join"), "Gå med");
+
+        let strings = translations.select(&TestRequest::with_uri("/?lang=xx").to_http_request());
+        assert_eq!(strings.get("form.join"), "Join");
+        assert_eq!(
+            strings.format("error.playlist_too_long", 100),
+            "Playlists can have at most 100 entries"
+        );
+        assert_eq!(strings.get("no.such.key"), "no.such.key");
+    }
+}
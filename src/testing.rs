@@ -0,0 +1,386 @@
+//! Runs a `SessionManager` with real players that are only missing the browser on the other end,
+//! so tests can check what each player would be sent
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use actix::{Actor, Addr};
+use actix_web::{error::PayloadError, web::Bytes};
+use actix_web_actors::ws;
+use futures_util::{stream, FutureExt, Stream, StreamExt};
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::{
+    blocklist::Blocklist,
+    player::{Heartbeat, PlayerActor, Transport, PROTOCOL_VERSION},
+    recent::RecentlyPlayed,
+    session::{self, NewSessionError, Permissions, SessionCode, SessionManager},
+    BrowserCompat, Config, Shitpost, ShitpostKind,
+};
+
+/// How long the actors get to pass their messages around before the players are read
+const SETTLE: Duration = Duration::from_millis(50);
+
+/// Keeps the files of harnesses running at the same time apart
+static HARNESSES: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts what each thread allocates, so tests can tell something wasn't copied.
+/// Tests run on threads of their own, as do the actors of their harness.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // Fails while the thread is shutting down, those allocations aren't of interest
+        let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + layout.size()));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Bytes allocated by the current thread so far
+pub fn allocated() -> usize {
+    ALLOCATED.with(Cell::get)
+}
+
+pub struct Harness {
+    pub manager: Addr<SessionManager>,
+    dir: PathBuf,
+    /// Fields added to the default config
+    config: String,
+}
+
+impl Harness {
+    /// A manager with the default config, keeping its files in a temporary folder
+    pub fn start() -> Self {
+        Self::start_with("")
+    }
+
+    /// Like `start`, with the given fields added to the config, like "straggler_timeout: 1"
+    pub fn start_with(config: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "harness-{}-{}",
+            std::process::id(),
+            HARNESSES.fetch_add(1, Ordering::SeqCst)
+        ));
+
+        Self {
+            manager: Self::manager(&dir, config).start(),
+            dir,
+            config: config.to_string(),
+        }
+    }
+
+    /// Replaces the manager with one that restored its sessions, like after a restart
+    pub async fn restart(&mut self) {
+        let sessions = self.manager.send(session::Persist).await.unwrap();
+        let mut manager = Self::manager(&self.dir, &self.config);
+        manager.restore(sessions);
+        self.manager = manager.start();
+    }
+
+    fn manager(dir: &Path, config: &str) -> SessionManager {
+        let config: Config = ron::from_str(&format!(
+            r#"(shitposts: [], bind: "", stats_dir: {:?}, {})"#,
+            dir.join("stats").to_string_lossy(),
+            config
+        ))
+        .unwrap();
+        let recent = Arc::new(RecentlyPlayed::load(
+            dir.join("recent.json"),
+            Duration::ZERO,
+        ));
+        let blocklist = Arc::new(Blocklist::load(dir.join("blocklist.json"), 0));
+
+        SessionManager::new(&config, recent, blocklist)
+    }
+
+    /// Creates an open session of videos named after their index
+    pub async fn new_session(&self, session: &str, entries: usize) -> Result<(), NewSessionError> {
+        self.new_session_with(session, entries, |_| {}).await
+    }
+
+    /// Like `new_session`, but players wait in the lobby until `starts_at`
+    pub async fn schedule_session(
+        &self,
+        session: &str,
+        entries: usize,
+        starts_at: SystemTime,
+    ) -> Result<(), NewSessionError> {
+        self.new_session_with(session, entries, |msg| msg.starts_at = Some(starts_at))
+            .await
+    }
+
+    /// Like `new_session`, with whatever the test cares about changed before it is sent
+    pub async fn new_session_with(
+        &self,
+        session: &str,
+        entries: usize,
+        change: impl FnOnce(&mut session::NewSession),
+    ) -> Result<(), NewSessionError> {
+        let mut msg = session::NewSession {
+            session: code(session),
+            shitposts: shitposts(entries),
+            password: None,
+            host_token: "host".to_string(),
+            permissions: Permissions::default(),
+            max_players: None,
+            public: false,
+            intermission_secs: None,
+            seed: None,
+            excluded: Vec::new(),
+            starts_at: None,
+        };
+        change(&mut msg);
+
+        self.manager.send(msg).await.unwrap()
+    }
+
+    /// Connects a player to the session, like it just said hello
+    pub async fn connect(&self, session: &str, name: &str) -> TestPlayer {
+        self.join(session, name, None, None)
+    }
+
+    /// Connects a player with the host token of sessions made by `new_session`
+    pub async fn connect_host(&self, session: &str, name: &str) -> TestPlayer {
+        self.join(session, name, Some("host".to_string()), None)
+    }
+
+    /// Connects a player whose page was made for the given generation of the session
+    pub async fn reconnect(&self, session: &str, name: &str, generation: u64) -> TestPlayer {
+        self.join(session, name, None, Some(generation))
+    }
+
+    /// Connects a player that says hello over its socket itself, so frames can be sent as the
+    /// browser would with `TestPlayer::send_frame`
+    pub async fn open_socket(&self, session: &str, name: &str) -> TestPlayer {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let client = stream::unfold(receiver, |mut receiver| async move {
+            let bytes = receiver.recv().await?;
+            Some((Ok::<_, PayloadError>(bytes), receiver))
+        });
+        let mut player = self.player(session, name, None, client);
+        player.client = Some(sender);
+        player.send_frame(
+            1,
+            format!(
+                r#"{{"Hello": {{"protocol_version": {{"major": {}, "minor": 0}}}}}}"#,
+                PROTOCOL_VERSION.major
+            )
+            .as_bytes(),
+        );
+        player
+    }
+
+    fn join(
+        &self,
+        session: &str,
+        name: &str,
+        host_token: Option<String>,
+        generation: Option<u64>,
+    ) -> TestPlayer {
+        // The client never sends anything, everything comes in through the manager
+        let player = self.player(
+            session,
+            name,
+            host_token.clone(),
+            stream::pending::<Result<Bytes, PayloadError>>(),
+        );
+
+        self.manager.do_send(session::PlayerConnect {
+            session: code(session),
+            player: player.addr.clone(),
+            name: Some(name.into()),
+            client: None,
+            address: None,
+            host_token,
+            spectator: false,
+            generation,
+        });
+
+        player
+    }
+
+    fn player(
+        &self,
+        session: &str,
+        name: &str,
+        host_token: Option<String>,
+        client: impl Stream<Item = Result<Bytes, PayloadError>> + 'static,
+    ) -> TestPlayer {
+        let player = PlayerActor::new(
+            self.manager.clone(),
+            code(session),
+            Some(name.into()),
+            None,
+            None,
+            host_token,
+            false,
+            // Never pings or times out while a test runs
+            Heartbeat {
+                interval: Duration::from_secs(3600),
+                timeout: Duration::from_secs(7200),
+                background_timeout: Duration::from_secs(7200),
+            },
+            Transport::Socket,
+        );
+        let (addr, frames) = ws::WebsocketContext::create_with_addr(player, client);
+
+        TestPlayer {
+            addr,
+            frames: Box::pin(frames),
+            buffer: Vec::new(),
+            closed: None,
+            client: None,
+        }
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Videos named after their index
+pub fn shitposts(entries: usize) -> Vec<Shitpost> {
+    (0..entries)
+        .map(|i| Shitpost {
+            title: i.to_string(),
+            url: format!("/shitposts/test/{}.mp4", i),
+            kind: ShitpostKind::Video,
+            thumbnail: None,
+            low_url: None,
+            fingerprint: None,
+            start_offset: 0.0,
+            loudness: None,
+            compat: BrowserCompat::Safe,
+            folder: "test".to_string(),
+            size: None,
+            modified: None,
+            tags: Vec::new(),
+        })
+        .collect()
+}
+
+pub fn code(code: &str) -> SessionCode {
+    SessionCode::try_from(code.to_string()).unwrap()
+}
+
+pub struct TestPlayer {
+    pub addr: Addr<PlayerActor>,
+    /// Encoded websocket frames, as they would be written to the socket
+    frames: Pin<Box<dyn Stream<Item = Result<Bytes, actix_web::Error>>>>,
+    /// Bytes of frames that haven't been read in full yet
+    buffer: Vec<u8>,
+    /// Set once the close frame has been read
+    pub closed: Option<ws::CloseReason>,
+    /// Where frames from the browser go, for players made by `open_socket`
+    client: Option<mpsc::UnboundedSender<Bytes>>,
+}
+
+impl TestPlayer {
+    /// The messages sent to the player since the last call, parsed like the client would
+    pub async fn received(&mut self) -> Vec<Value> {
+        actix_web::rt::time::sleep(SETTLE).await;
+        while let Some(Some(Ok(bytes))) = self.frames.next().now_or_never() {
+            self.buffer.extend_from_slice(&bytes);
+        }
+
+        let mut messages = Vec::new();
+        while let Some((opcode, payload, length)) = decode_frame(&self.buffer) {
+            // Pings and the like aren't part of the protocol
+            match opcode {
+                1 => messages.push(serde_json::from_slice(payload).unwrap()),
+                8 if payload.len() >= 2 => {
+                    self.closed = Some(ws::CloseReason {
+                        code: u16::from_be_bytes([payload[0], payload[1]]).into(),
+                        description: Some(String::from_utf8_lossy(&payload[2..]).to_string())
+                            .filter(|description| !description.is_empty()),
+                    })
+                }
+                _ => {}
+            }
+            self.buffer.drain(..length);
+        }
+        messages
+    }
+
+    /// Sends a frame to the player like the browser would, masked as clients have to.
+    /// The player only gets to it while it is being read by `received`.
+    /// Panics for players not made by `open_socket`.
+    pub fn send_frame(&self, opcode: u8, payload: &[u8]) {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            length @ 0..=125 => frame.push(0x80 | length as u8),
+            length => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+        }
+        // A mask of zeroes leaves the payload as it is
+        frame.extend_from_slice(&[0; 4]);
+        frame.extend_from_slice(payload);
+
+        let _ = self
+            .client
+            .as_ref()
+            .expect("only players made by open_socket have a socket")
+            .send(frame.into());
+    }
+
+    /// The names of the messages sent since the last call, like "change_state"
+    pub async fn received_kinds(&mut self) -> Vec<String> {
+        self.received().await.iter().map(kind).collect()
+    }
+}
+
+/// The name of a message, the key of its object or the string of ones without data
+pub fn kind(message: &Value) -> String {
+    match message {
+        Value::String(kind) => kind.clone(),
+        Value::Object(object) => object.keys().next().cloned().unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+/// The opcode and payload of an unmasked server frame, and the length of the whole frame
+fn decode_frame(buffer: &[u8]) -> Option<(u8, &[u8], usize)> {
+    let opcode = buffer.first()? & 0x0f;
+    let (length, header) = match buffer.get(1)? & 0x7f {
+        126 => (
+            u16::from_be_bytes(buffer.get(2..4)?.try_into().ok()?) as usize,
+            4,
+        ),
+        127 => (
+            u64::from_be_bytes(buffer.get(2..10)?.try_into().ok()?) as usize,
+            10,
+        ),
+        length => (length as usize, 2),
+    };
+
+    Some((
+        opcode,
+        buffer.get(header..header + length)?,
+        header + length,
+    ))
+}
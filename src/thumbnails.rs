@@ -0,0 +1,93 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::UNIX_EPOCH,
+};
+
+use crate::Config;
+
+/// Width of the generated thumbnails in pixels
+const WIDTH: u32 = 256;
+
+/// Generates video thumbnails with an external ffmpeg or ffmpegthumbnailer binary
+pub struct Thumbnailer {
+    binary: String,
+    dir: PathBuf,
+}
+
+impl Thumbnailer {
+    /// None if thumbnails are disabled
+    pub fn from_config(config: &Config) -> Option<Self> {
+        Some(Self {
+            binary: config.thumbnailer.clone()?,
+            dir: PathBuf::from(&config.thumbnail_dir),
+        })
+    }
+
+    /// Identifies the thumbnail of a file, a changed file gets a new one
+    pub fn key(path: &Path) -> io::Result<String> {
+        let modified = fs::metadata(path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        // Not the std hasher, its output can change between Rust versions and orphan the cache
+        Ok(crate::sha256_hex(&[
+            path.to_string_lossy().as_bytes(),
+            &modified.as_secs().to_le_bytes(),
+            &modified.subsec_nanos().to_le_bytes(),
+        ]))
+    }
+
+    pub fn url(key: &str) -> String {
+        format!("/thumbs/{}.jpg", key)
+    }
+
+    pub fn exists(&self, key: &str) -> bool {
+        self.path(key).is_file()
+    }
+
+    /// Renders a thumbnail of the video, blocking until the binary is done
+    pub fn generate(&self, source: &Path, key: &str) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|why| why.to_string())?;
+        let output = self.path(key);
+
+        let mut command = Command::new(&self.binary);
+        if self.binary.contains("ffmpegthumbnailer") {
+            command
+                .arg("-i")
+                .arg(source)
+                .arg("-o")
+                .arg(&output)
+                .arg("-s")
+                .arg(WIDTH.to_string());
+        } else {
+            command
+                .args(["-loglevel", "error", "-y", "-ss", "1", "-i"])
+                .arg(source)
+                .args(["-frames:v", "1", "-vf"])
+                .arg(format!("scale={}:-2", WIDTH))
+                .arg(&output);
+        }
+
+        let status = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_err(|why| format!(r#"Failed to run "{}": {}"#, self.binary, why))?;
+
+        if status.success() && output.is_file() {
+            Ok(())
+        } else {
+            // Don't leave partial files around to be served
+            let _ = fs::remove_file(&output);
+            Err(format!(r#""{}" exited with {}"#, self.binary, status))
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.jpg", key))
+    }
+}
@@ -0,0 +1,335 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_multipart::Multipart;
+use actix_web::{
+    post,
+    web::{self, Data, Query},
+    HttpResponse,
+};
+use futures_util::TryStreamExt;
+use rand::{distributions::Alphanumeric, Rng};
+
+use crate::{
+    api::{self, TokenQuery},
+    media::{self, MediaIndex},
+    MediaType, SharedConfig, Shitpost, UPLOAD_FOLDER,
+};
+
+/// Longer file names are shortened, keeping the extension
+const MAX_NAME_LENGTH: usize = 128;
+/// How many numbered names are tried before giving up on a colliding name
+const MAX_SUFFIX: usize = 1000;
+
+/// Accepts a file into the upload folder, where it can be picked right away.
+/// Takes the API token, as anyone else could fill the disk.
+#[post("/upload")]
+async fn upload(
+    config: Data<SharedConfig>,
+    media: Data<MediaIndex>,
+    query: Query<TokenQuery>,
+    mut payload: Multipart,
+) -> HttpResponse {
+    let config = config.get();
+    if !api::authorized(&config, &query) {
+        return HttpResponse::Forbidden()
+            .json(serde_json::json!({ "error": "Uploads need the API token" }));
+    }
+    let Some(upload_dir) = config.upload_dir.clone() else {
+        return HttpResponse::NotFound()
+            .json(serde_json::json!({ "error": "Uploads are disabled" }));
+    };
+
+    loop {
+        let mut field = match payload.try_next().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(why) => {
+                return HttpResponse::BadRequest()
+                    .json(serde_json::json!({ "error": why.to_string() }))
+            }
+        };
+
+        // Other form fields are ignored
+        let Some(filename) = field.content_disposition().get_filename().map(String::from) else {
+            continue;
+        };
+        let Some(name) = sanitize_filename(&filename) else {
+            return HttpResponse::BadRequest()
+                .json(serde_json::json!({ "error": "Invalid file name" }));
+        };
+        let Some(media_type) = MediaType::from_path(&name) else {
+            return HttpResponse::UnsupportedMediaType()
+                .json(serde_json::json!({ "error": "Unsupported file type" }));
+        };
+
+        let dir = PathBuf::from(&upload_dir);
+        let (mut partial, mut file) = match blocking(move || PartialUpload::create(&dir)).await {
+            Ok(created) => created,
+            Err(why) => {
+                tracing::error!(r#"Failed to store upload "{}": {}"#, filename, why);
+                return HttpResponse::InternalServerError()
+                    .json(serde_json::json!({ "error": "Failed to store the file" }));
+            }
+        };
+
+        // Written as it arrives, so large files aren't held in memory
+        let mut size = 0;
+        loop {
+            match field.try_next().await {
+                Ok(Some(chunk)) => {
+                    size += chunk.len();
+                    if size > config.max_upload_size {
+                        return HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                            "error": format!("Files may be at most {} bytes", config.max_upload_size)
+                        }));
+                    }
+                    file = match blocking(move || file.write_all(&chunk).map(|()| file)).await {
+                        Ok(file) => file,
+                        Err(why) => {
+                            tracing::error!(r#"Failed to store upload "{}": {}"#, filename, why);
+                            return HttpResponse::InternalServerError()
+                                .json(serde_json::json!({ "error": "Failed to store the file" }));
+                        }
+                    };
+                }
+                Ok(None) => break,
+                Err(why) => {
+                    return HttpResponse::BadRequest()
+                        .json(serde_json::json!({ "error": why.to_string() }))
+                }
+            }
+        }
+
+        let dir = PathBuf::from(&upload_dir);
+        let temp = partial.path.clone();
+        let dedupe = config.dedupe;
+        let stored = blocking(move || {
+            file.sync_all()?;
+            drop(file);
+            let name = store(&dir, &name, &temp)?;
+            // Read back from the disk like a scan would, so copies are recognized either way
+            let fingerprint = dedupe
+                .then(|| media::fingerprint(&dir.join(&name)).ok())
+                .flatten();
+            Ok((name, fingerprint))
+        })
+        .await;
+        let (name, fingerprint) = match stored {
+            Ok(stored) => {
+                partial.stored = true;
+                stored
+            }
+            Err(why) => {
+                tracing::error!(r#"Failed to store upload "{}": {}"#, filename, why);
+                return HttpResponse::InternalServerError()
+                    .json(serde_json::json!({ "error": "Failed to store the file" }));
+            }
+        };
+
+        tracing::info!(r#"Uploaded "{}""#, name);
+        let shitpost = Shitpost {
+            url: crate::file_url(UPLOAD_FOLDER, &name),
+            title: crate::sanitize_title(&name),
+            kind: media_type.kind,
+            thumbnail: None,
+            low_url: None,
+            start_offset: 0.0,
+            loudness: None,
+            compat: media_type.compat,
+            folder: UPLOAD_FOLDER.to_string(),
+            size: Some(size as u64),
+            modified: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|time| time.as_secs()),
+            tags: Vec::new(),
+            fingerprint,
+        };
+        media.insert(UPLOAD_FOLDER, shitpost.clone());
+
+        return HttpResponse::Ok().json(serde_json::json!({
+            "title": shitpost.title,
+            "url": shitpost.url,
+        }));
+    }
+
+    HttpResponse::BadRequest().json(serde_json::json!({ "error": "No file was uploaded" }))
+}
+
+/// Runs file IO on a blocking thread
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> io::Result<T> {
+    web::block(f).await.map_err(io::Error::other)?
+}
+
+/// A file being received, under a name scans skip until it is complete.
+/// Removed when dropped unless it was stored, like when the upload fails halfway.
+struct PartialUpload {
+    path: PathBuf,
+    stored: bool,
+}
+
+impl PartialUpload {
+    fn create(dir: &Path) -> io::Result<(Self, File)> {
+        let id = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect::<String>();
+        let path = dir.join(format!(".upload-{}.part", id));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+
+        Ok((
+            Self {
+                path,
+                stored: false,
+            },
+            file,
+        ))
+    }
+}
+
+impl Drop for PartialUpload {
+    fn drop(&mut self) {
+        if !self.stored {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// The last path component without control characters or leading dots, None if nothing is left
+fn sanitize_filename(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next()?;
+    let name = name.chars().filter(|c| !c.is_control()).collect::<String>();
+    let name = name.trim().trim_start_matches('.');
+
+    if name.is_empty() {
+        return None;
+    }
+    if name.chars().count() <= MAX_NAME_LENGTH {
+        return Some(name.to_string());
+    }
+
+    let (stem, extension) = split_extension(name);
+    let stem = stem
+        .chars()
+        .take(MAX_NAME_LENGTH.saturating_sub(extension.chars().count() + 1))
+        .collect::<String>();
+    Some(numbered(stem.trim_end(), extension, 0))
+}
+
+fn split_extension(name: &str) -> (&str, &str) {
+    name.rsplit_once('.').unwrap_or((name, ""))
+}
+
+/// "clip.mp4", then "clip (1).mp4" and so on
+fn numbered(stem: &str, extension: &str, number: usize) -> String {
+    match (number, extension) {
+        (0, "") => stem.to_string(),
+        (0, extension) => format!("{}.{}", stem, extension),
+        (number, "") => format!("{} ({})", stem, number),
+        (number, extension) => format!("{} ({}).{}", stem, number, extension),
+    }
+}
+
+/// Moves the received file to the first free name, returning the name it was stored under
+fn store(dir: &Path, name: &str, received: &Path) -> io::Result<String> {
+    let (stem, extension) = split_extension(name);
+
+    for number in 0..MAX_SUFFIX {
+        let name = numbered(stem, extension, number);
+        let path = dir.join(&name);
+
+        // Creating the file claims the name, so concurrent uploads can't overwrite each other
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => {}
+            Err(why) if why.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(why) => return Err(why),
+        }
+
+        // Replaces the claimed empty file, so the name never shows a partial upload
+        return match fs::rename(received, &path) {
+            Ok(()) => Ok(name),
+            Err(why) => {
+                let _ = fs::remove_file(&path);
+                Err(why)
+            }
+        };
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!(r#"Too many files named "{}""#, name),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use crate::upload::{sanitize_filename, store, PartialUpload, MAX_NAME_LENGTH};
+
+    #[test]
+    fn upload_names() {
+        assert_eq!(
+            sanitize_filename("../../etc/passwd.mp4").as_deref(),
+            Some("passwd.mp4")
+        );
+        assert_eq!(
+            sanitize_filename(r"C:\clips\cat.webm").as_deref(),
+            Some("cat.webm")
+        );
+        assert_eq!(
+            sanitize_filename(".hidden.png").as_deref(),
+            Some("hidden.png")
+        );
+        assert_eq!(
+            sanitize_filename("bad\nname.gif").as_deref(),
+            Some("badname.gif")
+        );
+        assert_eq!(sanitize_filename("clips/"), None);
+        assert_eq!(sanitize_filename(".."), None);
+
+        let long = sanitize_filename(&format!("{}.mp4", "a".repeat(300))).unwrap();
+        assert_eq!(long.chars().count(), MAX_NAME_LENGTH);
+        assert!(long.ends_with(".mp4"));
+
+        let folder = std::env::temp_dir().join(format!("uploads-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+
+        let received = |bytes: &[u8]| {
+            let (mut partial, mut file) = PartialUpload::create(&folder).unwrap();
+            file.write_all(bytes).unwrap();
+            partial.stored = true;
+            partial.path.clone()
+        };
+        assert_eq!(
+            store(&folder, "clip.mp4", &received(b"a")).unwrap(),
+            "clip.mp4"
+        );
+        assert_eq!(
+            store(&folder, "clip.mp4", &received(b"b")).unwrap(),
+            "clip (1).mp4"
+        );
+        assert_eq!(fs::read(folder.join("clip.mp4")).unwrap(), b"a");
+        assert_eq!(fs::read(folder.join("clip (1).mp4")).unwrap(), b"b");
+
+        // Dropped halfway, nothing is left behind
+        let (partial, _file) = PartialUpload::create(&folder).unwrap();
+        let path = partial.path.clone();
+        drop(partial);
+        assert!(!path.exists());
+        assert_eq!(fs::read_dir(&folder).unwrap().count(), 2);
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+//! Posts session events to a Discord webhook, so the group chat hears about sessions
+//! without someone pasting the link
+
+use std::time::{Duration, Instant};
+
+/// Lines waiting to go to a webhook. Lines arriving close together are posted as one message,
+/// which keeps bursts like a restart from running into Discord's rate limits.
+pub struct Webhook {
+    url: String,
+    pending: Vec<String>,
+    /// Set while a post is scheduled, later lines go out with it
+    scheduled: bool,
+    last_post: Option<Instant>,
+}
+
+impl Webhook {
+    /// How long lines are collected before they are posted
+    const COALESCE: Duration = Duration::from_secs(3);
+    /// Least time between posts, Discord allows about 30 a minute per webhook
+    const MIN_INTERVAL: Duration = Duration::from_secs(10);
+    /// Discord rejects longer messages
+    const MAX_LENGTH: usize = 2000;
+
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            pending: Vec::new(),
+            scheduled: false,
+            last_post: None,
+        }
+    }
+
+    /// Queues a line, returning how long to wait before calling `take`
+    /// if no post was scheduled yet
+    pub fn push(&mut self, line: String, now: Instant) -> Option<Duration> {
+        self.pending.push(line);
+        if self.scheduled {
+            return None;
+        }
+
+        self.scheduled = true;
+        let allowed_in = self
+            .last_post
+            .map(|last| (last + Self::MIN_INTERVAL).saturating_duration_since(now))
+            .unwrap_or_default();
+        Some(Self::COALESCE.max(allowed_in))
+    }
+
+    /// The pending lines as one message, lines that don't fit are only counted
+    pub fn take(&mut self, now: Instant) -> Option<String> {
+        self.scheduled = false;
+        if self.pending.is_empty() {
+            return None;
+        }
+        self.last_post = Some(now);
+
+        let lines = std::mem::take(&mut self.pending);
+        let mut content = String::new();
+        for (i, line) in lines.iter().enumerate() {
+            let rest = format!("\n…and {} more", lines.len() - i);
+            if content.len() + line.len() + 1 + rest.len() > Self::MAX_LENGTH {
+                content.push_str(&rest);
+                break;
+            }
+            if !content.is_empty() {
+                content.push('\n');
+            }
+            content.push_str(line);
+        }
+        Some(content)
+    }
+
+    /// Posts a message on the runtime, failures are only logged
+    pub fn post(&self, content: String) {
+        let url = self.url.clone();
+        actix_web::rt::spawn(async move {
+            let result = awc::Client::new()
+                .post(&url)
+                .send_json(&serde_json::json!({
+                    "content": content,
+                    // Nicknames and titles can't ping anyone
+                    "allowed_mentions": { "parse": [] },
+                }))
+                .await;
+
+            match result {
+                Ok(response) if response.status().is_success() => {}
+                Ok(response) => {
+                    tracing::warn!("The Discord webhook answered with {}", response.status())
+                }
+                Err(why) => tracing::warn!("Failed to post to the Discord webhook: {}", why),
+            }
+        });
+    }
+}
+
+/// Like "1h12m", "5m" or "40s"
+pub fn duration_text(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60) {
+        (0, 0) => format!("{}s", secs),
+        (0, minutes) => format!("{}m", minutes),
+        (hours, minutes) => format!("{}h{}m", hours, minutes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::webhook::{duration_text, Webhook};
+
+    #[test]
+    fn coalescing() {
+        let mut webhook = Webhook::new("https://discord.com/api/webhooks/1/abc".to_string());
+        let start = Instant::now();
+
+        assert_eq!(
+            webhook.push("first".to_string(), start),
+            Some(Webhook::COALESCE)
+        );
+        assert_eq!(webhook.push("second".to_string(), start), None);
+        assert_eq!(
+            webhook.take(start + Webhook::COALESCE).as_deref(),
+            Some("first\nsecond")
+        );
+        assert_eq!(webhook.take(start + Webhook::COALESCE), None);
+
+        // Posts right after another wait for the interval
+        let later = start + Webhook::COALESCE + Duration::from_secs(1);
+        assert_eq!(
+            webhook.push("third".to_string(), later),
+            Some(Webhook::MIN_INTERVAL - Duration::from_secs(1))
+        );
+
+        let long = "a".repeat(900);
+        for _ in 0..3 {
+            webhook.push(long.clone(), later);
+        }
+        let content = webhook.take(later + Webhook::MIN_INTERVAL).unwrap();
+        assert!(content.len() <= Webhook::MAX_LENGTH);
+        assert!(content.starts_with("third\n"));
+        assert!(content.ends_with("…and 1 more"));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(duration_text(Duration::from_secs(40)), "40s");
+        assert_eq!(duration_text(Duration::from_secs(5 * 60 + 10)), "5m");
+        assert_eq!(duration_text(Duration::from_secs(72 * 60)), "1h12m");
+    }
+}